@@ -0,0 +1,229 @@
+/* Accelerator table resources.
+ *
+ * 16-bit entries are five bytes: [byte] flags, [word] key, [word] command.
+ * 32-bit entries are eight: [word] flags, [word] key, [word] command,
+ * [word] padding. The last entry has the 0x80 flag set.
+ *
+ * The key is a virtual-key code if FVIRTKEY is set, and a character
+ * otherwise. Character accelerators with control codes are what RC makes
+ * of "^C" and the like. */
+
+use crate::util::{read_byte, read_word};
+use std::sync::Mutex;
+
+pub const FVIRTKEY: u16 = 0x01;
+pub const FNOINVERT: u16 = 0x02;
+pub const FSHIFT: u16 = 0x04;
+pub const FCONTROL: u16 = 0x08;
+pub const FALT: u16 = 0x10;
+pub const ACCEL_END: u16 = 0x80;
+
+#[derive(Clone, Debug, Default)]
+pub struct Accelerator {
+    pub flags: u16,
+    pub key: u16,
+    pub id: u16,
+}
+
+/* Labels of the module's menu items, by command ID, so that accelerators
+ * can say which item they invoke. Filled in before resources are printed,
+ * since the accelerator table may come before the menu. */
+static MENU_ITEMS: Mutex<Vec<(u16, String)>> = Mutex::new(Vec::new());
+
+pub fn set_menu_items(items: Vec<(u16, String)>) {
+    *MENU_ITEMS.lock().unwrap() = items;
+}
+
+pub fn menu_item(id: u16) -> Option<String> {
+    MENU_ITEMS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|(i, _)| *i == id)
+        .map(|(_, label)| label.clone())
+}
+
+pub fn read_accelerators(data: &Vec<u8>, wide: bool) -> Vec<Accelerator> {
+    let size = if wide { 8 } else { 5 };
+    let mut ret = Vec::new();
+    let mut offset = 0;
+
+    while offset + size <= data.len() {
+        let accel = if wide {
+            Accelerator {
+                flags: read_word(data, offset),
+                key: read_word(data, offset + 2),
+                id: read_word(data, offset + 4),
+            }
+        } else {
+            Accelerator {
+                flags: read_byte(data, offset) as u16,
+                key: read_word(data, offset + 1),
+                id: read_word(data, offset + 3),
+            }
+        };
+        let end = accel.flags & ACCEL_END != 0;
+        ret.push(accel);
+        if end {
+            break;
+        }
+        offset += size;
+    }
+    ret
+}
+
+pub fn vk_name(key: u16) -> String {
+    let name = match key {
+        0x01 => "VK_LBUTTON",
+        0x02 => "VK_RBUTTON",
+        0x03 => "VK_CANCEL",
+        0x04 => "VK_MBUTTON",
+        0x05 => "VK_XBUTTON1",
+        0x06 => "VK_XBUTTON2",
+        0x08 => "VK_BACK",
+        0x09 => "VK_TAB",
+        0x0c => "VK_CLEAR",
+        0x0d => "VK_RETURN",
+        0x10 => "VK_SHIFT",
+        0x11 => "VK_CONTROL",
+        0x12 => "VK_MENU",
+        0x13 => "VK_PAUSE",
+        0x14 => "VK_CAPITAL",
+        0x15 => "VK_KANA",
+        0x17 => "VK_JUNJA",
+        0x18 => "VK_FINAL",
+        0x19 => "VK_KANJI",
+        0x1b => "VK_ESCAPE",
+        0x1c => "VK_CONVERT",
+        0x1d => "VK_NONCONVERT",
+        0x1e => "VK_ACCEPT",
+        0x1f => "VK_MODECHANGE",
+        0x20 => "VK_SPACE",
+        0x21 => "VK_PRIOR",
+        0x22 => "VK_NEXT",
+        0x23 => "VK_END",
+        0x24 => "VK_HOME",
+        0x25 => "VK_LEFT",
+        0x26 => "VK_UP",
+        0x27 => "VK_RIGHT",
+        0x28 => "VK_DOWN",
+        0x29 => "VK_SELECT",
+        0x2a => "VK_PRINT",
+        0x2b => "VK_EXECUTE",
+        0x2c => "VK_SNAPSHOT",
+        0x2d => "VK_INSERT",
+        0x2e => "VK_DELETE",
+        0x2f => "VK_HELP",
+        /* letters and digits are their own codes, and have no VK_ names */
+        0x30..=0x39 | 0x41..=0x5a => return (key as u8 as char).to_string(),
+        0x5b => "VK_LWIN",
+        0x5c => "VK_RWIN",
+        0x5d => "VK_APPS",
+        0x5f => "VK_SLEEP",
+        0x60..=0x69 => return format!("VK_NUMPAD{}", key - 0x60),
+        0x6a => "VK_MULTIPLY",
+        0x6b => "VK_ADD",
+        0x6c => "VK_SEPARATOR",
+        0x6d => "VK_SUBTRACT",
+        0x6e => "VK_DECIMAL",
+        0x6f => "VK_DIVIDE",
+        0x70..=0x87 => return format!("VK_F{}", key - 0x6f),
+        0x90 => "VK_NUMLOCK",
+        0x91 => "VK_SCROLL",
+        0xa0 => "VK_LSHIFT",
+        0xa1 => "VK_RSHIFT",
+        0xa2 => "VK_LCONTROL",
+        0xa3 => "VK_RCONTROL",
+        0xa4 => "VK_LMENU",
+        0xa5 => "VK_RMENU",
+        0xa6 => "VK_BROWSER_BACK",
+        0xa7 => "VK_BROWSER_FORWARD",
+        0xa8 => "VK_BROWSER_REFRESH",
+        0xa9 => "VK_BROWSER_STOP",
+        0xaa => "VK_BROWSER_SEARCH",
+        0xab => "VK_BROWSER_FAVORITES",
+        0xac => "VK_BROWSER_HOME",
+        0xad => "VK_VOLUME_MUTE",
+        0xae => "VK_VOLUME_DOWN",
+        0xaf => "VK_VOLUME_UP",
+        0xb0 => "VK_MEDIA_NEXT_TRACK",
+        0xb1 => "VK_MEDIA_PREV_TRACK",
+        0xb2 => "VK_MEDIA_STOP",
+        0xb3 => "VK_MEDIA_PLAY_PAUSE",
+        0xb4 => "VK_LAUNCH_MAIL",
+        0xb5 => "VK_LAUNCH_MEDIA_SELECT",
+        0xb6 => "VK_LAUNCH_APP1",
+        0xb7 => "VK_LAUNCH_APP2",
+        0xba => "VK_OEM_1",
+        0xbb => "VK_OEM_PLUS",
+        0xbc => "VK_OEM_COMMA",
+        0xbd => "VK_OEM_MINUS",
+        0xbe => "VK_OEM_PERIOD",
+        0xbf => "VK_OEM_2",
+        0xc0 => "VK_OEM_3",
+        0xdb => "VK_OEM_4",
+        0xdc => "VK_OEM_5",
+        0xdd => "VK_OEM_6",
+        0xde => "VK_OEM_7",
+        0xdf => "VK_OEM_8",
+        0xe2 => "VK_OEM_102",
+        0xe5 => "VK_PROCESSKEY",
+        0xe7 => "VK_PACKET",
+        0xf6 => "VK_ATTN",
+        0xf7 => "VK_CRSEL",
+        0xf8 => "VK_EXSEL",
+        0xf9 => "VK_EREOF",
+        0xfa => "VK_PLAY",
+        0xfb => "VK_ZOOM",
+        0xfc => "VK_NONAME",
+        0xfd => "VK_PA1",
+        0xfe => "VK_OEM_CLEAR",
+        _ => return format!("(unknown key 0x{:02x})", key),
+    };
+    name.to_string()
+}
+
+/* The key combination, written the way RC scripts do. */
+pub fn describe_key(accel: &Accelerator) -> String {
+    let mut ret = String::new();
+
+    if accel.flags & FSHIFT != 0 {
+        ret += "Shift+";
+    }
+    if accel.flags & FCONTROL != 0 {
+        ret += "Ctrl+";
+    }
+    if accel.flags & FALT != 0 {
+        ret += "Alt+";
+    }
+
+    if accel.flags & FVIRTKEY != 0 {
+        ret += &vk_name(accel.key);
+    } else if accel.key < 0x20 {
+        ret += &format!("^{}", (accel.key as u8 + 0x40) as char);
+    } else if accel.key == 0x7f {
+        ret += "DEL";
+    } else if accel.key < 0x7f {
+        ret += &format!("'{}'", accel.key as u8 as char);
+    } else {
+        ret += &format!("0x{:02x}", accel.key);
+    }
+    ret
+}
+
+pub fn print_accelerators(table: &Vec<Accelerator>) {
+    for accel in table {
+        print!("    {}", describe_key(accel));
+        if accel.flags & FNOINVERT != 0 {
+            print!(" (FNOINVERT)");
+        }
+        print!(": {}", accel.id);
+        if let Some(label) = menu_item(accel.id) {
+            print!(" (menu item \"{}\")", label);
+        }
+        print!("\n");
+        if accel.flags & 0x60 != 0 {
+            eprintln!("Unknown accelerator flags 0x{:02x}", accel.flags & 0x60);
+        }
+    }
+}
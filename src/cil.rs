@@ -0,0 +1,462 @@
+/* CIL (MSIL) disassembly, for the method bodies of .NET assemblies. This is
+ * much simpler than x86: one opcode byte, or 0xfe and a second byte, then
+ * an operand whose size depends only on the opcode. Tokens are resolved by
+ * the caller, since that takes the metadata tables. */
+
+use crate::util::{read_byte, read_dword, read_qword, read_word};
+use crate::{NO_SHOW_ADDRESSES, NO_SHOW_RAW_INSN};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum CilOperand {
+    None,
+    Int8,
+    UInt8,
+    UInt16,
+    Int32,
+    Int64,
+    Float32,
+    Float64,
+    Token,
+    Branch8,
+    Branch32,
+    Switch,
+}
+
+pub const CIL_OPCODES: [(&str, CilOperand); 0xe1] = [
+    ("nop", CilOperand::None),            /* 00 */
+    ("break", CilOperand::None),          /* 01 */
+    ("ldarg.0", CilOperand::None),        /* 02 */
+    ("ldarg.1", CilOperand::None),        /* 03 */
+    ("ldarg.2", CilOperand::None),        /* 04 */
+    ("ldarg.3", CilOperand::None),        /* 05 */
+    ("ldloc.0", CilOperand::None),        /* 06 */
+    ("ldloc.1", CilOperand::None),        /* 07 */
+    ("ldloc.2", CilOperand::None),        /* 08 */
+    ("ldloc.3", CilOperand::None),        /* 09 */
+    ("stloc.0", CilOperand::None),        /* 0a */
+    ("stloc.1", CilOperand::None),        /* 0b */
+    ("stloc.2", CilOperand::None),        /* 0c */
+    ("stloc.3", CilOperand::None),        /* 0d */
+    ("ldarg.s", CilOperand::UInt8),       /* 0e */
+    ("ldarga.s", CilOperand::UInt8),      /* 0f */
+    ("starg.s", CilOperand::UInt8),       /* 10 */
+    ("ldloc.s", CilOperand::UInt8),       /* 11 */
+    ("ldloca.s", CilOperand::UInt8),      /* 12 */
+    ("stloc.s", CilOperand::UInt8),       /* 13 */
+    ("ldnull", CilOperand::None),         /* 14 */
+    ("ldc.i4.m1", CilOperand::None),      /* 15 */
+    ("ldc.i4.0", CilOperand::None),       /* 16 */
+    ("ldc.i4.1", CilOperand::None),       /* 17 */
+    ("ldc.i4.2", CilOperand::None),       /* 18 */
+    ("ldc.i4.3", CilOperand::None),       /* 19 */
+    ("ldc.i4.4", CilOperand::None),       /* 1a */
+    ("ldc.i4.5", CilOperand::None),       /* 1b */
+    ("ldc.i4.6", CilOperand::None),       /* 1c */
+    ("ldc.i4.7", CilOperand::None),       /* 1d */
+    ("ldc.i4.8", CilOperand::None),       /* 1e */
+    ("ldc.i4.s", CilOperand::Int8),       /* 1f */
+    ("ldc.i4", CilOperand::Int32),        /* 20 */
+    ("ldc.i8", CilOperand::Int64),        /* 21 */
+    ("ldc.r4", CilOperand::Float32),      /* 22 */
+    ("ldc.r8", CilOperand::Float64),      /* 23 */
+    ("", CilOperand::None),               /* 24 */
+    ("dup", CilOperand::None),            /* 25 */
+    ("pop", CilOperand::None),            /* 26 */
+    ("jmp", CilOperand::Token),           /* 27 */
+    ("call", CilOperand::Token),          /* 28 */
+    ("calli", CilOperand::Token),         /* 29 */
+    ("ret", CilOperand::None),            /* 2a */
+    ("br.s", CilOperand::Branch8),        /* 2b */
+    ("brfalse.s", CilOperand::Branch8),   /* 2c */
+    ("brtrue.s", CilOperand::Branch8),    /* 2d */
+    ("beq.s", CilOperand::Branch8),       /* 2e */
+    ("bge.s", CilOperand::Branch8),       /* 2f */
+    ("bgt.s", CilOperand::Branch8),       /* 30 */
+    ("ble.s", CilOperand::Branch8),       /* 31 */
+    ("blt.s", CilOperand::Branch8),       /* 32 */
+    ("bne.un.s", CilOperand::Branch8),    /* 33 */
+    ("bge.un.s", CilOperand::Branch8),    /* 34 */
+    ("bgt.un.s", CilOperand::Branch8),    /* 35 */
+    ("ble.un.s", CilOperand::Branch8),    /* 36 */
+    ("blt.un.s", CilOperand::Branch8),    /* 37 */
+    ("br", CilOperand::Branch32),         /* 38 */
+    ("brfalse", CilOperand::Branch32),    /* 39 */
+    ("brtrue", CilOperand::Branch32),     /* 3a */
+    ("beq", CilOperand::Branch32),        /* 3b */
+    ("bge", CilOperand::Branch32),        /* 3c */
+    ("bgt", CilOperand::Branch32),        /* 3d */
+    ("ble", CilOperand::Branch32),        /* 3e */
+    ("blt", CilOperand::Branch32),        /* 3f */
+    ("bne.un", CilOperand::Branch32),     /* 40 */
+    ("bge.un", CilOperand::Branch32),     /* 41 */
+    ("bgt.un", CilOperand::Branch32),     /* 42 */
+    ("ble.un", CilOperand::Branch32),     /* 43 */
+    ("blt.un", CilOperand::Branch32),     /* 44 */
+    ("switch", CilOperand::Switch),       /* 45 */
+    ("ldind.i1", CilOperand::None),       /* 46 */
+    ("ldind.u1", CilOperand::None),       /* 47 */
+    ("ldind.i2", CilOperand::None),       /* 48 */
+    ("ldind.u2", CilOperand::None),       /* 49 */
+    ("ldind.i4", CilOperand::None),       /* 4a */
+    ("ldind.u4", CilOperand::None),       /* 4b */
+    ("ldind.i8", CilOperand::None),       /* 4c */
+    ("ldind.i", CilOperand::None),        /* 4d */
+    ("ldind.r4", CilOperand::None),       /* 4e */
+    ("ldind.r8", CilOperand::None),       /* 4f */
+    ("ldind.ref", CilOperand::None),      /* 50 */
+    ("stind.ref", CilOperand::None),      /* 51 */
+    ("stind.i1", CilOperand::None),       /* 52 */
+    ("stind.i2", CilOperand::None),       /* 53 */
+    ("stind.i4", CilOperand::None),       /* 54 */
+    ("stind.i8", CilOperand::None),       /* 55 */
+    ("stind.r4", CilOperand::None),       /* 56 */
+    ("stind.r8", CilOperand::None),       /* 57 */
+    ("add", CilOperand::None),            /* 58 */
+    ("sub", CilOperand::None),            /* 59 */
+    ("mul", CilOperand::None),            /* 5a */
+    ("div", CilOperand::None),            /* 5b */
+    ("div.un", CilOperand::None),         /* 5c */
+    ("rem", CilOperand::None),            /* 5d */
+    ("rem.un", CilOperand::None),         /* 5e */
+    ("and", CilOperand::None),            /* 5f */
+    ("or", CilOperand::None),             /* 60 */
+    ("xor", CilOperand::None),            /* 61 */
+    ("shl", CilOperand::None),            /* 62 */
+    ("shr", CilOperand::None),            /* 63 */
+    ("shr.un", CilOperand::None),         /* 64 */
+    ("neg", CilOperand::None),            /* 65 */
+    ("not", CilOperand::None),            /* 66 */
+    ("conv.i1", CilOperand::None),        /* 67 */
+    ("conv.i2", CilOperand::None),        /* 68 */
+    ("conv.i4", CilOperand::None),        /* 69 */
+    ("conv.i8", CilOperand::None),        /* 6a */
+    ("conv.r4", CilOperand::None),        /* 6b */
+    ("conv.r8", CilOperand::None),        /* 6c */
+    ("conv.u4", CilOperand::None),        /* 6d */
+    ("conv.u8", CilOperand::None),        /* 6e */
+    ("callvirt", CilOperand::Token),      /* 6f */
+    ("cpobj", CilOperand::Token),         /* 70 */
+    ("ldobj", CilOperand::Token),         /* 71 */
+    ("ldstr", CilOperand::Token),         /* 72 */
+    ("newobj", CilOperand::Token),        /* 73 */
+    ("castclass", CilOperand::Token),     /* 74 */
+    ("isinst", CilOperand::Token),        /* 75 */
+    ("conv.r.un", CilOperand::None),      /* 76 */
+    ("", CilOperand::None),               /* 77 */
+    ("", CilOperand::None),               /* 78 */
+    ("unbox", CilOperand::Token),         /* 79 */
+    ("throw", CilOperand::None),          /* 7a */
+    ("ldfld", CilOperand::Token),         /* 7b */
+    ("ldflda", CilOperand::Token),        /* 7c */
+    ("stfld", CilOperand::Token),         /* 7d */
+    ("ldsfld", CilOperand::Token),        /* 7e */
+    ("ldsflda", CilOperand::Token),       /* 7f */
+    ("stsfld", CilOperand::Token),        /* 80 */
+    ("stobj", CilOperand::Token),         /* 81 */
+    ("conv.ovf.i1.un", CilOperand::None), /* 82 */
+    ("conv.ovf.i2.un", CilOperand::None), /* 83 */
+    ("conv.ovf.i4.un", CilOperand::None), /* 84 */
+    ("conv.ovf.i8.un", CilOperand::None), /* 85 */
+    ("conv.ovf.u1.un", CilOperand::None), /* 86 */
+    ("conv.ovf.u2.un", CilOperand::None), /* 87 */
+    ("conv.ovf.u4.un", CilOperand::None), /* 88 */
+    ("conv.ovf.u8.un", CilOperand::None), /* 89 */
+    ("conv.ovf.i.un", CilOperand::None),  /* 8a */
+    ("conv.ovf.u.un", CilOperand::None),  /* 8b */
+    ("box", CilOperand::Token),           /* 8c */
+    ("newarr", CilOperand::Token),        /* 8d */
+    ("ldlen", CilOperand::None),          /* 8e */
+    ("ldelema", CilOperand::Token),       /* 8f */
+    ("ldelem.i1", CilOperand::None),      /* 90 */
+    ("ldelem.u1", CilOperand::None),      /* 91 */
+    ("ldelem.i2", CilOperand::None),      /* 92 */
+    ("ldelem.u2", CilOperand::None),      /* 93 */
+    ("ldelem.i4", CilOperand::None),      /* 94 */
+    ("ldelem.u4", CilOperand::None),      /* 95 */
+    ("ldelem.i8", CilOperand::None),      /* 96 */
+    ("ldelem.i", CilOperand::None),       /* 97 */
+    ("ldelem.r4", CilOperand::None),      /* 98 */
+    ("ldelem.r8", CilOperand::None),      /* 99 */
+    ("ldelem.ref", CilOperand::None),     /* 9a */
+    ("stelem.i", CilOperand::None),       /* 9b */
+    ("stelem.i1", CilOperand::None),      /* 9c */
+    ("stelem.i2", CilOperand::None),      /* 9d */
+    ("stelem.i4", CilOperand::None),      /* 9e */
+    ("stelem.i8", CilOperand::None),      /* 9f */
+    ("stelem.r4", CilOperand::None),      /* a0 */
+    ("stelem.r8", CilOperand::None),      /* a1 */
+    ("stelem.ref", CilOperand::None),     /* a2 */
+    ("ldelem", CilOperand::Token),        /* a3 */
+    ("stelem", CilOperand::Token),        /* a4 */
+    ("unbox.any", CilOperand::Token),     /* a5 */
+    ("", CilOperand::None),               /* a6 */
+    ("", CilOperand::None),               /* a7 */
+    ("", CilOperand::None),               /* a8 */
+    ("", CilOperand::None),               /* a9 */
+    ("", CilOperand::None),               /* aa */
+    ("", CilOperand::None),               /* ab */
+    ("", CilOperand::None),               /* ac */
+    ("", CilOperand::None),               /* ad */
+    ("", CilOperand::None),               /* ae */
+    ("", CilOperand::None),               /* af */
+    ("", CilOperand::None),               /* b0 */
+    ("", CilOperand::None),               /* b1 */
+    ("", CilOperand::None),               /* b2 */
+    ("conv.ovf.i1", CilOperand::None),    /* b3 */
+    ("conv.ovf.u1", CilOperand::None),    /* b4 */
+    ("conv.ovf.i2", CilOperand::None),    /* b5 */
+    ("conv.ovf.u2", CilOperand::None),    /* b6 */
+    ("conv.ovf.i4", CilOperand::None),    /* b7 */
+    ("conv.ovf.u4", CilOperand::None),    /* b8 */
+    ("conv.ovf.i8", CilOperand::None),    /* b9 */
+    ("conv.ovf.u8", CilOperand::None),    /* ba */
+    ("", CilOperand::None),               /* bb */
+    ("", CilOperand::None),               /* bc */
+    ("", CilOperand::None),               /* bd */
+    ("", CilOperand::None),               /* be */
+    ("", CilOperand::None),               /* bf */
+    ("", CilOperand::None),               /* c0 */
+    ("", CilOperand::None),               /* c1 */
+    ("refanyval", CilOperand::Token),     /* c2 */
+    ("ckfinite", CilOperand::None),       /* c3 */
+    ("", CilOperand::None),               /* c4 */
+    ("", CilOperand::None),               /* c5 */
+    ("mkrefany", CilOperand::Token),      /* c6 */
+    ("", CilOperand::None),               /* c7 */
+    ("", CilOperand::None),               /* c8 */
+    ("", CilOperand::None),               /* c9 */
+    ("", CilOperand::None),               /* ca */
+    ("", CilOperand::None),               /* cb */
+    ("", CilOperand::None),               /* cc */
+    ("", CilOperand::None),               /* cd */
+    ("", CilOperand::None),               /* ce */
+    ("", CilOperand::None),               /* cf */
+    ("ldtoken", CilOperand::Token),       /* d0 */
+    ("conv.u2", CilOperand::None),        /* d1 */
+    ("conv.u1", CilOperand::None),        /* d2 */
+    ("conv.i", CilOperand::None),         /* d3 */
+    ("conv.ovf.i", CilOperand::None),     /* d4 */
+    ("conv.ovf.u", CilOperand::None),     /* d5 */
+    ("add.ovf", CilOperand::None),        /* d6 */
+    ("add.ovf.un", CilOperand::None),     /* d7 */
+    ("mul.ovf", CilOperand::None),        /* d8 */
+    ("mul.ovf.un", CilOperand::None),     /* d9 */
+    ("sub.ovf", CilOperand::None),        /* da */
+    ("sub.ovf.un", CilOperand::None),     /* db */
+    ("endfinally", CilOperand::None),     /* dc */
+    ("leave", CilOperand::Branch32),      /* dd */
+    ("leave.s", CilOperand::Branch8),     /* de */
+    ("stind.i", CilOperand::None),        /* df */
+    ("conv.u", CilOperand::None),         /* e0 */
+];
+
+pub const CIL_OPCODES_FE: [(&str, CilOperand); 0x1f] = [
+    ("arglist", CilOperand::None),       /* 00 */
+    ("ceq", CilOperand::None),           /* 01 */
+    ("cgt", CilOperand::None),           /* 02 */
+    ("cgt.un", CilOperand::None),        /* 03 */
+    ("clt", CilOperand::None),           /* 04 */
+    ("clt.un", CilOperand::None),        /* 05 */
+    ("ldftn", CilOperand::Token),        /* 06 */
+    ("ldvirtftn", CilOperand::Token),    /* 07 */
+    ("", CilOperand::None),              /* 08 */
+    ("ldarg", CilOperand::UInt16),       /* 09 */
+    ("ldarga", CilOperand::UInt16),      /* 0a */
+    ("starg", CilOperand::UInt16),       /* 0b */
+    ("ldloc", CilOperand::UInt16),       /* 0c */
+    ("ldloca", CilOperand::UInt16),      /* 0d */
+    ("stloc", CilOperand::UInt16),       /* 0e */
+    ("localloc", CilOperand::None),      /* 0f */
+    ("", CilOperand::None),              /* 10 */
+    ("endfilter", CilOperand::None),     /* 11 */
+    ("unaligned.", CilOperand::UInt8),   /* 12 */
+    ("volatile.", CilOperand::None),     /* 13 */
+    ("tail.", CilOperand::None),         /* 14 */
+    ("initobj", CilOperand::Token),      /* 15 */
+    ("constrained.", CilOperand::Token), /* 16 */
+    ("cpblk", CilOperand::None),         /* 17 */
+    ("initblk", CilOperand::None),       /* 18 */
+    ("no.", CilOperand::UInt8),          /* 19 */
+    ("rethrow", CilOperand::None),       /* 1a */
+    ("", CilOperand::None),              /* 1b */
+    ("sizeof", CilOperand::Token),       /* 1c */
+    ("refanytype", CilOperand::None),    /* 1d */
+    ("readonly.", CilOperand::None),     /* 1e */
+];
+
+pub enum CilArg {
+    None,
+    Int(i64),
+    Float(f64),
+    Token(u32),
+    Branch(usize), /* offset into the method body */
+    Switch(Vec<usize>),
+}
+
+pub struct CilInstr {
+    pub opcode: u16, /* 0xfeXX for two-byte opcodes */
+    pub name: &'static str,
+    pub length: usize,
+    pub arg: CilArg,
+}
+
+/* Decode the instruction at `offset` into a method body. Unknown opcodes
+ * and instructions hanging off the end of the body give None. */
+pub fn get_cil_instr(code: &Vec<u8>, offset: usize) -> Option<CilInstr> {
+    let mut opcode = *code.get(offset)? as u16;
+    let mut length = 1;
+    let (name, operand) = if opcode == 0xfe {
+        let second = *code.get(offset + 1)?;
+        opcode = 0xfe00 | second as u16;
+        length = 2;
+        *CIL_OPCODES_FE.get(second as usize)?
+    } else {
+        *CIL_OPCODES.get(opcode as usize)?
+    };
+    if name.is_empty() {
+        return None;
+    }
+
+    let size = match operand {
+        CilOperand::None => 0,
+        CilOperand::Int8 | CilOperand::UInt8 | CilOperand::Branch8 => 1,
+        CilOperand::UInt16 => 2,
+        CilOperand::Int32 | CilOperand::Float32 | CilOperand::Token | CilOperand::Branch32 => 4,
+        CilOperand::Int64 | CilOperand::Float64 => 8,
+        CilOperand::Switch => {
+            /* the count comes first, and has to be there to be read */
+            if offset + length + 4 > code.len() {
+                return None;
+            }
+            4 + 4 * read_dword(code, offset + length) as usize
+        }
+    };
+    if offset + length + size > code.len() {
+        return None;
+    }
+
+    let at = offset + length;
+    let next = (offset + length + size) as i64;
+    let arg = match operand {
+        CilOperand::None => CilArg::None,
+        CilOperand::Int8 => CilArg::Int(read_byte(code, at) as i8 as i64),
+        CilOperand::UInt8 => CilArg::Int(read_byte(code, at) as i64),
+        CilOperand::UInt16 => CilArg::Int(read_word(code, at) as i64),
+        CilOperand::Int32 => CilArg::Int(read_dword(code, at) as i32 as i64),
+        CilOperand::Int64 => CilArg::Int(read_qword(code, at) as i64),
+        CilOperand::Float32 => CilArg::Float(f32::from_bits(read_dword(code, at)) as f64),
+        CilOperand::Float64 => CilArg::Float(f64::from_bits(read_qword(code, at))),
+        CilOperand::Token => CilArg::Token(read_dword(code, at)),
+        /* branches are relative to the next instruction */
+        CilOperand::Branch8 => CilArg::Branch((next + read_byte(code, at) as i8 as i64) as usize),
+        CilOperand::Branch32 => {
+            CilArg::Branch((next + read_dword(code, at) as i32 as i64) as usize)
+        }
+        CilOperand::Switch => {
+            let count = read_dword(code, at) as usize;
+            CilArg::Switch(
+                (0..count)
+                    .map(|i| (next + read_dword(code, at + 4 + i * 4) as i32 as i64) as usize)
+                    .collect(),
+            )
+        }
+    };
+
+    Some(CilInstr {
+        opcode,
+        name,
+        length: length + size,
+        arg,
+    })
+}
+
+/* The branch targets in a method body, for marking them as x86 does. */
+pub fn get_cil_branch_targets(code: &Vec<u8>) -> Vec<usize> {
+    let mut targets = Vec::new();
+    let mut offset = 0;
+    while let Some(instr) = get_cil_instr(code, offset) {
+        match &instr.arg {
+            CilArg::Branch(target) => targets.push(*target),
+            CilArg::Switch(list) => targets.extend(list.iter().copied()),
+            _ => {}
+        }
+        offset += instr.length;
+    }
+    targets.sort();
+    targets.dedup();
+    targets
+}
+
+/* A user string as a C# literal. */
+pub fn quote_string(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted += "\\\"",
+            '\\' => quoted += "\\\\",
+            '\n' => quoted += "\\n",
+            '\r' => quoted += "\\r",
+            '\t' => quoted += "\\t",
+            '\0' => quoted += "\\0",
+            c if (c as u32) < 0x20 => quoted += &format!("\\x{:02x}", c as u32),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/* Print one instruction, in the same columns as x86 code. `base` is the
+ * address of the method body and `resolve` names a token. */
+pub fn print_cil_instr(
+    code: &Vec<u8>,
+    offset: usize,
+    base: u64,
+    is_target: bool,
+    resolve: &dyn Fn(u32) -> String,
+) -> usize {
+    let instr = get_cil_instr(code, offset);
+    let length = instr.as_ref().map_or(1, |instr| instr.length);
+
+    if opts & NO_SHOW_ADDRESSES == 0 {
+        print!("{:8x}", base + offset as u64);
+    }
+    print!("\t");
+    if opts & NO_SHOW_RAW_INSN == 0 {
+        for i in 0..8 {
+            if i < length {
+                print!("{:02x} ", code[offset + i]);
+            } else {
+                print!("   ");
+            }
+        }
+    }
+    print!("{}", if is_target { ">" } else { " " });
+
+    let instr = match instr {
+        Some(instr) => instr,
+        None => {
+            eprintln!("Unknown CIL opcode 0x{:02x}", code[offset]);
+            print!("db 0x{:02x}\n", code[offset]);
+            return 1;
+        }
+    };
+
+    print!("{}", instr.name);
+    match &instr.arg {
+        CilArg::None => {}
+        CilArg::Int(value) => print!("\t{}", value),
+        CilArg::Float(value) => print!("\t{:?}", value),
+        CilArg::Token(token) => print!("\t{}", resolve(*token)),
+        CilArg::Branch(target) => print!("\t{:x}", base + *target as u64),
+        CilArg::Switch(list) => print!(
+            "\t({})",
+            list.iter()
+                .map(|target| format!("{:x}", base + *target as u64))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+    print!("\n");
+    length
+}
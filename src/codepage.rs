@@ -0,0 +1,158 @@
+/* Decoding of 8-bit and DBCS text to UTF-8.
+ *
+ * Strings in 16-bit resources and name tables are in whatever ANSI (or, for
+ * OS/2, OEM) codepage the module was built for. We take it from the
+ * --codepage option if given, otherwise from the Translation value of the
+ * module's version resource, and fall back to 1252.
+ *
+ * The single-byte DOS codepages are small enough to keep as tables here,
+ * like the rest of our tables, but the DBCS ones (932, 936, 949, 950) each
+ * map tens of thousands of characters, so for those and the Windows
+ * codepages we use encoding_rs rather than carry the mappings ourselves. */
+
+use encoding_rs::{
+    Encoding, BIG5, EUC_KR, GBK, SHIFT_JIS, WINDOWS_1250, WINDOWS_1251, WINDOWS_1252, WINDOWS_1253,
+    WINDOWS_1254, WINDOWS_1255, WINDOWS_1256, WINDOWS_1257, WINDOWS_1258,
+};
+use std::sync::Mutex;
+
+pub const DEFAULT_CODEPAGE: u16 = 1252;
+
+/* codepage given on the command line, which overrides the file's own */
+static OPTION_CODEPAGE: Mutex<Option<u16>> = Mutex::new(None);
+/* codepage taken from the file currently being dumped */
+static FILE_CODEPAGE: Mutex<Option<u16>> = Mutex::new(None);
+
+/* upper halves of the DOS codepages, which encoding_rs doesn't have */
+const CP437_HIGH: [u16; 128] = [
+    0x00c7, 0x00fc, 0x00e9, 0x00e2, 0x00e4, 0x00e0, 0x00e5, 0x00e7, 0x00ea, 0x00eb, 0x00e8, 0x00ef,
+    0x00ee, 0x00ec, 0x00c4, 0x00c5, 0x00c9, 0x00e6, 0x00c6, 0x00f4, 0x00f6, 0x00f2, 0x00fb, 0x00f9,
+    0x00ff, 0x00d6, 0x00dc, 0x00a2, 0x00a3, 0x00a5, 0x20a7, 0x0192, 0x00e1, 0x00ed, 0x00f3, 0x00fa,
+    0x00f1, 0x00d1, 0x00aa, 0x00ba, 0x00bf, 0x2310, 0x00ac, 0x00bd, 0x00bc, 0x00a1, 0x00ab, 0x00bb,
+    0x2591, 0x2592, 0x2593, 0x2502, 0x2524, 0x2561, 0x2562, 0x2556, 0x2555, 0x2563, 0x2551, 0x2557,
+    0x255d, 0x255c, 0x255b, 0x2510, 0x2514, 0x2534, 0x252c, 0x251c, 0x2500, 0x253c, 0x255e, 0x255f,
+    0x255a, 0x2554, 0x2569, 0x2566, 0x2560, 0x2550, 0x256c, 0x2567, 0x2568, 0x2564, 0x2565, 0x2559,
+    0x2558, 0x2552, 0x2553, 0x256b, 0x256a, 0x2518, 0x250c, 0x2588, 0x2584, 0x258c, 0x2590, 0x2580,
+    0x03b1, 0x00df, 0x0393, 0x03c0, 0x03a3, 0x03c3, 0x00b5, 0x03c4, 0x03a6, 0x0398, 0x03a9, 0x03b4,
+    0x221e, 0x03c6, 0x03b5, 0x2229, 0x2261, 0x00b1, 0x2265, 0x2264, 0x2320, 0x2321, 0x00f7, 0x2248,
+    0x00b0, 0x2219, 0x00b7, 0x221a, 0x207f, 0x00b2, 0x25a0, 0x00a0,
+];
+
+const CP850_HIGH: [u16; 128] = [
+    0x00c7, 0x00fc, 0x00e9, 0x00e2, 0x00e4, 0x00e0, 0x00e5, 0x00e7, 0x00ea, 0x00eb, 0x00e8, 0x00ef,
+    0x00ee, 0x00ec, 0x00c4, 0x00c5, 0x00c9, 0x00e6, 0x00c6, 0x00f4, 0x00f6, 0x00f2, 0x00fb, 0x00f9,
+    0x00ff, 0x00d6, 0x00dc, 0x00f8, 0x00a3, 0x00d8, 0x00d7, 0x0192, 0x00e1, 0x00ed, 0x00f3, 0x00fa,
+    0x00f1, 0x00d1, 0x00aa, 0x00ba, 0x00bf, 0x00ae, 0x00ac, 0x00bd, 0x00bc, 0x00a1, 0x00ab, 0x00bb,
+    0x2591, 0x2592, 0x2593, 0x2502, 0x2524, 0x00c1, 0x00c2, 0x00c0, 0x00a9, 0x2563, 0x2551, 0x2557,
+    0x255d, 0x00a2, 0x00a5, 0x2510, 0x2514, 0x2534, 0x252c, 0x251c, 0x2500, 0x253c, 0x00e3, 0x00c3,
+    0x255a, 0x2554, 0x2569, 0x2566, 0x2560, 0x2550, 0x256c, 0x00a4, 0x00f0, 0x00d0, 0x00ca, 0x00cb,
+    0x00c8, 0x0131, 0x00cd, 0x00ce, 0x00cf, 0x2518, 0x250c, 0x2588, 0x2584, 0x00a6, 0x00cc, 0x2580,
+    0x00d3, 0x00df, 0x00d4, 0x00d2, 0x00f5, 0x00d5, 0x00b5, 0x00fe, 0x00de, 0x00da, 0x00db, 0x00d9,
+    0x00fd, 0x00dd, 0x00af, 0x00b4, 0x00ad, 0x00b1, 0x2017, 0x00be, 0x00b6, 0x00a7, 0x00f7, 0x00b8,
+    0x00b0, 0x00a8, 0x00b7, 0x00b9, 0x00b3, 0x00b2, 0x25a0, 0x00a0,
+];
+
+fn encoding_for(codepage: u16) -> Option<&'static Encoding> {
+    match codepage {
+        932 => Some(SHIFT_JIS),
+        936 => Some(GBK),
+        949 => Some(EUC_KR), /* encoding_rs's EUC-KR is the Unified Hangul Code superset */
+        950 => Some(BIG5),
+        1250 => Some(WINDOWS_1250),
+        1251 => Some(WINDOWS_1251),
+        1252 => Some(WINDOWS_1252),
+        1253 => Some(WINDOWS_1253),
+        1254 => Some(WINDOWS_1254),
+        1255 => Some(WINDOWS_1255),
+        1256 => Some(WINDOWS_1256),
+        1257 => Some(WINDOWS_1257),
+        1258 => Some(WINDOWS_1258),
+        _ => None,
+    }
+}
+
+pub fn is_supported(codepage: u16) -> bool {
+    codepage == 437 || codepage == 850 || encoding_for(codepage).is_some()
+}
+
+/* Whether the codepage is a DBCS one, i.e. has lead bytes. */
+pub fn is_dbcs(codepage: u16) -> bool {
+    match codepage {
+        932 | 936 | 949 | 950 => true,
+        _ => false,
+    }
+}
+
+/* Returns false if the codepage isn't one we know. */
+pub fn set_codepage(codepage: u16) -> bool {
+    if !is_supported(codepage) {
+        return false;
+    }
+    *OPTION_CODEPAGE.lock().unwrap() = Some(codepage);
+    true
+}
+
+/* Called once per file. Unknown codepages, and the language-neutral and
+ * Unicode ones (0 and 1200) which don't say anything about 8-bit text,
+ * leave the default in place. */
+pub fn set_file_codepage(codepage: Option<u16>) {
+    let codepage = codepage.filter(|&cp| is_supported(cp));
+    *FILE_CODEPAGE.lock().unwrap() = codepage;
+}
+
+pub fn codepage() -> u16 {
+    if let Some(cp) = *OPTION_CODEPAGE.lock().unwrap() {
+        return cp;
+    }
+    FILE_CODEPAGE.lock().unwrap().unwrap_or(DEFAULT_CODEPAGE)
+}
+
+pub fn decode_with(bytes: &[u8], codepage: u16) -> String {
+    let high = match codepage {
+        437 => Some(&CP437_HIGH),
+        850 => Some(&CP850_HIGH),
+        _ => None,
+    };
+    if let Some(high) = high {
+        return bytes
+            .iter()
+            .map(|&b| {
+                if b < 0x80 {
+                    b as char
+                } else {
+                    std::char::from_u32(high[b as usize - 0x80] as u32).unwrap()
+                }
+            })
+            .collect();
+    }
+
+    let encoding = encoding_for(codepage).unwrap_or(WINDOWS_1252);
+    let (text, _) = encoding.decode_without_bom_handling(bytes);
+    text.into_owned()
+}
+
+/* Decode using the current codepage. */
+pub fn decode(bytes: &[u8]) -> String {
+    decode_with(bytes, codepage())
+}
+
+/* Escape a decoded string for printing between double quotes. Non-ASCII
+ * characters are printed as they are; control characters, and the
+ * replacement character that bytes we couldn't decode come out as, are
+ * escaped. */
+pub fn escape(text: &str) -> String {
+    let mut ret = String::new();
+    for c in text.chars() {
+        match c {
+            '\t' => ret += "\\t",
+            '\n' => ret += "\\n",
+            '\r' => ret += "\\r",
+            '"' => ret += "\\\"",
+            '\\' => ret += "\\\\",
+            '\u{fffd}' => ret += "\\ufffd",
+            c if (c as u32) < 0x20 || c == '\x7f' => ret += &format!("\\x{:02x}", c as u32),
+            c => ret.push(c),
+        }
+    }
+    ret
+}
@@ -0,0 +1,204 @@
+/* A minimal DER reader, enough to pick apart PKCS#7 signatures and the
+ * X.509 certificates in them. Elements are borrowed slices of the input;
+ * nothing is validated beyond what we need to walk the structure. */
+
+use std::cmp::min;
+
+pub const TAG_INTEGER: u8 = 0x02;
+pub const TAG_BIT_STRING: u8 = 0x03;
+pub const TAG_OCTET_STRING: u8 = 0x04;
+pub const TAG_NULL: u8 = 0x05;
+pub const TAG_OID: u8 = 0x06;
+pub const TAG_UTF8_STRING: u8 = 0x0c;
+pub const TAG_PRINTABLE_STRING: u8 = 0x13;
+pub const TAG_T61_STRING: u8 = 0x14;
+pub const TAG_IA5_STRING: u8 = 0x16;
+pub const TAG_UTC_TIME: u8 = 0x17;
+pub const TAG_GENERALIZED_TIME: u8 = 0x18;
+pub const TAG_BMP_STRING: u8 = 0x1e;
+pub const TAG_SEQUENCE: u8 = 0x30;
+pub const TAG_SET: u8 = 0x31;
+
+/* context-specific tags, [0], [1] and so on */
+pub const fn context(n: u8, constructed: bool) -> u8 {
+    0x80 | if constructed { 0x20 } else { 0 } | n
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Element<'a> {
+    pub tag: u8,
+    pub contents: &'a [u8],
+    pub raw: &'a [u8], /* including the tag and length */
+}
+
+/* Read one element, returning it and whatever follows. Multi-byte tags and
+ * indefinite lengths aren't DER, so we don't take them. */
+pub fn read_element(data: &[u8]) -> Result<(Element<'_>, &[u8]), String> {
+    if data.len() < 2 {
+        return Err("Truncated DER element.".to_string());
+    }
+    let tag = data[0];
+    if tag & 0x1f == 0x1f {
+        return Err(format!("Multi-byte DER tag 0x{:02x}.", tag));
+    }
+
+    let (length, header) = match data[1] {
+        0x80 => return Err("Indefinite length in DER element.".to_string()),
+        n if n < 0x80 => (n as usize, 2),
+        n => {
+            let count = (n & 0x7f) as usize;
+            if count > 4 || data.len() < 2 + count {
+                return Err("Bad DER length.".to_string());
+            }
+            let length = data[2..2 + count]
+                .iter()
+                .fold(0usize, |a, &b| (a << 8) | b as usize);
+            (length, 2 + count)
+        }
+    };
+    if data.len() - header < length {
+        return Err(format!(
+            "DER element (tag 0x{:02x}) exceeds its container.",
+            tag
+        ));
+    }
+
+    let element = Element {
+        tag,
+        contents: &data[header..header + length],
+        raw: &data[..header + length],
+    };
+    Ok((element, &data[header + length..]))
+}
+
+impl<'a> Element<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Element<'a>, String> {
+        read_element(data).map(|(element, _)| element)
+    }
+
+    /* The elements inside a constructed one. */
+    pub fn children(&self) -> Result<Vec<Element<'a>>, String> {
+        let mut children = Vec::new();
+        let mut rest = self.contents;
+        while !rest.is_empty() {
+            let (child, next) = read_element(rest)?;
+            children.push(child);
+            rest = next;
+        }
+        Ok(children)
+    }
+
+    /* The nth child, checking its tag. */
+    pub fn child(&self, n: usize, tag: u8) -> Result<Element<'a>, String> {
+        let children = self.children()?;
+        match children.get(n) {
+            Some(child) if child.tag == tag => Ok(*child),
+            Some(child) => Err(format!(
+                "Expected DER tag 0x{:02x}, got 0x{:02x}.",
+                tag, child.tag
+            )),
+            None => Err(format!("Missing DER element {}.", n)),
+        }
+    }
+
+    pub fn expect(&self, tag: u8) -> Result<&Self, String> {
+        if self.tag == tag {
+            Ok(self)
+        } else {
+            Err(format!(
+                "Expected DER tag 0x{:02x}, got 0x{:02x}.",
+                tag, self.tag
+            ))
+        }
+    }
+}
+
+/* An OID in dotted form. */
+pub fn oid_to_string(contents: &[u8]) -> String {
+    let mut parts = Vec::new();
+    let mut value = 0u64;
+    for &b in contents {
+        value = (value << 7) | (b & 0x7f) as u64;
+        if b & 0x80 != 0 {
+            continue;
+        }
+        if parts.is_empty() {
+            /* the first byte holds two components */
+            let first = min(value / 40, 2);
+            parts.push(first.to_string());
+            parts.push((value - first * 40).to_string());
+        } else {
+            parts.push(value.to_string());
+        }
+        value = 0;
+    }
+    parts.join(".")
+}
+
+/* An integer (e.g. a serial number) in hex, without the sign padding. */
+pub fn integer_to_hex(contents: &[u8]) -> String {
+    let mut bytes = contents;
+    while bytes.len() > 1 && bytes[0] == 0 {
+        bytes = &bytes[1..];
+    }
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/* Any of the string types. */
+pub fn string_value(element: &Element) -> String {
+    match element.tag {
+        TAG_BMP_STRING => {
+            let units: Vec<u16> = element
+                .contents
+                .chunks(2)
+                .filter(|c| c.len() == 2)
+                .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        /* T61 is close enough to Latin-1 for names */
+        TAG_T61_STRING => element.contents.iter().map(|&c| c as char).collect(),
+        _ => String::from_utf8_lossy(element.contents).into_owned(),
+    }
+}
+
+fn attribute_name(oid: &str) -> String {
+    match oid {
+        "2.5.4.3" => "CN".to_string(),
+        "2.5.4.5" => "serialNumber".to_string(),
+        "2.5.4.6" => "C".to_string(),
+        "2.5.4.7" => "L".to_string(),
+        "2.5.4.8" => "ST".to_string(),
+        "2.5.4.9" => "street".to_string(),
+        "2.5.4.10" => "O".to_string(),
+        "2.5.4.11" => "OU".to_string(),
+        "2.5.4.15" => "businessCategory".to_string(),
+        "1.2.840.113549.1.9.1" => "emailAddress".to_string(),
+        "1.3.6.1.4.1.311.60.2.1.2" => "jurisdictionST".to_string(),
+        "1.3.6.1.4.1.311.60.2.1.3" => "jurisdictionC".to_string(),
+        _ => oid.to_string(),
+    }
+}
+
+/* An X.500 name (a SEQUENCE of SETs of type and value) as "CN=..., O=...".
+ * Unreadable names come out as "?". */
+pub fn name_to_string(name: &Element) -> String {
+    let mut parts = Vec::new();
+    for rdn in name.children().unwrap_or_default() {
+        for attribute in rdn.children().unwrap_or_default() {
+            let fields = attribute.children().unwrap_or_default();
+            if fields.len() == 2 && fields[0].tag == TAG_OID {
+                parts.push(format!(
+                    "{}={}",
+                    attribute_name(&oid_to_string(fields[0].contents)),
+                    string_value(&fields[1])
+                ));
+            }
+        }
+    }
+    if parts.is_empty() {
+        "?".to_string()
+    } else {
+        parts.join(", ")
+    }
+}
@@ -0,0 +1,388 @@
+/* Decoding of device-independent bitmaps, as stored in bitmap, icon and
+ * cursor resources (i.e. without a BITMAPFILEHEADER).
+ *
+ * We handle BITMAPCOREHEADER (12 bytes) and BITMAPINFOHEADER and its later
+ * versions (40, 52, 56, 108 and 124 bytes); 1, 4, 8, 16, 24 and 32 bits
+ * per pixel; BI_RGB, BI_RLE8, BI_RLE4, BI_BITFIELDS and BI_ALPHABITFIELDS.
+ * Icons and cursors have twice the height in their header, since the color
+ * (XOR) bitmap is followed by a monochrome AND mask, which we turn into
+ * alpha. */
+
+use crate::util::{read_byte, read_dword, read_word};
+
+pub const BI_RGB: u32 = 0;
+pub const BI_RLE8: u32 = 1;
+pub const BI_RLE4: u32 = 2;
+pub const BI_BITFIELDS: u32 = 3;
+pub const BI_ALPHABITFIELDS: u32 = 6;
+
+/* decoded image, RGBA, top row first */
+#[derive(Clone, Debug, Default)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct DibHeader {
+    size: u32,
+    width: u32,
+    height: u32,
+    top_down: bool,
+    bit_count: u16,
+    compression: u32,
+    colors: usize,
+    masks: [u32; 4], /* red, green, blue, alpha */
+}
+
+fn read_header(data: &Vec<u8>) -> Result<DibHeader, String> {
+    if data.len() < 12 {
+        return Err(format!("Bitmap is too short ({} bytes).", data.len()));
+    }
+    let size = read_dword(data, 0);
+    let mut header = DibHeader {
+        size,
+        ..Default::default()
+    };
+
+    if size == 12 {
+        /* BITMAPCOREHEADER */
+        header.width = read_word(data, 4) as u32;
+        header.height = read_word(data, 6) as u32;
+        header.bit_count = read_word(data, 10);
+        header.compression = BI_RGB;
+    } else if size >= 40 && size as usize <= data.len() {
+        let height = read_dword(data, 8) as i32;
+        header.width = read_dword(data, 4);
+        header.height = height.unsigned_abs();
+        header.top_down = height < 0;
+        header.bit_count = read_word(data, 14);
+        header.compression = read_dword(data, 16);
+        header.colors = read_dword(data, 32) as usize;
+
+        /* the masks are in the header from the 52-byte version on */
+        if size >= 52 {
+            for i in 0..3 {
+                header.masks[i] = read_dword(data, 40 + i * 4);
+            }
+        }
+        if size >= 56 {
+            header.masks[3] = read_dword(data, 52);
+        }
+    } else {
+        return Err(format!("Unknown bitmap header size {}.", size));
+    }
+
+    if header.width == 0 || header.height == 0 || header.width > 0x4000 || header.height > 0x8000 {
+        return Err(format!(
+            "Bad bitmap dimensions {}x{}.",
+            header.width, header.height
+        ));
+    }
+
+    if header.bit_count <= 8 {
+        if header.colors == 0 || header.colors > 1 << header.bit_count {
+            header.colors = 1 << header.bit_count;
+        }
+    }
+    Ok(header)
+}
+
+/* (shift, bits) of a mask */
+fn mask_shift(mask: u32) -> (u32, u32) {
+    if mask == 0 {
+        (0, 0)
+    } else {
+        (
+            mask.trailing_zeros(),
+            (mask >> mask.trailing_zeros()).count_ones(),
+        )
+    }
+}
+
+fn extract(value: u32, mask: u32) -> u8 {
+    let (shift, bits) = mask_shift(mask);
+    if bits == 0 {
+        return 0;
+    }
+    let v = (value & mask) >> shift;
+    let max = if bits >= 32 {
+        u32::MAX
+    } else {
+        (1 << bits) - 1
+    };
+    (v as u64 * 255 / max as u64) as u8
+}
+
+/* Expand RLE8 or RLE4 data into one palette index per pixel, bottom row
+ * first. Pixels the data skips over (with delta or an early end of line)
+ * are left as None. */
+fn decode_rle(
+    data: &Vec<u8>,
+    mut offset: usize,
+    width: usize,
+    height: usize,
+    rle4: bool,
+) -> Result<Vec<Option<u8>>, String> {
+    let mut pixels = vec![None; width * height];
+    let (mut x, mut y) = (0usize, 0usize);
+
+    let mut put = |x: usize, y: usize, value: u8| {
+        if x < width && y < height {
+            pixels[y * width + x] = Some(value);
+        }
+    };
+
+    while offset + 1 < data.len() && y < height {
+        let count = read_byte(data, offset) as usize;
+        let value = read_byte(data, offset + 1);
+        offset += 2;
+
+        if count > 0 {
+            /* encoded run; for RLE4 the two nibbles alternate */
+            for i in 0..count {
+                let v = if !rle4 {
+                    value
+                } else if i % 2 == 0 {
+                    value >> 4
+                } else {
+                    value & 0xf
+                };
+                put(x, y, v);
+                x += 1;
+            }
+            continue;
+        }
+
+        match value {
+            0 => {
+                /* end of line */
+                x = 0;
+                y += 1;
+            }
+            1 => break, /* end of bitmap */
+            2 => {
+                /* delta */
+                if offset + 2 > data.len() {
+                    return Err("RLE delta exceeds resource length.".to_string());
+                }
+                x += read_byte(data, offset) as usize;
+                y += read_byte(data, offset + 1) as usize;
+                offset += 2;
+            }
+            n => {
+                /* absolute run, padded to a word */
+                let n = n as usize;
+                let bytes = if rle4 { (n + 1) / 2 } else { n };
+                if offset + bytes > data.len() {
+                    return Err("RLE absolute run exceeds resource length.".to_string());
+                }
+                for i in 0..n {
+                    let v = if !rle4 {
+                        read_byte(data, offset + i)
+                    } else if i % 2 == 0 {
+                        read_byte(data, offset + i / 2) >> 4
+                    } else {
+                        read_byte(data, offset + i / 2) & 0xf
+                    };
+                    put(x, y, v);
+                    x += 1;
+                }
+                offset += (bytes + 1) & !1;
+            }
+        }
+    }
+    Ok(pixels)
+}
+
+/* Decode a DIB. If `icon` is set the data is an icon or cursor image, with
+ * an AND mask after the color bitmap. */
+pub fn decode_dib(data: &Vec<u8>, icon: bool) -> Result<Image, String> {
+    let header = read_header(data)?;
+    let width = header.width as usize;
+    let height = if icon {
+        header.height as usize / 2
+    } else {
+        header.height as usize
+    };
+    if height == 0 {
+        return Err("Icon has no height.".to_string());
+    }
+
+    /* masks follow a plain BITMAPINFOHEADER */
+    let mut masks = header.masks;
+    let mut offset = header.size as usize;
+    if header.size == 40 {
+        let mask_count = match header.compression {
+            BI_BITFIELDS => 3,
+            BI_ALPHABITFIELDS => 4,
+            _ => 0,
+        };
+        if offset + mask_count * 4 > data.len() {
+            return Err("Bitmap masks exceed resource length.".to_string());
+        }
+        if header.compression == BI_BITFIELDS {
+            for i in 0..3 {
+                masks[i] = read_dword(data, offset + i * 4);
+            }
+            offset += 12;
+        } else if header.compression == BI_ALPHABITFIELDS {
+            for i in 0..4 {
+                masks[i] = read_dword(data, offset + i * 4);
+            }
+            offset += 16;
+        }
+    }
+    let bitfields = header.compression == BI_BITFIELDS || header.compression == BI_ALPHABITFIELDS;
+    if !bitfields {
+        masks = match header.bit_count {
+            16 => [0x7c00, 0x03e0, 0x001f, 0],
+            _ => [0xff0000, 0x00ff00, 0x0000ff, 0],
+        };
+    }
+
+    /* palette: RGBTRIPLEs for core headers, RGBQUADs otherwise */
+    let entry_size = if header.size == 12 { 3 } else { 4 };
+    let mut colors = header.colors;
+    if colors
+        .checked_mul(entry_size)
+        .and_then(|size| size.checked_add(offset))
+        .map_or(true, |end| end > data.len())
+    {
+        /* Above 8 bpp the palette is only a hint for display on palette
+         * devices, so we can do without one that isn't there. */
+        if header.bit_count <= 8 {
+            return Err("Bitmap palette exceeds resource length.".to_string());
+        }
+        colors = 0;
+    }
+    let mut palette = Vec::new();
+    for i in 0..colors {
+        let p = offset + i * entry_size;
+        palette.push([
+            read_byte(data, p + 2),
+            read_byte(data, p + 1),
+            read_byte(data, p),
+        ]);
+    }
+    offset += colors * entry_size;
+
+    let bpp = header.bit_count as usize;
+    let stride = (width * bpp + 31) / 32 * 4;
+
+    /* Make sure the data is there before allocating for it, since the header
+     * alone could ask for gigabytes. RLE data can't be measured by rows, but
+     * a two-byte run covers at most 255 pixels. */
+    let remaining = data.len().saturating_sub(offset);
+    let fits = match header.compression {
+        BI_RLE8 | BI_RLE4 => (width * height) / 255 <= remaining / 2,
+        _ => stride
+            .checked_mul(height)
+            .map_or(false, |size| size <= remaining),
+    };
+    if !fits {
+        return Err("Bitmap data exceeds resource length.".to_string());
+    }
+    let mut rgba = vec![0u8; width * height * 4];
+
+    /* rows are stored bottom-up unless the height was negative */
+    let row_of = |y: usize| if header.top_down { y } else { height - 1 - y };
+
+    match (header.compression, bpp) {
+        (BI_RLE8, 8) | (BI_RLE4, 4) => {
+            let pixels = decode_rle(data, offset, width, height, header.compression == BI_RLE4)?;
+            for y in 0..height {
+                for x in 0..width {
+                    if let Some(index) = pixels[y * width + x] {
+                        let color = palette.get(index as usize).cloned().unwrap_or([0, 0, 0]);
+                        let p = (row_of(y) * width + x) * 4;
+                        rgba[p..p + 3].copy_from_slice(&color);
+                        rgba[p + 3] = 0xff;
+                    }
+                }
+            }
+        }
+        (BI_RGB, 1) | (BI_RGB, 4) | (BI_RGB, 8) => {
+            for y in 0..height {
+                let row = offset + y * stride;
+                for x in 0..width {
+                    let bit = x * bpp;
+                    let byte = read_byte(data, row + bit / 8);
+                    let index = (byte >> (8 - bpp - bit % 8)) & ((1u16 << bpp) - 1) as u8;
+                    let color = palette.get(index as usize).cloned().unwrap_or([0, 0, 0]);
+                    let p = (row_of(y) * width + x) * 4;
+                    rgba[p..p + 3].copy_from_slice(&color);
+                    rgba[p + 3] = 0xff;
+                }
+            }
+        }
+        (BI_RGB, 16)
+        | (BI_RGB, 24)
+        | (BI_RGB, 32)
+        | (BI_BITFIELDS, 16)
+        | (BI_BITFIELDS, 32)
+        | (BI_ALPHABITFIELDS, 16)
+        | (BI_ALPHABITFIELDS, 32) => {
+            for y in 0..height {
+                let row = offset + y * stride;
+                for x in 0..width {
+                    let value = match bpp {
+                        16 => read_word(data, row + x * 2) as u32,
+                        24 => {
+                            read_word(data, row + x * 3) as u32
+                                | (read_byte(data, row + x * 3 + 2) as u32) << 16
+                        }
+                        _ => read_dword(data, row + x * 4),
+                    };
+                    let p = (row_of(y) * width + x) * 4;
+                    rgba[p] = extract(value, masks[0]);
+                    rgba[p + 1] = extract(value, masks[1]);
+                    rgba[p + 2] = extract(value, masks[2]);
+                    rgba[p + 3] = if masks[3] != 0 {
+                        extract(value, masks[3])
+                    } else if bpp == 32 && icon {
+                        /* 32-bit icons keep alpha in the fourth byte */
+                        (value >> 24) as u8
+                    } else {
+                        0xff
+                    };
+                }
+            }
+        }
+        _ => {
+            return Err(format!(
+                "Unsupported bitmap format ({} bpp, compression {}).",
+                bpp, header.compression
+            ))
+        }
+    }
+
+    if icon {
+        /* Use the AND mask for transparency, unless this is a 32-bit icon
+         * which actually uses its alpha channel. */
+        let has_alpha = bpp == 32 && rgba.chunks(4).any(|p| p[3] != 0);
+        let mask_offset = offset + stride * height;
+        let mask_stride = (width + 31) / 32 * 4;
+
+        if !has_alpha {
+            if mask_offset + mask_stride * height > data.len() {
+                return Err("Icon mask exceeds resource length.".to_string());
+            }
+            for y in 0..height {
+                for x in 0..width {
+                    let byte = read_byte(data, mask_offset + y * mask_stride + x / 8);
+                    let transparent = (byte >> (7 - x % 8)) & 1 != 0;
+                    let p = (row_of(y) * width + x) * 4;
+                    rgba[p + 3] = if transparent { 0 } else { 0xff };
+                }
+            }
+        }
+    }
+
+    Ok(Image {
+        width: header.width,
+        height: height as u32,
+        rgba,
+    })
+}
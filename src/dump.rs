@@ -25,6 +25,7 @@ use std::{error::Error, fs::File};
 
 use memmap::MmapOptions;
 
+use crate::ne::font::print_rsrc_font;
 use crate::util::{read_dword, read_word};
 
 // #include "semblance.h"
@@ -33,8 +34,6 @@ use crate::util::{read_dword, read_word};
 
 // word mode;
 // word opts;
-// char **resource_filters;
-// unsigned resource_filters_count;
 // enum AsmSyntax AsmSyntax;
 
 pub fn dump_file(file_name_path: &str) -> Result<(), Box<dyn Error>> {
@@ -79,10 +78,16 @@ pub fn dump_file(file_name_path: &str) -> Result<(), Box<dyn Error>> {
         if magic == 0x4550 {
             dumppe(offset);
         } else if magic == 0x454e {
-            dumpne(offset);
+            dumpne(&map.to_vec(), offset);
         } else {
             dumpmz();
         }
+    } else if (magic == 0x0100 || magic == 0x0200 || magic == 0x0300)
+        && read_dword(&map.to_vec(), 2) as usize == map.len()
+    {
+        /* a bare .FNT file: version word followed by the file size */
+        println!("Module type: FNT (Windows font)");
+        print_rsrc_font(&map.to_vec(), 0, map.len());
     } else {
         eprintln!("file format not recognized");
         // eprint!( "File format not recognized\n");
@@ -95,6 +100,8 @@ pub fn dump_file(file_name_path: &str) -> Result<(), Box<dyn Error>> {
 // "Usage: dump [options] <file(s)>\n"
 // "Available options:\n"
 // "\t-a, --resource[=filter]              Print embedded resources.\n"
+// "\t\tA filter is a comma-separated list of terms `[!]TYPE [ID] [@LANG]',\n"
+// "\t\te.g. `Icon', `String 1-100', `Dialog* MAIN*', `!Font*' or `Menu 1 @0x409'.\n"
 // "\t-c, --compilable                     Produce output that can be compiled.\n"
 // "\t-C, --demangle                       Demangle C++ function names.\n"
 // "\t-d, --disassemble                    Print disassembled machine code.\n"
@@ -112,9 +119,17 @@ pub fn dump_file(file_name_path: &str) -> Result<(), Box<dyn Error>> {
 // "\t-s, --full-contents                  Display full contents of all sections.\n"
 // "\t-v, --version                        Print the version number of semblance.\n"
 // "\t-x, --all-headers                    Print all headers.\n"
+// "\t--bdf                                Export raster font resources as BDF files.\n"
+// "\t--check                              Check the file for structural anomalies.\n"
+// "\t--codepage=<number>                  Codepage of 8-bit strings (default: from version resource).\n"
+// "\t--fix-checksum=<file>                Write a copy of a single PE file with its checksum corrected.\n"
+// "\t--json                               Print message tables and the debug directory as JSON.\n"
+// "\t--lib-path=<dir>[:<dir>...]          Look for imported NE modules in these directories.\n"
 // "\t--no-show-addresses                  Don't print instruction addresses.\n"
 // "\t--no-show-raw-insn                   Don't print raw instruction hex code.\n"
 // "\t--pe-rel-addr=[y/n]                  Use relative addresses for PE files.\n"
+// "\t--png                                Export bitmap, icon and cursor resources as PNG files.\n"
+// "\t--unwind                             Print x64 unwind info, also alongside disassembly.\n"
 // ;
 
 // static const struct option long_options[] = {
@@ -135,8 +150,16 @@ pub fn dump_file(file_name_path: &str) -> Result<(), Box<dyn Error>> {
 //     {"full-contents",           no_argument,        NULL, 's'},
 //     {"version",                 no_argument,        NULL, 'v'},
 //     {"all-headers",             no_argument,        NULL, 'x'},
+//     {"bdf",                     no_argument,        NULL, EXPORT_BDF},
+//     {"png",                     no_argument,        NULL, EXPORT_PNG},
 //     {"no-show-raw-insn",        no_argument,        NULL, NO_SHOW_RAW_INSN},
 //     {"no-prefix-addresses",     no_argument,        NULL, NO_SHOW_ADDRESSES},
 //     {"pe-rel-addr",             required_argument,  NULL, 0x80},
+//     {"lib-path",                required_argument,  NULL, 0x81},
+//     {"codepage",                required_argument,  NULL, 0x82},
+//     {"check",                   no_argument,        NULL, 0x83},
+//     {"unwind",                  no_argument,        NULL, 0x84},
+//     {"fix-checksum",            required_argument,  NULL, 0x85},
+//     {"json",                    no_argument,        NULL, 0x86},
 //     {0}
 // };
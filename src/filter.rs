@@ -0,0 +1,225 @@
+/* Resource filters, as given to -a. A filter is a comma-separated list of
+ * terms, each of the form
+ *
+ *     [!]TYPE [ID] [@LANG]
+ *
+ * TYPE is a type name ("Icon", "Dialog box" or "DialogBox", or a custom
+ * type's name) or number, and may be a glob. Names with spaces may also be
+ * quoted. ID is a number, a range "1-100", or a glob matched against the
+ * ID's name or decimal value. LANG is a language ID, which only PE resources
+ * have. Matching is case-insensitive.
+ *
+ * A resource is shown if it matches any positive term (or there are none)
+ * and no negated term. So "Icon,Cursor" shows icons and cursors, "!Font*"
+ * everything except fonts, and "String 1-100, !String 50" the first hundred
+ * strings but one. */
+
+use std::sync::Mutex;
+
+#[derive(Clone, Debug, PartialEq)]
+enum IdPattern {
+    Any,
+    Range(u32, u32),
+    Glob(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct FilterTerm {
+    negate: bool,
+    type_pattern: String,
+    id: IdPattern,
+    lang: Option<u16>,
+    /* all the words as a type, for unquoted names like "Dialog box" */
+    whole_type: Option<String>,
+}
+
+static RESOURCE_FILTERS: Mutex<Vec<FilterTerm>> = Mutex::new(Vec::new());
+
+fn parse_number(s: &str) -> Option<u32> {
+    if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        u32::from_str_radix(hex, 16).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+/* Split a term into words, keeping quoted strings together. */
+fn split_words(term: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut chars = term.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            let mut word = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some(c) => word.push(c),
+                    None => return Err(format!("Unterminated quote in `{}'.", term)),
+                }
+            }
+            words.push(word);
+        } else {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || c == '"' {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            words.push(word);
+        }
+    }
+    Ok(words)
+}
+
+fn parse_id(s: &str) -> Result<IdPattern, String> {
+    if s == "*" {
+        return Ok(IdPattern::Any);
+    }
+    if let Some((low, high)) = s.split_once('-') {
+        if let (Some(low), Some(high)) = (parse_number(low), parse_number(high)) {
+            if low > high {
+                return Err(format!("Bad resource ID range `{}'.", s));
+            }
+            return Ok(IdPattern::Range(low, high));
+        }
+    }
+    if let Some(n) = parse_number(s) {
+        return Ok(IdPattern::Range(n, n));
+    }
+    Ok(IdPattern::Glob(s.to_lowercase()))
+}
+
+pub fn parse_filter_term(term: &str) -> Result<FilterTerm, String> {
+    let mut term = term.trim();
+    let negate = term.starts_with('!');
+    if negate {
+        term = term[1..].trim_start();
+    }
+
+    let mut words = split_words(term)?;
+    let mut lang = None;
+    if let Some(last) = words.last() {
+        if let Some(l) = last.strip_prefix('@') {
+            match parse_number(l) {
+                Some(n) if n <= 0xffff => lang = Some(n as u16),
+                _ => return Err(format!("Bad language ID `{}'.", l)),
+            }
+            words.pop();
+        }
+    }
+
+    let whole_type = if words.len() >= 2 {
+        Some(words.join(" ").to_lowercase())
+    } else {
+        None
+    };
+    let (type_pattern, id) = match words.len() {
+        0 => ("*".to_string(), IdPattern::Any),
+        1 => (words[0].to_lowercase(), IdPattern::Any),
+        2 => (words[0].to_lowercase(), parse_id(&words[1])?),
+        _ => {
+            /* an unquoted type name with spaces, e.g. "Dialog box 100" */
+            let id = words.pop().unwrap();
+            (words.join(" ").to_lowercase(), parse_id(&id)?)
+        }
+    };
+
+    Ok(FilterTerm {
+        negate,
+        type_pattern,
+        id,
+        lang,
+        whole_type,
+    })
+}
+
+/* Add the terms of a filter given on the command line. */
+pub fn add_resource_filter(filter: &str) -> Result<(), String> {
+    let mut terms = Vec::new();
+    for term in filter.split(',') {
+        if !term.trim().is_empty() {
+            terms.push(parse_filter_term(term)?);
+        }
+    }
+    RESOURCE_FILTERS.lock().unwrap().extend(terms);
+    Ok(())
+}
+
+/* Glob match with '*' and '?'; both strings are already lowercase. */
+fn glob_match(pattern: &[char], text: &[char]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some('*') => (0..=text.len()).any(|i| glob_match(&pattern[1..], &text[i..])),
+        Some('?') => !text.is_empty() && glob_match(&pattern[1..], &text[1..]),
+        Some(c) => text.first() == Some(c) && glob_match(&pattern[1..], &text[1..]),
+    }
+}
+
+fn glob(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    glob_match(&pattern, &text)
+}
+
+impl FilterTerm {
+    fn matches_type(pattern: &str, type_name: &str, type_id: Option<u16>) -> bool {
+        if let (Some(n), Some(id)) = (parse_number(pattern), type_id) {
+            return n == id as u32;
+        }
+        glob(pattern, type_name) || glob(pattern, &type_name.replace(' ', ""))
+    }
+
+    fn matches_id(&self, id: &str) -> bool {
+        match &self.id {
+            IdPattern::Any => true,
+            IdPattern::Range(low, high) => match id.parse::<u32>() {
+                Ok(n) => n >= *low && n <= *high,
+                Err(_) => false,
+            },
+            IdPattern::Glob(pattern) => glob(pattern, id),
+        }
+    }
+
+    /* Resources without a language (i.e. NE ones) match any language. */
+    fn matches(&self, type_name: &str, type_id: Option<u16>, id: &str, lang: Option<u16>) -> bool {
+        let type_and_id = (Self::matches_type(&self.type_pattern, type_name, type_id)
+            && self.matches_id(id))
+            || self
+                .whole_type
+                .as_ref()
+                .map_or(false, |t| Self::matches_type(t, type_name, type_id));
+        type_and_id
+            && match (self.lang, lang) {
+                (Some(want), Some(have)) => want == have,
+                _ => true,
+            }
+    }
+}
+
+/* Whether to show a resource. `type_name` is what we print for the type,
+ * `type_id` its number if it has one, `id` the resource's number in decimal
+ * or its name, and `lang` its language, if any. */
+pub fn filter_resource(type_name: &str, type_id: Option<u16>, id: &str, lang: Option<u16>) -> bool {
+    let filters = RESOURCE_FILTERS.lock().unwrap();
+    let mut positive = false;
+    let mut matched = false;
+
+    for term in filters.iter() {
+        let m = term.matches(type_name, type_id, id, lang);
+        if term.negate {
+            if m {
+                return false;
+            }
+        } else {
+            positive = true;
+            matched |= m;
+        }
+    }
+    matched || !positive
+}
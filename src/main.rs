@@ -1,12 +1,23 @@
 #[macro_use]
 extern crate scan_fmt;
 
+mod accel;
+mod cil;
+mod codepage;
 mod defs;
+mod der;
+mod dib;
 mod dump;
+mod filter;
+mod hash;
+mod msgtable;
 mod mz;
 mod ne;
 mod pe;
+mod png;
+mod rsrc;
 mod util;
+mod version;
 mod x86;
 
 // int main(int argc, char *argv[]){
@@ -24,14 +35,22 @@ mod x86;
 //         case NO_SHOW_ADDRESSES:
 //             opts |= NO_SHOW_ADDRESSES;
 //             break;
+//         case EXPORT_BDF:
+//             opts |= EXPORT_BDF;
+//             break;
+//         case EXPORT_PNG:
+//             opts |= EXPORT_PNG;
+//             break;
 //         case 'a': /* dump resources only */
 //         {
 //             mode |= DUMPRSRC;
 //             if (optarg){
 //                 const char *p = optarg;
 //                 while (*p == ' ' || *p == '=') ++p;
-//                 resource_filters = realloc(resource_filters, (resource_filters_count + 1) * sizeof(*resource_filters));
-//                 resource_filters[resource_filters_count++] = strdup(p);
+//                 if let Err(e) = add_resource_filter(p) {
+//                     eprint!("{}\n", e);
+//                     return 1;
+//                 }
 //             }
 //             break;
 //         }
@@ -93,14 +112,42 @@ mod x86;
 //                 return 1;
 //             }
 //             break;
+//         case 0x81:
+//             add_lib_path(optarg);
+//             break;
+//         case 0x82:
+//             if (!set_codepage(atoi(optarg))) {
+//                 eprint!( "Unsupported codepage `{}'.\n", optarg);
+//                 return 1;
+//             }
+//             break;
+//         case 0x83:
+//             mode |= DUMP_CHECK;
+//             break;
+//         case 0x84:
+//             mode |= DUMP_UNWIND;
+//             break;
+//         case 0x85:
+//             set_fix_checksum_path(optarg);
+//             break;
+//         case 0x86:
+//             set_json_output();
+//             break;
 //         default:
 //             eprint!( "Usage: dumpne [options] <file>\n");
 //             return 1;
 //         }
 //     }
 
+//     /* everything but the extras, which have to be asked for */
 //     if (mode == 0)
-//         mode = ~0;
+//         mode = ~(DUMP_CHECK | DUMP_UNWIND);
+
+//     /* there's only the one output name, so only one file can be fixed */
+//     if (fix_checksum_requested() && argc - optind > 1) {
+//         eprint!( "--fix-checksum takes only one input file.\n");
+//         return 1;
+//     }
 
 //     if (optind == argc)
 //         printf(help_message);
@@ -127,8 +174,12 @@ pub const NO_SHOW_RAW_INSN: u8 = 0x04;
 pub const NO_SHOW_ADDRESSES: u8 = 0x08;
 pub const COMPILABLE: u8 = 0x10;
 pub const FULL_CONTENTS: u8 = 0x20;
+pub const EXPORT_BDF: u8 = 0x40;
+pub const EXPORT_PNG: u8 = 0x80;
 pub const DUMP_HEADER: u8 = 0x01;
 pub const DUMP_RSRC: u8 = 0x02;
 pub const DUMP_EXPORT: u8 = 0x04;
 
 pub const DUMP_IMPORT: u8 = 0x08;
+pub const DUMP_CHECK: u8 = 0x20;
+pub const DUMP_UNWIND: u8 = 0x40;
@@ -0,0 +1,254 @@
+/* Message table resources (RT_MESSAGETABLE).
+ *
+ *     [dword] number of blocks
+ *     blocks, each: [dword] lowest ID, [dword] highest ID,
+ *                   [dword] offset of the first entry from the resource start
+ *     entries, each: [word] length of the entry, including this header
+ *                    [word] flags: 1 if the text is UTF-16, else ANSI
+ *                    text, zero-terminated and padded
+ *
+ * The entries of a block are consecutive, one for each ID from the lowest to
+ * the highest. The text is a FormatMessage() template: "%1" through "%99"
+ * are inserts, optionally followed by a printf format as in "%1!d!". */
+
+use crate::codepage;
+use crate::util::{json_output, json_string, read_dword, read_word};
+use std::sync::Mutex;
+
+#[derive(Clone, Debug, Default)]
+pub struct Message {
+    pub id: u32,
+    pub unicode: bool,
+    pub text: String,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct MessageTable {
+    pub messages: Vec<Message>,
+}
+
+/* The module's messages, so that the disassembly can say what an error
+ * code means. Filled in before the code is printed. */
+static MESSAGES: Mutex<MessageTable> = Mutex::new(MessageTable {
+    messages: Vec::new(),
+});
+
+pub fn set_messages(table: MessageTable) {
+    *MESSAGES.lock().unwrap() = table;
+}
+
+pub fn describe_message(id: u32) -> Option<String> {
+    MESSAGES.lock().unwrap().describe(id)
+}
+
+/* A piece of a message template: literal text, or an insert with its
+ * number and printf-style format (which defaults to "s"). */
+#[derive(Clone, Debug, PartialEq)]
+pub enum MessagePart {
+    Text(String),
+    Insert(u32, String),
+}
+
+pub fn read_message_table(data: &Vec<u8>) -> Option<MessageTable> {
+    if data.len() < 4 {
+        return None;
+    }
+    let count = read_dword(data, 0) as usize;
+    if 4 + count * 12 > data.len() {
+        eprintln!(
+            "Message table has {} blocks, which exceeds its length.",
+            count
+        );
+        return None;
+    }
+
+    let mut table = MessageTable::default();
+    for i in 0..count {
+        let low = read_dword(data, 4 + i * 12);
+        let high = read_dword(data, 4 + i * 12 + 4);
+        let mut offset = read_dword(data, 4 + i * 12 + 8) as usize;
+
+        if high < low {
+            eprintln!("Message block {} has IDs {}-{} out of order.", i, low, high);
+            continue;
+        }
+
+        for id in low..=high {
+            if offset + 4 > data.len() {
+                eprintln!("Message {} exceeds resource length.", id);
+                break;
+            }
+            let length = read_word(data, offset) as usize;
+            let flags = read_word(data, offset + 2);
+            if length < 4 || offset + length > data.len() {
+                eprintln!("Message {} has bad length {}.", id, length);
+                break;
+            }
+
+            let bytes = &data[offset + 4..offset + length];
+            let unicode = flags & 1 != 0;
+            let text = if unicode {
+                let chars: Vec<u16> = bytes
+                    .chunks_exact(2)
+                    .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                    .take_while(|&c| c != 0)
+                    .collect();
+                String::from_utf16_lossy(&chars)
+            } else {
+                let len = bytes.iter().position(|&c| c == 0).unwrap_or(bytes.len());
+                codepage::decode(&bytes[..len])
+            };
+            if flags & !1 != 0 {
+                eprintln!("Message {} has unknown flags 0x{:04x}.", id, flags);
+            }
+
+            table.messages.push(Message { id, unicode, text });
+            offset += length;
+        }
+    }
+    Some(table)
+}
+
+impl MessageTable {
+    pub fn get(&self, id: u32) -> Option<&str> {
+        self.messages
+            .iter()
+            .find(|m| m.id == id)
+            .map(|m| m.text.as_str())
+    }
+
+    /* Messages are usually written with a trailing newline; strip it for
+     * use in comments. */
+    pub fn describe(&self, id: u32) -> Option<String> {
+        self.get(id).map(|text| {
+            format_message(text, &[])
+                .trim_end_matches(|c| c == '\r' || c == '\n')
+                .to_string()
+        })
+    }
+}
+
+/* Split a template into text and inserts, expanding the escapes %n, %r, %t,
+ * %0 and %% and friends as FormatMessage does. */
+pub fn parse_message(text: &str) -> Vec<MessagePart> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let chars: Vec<char> = text.chars().collect();
+    let mut i = 0;
+
+    while i < chars.len() {
+        if chars[i] != '%' || i + 1 >= chars.len() {
+            literal.push(chars[i]);
+            i += 1;
+            continue;
+        }
+
+        let c = chars[i + 1];
+        i += 2;
+        match c {
+            '0' => break, /* end of message, without a newline */
+            '1'..='9' => {
+                let mut number = c.to_digit(10).unwrap();
+                if i < chars.len() && chars[i].is_ascii_digit() {
+                    number = number * 10 + chars[i].to_digit(10).unwrap();
+                    i += 1;
+                }
+                let mut format = "s".to_string();
+                if i < chars.len() && chars[i] == '!' {
+                    if let Some(end) = chars[i + 1..].iter().position(|&c| c == '!') {
+                        format = chars[i + 1..i + 1 + end].iter().collect();
+                        i += end + 2;
+                    }
+                }
+                if !literal.is_empty() {
+                    parts.push(MessagePart::Text(literal.clone()));
+                    literal.clear();
+                }
+                parts.push(MessagePart::Insert(number, format));
+            }
+            'n' => literal.push('\n'),
+            'r' => literal.push('\r'),
+            't' => literal.push('\t'),
+            'b' => literal.push(' '),
+            c => literal.push(c), /* %%, %., %!, "% " and anything else */
+        }
+    }
+    if !literal.is_empty() {
+        parts.push(MessagePart::Text(literal));
+    }
+    parts
+}
+
+/* The insert numbers a message uses, in order and without duplicates. */
+pub fn message_inserts(text: &str) -> Vec<u32> {
+    let mut ret = Vec::new();
+    for part in parse_message(text) {
+        if let MessagePart::Insert(n, _) = part {
+            if !ret.contains(&n) {
+                ret.push(n);
+            }
+        }
+    }
+    ret
+}
+
+/* Fill in the inserts of a message. Inserts without an argument are left
+ * as "%N". */
+pub fn format_message(text: &str, args: &[&str]) -> String {
+    let mut ret = String::new();
+    for part in parse_message(text) {
+        match part {
+            MessagePart::Text(s) => ret += &s,
+            MessagePart::Insert(n, _) => match args.get(n as usize - 1) {
+                Some(arg) => ret += arg,
+                None => ret += &format!("%{}", n),
+            },
+        }
+    }
+    ret
+}
+
+pub fn print_message_table(table: &MessageTable) {
+    if json_output() {
+        print!("    {}\n", message_table_json(table));
+        return;
+    }
+    for message in &table.messages {
+        print!(
+            "    {} (0x{:08x}){}: \"{}\"",
+            message.id,
+            message.id,
+            if message.unicode { " [Unicode]" } else { "" },
+            codepage::escape(&message.text)
+        );
+        let inserts = message_inserts(&message.text);
+        if !inserts.is_empty() {
+            let inserts: Vec<String> = inserts.iter().map(|n| format!("%{}", n)).collect();
+            print!(" (inserts: {})", inserts.join(", "));
+        }
+        print!("\n");
+    }
+}
+
+/* The table as a JSON array of {"id", "unicode", "text", "inserts"}
+ * objects, for --json. */
+pub fn message_table_json(table: &MessageTable) -> String {
+    let entries: Vec<String> = table
+        .messages
+        .iter()
+        .map(|m| {
+            let inserts: Vec<String> = message_inserts(&m.text)
+                .iter()
+                .map(|n| n.to_string())
+                .collect();
+            format!(
+                "{{\"id\": {}, \"unicode\": {}, \"text\": {}, \"inserts\": [{}]}}",
+                m.id,
+                m.unicode,
+                json_string(&m.text),
+                inserts.join(", ")
+            )
+        })
+        .collect();
+    format!("[{}]", entries.join(", "))
+}
@@ -0,0 +1,741 @@
+/* Structural checks for NE files, for --check.
+ *
+ * Malformed and hand-patched files are common enough among old software, and
+ * the rest of the dumper mostly trusts what the header says. Here we read the
+ * tables again from the raw file, checking everything against the file size
+ * and the segment table, and report each problem with the file offset it was
+ * found at. */
+
+use crate::ne::NeHeader;
+use crate::util::{read_byte, read_data, read_word};
+
+#[derive(Clone, Debug, Default)]
+pub struct NeIssue {
+    pub offset: usize,    /* file offset of the offending data */
+    pub location: String, /* which structure it is in */
+    pub message: String,
+}
+
+struct Checker<'a> {
+    map: &'a Vec<u8>,
+    header: &'a NeHeader,
+    offset_ne: usize,
+    issues: Vec<NeIssue>,
+}
+
+/* Where a segment or resource lives in the file, for overlap checks. */
+struct Extent {
+    start: usize,
+    end: usize,
+    what: String,
+}
+
+/* in the header, in their usual order */
+const NE_TABLES: [(&str, usize); 6] = [
+    ("segment table", 0x22),
+    ("resource table", 0x24),
+    ("resident name table", 0x26),
+    ("module reference table", 0x28),
+    ("imported name table", 0x2a),
+    ("entry table", 0x04),
+];
+
+impl<'a> Checker<'a> {
+    fn report(&mut self, offset: usize, location: &str, message: String) {
+        self.issues.push(NeIssue {
+            offset,
+            location: location.to_string(),
+            message,
+        });
+    }
+
+    fn file_len(&self) -> usize {
+        self.map.len()
+    }
+
+    /* only as many segments as the file has room for */
+    fn segment_count(&self) -> usize {
+        let start = self.offset_ne + self.header.ne_segtab as usize;
+        let room = self.file_len().saturating_sub(start) / 8;
+        (self.header.ne_cseg as usize).min(room)
+    }
+
+    fn segment_flags(&self, seg: usize) -> u16 {
+        let start = self.offset_ne + self.header.ne_segtab as usize;
+        read_word(&self.map, start + (seg - 1) * 8 + 4)
+    }
+
+    /* the size the segment has in memory; 0 means 64K */
+    fn segment_size(&self, seg: usize) -> usize {
+        let start = self.offset_ne + self.header.ne_segtab as usize;
+        match read_word(&self.map, start + (seg - 1) * 8 + 6) {
+            0 => 0x10000,
+            n => n as usize,
+        }
+    }
+
+    fn check_tables(&mut self) {
+        let header = self.header;
+        let offset_ne = self.offset_ne;
+        let len = self.file_len();
+
+        /* sizes, where we know them */
+        let sizes = [
+            header.ne_cseg as usize * 8,
+            header.ne_restab.saturating_sub(header.ne_rsrctab) as usize,
+            header.ne_modtab.saturating_sub(header.ne_restab) as usize,
+            header.ne_cmod as usize * 2,
+            header.ne_enttab.saturating_sub(header.ne_imptab) as usize,
+            header.ne_cbenttab as usize,
+        ];
+
+        let mut previous = 0x40;
+        for (i, &(name, field)) in NE_TABLES.iter().enumerate() {
+            let value = read_word(&self.map, offset_ne + field) as usize;
+            if value < previous {
+                self.report(
+                    offset_ne + field,
+                    "header",
+                    format!(
+                        "{} at 0x{:04x} comes before the end of the {} (0x{:04x}).",
+                        name,
+                        value,
+                        if i == 0 { "header" } else { NE_TABLES[i - 1].0 },
+                        previous
+                    ),
+                );
+            }
+            if offset_ne + value + sizes[i] > len {
+                self.report(
+                    offset_ne + field,
+                    "header",
+                    format!(
+                        "{} (0x{:x}, {} bytes) exceeds file length 0x{:x}.",
+                        name,
+                        offset_ne + value,
+                        sizes[i],
+                        len
+                    ),
+                );
+            }
+            previous = previous.max(value + sizes[i]);
+        }
+
+        /* the non-resident name table is the only one with an absolute offset */
+        let nrestab = header.ne_nrestab as usize;
+        if header.ne_cbnrestab != 0 {
+            if nrestab < offset_ne + previous {
+                self.report(
+                    offset_ne + 0x2c,
+                    "header",
+                    format!(
+                        "non-resident name table at 0x{:x} overlaps the NE tables (end 0x{:x}).",
+                        nrestab,
+                        offset_ne + previous
+                    ),
+                );
+            }
+            if nrestab + header.ne_cbnrestab as usize > len {
+                self.report(
+                    offset_ne + 0x2c,
+                    "header",
+                    format!(
+                        "non-resident name table (0x{:x}, {} bytes) exceeds file length 0x{:x}.",
+                        nrestab, header.ne_cbnrestab, len
+                    ),
+                );
+            }
+        }
+
+        if header.ne_align == 0 {
+            self.report(
+                offset_ne + 0x32,
+                "header",
+                "alignment shift is 0; the loader will use 9.".to_string(),
+            );
+        } else if header.ne_align > 15 {
+            self.report(
+                offset_ne + 0x32,
+                "header",
+                format!("alignment shift {} is too large.", header.ne_align),
+            );
+        }
+    }
+
+    /* ne_autodata, ne_cs and ne_ss are one-based segment numbers */
+    fn check_segment_ref(&mut self, field: usize, name: &str, seg: usize, data: bool) {
+        if seg == 0 {
+            return;
+        }
+        if seg > self.segment_count() {
+            self.report(
+                self.offset_ne + field,
+                "header",
+                format!(
+                    "{} is segment {}, but there are only {} segments.",
+                    name,
+                    seg,
+                    self.segment_count()
+                ),
+            );
+        } else if (self.segment_flags(seg) & 0x0001 != 0) != data {
+            self.report(
+                self.offset_ne + field,
+                "header",
+                format!(
+                    "{} is segment {}, which is a {} segment.",
+                    name,
+                    seg,
+                    if data { "code" } else { "data" }
+                ),
+            );
+        }
+    }
+
+    fn check_header_segments(&mut self) {
+        let header = self.header;
+        let (autodata, cs, ss, ip, sp) = (
+            header.ne_autodata as usize,
+            header.ne_cs as usize,
+            header.ne_ss as usize,
+            header.ne_ip as usize,
+            header.ne_sp as usize,
+        );
+
+        self.check_segment_ref(0x0e, "automatic data segment", autodata, true);
+        self.check_segment_ref(0x16, "initial CS", cs, false);
+        self.check_segment_ref(0x1a, "initial SS", ss, true);
+
+        if cs != 0 && cs <= self.segment_count() && ip >= self.segment_size(cs) {
+            self.report(
+                self.offset_ne + 0x14,
+                "header",
+                format!(
+                    "initial IP 0x{:04x} is past the end of segment {} (0x{:x} bytes).",
+                    ip,
+                    cs,
+                    self.segment_size(cs)
+                ),
+            );
+        }
+        /* SP 0 means the top of the automatic data segment */
+        if ss != 0 && ss <= self.segment_count() && sp > self.segment_size(ss) {
+            self.report(
+                self.offset_ne + 0x18,
+                "header",
+                format!(
+                    "initial SP 0x{:04x} is past the end of segment {} (0x{:x} bytes).",
+                    sp,
+                    ss,
+                    self.segment_size(ss)
+                ),
+            );
+        }
+    }
+
+    /* Check each segment's data and relocations, and return where they are
+     * in the file. */
+    fn check_segments(&mut self) -> Vec<Extent> {
+        let start = self.offset_ne + self.header.ne_segtab as usize;
+        /* a shift of 0 means 9, as reported by check_header() */
+        let align = match self.header.ne_align {
+            0 => 9,
+            n => n,
+        };
+        let len = self.file_len();
+        let mut extents = Vec::new();
+
+        for i in 1..=self.segment_count() {
+            let entry = start + (i - 1) * 8;
+            if entry + 8 > len {
+                break;
+            }
+            let sector = read_word(&self.map, entry) as usize;
+            let length = match read_word(&self.map, entry + 2) {
+                0 if sector != 0 => 0x10000,
+                n => n as usize,
+            };
+            let flags = read_word(&self.map, entry + 4);
+            let location = format!("segment {}", i);
+
+            if length > self.segment_size(i) {
+                self.report(
+                    entry + 6,
+                    &location,
+                    format!(
+                        "minimum allocation 0x{:x} is less than the data length 0x{:x}.",
+                        self.segment_size(i),
+                        length
+                    ),
+                );
+            }
+
+            /* a bad shift was already reported, and would put the data
+             * anywhere */
+            if sector == 0 || align > 15 {
+                continue;
+            }
+            let data_start = sector << align;
+            let mut data_end = data_start + length;
+            if data_end > len {
+                self.report(
+                    entry,
+                    &location,
+                    format!(
+                        "data (0x{:x}-0x{:x}) exceeds file length 0x{:x}.",
+                        data_start, data_end, len
+                    ),
+                );
+                continue;
+            }
+
+            if flags & 0x0100 != 0 {
+                if data_end + 2 > len {
+                    self.report(
+                        data_end,
+                        &location,
+                        "relocation count is past the end of the file.".to_string(),
+                    );
+                    continue;
+                }
+                let count = read_word(&self.map, data_end) as usize;
+                let table_end = data_end + 2 + count * 8;
+                if table_end > len {
+                    self.report(
+                        data_end,
+                        &location,
+                        format!(
+                            "{} relocations (to 0x{:x}) exceed file length 0x{:x}.",
+                            count, table_end, len
+                        ),
+                    );
+                } else {
+                    self.check_relocations(i, data_start, length, data_end + 2, count);
+                    data_end = table_end;
+                }
+            }
+
+            extents.push(Extent {
+                start: data_start,
+                end: data_end,
+                what: location,
+            });
+        }
+        extents
+    }
+
+    fn check_relocations(
+        &mut self,
+        seg: usize,
+        data: usize,
+        length: usize,
+        table: usize,
+        count: usize,
+    ) {
+        let location = format!("segment {} relocations", seg);
+
+        for j in 0..count {
+            let entry = table + j * 8;
+            let r_type = read_byte(&self.map, entry + 1);
+            let offset = read_word(&self.map, entry + 2) as usize;
+            let module = read_word(&self.map, entry + 4) as usize;
+            let target = read_word(&self.map, entry + 6) as usize;
+
+            if offset >= length {
+                self.report(
+                    entry + 2,
+                    &location,
+                    format!(
+                        "relocation {} at 0x{:04x} is past the end of the segment (0x{:x} bytes).",
+                        j + 1,
+                        offset,
+                        length
+                    ),
+                );
+                continue;
+            }
+
+            match r_type & 3 {
+                0 if module == 0xff => {
+                    if target == 0 || target > self.entry_count() {
+                        self.report(
+                            entry + 6,
+                            &location,
+                            format!(
+                                "relocation {} refers to entry {}, which doesn't exist.",
+                                j + 1,
+                                target
+                            ),
+                        );
+                    }
+                }
+                0 => {
+                    if module == 0 || module > self.segment_count() {
+                        self.report(
+                            entry + 4,
+                            &location,
+                            format!(
+                                "relocation {} refers to segment {}, but there are only {} segments.",
+                                j + 1,
+                                module,
+                                self.segment_count()
+                            ),
+                        );
+                    } else if target >= self.segment_size(module) {
+                        self.report(
+                            entry + 6,
+                            &location,
+                            format!(
+                                "relocation {} points to {}:{:04x}, past the end of the segment.",
+                                j + 1,
+                                module,
+                                target
+                            ),
+                        );
+                    }
+                }
+                1 | 2 => {
+                    if module == 0 || module > self.header.ne_cmod as usize {
+                        self.report(
+                            entry + 4,
+                            &location,
+                            format!(
+                                "relocation {} refers to module {}, but there are only {} modules.",
+                                j + 1,
+                                module,
+                                self.header.ne_cmod
+                            ),
+                        );
+                    }
+                }
+                _ => continue, /* OSFIXUPs patch a single location */
+            }
+
+            /* follow the chain of locations, unless additive */
+            if r_type & 4 == 0 {
+                let mut o = offset;
+                let mut steps = 0;
+                loop {
+                    if o + 2 > length {
+                        self.report(
+                            entry + 2,
+                            &location,
+                            format!(
+                                "relocation {} chains to 0x{:04x}, past the end of the segment.",
+                                j + 1,
+                                o
+                            ),
+                        );
+                        break;
+                    }
+                    let next = read_word(&self.map, data + o) as usize;
+                    steps += 1;
+                    if next == 0xffff {
+                        break;
+                    }
+                    if steps > length / 2 {
+                        self.report(
+                            entry + 2,
+                            &location,
+                            format!("relocation {} has a looping chain.", j + 1),
+                        );
+                        break;
+                    }
+                    o = next;
+                }
+            }
+        }
+    }
+
+    fn entry_count(&self) -> usize {
+        let start = self.offset_ne + self.header.ne_enttab as usize;
+        let end = start + self.header.ne_cbenttab as usize;
+        let mut cursor = start;
+        let mut count = 0;
+
+        while cursor + 2 <= end.min(self.file_len()) {
+            let length = read_byte(&self.map, cursor) as usize;
+            let index = read_byte(&self.map, cursor + 1);
+            if length == 0 {
+                break;
+            }
+            count += length;
+            cursor += 2 + match index {
+                0 => 0,
+                0xff => 6 * length,
+                _ => 3 * length,
+            };
+        }
+        count
+    }
+
+    fn check_entries(&mut self) {
+        let start = self.offset_ne + self.header.ne_enttab as usize;
+        let end = (start + self.header.ne_cbenttab as usize).min(self.file_len());
+        let mut cursor = start;
+        let mut ordinal = 1;
+
+        while cursor + 2 <= end {
+            let length = read_byte(&self.map, cursor) as usize;
+            let index = read_byte(&self.map, cursor + 1) as usize;
+            if length == 0 {
+                break;
+            }
+            cursor += 2;
+            if index == 0 {
+                /* unused ordinals */
+                ordinal += length;
+                continue;
+            }
+
+            let size = if index == 0xff { 6 } else { 3 };
+            for _ in 0..length {
+                if cursor + size > end {
+                    self.report(
+                        cursor,
+                        "entry table",
+                        format!("entry {} exceeds the entry table length.", ordinal),
+                    );
+                    return;
+                }
+                /* 0xfe is a constant, not a segment */
+                let (seg, offset) = match index {
+                    0xff => (
+                        read_byte(&self.map, cursor + 3) as usize,
+                        read_word(&self.map, cursor + 4) as usize,
+                    ),
+                    0xfe => (0, 0),
+                    seg => (seg, read_word(&self.map, cursor + 1) as usize),
+                };
+
+                if index == 0xff && read_word(&self.map, cursor + 1) != 0x3fcd {
+                    self.report(
+                        cursor + 1,
+                        "entry table",
+                        format!("movable entry {} lacks an INT 3Fh instruction.", ordinal),
+                    );
+                }
+                if seg > self.segment_count() || (index == 0xff && seg == 0) {
+                    self.report(
+                        cursor,
+                        "entry table",
+                        format!(
+                            "entry {} refers to segment {}, but there are only {} segments.",
+                            ordinal,
+                            seg,
+                            self.segment_count()
+                        ),
+                    );
+                } else if seg != 0 && offset >= self.segment_size(seg) {
+                    self.report(
+                        cursor,
+                        "entry table",
+                        format!(
+                            "entry {} at {}:{:04x} is past the end of the segment.",
+                            ordinal, seg, offset
+                        ),
+                    );
+                }
+                cursor += size;
+                ordinal += 1;
+            }
+        }
+    }
+
+    /* Only for Windows resources; OS/2 keeps its resources in segments. */
+    fn check_resources(&mut self) -> Vec<Extent> {
+        let header = self.header;
+        let mut extents = Vec::new();
+        if header.ne_exetyp == 1 || header.ne_rsrctab == header.ne_restab {
+            return extents;
+        }
+
+        let start = self.offset_ne + header.ne_rsrctab as usize;
+        let len = self.file_len();
+        /* check_tables() has already said if the table runs off the end */
+        let end = (self.offset_ne + header.ne_restab as usize).min(len);
+        if start + 2 > len {
+            return extents;
+        }
+        let align = read_word(&self.map, start);
+        let mut cursor = start + 2;
+
+        if align > 15 {
+            self.report(
+                start,
+                "resource table",
+                format!("alignment shift {} is too large.", align),
+            );
+            return extents;
+        }
+
+        loop {
+            if cursor + 2 > end {
+                self.report(
+                    cursor,
+                    "resource table",
+                    "missing end of types marker.".to_string(),
+                );
+                break;
+            }
+            let type_id = read_word(&self.map, cursor);
+            if type_id == 0 {
+                break;
+            }
+            if cursor + 8 > end {
+                self.report(
+                    cursor,
+                    "resource table",
+                    format!("type 0x{:04x} is cut off by the end of the table.", type_id),
+                );
+                break;
+            }
+            let count = read_word(&self.map, cursor + 2) as usize;
+            if cursor + 8 + count * 12 > end {
+                self.report(
+                    cursor,
+                    "resource table",
+                    format!(
+                        "type 0x{:04x} has {} resources, which exceeds the table.",
+                        type_id, count
+                    ),
+                );
+                break;
+            }
+            if type_id & 0x8000 == 0 && start + type_id as usize >= end {
+                self.report(
+                    cursor,
+                    "resource table",
+                    format!("type name offset 0x{:04x} is outside the table.", type_id),
+                );
+            }
+
+            for i in 0..count {
+                let rn = cursor + 8 + i * 12;
+                let offset = (read_word(&self.map, rn) as usize) << align;
+                let length = (read_word(&self.map, rn + 2) as usize) << align;
+                let id = read_word(&self.map, rn + 6);
+                let what = if id & 0x8000 != 0 {
+                    format!("resource 0x{:04x} {}", type_id, id & 0x7fff)
+                } else {
+                    format!("resource 0x{:04x} (name at 0x{:04x})", type_id, id)
+                };
+
+                if id & 0x8000 == 0 && start + id as usize >= end {
+                    self.report(
+                        rn + 6,
+                        "resource table",
+                        format!("{}: name offset is outside the table.", what),
+                    );
+                }
+                if offset + length > len {
+                    self.report(
+                        rn,
+                        "resource table",
+                        format!(
+                            "{} (0x{:x}-0x{:x}) exceeds file length 0x{:x}.",
+                            what,
+                            offset,
+                            offset + length,
+                            len
+                        ),
+                    );
+                } else if length != 0 {
+                    extents.push(Extent {
+                        start: offset,
+                        end: offset + length,
+                        what,
+                    });
+                }
+            }
+            cursor += 8 + count * 12;
+        }
+        extents
+    }
+
+    fn check_overlaps(&mut self, mut extents: Vec<Extent>) {
+        /* the NE header and its tables are data too */
+        let header = self.header;
+        let tables_end = [
+            header.ne_enttab as usize + header.ne_cbenttab as usize,
+            header.ne_imptab as usize,
+            header.ne_modtab as usize + header.ne_cmod as usize * 2,
+        ]
+        .iter()
+        .copied()
+        .max()
+        .unwrap();
+        extents.push(Extent {
+            start: self.offset_ne,
+            end: self.offset_ne + tables_end,
+            what: "NE header and tables".to_string(),
+        });
+
+        extents.sort_by_key(|e| e.start);
+        for i in 1..extents.len() {
+            /* compare with every earlier extent still open, not just the last */
+            for j in 0..i {
+                if extents[j].end > extents[i].start {
+                    let message = format!(
+                        "{} (0x{:x}-0x{:x}) overlaps {} (0x{:x}-0x{:x}).",
+                        extents[i].what,
+                        extents[i].start,
+                        extents[i].end,
+                        extents[j].what,
+                        extents[j].start,
+                        extents[j].end
+                    );
+                    self.report(extents[i].start, "file layout", message);
+                }
+            }
+        }
+    }
+}
+
+pub fn check_ne(map: &Vec<u8>, offset_ne: usize) -> Vec<NeIssue> {
+    if offset_ne + 0x40 > map.len() {
+        return vec![NeIssue {
+            offset: offset_ne,
+            location: "header".to_string(),
+            message: "NE header is truncated.".to_string(),
+        }];
+    }
+    let header = NeHeader::from_bytes(&read_data(map, offset_ne, 0x40));
+    let mut checker = Checker {
+        map,
+        header: &header,
+        offset_ne,
+        issues: Vec::new(),
+    };
+
+    checker.check_tables();
+    checker.check_header_segments();
+    let mut extents = checker.check_segments();
+    checker.check_entries();
+    extents.extend(checker.check_resources());
+    checker.check_overlaps(extents);
+
+    checker.issues.sort_by_key(|i| i.offset);
+    checker.issues
+}
+
+pub fn print_check(map: &Vec<u8>, offset_ne: usize) {
+    let issues = check_ne(map, offset_ne);
+
+    print!("\nConsistency check:\n");
+    if issues.is_empty() {
+        print!("    No problems found.\n");
+        return;
+    }
+    for issue in &issues {
+        print!(
+            "    0x{:08x} [{}]: {}\n",
+            issue.offset, issue.location, issue.message
+        );
+    }
+    print!(
+        "    {} problem{} found.\n",
+        issues.len(),
+        if issues.len() == 1 { "" } else { "s" }
+    );
+}
@@ -0,0 +1,531 @@
+/* Windows 1.x-3.x raster fonts, as found in .FNT files and in the Font
+ * component (RT_FONT) resources of NE .FON files. */
+
+use crate::util::{read_byte, read_data, read_dword, read_word};
+use std::error::Error;
+use std::fs::File;
+use std::io::Write;
+
+#[derive(Clone, Debug, Default)]
+pub struct FontInfo {
+    pub dfVersion: u16,         /* 00 0x100, 0x200 or 0x300 */
+    pub dfSize: u32,            /* 02 */
+    pub dfCopyright: String,    /* 06 60 bytes */
+    pub dfType: u16,            /* 42 */
+    pub dfPoints: u16,          /* 44 */
+    pub dfVertRes: u16,         /* 46 */
+    pub dfHorizRes: u16,        /* 48 */
+    pub dfAscent: u16,          /* 4a */
+    pub dfInternalLeading: u16, /* 4c */
+    pub dfExternalLeading: u16, /* 4e */
+    pub dfItalic: u8,           /* 50 */
+    pub dfUnderline: u8,        /* 51 */
+    pub dfStrikeOut: u8,        /* 52 */
+    pub dfWeight: u16,          /* 53 */
+    pub dfCharSet: u8,          /* 55 */
+    pub dfPixWidth: u16,        /* 56 zero for variable-pitch fonts */
+    pub dfPixHeight: u16,       /* 58 */
+    pub dfPitchAndFamily: u8,   /* 5a */
+    pub dfAvgWidth: u16,        /* 5b */
+    pub dfMaxWidth: u16,        /* 5d */
+    pub dfFirstChar: u8,        /* 5f */
+    pub dfLastChar: u8,         /* 60 */
+    pub dfDefaultChar: u8,      /* 61 relative to dfFirstChar */
+    pub dfBreakChar: u8,        /* 62 relative to dfFirstChar */
+    pub dfWidthBytes: u16,      /* 63 */
+    pub dfDevice: u32,          /* 65 */
+    pub dfFace: u32,            /* 69 */
+    pub dfBitsPointer: u32,     /* 6d */
+    pub dfBitsOffset: u32,      /* 71 */
+    /* 3.0 fields */
+    pub dfFlags: u32,        /* 76 */
+    pub dfAspace: u16,       /* 7a */
+    pub dfBspace: u16,       /* 7c */
+    pub dfCspace: u16,       /* 7e */
+    pub dfColorPointer: u32, /* 80 */
+}
+
+impl FontInfo {
+    pub fn from_bytes(map: &Vec<u8>, offset: usize) -> Self {
+        let copyright = read_data(map, offset + 0x06, 60);
+        let end = copyright.iter().position(|&c| c == 0).unwrap_or(60);
+        let mut info = FontInfo {
+            dfVersion: read_word(map, offset),
+            dfSize: read_dword(map, offset + 0x02),
+            dfCopyright: String::from_utf8_lossy(&copyright[..end]).to_string(),
+            dfType: read_word(map, offset + 0x42),
+            dfPoints: read_word(map, offset + 0x44),
+            dfVertRes: read_word(map, offset + 0x46),
+            dfHorizRes: read_word(map, offset + 0x48),
+            dfAscent: read_word(map, offset + 0x4a),
+            dfInternalLeading: read_word(map, offset + 0x4c),
+            dfExternalLeading: read_word(map, offset + 0x4e),
+            dfItalic: read_byte(map, offset + 0x50),
+            dfUnderline: read_byte(map, offset + 0x51),
+            dfStrikeOut: read_byte(map, offset + 0x52),
+            dfWeight: read_word(map, offset + 0x53),
+            dfCharSet: read_byte(map, offset + 0x55),
+            dfPixWidth: read_word(map, offset + 0x56),
+            dfPixHeight: read_word(map, offset + 0x58),
+            dfPitchAndFamily: read_byte(map, offset + 0x5a),
+            dfAvgWidth: read_word(map, offset + 0x5b),
+            dfMaxWidth: read_word(map, offset + 0x5d),
+            dfFirstChar: read_byte(map, offset + 0x5f),
+            dfLastChar: read_byte(map, offset + 0x60),
+            dfDefaultChar: read_byte(map, offset + 0x61),
+            dfBreakChar: read_byte(map, offset + 0x62),
+            dfWidthBytes: read_word(map, offset + 0x63),
+            dfDevice: read_dword(map, offset + 0x65),
+            dfFace: read_dword(map, offset + 0x69),
+            dfBitsPointer: read_dword(map, offset + 0x6d),
+            dfBitsOffset: read_dword(map, offset + 0x71),
+            ..Default::default()
+        };
+        if info.dfVersion >= 0x300 {
+            info.dfFlags = read_dword(map, offset + 0x76);
+            info.dfAspace = read_word(map, offset + 0x7a);
+            info.dfBspace = read_word(map, offset + 0x7c);
+            info.dfCspace = read_word(map, offset + 0x7e);
+            info.dfColorPointer = read_dword(map, offset + 0x80);
+        }
+        info
+    }
+
+    /* where the character table starts, relative to the font */
+    pub fn char_table_offset(&self) -> usize {
+        match self.dfVersion {
+            0x100 => 0x75,
+            0x200 => 0x76,
+            _ => 0x94,
+        }
+    }
+
+    /* Note that this bit is the opposite of the LOGFONT convention. */
+    pub fn is_variable_pitch(&self) -> bool {
+        self.dfPitchAndFamily & 1 != 0
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Glyph {
+    pub code: u8,
+    pub width: u16,
+    /* one row of booleans per scanline, top to bottom */
+    pub rows: Vec<Vec<bool>>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Font {
+    pub info: FontInfo,
+    pub face: String,
+    pub device: String,
+    pub glyphs: Vec<Glyph>,
+}
+
+pub const FONT_CHARSETS: [(u8, &str); 19] = [
+    (0, "ANSI"),
+    (1, "default"),
+    (2, "symbol"),
+    (77, "Macintosh"),
+    (128, "Shift-JIS"),
+    (129, "Hangul"),
+    (130, "Johab"),
+    (134, "GB2312"),
+    (136, "Big5"),
+    (161, "Greek"),
+    (162, "Turkish"),
+    (163, "Vietnamese"),
+    (177, "Hebrew"),
+    (178, "Arabic"),
+    (186, "Baltic"),
+    (204, "Russian"),
+    (222, "Thai"),
+    (238, "Eastern European"),
+    (255, "OEM"),
+];
+
+pub const FONT_FAMILIES: [&str; 8] = [
+    "don't care", /* 00 FF_DONTCARE */
+    "roman",      /* 10 FF_ROMAN */
+    "swiss",      /* 20 FF_SWISS */
+    "modern",     /* 30 FF_MODERN */
+    "script",     /* 40 FF_SCRIPT */
+    "decorative", /* 50 FF_DECORATIVE */
+    "(unknown family 6)",
+    "(unknown family 7)",
+];
+
+pub fn charset_name(charset: u8) -> String {
+    match FONT_CHARSETS.iter().find(|(id, _)| *id == charset) {
+        Some((_, name)) => name.to_string(),
+        None => format!("(unknown charset {})", charset),
+    }
+}
+
+/* The XLFD CHARSET_REGISTRY-CHARSET_ENCODING for a charset. Windows' ANSI
+ * is really cp1252, but ISO 8859-1 is what X has the most fonts for. */
+fn charset_registry(charset: u8) -> String {
+    match charset {
+        0 | 1 => "ISO8859-1".to_string(),
+        2 => "Microsoft-Symbol".to_string(),
+        77 => "Apple-Roman".to_string(),
+        128 => "Microsoft-CP932".to_string(),
+        129 => "Microsoft-CP949".to_string(),
+        130 => "Microsoft-CP1361".to_string(),
+        134 => "Microsoft-CP936".to_string(),
+        136 => "Microsoft-CP950".to_string(),
+        161 => "Microsoft-CP1253".to_string(),
+        162 => "Microsoft-CP1254".to_string(),
+        163 => "Microsoft-CP1258".to_string(),
+        177 => "Microsoft-CP1255".to_string(),
+        178 => "Microsoft-CP1256".to_string(),
+        186 => "Microsoft-CP1257".to_string(),
+        204 => "Microsoft-CP1251".to_string(),
+        222 => "Microsoft-CP874".to_string(),
+        238 => "Microsoft-CP1250".to_string(),
+        255 => "IBM-CP437".to_string(),
+        _ => format!("Microsoft-Charset{}", charset),
+    }
+}
+
+fn read_cstring(map: &Vec<u8>, offset: usize, end: usize) -> String {
+    let mut s = String::new();
+    let mut cursor = offset;
+    while cursor < end && map[cursor] != 0 {
+        s.push(map[cursor] as char);
+        cursor += 1;
+    }
+    s
+}
+
+/* Reads a font starting at offset. length bounds all of the internal offsets,
+ * which are relative to the start of the font. */
+pub fn read_font(map: &Vec<u8>, offset: usize, length: usize) -> Option<Font> {
+    let end = offset + length;
+    if length < 0x76 || end > map.len() {
+        eprintln!("Font resource is too short ({} bytes).", length);
+        return None;
+    }
+
+    let info = FontInfo::from_bytes(map, offset);
+    if info.dfVersion != 0x100 && info.dfVersion != 0x200 && info.dfVersion != 0x300 {
+        eprintln!("Unknown font version {:04x}.", info.dfVersion);
+        return None;
+    }
+
+    let mut font = Font::default();
+    if info.dfFace != 0 && (info.dfFace as usize) < length {
+        font.face = read_cstring(map, offset + info.dfFace as usize, end);
+    }
+    if info.dfDevice != 0 && (info.dfDevice as usize) < length {
+        font.device = read_cstring(map, offset + info.dfDevice as usize, end);
+    }
+
+    if info.dfType & 1 != 0 {
+        /* vector font; the strokes aren't decoded */
+        font.info = info;
+        return Some(font);
+    }
+
+    let height = info.dfPixHeight as usize;
+    let table = offset + info.char_table_offset();
+    let count = (info.dfLastChar as usize).saturating_sub(info.dfFirstChar as usize) + 1;
+
+    for i in 0..count {
+        let mut glyph = Glyph {
+            code: info.dfFirstChar.wrapping_add(i as u8),
+            ..Default::default()
+        };
+
+        if info.dfVersion == 0x100 {
+            /* 1.x fonts store all glyphs side by side in one bitmap, one
+             * row of dfWidthBytes per scanline. Variable-width fonts have a
+             * table of bit offsets; fixed-width ones don't. */
+            let (start, width) = if info.dfPixWidth == 0 {
+                if table + (i + 2) * 2 > end {
+                    break;
+                }
+                let start = read_word(map, table + i * 2) as usize;
+                let next = read_word(map, table + (i + 1) * 2) as usize;
+                (start, next.saturating_sub(start))
+            } else {
+                (i * info.dfPixWidth as usize, info.dfPixWidth as usize)
+            };
+            glyph.width = width as u16;
+            for y in 0..height {
+                let row_start =
+                    offset + info.dfBitsOffset as usize + y * info.dfWidthBytes as usize;
+                let mut row = Vec::new();
+                for x in start..start + width {
+                    let byte_offset = row_start + x / 8;
+                    row.push(byte_offset < end && map[byte_offset] & (0x80 >> (x % 8)) != 0);
+                }
+                glyph.rows.push(row);
+            }
+        } else {
+            /* 2.x and 3.x fonts store each glyph separately, in columns of
+             * eight pixels, each column running the full height. */
+            let entry_size = if info.dfVersion == 0x200 { 4 } else { 6 };
+            if table + (i + 1) * entry_size > end {
+                break;
+            }
+            let width = read_word(map, table + i * entry_size) as usize;
+            let bits = if info.dfVersion == 0x200 {
+                read_word(map, table + i * entry_size + 2) as usize
+            } else {
+                read_dword(map, table + i * entry_size + 2) as usize
+            };
+            glyph.width = width as u16;
+            for y in 0..height {
+                let mut row = Vec::new();
+                for x in 0..width {
+                    let byte_offset = offset + bits + (x / 8) * height + y;
+                    row.push(byte_offset < end && map[byte_offset] & (0x80 >> (x % 8)) != 0);
+                }
+                glyph.rows.push(row);
+            }
+        }
+
+        font.glyphs.push(glyph);
+    }
+
+    font.info = info;
+    Some(font)
+}
+
+pub fn print_font_info(info: &FontInfo, face: &str) {
+    println!(
+        "    Version: {}.{}",
+        info.dfVersion >> 8,
+        info.dfVersion & 0xff
+    );
+    println!("    Face name: \"{}\"", face);
+    println!("    Copyright: \"{}\"", info.dfCopyright);
+    println!(
+        "    Type: {}",
+        if info.dfType & 1 != 0 {
+            "vector"
+        } else {
+            "raster"
+        }
+    );
+    println!("    Point size: {}", info.dfPoints);
+    println!("    Resolution: {}x{} dpi", info.dfHorizRes, info.dfVertRes);
+    println!(
+        "    Ascent: {}, internal leading: {}, external leading: {}",
+        info.dfAscent, info.dfInternalLeading, info.dfExternalLeading
+    );
+
+    let mut buffer = String::new();
+    if info.dfItalic != 0 {
+        buffer += ", italic";
+    }
+    if info.dfUnderline != 0 {
+        buffer += ", underline";
+    }
+    if info.dfStrikeOut != 0 {
+        buffer += ", strikeout";
+    }
+    println!("    Weight: {}{}", info.dfWeight, buffer);
+    println!("    Charset: {}", charset_name(info.dfCharSet));
+    println!(
+        "    Pitch: {}, family: {}",
+        if info.is_variable_pitch() {
+            "variable"
+        } else {
+            "fixed"
+        },
+        FONT_FAMILIES[((info.dfPitchAndFamily >> 4) & 7) as usize]
+    );
+    if info.dfPixWidth != 0 {
+        println!("    Size: {}x{} pixels", info.dfPixWidth, info.dfPixHeight);
+    } else {
+        println!(
+            "    Size: variable width (average {}, maximum {}) x {} pixels",
+            info.dfAvgWidth, info.dfMaxWidth, info.dfPixHeight
+        );
+    }
+    println!(
+        "    Characters: 0x{:02x}-0x{:02x} (default 0x{:02x}, break 0x{:02x})",
+        info.dfFirstChar,
+        info.dfLastChar,
+        info.dfFirstChar.wrapping_add(info.dfDefaultChar),
+        info.dfFirstChar.wrapping_add(info.dfBreakChar)
+    );
+    if info.dfVersion >= 0x300 && info.dfFlags != 0 {
+        println!("    Flags: 0x{:08x}", info.dfFlags);
+    }
+}
+
+pub fn print_glyphs(font: &Font) {
+    for glyph in font.glyphs.iter() {
+        print!("    Character 0x{:02x}", glyph.code);
+        if glyph.code >= 0x20 && glyph.code <= 0x7e {
+            print!(" '{}'", glyph.code as char);
+        }
+        println!(" (width {}):", glyph.width);
+        for row in glyph.rows.iter() {
+            print!("        ");
+            for &pixel in row.iter() {
+                print!("{}", if pixel { '#' } else { '.' });
+            }
+            print!("\n");
+        }
+    }
+}
+
+/* Font component (RT_FONT) resource, or a whole .FNT file */
+pub fn print_rsrc_font(map: &Vec<u8>, offset: usize, length: usize) {
+    let font = match read_font(map, offset, length) {
+        Some(font) => font,
+        None => return,
+    };
+
+    print_font_info(&font.info, &font.face);
+    if font.info.dfType & 1 != 0 {
+        return;
+    }
+    print_glyphs(&font);
+}
+
+/* Font directory (RT_FONTDIR) resource. Each entry is a copy of the start of
+ * the font header, up to and including dfBitsPointer, followed by the device
+ * and face names. */
+pub fn print_rsrc_fontdir(map: &Vec<u8>, offset: usize, length: usize) {
+    let end = offset + length;
+    let count = read_word(map, offset);
+    let mut cursor = offset + 2;
+
+    for _ in 0..count {
+        if cursor + 2 + 0x71 > end {
+            eprintln!(
+                "Font directory entry at 0x{:x} exceeds resource length.",
+                cursor
+            );
+            return;
+        }
+        let ordinal = read_word(map, cursor);
+        let info = FontInfo::from_bytes(map, cursor + 2);
+        cursor += 2 + 0x71;
+        let device = read_cstring(map, cursor, end);
+        cursor += device.len() + 1;
+        let face = read_cstring(map, cursor, end);
+        cursor += face.len() + 1;
+
+        println!(
+            "    Font #{}: \"{}\", {} pt, {}x{} pixels, charset {}{}",
+            ordinal,
+            face,
+            info.dfPoints,
+            info.dfPixWidth,
+            info.dfPixHeight,
+            charset_name(info.dfCharSet),
+            if device.is_empty() {
+                String::new()
+            } else {
+                format!(", device \"{}\"", device)
+            }
+        );
+    }
+}
+
+/* Writes the font out as BDF 2.1, which X11 and most font editors can read. */
+pub fn write_bdf(font: &Font, path: &str) -> Result<(), Box<dyn Error>> {
+    let info = &font.info;
+    let mut bdf = File::create(path)?;
+    let descent = info.dfPixHeight as i32 - info.dfAscent as i32;
+    let weight = if info.dfWeight >= 600 {
+        "Bold"
+    } else {
+        "Medium"
+    };
+    let slant = if info.dfItalic != 0 { "I" } else { "R" };
+    let face = if font.face.is_empty() {
+        "Unknown"
+    } else {
+        &font.face
+    };
+    let registry = charset_registry(info.dfCharSet);
+
+    writeln!(bdf, "STARTFONT 2.1")?;
+    writeln!(
+        bdf,
+        "FONT -Windows-{}-{}-{}-Normal--{}-{}-{}-{}-{}-{}-{}",
+        face.replace('-', " "),
+        weight,
+        slant,
+        info.dfPixHeight,
+        info.dfPoints as u32 * 10,
+        info.dfHorizRes,
+        info.dfVertRes,
+        if info.is_variable_pitch() { "P" } else { "C" },
+        info.dfAvgWidth as u32 * 10,
+        registry
+    )?;
+    writeln!(
+        bdf,
+        "SIZE {} {} {}",
+        info.dfPoints, info.dfHorizRes, info.dfVertRes
+    )?;
+    writeln!(
+        bdf,
+        "FONTBOUNDINGBOX {} {} 0 {}",
+        info.dfMaxWidth.max(info.dfPixWidth),
+        info.dfPixHeight,
+        -descent
+    )?;
+    writeln!(bdf, "STARTPROPERTIES 5")?;
+    writeln!(bdf, "FONT_ASCENT {}", info.dfAscent)?;
+    writeln!(bdf, "FONT_DESCENT {}", descent)?;
+    writeln!(bdf, "FAMILY_NAME \"{}\"", face.replace('"', "\"\""))?;
+    writeln!(
+        bdf,
+        "COPYRIGHT \"{}\"",
+        info.dfCopyright.replace('"', "\"\"")
+    )?;
+    writeln!(
+        bdf,
+        "DEFAULT_CHAR {}",
+        info.dfFirstChar.wrapping_add(info.dfDefaultChar)
+    )?;
+    writeln!(bdf, "ENDPROPERTIES")?;
+    writeln!(bdf, "CHARS {}", font.glyphs.len())?;
+
+    for glyph in font.glyphs.iter() {
+        writeln!(bdf, "STARTCHAR char{:02X}", glyph.code)?;
+        writeln!(bdf, "ENCODING {}", glyph.code)?;
+        writeln!(
+            bdf,
+            "SWIDTH {} 0",
+            glyph.width as u32 * 72000
+                / (info.dfPoints.max(1) as u32 * info.dfHorizRes.max(1) as u32)
+        )?;
+        writeln!(bdf, "DWIDTH {} 0", glyph.width)?;
+        writeln!(
+            bdf,
+            "BBX {} {} 0 {}",
+            glyph.width, info.dfPixHeight, -descent
+        )?;
+        writeln!(bdf, "BITMAP")?;
+        for row in glyph.rows.iter() {
+            let mut line = String::new();
+            for chunk in row.chunks(8) {
+                let mut byte = 0u8;
+                for (bit, &pixel) in chunk.iter().enumerate() {
+                    if pixel {
+                        byte |= 0x80 >> bit;
+                    }
+                }
+                line += &format!("{:02X}", byte);
+            }
+            if line.is_empty() {
+                line += "00";
+            }
+            writeln!(bdf, "{}", line)?;
+        }
+        writeln!(bdf, "ENDCHAR")?;
+    }
+
+    writeln!(bdf, "ENDFONT")?;
+    Ok(())
+}
@@ -0,0 +1,209 @@
+/* Resolving imports against the imported modules themselves. Given one or
+ * more directories with --lib-path, we look for e.g. USER.EXE or
+ * COMMDLG.DLL there and read its resident and non-resident name tables, so
+ * that ordinals can be named without a specfile. Modules are cached by name
+ * for the whole run, since a batch of files usually imports the same few
+ * system modules over and over. */
+
+use crate::codepage;
+use crate::ne::NeExport;
+use crate::util::{read_byte, read_data, read_dword, read_word};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+static LIB_PATH: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/* Misses are cached as well, so that a missing module is only looked for
+ * (and complained about) once. */
+static MODULE_CACHE: Mutex<Option<HashMap<String, Option<Vec<NeExport>>>>> = Mutex::new(None);
+
+const MODULE_EXTENSIONS: [&str; 6] = ["", "EXE", "DLL", "DRV", "SYS", "FON"];
+
+/* Directories may be given as several options or as one list, separated
+ * as in PATH (':', or ';' on Windows, where ':' belongs to drive letters). */
+pub fn add_lib_path(arg: &str) {
+    let mut path = LIB_PATH.lock().unwrap();
+    for dir in std::env::split_paths(arg) {
+        if !dir.as_os_str().is_empty() {
+            path.push(dir);
+        }
+    }
+}
+
+pub fn have_lib_path() -> bool {
+    !LIB_PATH.lock().unwrap().is_empty()
+}
+
+fn list_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|f| f.is_file())
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    files.sort();
+    files
+}
+
+/* File names on the library path may be in any case (they usually come off
+ * a FAT disk), so compare them against the module name ignoring case. */
+fn find_module_file(dir: &Path, name: &str) -> Option<PathBuf> {
+    let files = list_files(dir);
+
+    for ext in MODULE_EXTENSIONS.iter() {
+        let wanted = if ext.is_empty() {
+            name.to_uppercase()
+        } else {
+            format!("{}.{}", name.to_uppercase(), ext)
+        };
+        for file in &files {
+            let matches = file
+                .file_name()
+                .and_then(|f| f.to_str())
+                .map_or(false, |f| f.to_uppercase() == wanted);
+            if matches {
+                return Some(file.clone());
+            }
+        }
+    }
+    None
+}
+
+/* Each entry is a length byte, the name, and the ordinal it names. The
+ * first entry is the module name (or description) and has ordinal 0. */
+fn read_name_table(map: &Vec<u8>, mut cursor: usize, end: usize, exports: &mut Vec<NeExport>) {
+    let mut first = true;
+
+    while cursor < end && cursor < map.len() {
+        let length = read_byte(map, cursor) as usize;
+        if length == 0 || cursor + 1 + length + 2 > map.len() {
+            break;
+        }
+        let name = codepage::decode(&read_data(map, cursor + 1, length));
+        let ordinal = read_word(map, cursor + 1 + length);
+        cursor += 1 + length + 2;
+
+        if first {
+            first = false;
+            continue;
+        }
+        if !exports.iter().any(|e| e.ordinal == ordinal) {
+            exports.push(NeExport { ordinal, name });
+        }
+    }
+}
+
+fn ne_offset(map: &Vec<u8>) -> Option<usize> {
+    if map.len() < 0x40 || read_word(map, 0) != 0x5a4d {
+        return None;
+    }
+    let offset_ne = read_dword(map, 0x3c) as usize;
+    if offset_ne + 0x40 > map.len() || read_word(map, offset_ne) != 0x454e {
+        return None;
+    }
+    Some(offset_ne)
+}
+
+/* The module name, which is the first entry of the resident name table. */
+fn read_module_name(map: &Vec<u8>) -> Option<String> {
+    let offset_ne = ne_offset(map)?;
+    let restab = offset_ne + read_word(map, offset_ne + 0x26) as usize;
+    if restab >= map.len() {
+        return None;
+    }
+    let length = read_byte(map, restab) as usize;
+    if restab + 1 + length > map.len() {
+        return None;
+    }
+    Some(codepage::decode(&read_data(map, restab + 1, length)))
+}
+
+/* Returns the named exports of an NE file, or None if it isn't one. */
+pub fn read_module_exports(map: &Vec<u8>) -> Option<Vec<NeExport>> {
+    let offset_ne = ne_offset(map)?;
+
+    let restab = offset_ne + read_word(map, offset_ne + 0x26) as usize;
+    let modtab = offset_ne + read_word(map, offset_ne + 0x28) as usize;
+    let nrestab = read_dword(map, offset_ne + 0x2c) as usize;
+    let cbnrestab = read_word(map, offset_ne + 0x20) as usize;
+
+    let mut exports = Vec::new();
+    read_name_table(map, restab, modtab, &mut exports);
+    if nrestab != 0 {
+        read_name_table(map, nrestab, nrestab + cbnrestab, &mut exports);
+    }
+    exports.sort_by_key(|e| e.ordinal);
+    Some(exports)
+}
+
+fn load_module(name: &str) -> Option<Vec<NeExport>> {
+    let dirs = LIB_PATH.lock().unwrap().clone();
+
+    for dir in &dirs {
+        let file = match find_module_file(dir, name) {
+            Some(file) => file,
+            None => continue,
+        };
+        let map = match std::fs::read(&file) {
+            Ok(map) => map,
+            Err(e) => {
+                eprintln!("Couldn't read {}: {}", file.display(), e);
+                continue;
+            }
+        };
+        match read_module_exports(&map) {
+            Some(exports) => return Some(exports),
+            None => eprintln!("Note: {} is not an NE module; skipping.", file.display()),
+        }
+    }
+
+    /* Some modules live in files with other names, e.g. KERNEL in
+     * KRNL386.EXE, so look inside the files for the module name. */
+    for dir in &dirs {
+        for file in list_files(dir) {
+            let ext = file
+                .extension()
+                .and_then(|e| e.to_str())
+                .unwrap_or("")
+                .to_uppercase();
+            if ext.is_empty() || !MODULE_EXTENSIONS.contains(&ext.as_str()) {
+                continue;
+            }
+            let map = match std::fs::read(&file) {
+                Ok(map) => map,
+                Err(_) => continue,
+            };
+            let matches = read_module_name(&map).map_or(false, |n| n.eq_ignore_ascii_case(name));
+            if matches {
+                if let Some(exports) = read_module_exports(&map) {
+                    return Some(exports);
+                }
+            }
+        }
+    }
+
+    eprintln!("Note: couldn't find module {} in the library path.", name);
+    None
+}
+
+/* Look up a module by name, loading it from the library path the first
+ * time it is asked for. */
+pub fn get_module_exports(name: &str) -> Option<Vec<NeExport>> {
+    if !have_lib_path() {
+        return None;
+    }
+
+    let key = name.to_uppercase();
+    let mut cache = MODULE_CACHE.lock().unwrap();
+    let cache = cache.get_or_insert_with(HashMap::new);
+    if let Some(exports) = cache.get(&key) {
+        return exports.clone();
+    }
+
+    let exports = load_module(name);
+    cache.insert(key, exports.clone());
+    exports
+}
@@ -1,11 +1,26 @@
-use crate::util::{read_byte, read_data, read_dword, read_string, read_word};
+pub mod check;
+pub mod font;
+pub mod libpath;
+pub mod os2;
+
+use crate::accel;
+use crate::codepage;
+use crate::dib;
+use crate::msgtable;
+use crate::png;
+use crate::rsrc::{self, ResourceEntry, ResourceId};
+use crate::version;
+use crate::util::{file_name_part, read_byte, read_data, read_dword, read_string, read_word};
 use crate::x86::defines::Instruction;
 use crate::x86::defines::X86ArgType::{IMM, MEM, REL, SEGPTR};
 use crate::x86::defines::{
     Argument, INSTR_FAR, INSTR_FUNC, INSTR_JUMP, INSTR_RELOC, INSTR_SCANNED, INSTR_VALID,
-    OP_BRANCH, OP_STOP,
+    MAX_INSTR, OP_BRANCH, OP_STOP,
+};
+use crate::{
+    DEMANGLE, DISASSEMBLE, DISASSEMBLE_ALL, DUMP_CHECK, EXPORT_BDF, EXPORT_PNG, FULL_CONTENTS,
+    SPECFILE,
 };
-use crate::{DEMANGLE, DISASSEMBLE, DISASSEMBLE_ALL, FULL_CONTENTS, SPECFILE};
 use std::cmp::min;
 use std::fs::File;
 use std::io::{Read, Write};
@@ -50,8 +65,44 @@ pub struct NeHeader {
 }
 
 impl NeHeader {
+    /* from the 0x40 bytes at the start of the header */
     pub fn from_bytes(bytes: &Vec<u8>) -> Self {
-        unimplemented!()
+        NeHeader {
+            ne_magic: read_word(bytes, 0x00),
+            ne_ver: read_byte(bytes, 0x02),
+            ne_rev: read_byte(bytes, 0x03),
+            ne_enttab: read_word(bytes, 0x04),
+            ne_cbenttab: read_word(bytes, 0x06),
+            ne_crc: read_dword(bytes, 0x08),
+            ne_flags: read_word(bytes, 0x0c),
+            ne_autodata: read_byte(bytes, 0x0e),
+            ne_unused: read_byte(bytes, 0x0f),
+            ne_heap: read_word(bytes, 0x10),
+            ne_stack: read_word(bytes, 0x12),
+            ne_ip: read_word(bytes, 0x14),
+            ne_cs: read_word(bytes, 0x16),
+            ne_sp: read_word(bytes, 0x18),
+            ne_ss: read_word(bytes, 0x1a),
+            ne_cseg: read_word(bytes, 0x1c),
+            ne_cmod: read_word(bytes, 0x1e),
+            ne_cbnrestab: read_word(bytes, 0x20),
+            ne_segtab: read_word(bytes, 0x22),
+            ne_rsrctab: read_word(bytes, 0x24),
+            ne_restab: read_word(bytes, 0x26),
+            ne_modtab: read_word(bytes, 0x28),
+            ne_imptab: read_word(bytes, 0x2a),
+            ne_nrestab: read_dword(bytes, 0x2c),
+            ne_cmovent: read_word(bytes, 0x30),
+            ne_align: read_word(bytes, 0x32),
+            ne_cres: read_word(bytes, 0x34),
+            ne_exetyp: read_byte(bytes, 0x36),
+            ne_flagsothers: read_byte(bytes, 0x37),
+            ne_pretthunks: read_word(bytes, 0x38),
+            ne_psegrefbytes: read_word(bytes, 0x3a),
+            ne_swaparea: read_word(bytes, 0x3c),
+            ne_expver_min: read_byte(bytes, 0x3e),
+            ne_expver_maj: read_byte(bytes, 0x3f),
+        }
     }
 }
 
@@ -79,13 +130,45 @@ pub struct NeImportModule {
 pub struct NeReloc {
     pub size: u8,
     pub reloc_type: u8,
+    pub additive: bool,
     pub offset_count: u16,
     pub offsets: Vec<u16>,
-    pub tseg: u16,
+    pub addend: u16, /* for additive relocations, the word already at the location */
+    pub tseg: u16,   /* or module, or OSFIXUP type */
     pub toffset: u16,
     pub text: String,
 }
 
+impl NeReloc {
+    /* offset the relocated location ends up pointing to */
+    pub fn target_offset(&self) -> u16 {
+        if self.additive {
+            self.toffset.wrapping_add(self.addend)
+        } else {
+            self.toffset
+        }
+    }
+
+    /* what to print after an imported name for an additive relocation */
+    pub fn addend_suffix(&self) -> String {
+        if self.additive && self.addend != 0 {
+            format!("+0x{:x}", self.addend)
+        } else {
+            String::new()
+        }
+    }
+}
+
+pub const NE_OSFIXUP_NAMES: [&str; 7] = [
+    "",
+    "FIARQQ/FJARQQ", /* 1 */
+    "FISRQQ/FJSRQQ", /* 2 */
+    "FICRQQ/FJCRQQ", /* 3 */
+    "FIERQQ",        /* 4 */
+    "FIDRQQ",        /* 5 */
+    "FIWRQQ",        /* 6 */
+];
+
 #[derive(Clone, Debug, Default)]
 pub struct NeSegment {
     pub cs: u16,
@@ -93,8 +176,15 @@ pub struct NeSegment {
     pub length: u16,
     pub flags: u16,
     pub min_alloc: u16,
+    /* Contents of the segment as the loader sees them. This is a copy of the
+     * bytes at start..start+length, except for iterated segments, whose
+     * records are expanded here. start and length still describe the data
+     * as it is laid out in the file. */
+    pub data: Vec<u8>,
     pub instr_flags: Vec<u8>,
     pub reloc_table: Vec<NeReloc>,
+    /* (ip, bytes) for far functions whose "retf N" we found */
+    pub far_params: Vec<(u16, u16)>,
 }
 
 #[derive(Clone, Debug, Default)]
@@ -281,6 +371,12 @@ pub fn print_export(ne: &NeExecutable) {
                     "<no name>"
                 }
             );
+            if os2::is_os2(ne) && os2::entry_param_words(&ne.enttab[i]) != 0 {
+                print!(
+                    "\t\t(call gate, {} parameter words)\n",
+                    os2::entry_param_words(&ne.enttab[i])
+                );
+            }
         }
     }
     print!('\n');
@@ -566,9 +662,8 @@ pub fn read_res_name_table(map: &Vec<u8>, start: usize, entry_table: &Vec<NeEntr
     cursor += 1;
     length = read_byte(map, cursor);
     // first = malloc((length+1)*sizeof(char));
-    first = read_data(map, cursor, length as usize).into();
+    first = codepage::decode(&read_data(map, cursor, length as usize));
     // memcpy(first, read_data(cursor), length);
-    first[length] = 0;
     cursor += length + 2;
 
     // while (length = read_byte(map, cursor += 1) > 0)
@@ -576,9 +671,8 @@ pub fn read_res_name_table(map: &Vec<u8>, start: usize, entry_table: &Vec<NeEntr
         cursor += 1;
         length = read_byte(map, cursor);
         // name = malloc((length+1)*sizeof(char));
-        name = read_data(map, cursor, length as usize).into();
+        name = codepage::decode(&read_data(map, cursor, length as usize));
         // memcpy(name, read_data(map, cursor, length), length);
-        name[length] = 0;
         cursor += length;
 
         if (opts & DEMANGLE) && name[0] == '?' {
@@ -652,76 +746,58 @@ pub fn get_entry_table(start: usize, ne: &mut NeExecutable) {
     ne.entcount = count;
 }
 
-pub fn load_exports(module: &mut NeImportModule) {
-    let mut line = String::New();
-    let mut p = String::new();
-    let mut count = 0usize;
-    let mut ordinal = 0u16;
-
-    let spec_name = fmt!("%.8s.ORD", module.name);
-    let mut specfile = std::fs::File::open(spec_name)?;
-    // if (!specfile) {
-    //     sprintf(spec_name, "spec/%.8s.ORD", module.name);
-    //     specfile = fopen(spec_name, "r");
-    //     if (!specfile) {
-    //         eprint!( "Note: couldn't find specfile for module {}; exported names won't be given.\n", module.name);
-    //         eprint!( "      To create a specfile, run `dumpne -o <module.dll>'.\n");
-    //         module.exports = NULL;
-    //         module.export_count = 0;
-    //         return;
-    //     }
-    // }
-
-    /* first grab a count */
-    // count = 0;
-    // while (fgets(line, sizeof(line), specfile)) {
-    //     if (line[0] == '#' || line[0] == '\n') continue;
-    //     count += 1;
-    // }
-    let mut file_buf: Vec<u8> = Vec::new();
-    let bytes_read = specfile.read(&mut file_buf)?;
-    let lines = file_buf.split("\n");
-    let mut spec_lines: Vec<u8> = Vec::new();
-    for line in lines {
-        if line[0] == '#'.into() || line[0] == '\n'.into() {
+/* A specfile has one export per line, as the ordinal, a tab and the name;
+ * lines starting with '#' are comments. */
+fn read_specfile(path: &str) -> Option<Vec<NeExport>> {
+    let text = std::fs::read_to_string(path).ok()?;
+    let mut exports = Vec::new();
+    for line in text.lines() {
+        if line.is_empty() || line.starts_with('#') {
             continue;
         }
+        let mut fields = line.splitn(2, '\t');
+        match fields.next().unwrap_or("").trim().parse::<u16>() {
+            Ok(ordinal) => exports.push(NeExport {
+                ordinal,
+                name: fields.next().unwrap_or("").trim_end().to_string(),
+            }),
+            Err(_) => eprint!("Error reading specfile near line: `{}'\n", line),
+        }
+    }
+    Some(exports)
+}
 
-        let splits = line.split('\t')?;
-        module.exports[count].ordinal = splits[0];
-        module.exports[count].name = splits[1];
-    }
-
-    // module.exports = malloc(count * sizeof(struct export));
-
-    // fseek(specfile, 0, SEEK_SET);
-    // count = 0;
-    // while (fgets(line, sizeof(line), specfile)) {
-    //     if (line[0] == '#' || line[0] == '\n') continue;
-    //     if ((p = strchr(line, '\n'))) *p = 0;   /* kill final newline */
-    //     if (sscanf(line, "%hu", &ordinal) != 1) {
-    //         eprint!( "Error reading specfile near line: `{}'\n", line);
-    //         continue;
-    //     }
-    //     module.exports[count].ordinal = ordinal;
-    //
-    //     p = strchr(line, '\t');
-    //     if (p) {
-    //         p += 1;
-    //         module.exports[count].name = strdup(p);
-    //
-    //         if ((opts & DEMANGLE) && module.exports[count].name[0] == '?')
-    //             module.exports[count].name = demangle(module.exports[count].name);
-    //     } else {
-    //         module.exports[count].name = NULL;
-    //     }
-    //     count += 1;
-    // }
-
-    module.export_count = count;
+pub fn load_exports(module: &mut NeImportModule) {
+    /* The module itself, if we can find it on the library path, has every
+     * name, including the non-resident ones; otherwise fall back to a
+     * specfile. OS/2 system modules (DOSCALLS, VIOCALLS, ...) keep theirs
+     * apart, since DOSCALLS lives in the kernel and has no file to read. */
+    let name: String = module.name.chars().take(8).collect();
+    let spec_names = [
+        format!("{}.ORD", name),
+        format!("spec/{}.ORD", name),
+        format!("spec/os2/{}.ORD", name),
+    ];
+    let exports = libpath::get_module_exports(&module.name)
+        .or_else(|| spec_names.iter().find_map(|path| read_specfile(path)));
+    module.exports = match exports {
+        Some(exports) => exports,
+        None => {
+            eprint!(
+                "Note: couldn't find specfile for module {}; exported names won't be given.\n",
+                module.name
+            );
+            eprint!("      To create a specfile, run `dumpne -o <module.dll>',\n");
+            eprint!("      or give the directory containing it with --lib-path.\n");
+            Vec::new()
+        }
+    };
 
-    // fclose(specfile);
-    drop(specfile);
+    for export in module.exports.iter_mut() {
+        if (opts & DEMANGLE) != 0 && export.name.starts_with('?') {
+            export.name = demangle(&mut export.name);
+        }
+    }
 }
 
 pub fn get_import_module_table(start: usize, ne: &NeExecutable) {
@@ -758,6 +834,21 @@ pub fn readne(offset_ne: usize, ne: &mut NeExecutable) -> Result<(), Box<dyn err
     *ne.header =
         NeHeader::from_bytes(read_data(&ne.file, offset_ne, mem::size_of::<NeHeader>()).as_ref());
 
+    /* strings are decoded in the module's codepage, so find it first */
+    if ne.header.ne_exetyp != 1 && ne.header.ne_rsrctab != ne.header.ne_restab {
+        codepage::set_file_codepage(find_version_codepage(
+            &ne.file,
+            offset_ne + ne.header.ne_rsrctab as usize,
+        ));
+        accel::set_menu_items(find_menu_items(
+            &ne.file,
+            offset_ne + ne.header.ne_rsrctab as usize,
+        ));
+    } else {
+        codepage::set_file_codepage(None);
+        accel::set_menu_items(Vec::new());
+    }
+
     /* read our various tables */
     get_entry_table(offset_ne + ne.header.ne_enttab, ne);
     ne.name = read_res_name_table(&ne.file, offset_ne + ne.header.ne_restab, &ne.enttab);
@@ -773,10 +864,24 @@ pub fn readne(offset_ne: usize, ne: &mut NeExecutable) -> Result<(), Box<dyn err
     Ok(())
 }
 
-pub fn dumpne(offset_ne: usize) {
+pub fn dumpne(map: &Vec<u8>, offset_ne: usize) {
     let mut ne: NeExecutable = NeExecutable::default();
+    ne.file = map.clone();
+
+    if mode != SPECFILE {
+        print!("Module type: NE (New Executable)\n");
+    }
+
+    /* Check the raw file before parsing it; the files worth checking are the
+     * ones readne() is likely to choke on. */
+    if mode & DUMP_CHECK {
+        check::print_check(map, offset_ne);
+    }
 
-    readne(offset_ne, &mut ne)?;
+    if let Err(error) = readne(offset_ne, &mut ne) {
+        eprintln!("{}", error);
+        return;
+    }
 
     if mode == SPECFILE {
         print_specfile(&ne);
@@ -784,7 +889,6 @@ pub fn dumpne(offset_ne: usize) {
         return;
     }
 
-    print!("Module type: NE (New Executable)\n");
     print!("Module name: {}\n", ne.name);
     if ne.description {
         print!("Module description: {}\n", ne.description);
@@ -813,8 +917,16 @@ pub fn dumpne(offset_ne: usize) {
     }
 
     if mode & DUMPRSRC {
-        if ne.header.ne_rsrctab != ne.header.ne_restab {
-            print_rsrc(offset_ne + ne.header.ne_rsrctab);
+        if os2::is_os2(&ne) {
+            /* OS/2 keeps its resources in segments; the table only maps
+             * them to types and IDs. */
+            if ne.header.ne_cres != 0 {
+                os2::print_os2_rsrc(&ne, offset_ne + ne.header.ne_rsrctab as usize);
+            } else {
+                print!("No resource table\n");
+            }
+        } else if ne.header.ne_rsrctab != ne.header.ne_restab {
+            print_rsrc(&ne.file, offset_ne + ne.header.ne_rsrctab as usize);
         } else {
             print!("No resource table\n");
         }
@@ -840,89 +952,27 @@ pub struct header_bitmap_info {
 
 pub fn dup_string_resource(map: &Vec<u8>, offset: usize) -> String {
     let length = read_byte(map, offset);
-    let mut ret = String::new();
-    ret = read_data(map, offset + 1, length as usize).into();
-    return ret;
+    codepage::decode(&read_data(map, offset + 1, length as usize))
 }
 
-pub fn print_escaped_string(map: &Vec<u8>, mut offset: usize, mut length: i32) {
-    print!('"');
-    while length -= 1 {
-        offset += 1;
-        let c = read_byte(map, offset);
-        if c == '\t' as u8 {
-            print!("\\t");
-        } else if c == '\n' as u8 {
-            print!("\\n");
-        } else if c == '\r' as u8 {
-            print!("\\r");
-        } else if c == '"' as u8 {
-            print!("\\\"");
-        } else if c == '\\' as u8 {
-            print!("\\\\");
-        } else if c >= ' ' as u8 && c <= '~' as u8 {
-            print!(c);
-        } else {
-            print!("\\x{:02x}", c);
-        }
-    }
-    print!('"');
+pub fn print_escaped_string(map: &Vec<u8>, offset: usize, length: i32) {
+    let text = codepage::decode(&read_data(map, offset, length as usize));
+    print!("\"{}\"", codepage::escape(&text));
 }
 
-pub fn print_escaped_string0(map: &Vec<u8>, mut offset: usize) -> usize {
-    print!('"');
-    offset += 1;
-    let mut c = read_byte(map, offset);
-    while c != 0 {
-        if c == '\t' as u8 {
-            print!("\\t");
-        } else if c == '\n' as u8 {
-            print!("\\n");
-        } else if c == '\r' as u8 {
-            print!("\\r");
-        } else if c == '"' as u8 {
-            print!("\\\"");
-        } else if c == '\\' as u8 {
-            print!("\\\\");
-        } else if c >= ' ' as u8 && c <= '~' as u8 {
-            print!(c);
-        } else {
-            print!("\\x{:02x}", c);
-        }
-        offset += 1;
-        c = read_byte(map, offset);
+pub fn print_escaped_string0(map: &Vec<u8>, offset: usize) -> usize {
+    let mut end = offset;
+    while end < map.len() && read_byte(map, end) != 0 {
+        end += 1;
     }
-    print!('"');
-    return offset;
+    print_escaped_string(map, offset, (end - offset) as i32);
+    return end + 1;
 }
 
 pub fn print_timestamp(high: u32, low: u32) {
     unimplemented!()
 }
 
-pub const RSRC_TYPES: [String; 19] = [
-    "".to_string(),
-    "Cursor".to_string(),            /* 1 */
-    "Bitmap".to_string(),            /* 2 */
-    "Icon".to_string(),              /* 3 */
-    "Menu".to_string(),              /* 4 */
-    "Dialog box".to_string(),        /* 5 */
-    "String".to_string(),            /* 6 */
-    "Font directory".to_string(),    /* 7 */
-    "Font component".to_string(),    /* 8 */
-    "Accelerator table".to_string(), /* 9 */
-    "Resource data".to_string(),     /* a */
-    "Message table".to_string(),     /* b */
-    /* fixme: error table? */
-    "Cursor directory".to_string(), /* c */
-    "".to_string(),
-    "Icon directory".to_string(), /* e */
-    "Name table".to_string(),     /* f */
-    "Version".to_string(),        /* 10 */
-    "".to_string(),               /* fixme: RT_DLGINCLUDE? */
-    "".to_string(),
-];
-
 pub const RSRC_BMP_COMPRESSION: [String; 15] = [
     "none".to_string(),                 /* 0 */
     "RLE (8 bpp)".to_string(),          /* 1 */
@@ -1327,71 +1377,86 @@ pub const rsrc_dialog_class: [String; 7] = [
     "".to_string(),
 ];
 
-pub fn print_rsrc_menu_items(map: &Vec<u8>, depth: i32, mut offset: usize) -> usize {
-    // u16 flags, id;
-    // char buffer[1024];
-    // int i;
-    let mut buffer = String::new();
-    let mut flags = 0u16;
-    let mut id = 0u16;
+/* Read a zero-terminated string from a resource, in the module's codepage
+ * or as UTF-16 for 32-bit resources, and return it with the offset past the
+ * terminator. */
+pub fn read_rsrc_string(map: &Vec<u8>, offset: usize, wide: bool) -> (String, usize) {
+    let mut end = offset;
+    if wide {
+        let mut chars = Vec::new();
+        while end + 2 <= map.len() && read_word(map, end) != 0 {
+            chars.push(read_word(map, end));
+            end += 2;
+        }
+        (String::from_utf16_lossy(&chars), end + 2)
+    } else {
+        while end < map.len() && read_byte(map, end) != 0 {
+            end += 1;
+        }
+        (codepage::decode(&read_data(map, offset, end - offset)), end + 1)
+    }
+}
 
-    loop {
-        flags = read_word(map, offset);
+/* Standard menu items are the same in 16-bit and 32-bit modules, except that
+ * the text of the latter is UTF-16. */
+pub fn print_rsrc_menu_items(map: &Vec<u8>, depth: i32, mut offset: usize, wide: bool) -> usize {
+    while offset + 2 <= map.len() {
+        let flags = read_word(map, offset);
         offset += 2;
 
         print!("        ");
-        for i in 0..depth {
+        for _ in 0..depth {
             print!("  ");
         }
-        if !(flags & 0x0010) {
+        if flags & 0x0010 == 0 {
             /* item ID */
-            id = read_word(map, offset);
+            print!("{}: ", read_word(map, offset));
             offset += 2;
-            print!("{}: ", id);
         }
 
-        offset = print_escaped_string0(map, offset);
+        let (text, next) = read_rsrc_string(map, offset, wide);
+        print!("\"{}\"", codepage::escape(&text));
+        offset = next;
 
         /* and print flags */
-        buffer[0] = '\0';
-        if flags & 0x0001 {
+        let mut buffer = String::new();
+        if flags & 0x0001 != 0 {
             buffer += ", grayed";
         }
-        if flags & 0x0002 {
+        if flags & 0x0002 != 0 {
             buffer += ", inactive";
         }
-        if (flags & 0x0004) {
+        if flags & 0x0004 != 0 {
             buffer += ", bitmap";
         }
-        if (flags & 0x0008) {
+        if flags & 0x0008 != 0 {
             buffer += ", checked";
         }
-        if (flags & 0x0010) {
+        if flags & 0x0010 != 0 {
             buffer += ", popup";
         }
-        if (flags & 0x0020) {
+        if flags & 0x0020 != 0 {
             buffer += ", menu bar break";
         }
-        if flags & 0x0040 {
+        if flags & 0x0040 != 0 {
             buffer += ", menu break";
         }
         /* don't print ENDMENU */
-        if flags & 0xff00 {
-            // sprintf(buffer + strlen(buffer), ", unknown flags 0x{:04x}", flags & 0xff00);
-            buffer += fmt!(", unknown flags 0x{:04x}", flags & 0xff00);
+        if flags & 0xff00 != 0 {
+            buffer += &format!(", unknown flags 0x{:04x}", flags & 0xff00);
         }
 
-        if buffer[0] {
-            print!(" ({})", buffer[2..]);
+        if !buffer.is_empty() {
+            print!(" ({})", &buffer[2..]);
         }
-        print!('\n');
+        print!("\n");
 
         /* if we have a popup, recurse */
-        if (flags & 0x0010) {
-            offset = print_rsrc_menu_items(map, depth + 1, offset);
+        if flags & 0x0010 != 0 {
+            offset = print_rsrc_menu_items(map, depth + 1, offset, wide);
         }
 
-        if (flags & 0x0080) {
+        if flags & 0x0080 != 0 {
             break;
         }
     }
@@ -1399,237 +1464,49 @@ pub fn print_rsrc_menu_items(map: &Vec<u8>, depth: i32, mut offset: usize) -> us
     return offset;
 }
 
-pub struct version_header {
-    pub length: u16,       /* 00 */
-    pub value_length: u16, /* 02 - always 52 (0x34), the length of the second header */
-    /* the "type" field given by Windows is missing */
-    pub string: [u8; 16], /* 04 - the fixed string VS_VERSION_INFO\0 */
-    pub magic: u32,       /* 14 - 0xfeef04bd */
-    pub struct_2: u16,    /* 18 - seems to always be 1.0 */
-    pub struct_1: u16,    /* 1a */
-    /* 1.2.3.4 &c. */
-    pub file_2: u16,          /* 1c */
-    pub file_1: u16,          /* 1e */
-    pub file_4: u16,          /* 20 */
-    pub file_3: u16,          /* 22 */
-    pub prod_2: u16,          /* 24 - always the same as the above? */
-    pub prod_1: u16,          /* 26 */
-    pub prod_4: u16,          /* 28 */
-    pub prod_3: u16,          /* 2a */
-    pub flags_file_mask: u32, /* 2c - always 2 or 3f...? */
-    pub flags_file: u32,      /* 30 */
-    pub flags_os: u32,        /* 34 */
-    pub flags_type: u32,      /* 38 */
-    pub flags_subtype: u32,   /* 3c */
-    pub date_1: u32,          /* 40 - always 0? */
-    pub date_2: u32,          /* 44 */
-}
-
-pub const rsrc_version_file: [String; 7] = [
-    "VS_FF_DEBUG".to_string(),        /* 0001 */
-    "VS_FF_PRERELEASE".to_string(),   /* 0002 */
-    "VS_FF_PATCHED".to_string(),      /* 0004 */
-    "VS_FF_PRIVATEBUILD".to_string(), /* 0008 */
-    "VS_FF_INFOINFERRED".to_string(), /* 0010 */
-    "VS_FF_SPECIALBUILD".to_string(), /* 0020 */
-    "".to_string(),
-];
-
-pub const rsrc_version_type: [String; 9] = [
-    "unknown".to_string(),        /* 0 VFT_UNKNOWN */
-    "application".to_string(),    /* 1 VFT_APP */
-    "DLL".to_string(),            /* 2 VFT_DLL */
-    "device driver".to_string(),  /* 3 VFT_DRV */
-    "font".to_string(),           /* 4 VFT_FONT */
-    "virtual device".to_string(), /* 5 VFT_VXD */
-    "(unknown type 6)".to_string(),
-    "static-link library".to_string(), /* 7 VFT_STATIC_LIB */
-    "".to_string(),
-];
-
-pub const rsrc_version_subtype_drv: [String; 14] = [
-    "unknown".to_string(),           /* 0 VFT2_UNKNOWN */
-    "printer".to_string(),           /* 1 VFT2_DRV_PRINTER etc. */
-    "keyboard".to_string(),          /* 2 */
-    "language".to_string(),          /* 3 */
-    "display".to_string(),           /* 4 */
-    "mouse".to_string(),             /* 5 */
-    "network".to_string(),           /* 6 */
-    "system".to_string(),            /* 7 */
-    "installable".to_string(),       /* 8 */
-    "sound".to_string(),             /* 9 */
-    "communications".to_string(),    /* 10 */
-    "input method".to_string(),      /* 11, found in WINE */
-    "versioned printer".to_string(), /* 12 */
-    "".to_string(),
-];
-
-pub fn print_rsrc_version_flags(header: &version_header) {
-    let mut buffer = String::new();
-    let mut i = 0i32;
-
-    buffer[0] = '\0';
-    for i in 0..6 {
-        if header.flags_file & (1 << i) {
-            buffer += ", ";
-            buffer += &*rsrc_version_file[i];
-        }
-    }
-    if header.flags_file & 0xffc0 {
-        buffer += fmt!(", (unknown flags 0x{:04x})", header.flags_file & 0xffc0);
-    }
-    print!("    File flags: ");
-    if (header.flags_file) {
-        print!("{}", buffer[2..]);
-    }
+/* Gather the IDs and labels of a menu's items, as "Popup > Item", so that
+ * other resources can refer to them. Ampersands and the accelerator text
+ * after a tab are dropped. */
+pub fn collect_rsrc_menu_items(
+    map: &Vec<u8>,
+    prefix: &str,
+    mut offset: usize,
+    wide: bool,
+    items: &mut Vec<(u16, String)>,
+) -> usize {
+    while offset + 2 <= map.len() {
+        let flags = read_word(map, offset);
+        let mut id = None;
+        offset += 2;
 
-    buffer[0] = '\0';
-    if (header.flags_os == 0) {
-        buffer += ", VOS_UNKNOWN";
-    } else {
-        match (header.flags_os & 0xffff) {
-            1 => {
-                buffer += ", VOS__WINDOWS16";
-            }
-            2 => {
-                buffer += ", VOS__PM16";
-            }
-            3 => {
-                buffer += ", VOS__PM32";
-            }
-            4 => {
-                buffer += ", VOS__WINDOWS32";
-            }
-            _ => {
-                buffer += fmt!(", (unknown OS 0x{:04x})", header.flags_os & 0xffff);
-            }
-        }
-        match header.flags_os >> 16 {
-            1 => {
-                buffer += ", VOS_DOS";
-            }
-            2 => {
-                buffer += ", VOS_OS216";
-            }
-            3 => {
-                buffer += ", VOS_OS232";
-            }
-            4 => {
-                buffer += ", VOS_NT";
-            }
-            5 => {
-                buffer += ", VOS_WINCE";
-            } /* found in WINE */
-            _ => {
-                buffer += fmt!(", (unknown OS 0x{:04x})", header.flags_os >> 16);
-            }
+        if flags & 0x0010 == 0 {
+            id = Some(read_word(map, offset));
+            offset += 2;
         }
-    }
-    print!("\n    OS flags: {}\n", buffer[2..]);
 
-    if header.flags_type <= 7 {
-        print!("    Type: {}\n", rsrc_version_type[header.flags_type]);
-    } else {
-        print!("    Type: (unknown type {})\n", header.flags_type);
-    }
-
-    if header.flags_type == 3 {
-        /* driver */
-        if header.flags_subtype <= 12 {
-            print!(
-                "    Subtype: {} driver\n",
-                rsrc_version_subtype_drv[header.flags_subtype]
-            );
-        } else {
-            print!("    Subtype: (unknown subtype {})\n", header.flags_subtype);
-        }
-    } else if (header.flags_type == 4) {
-        /* font */
-        if (header.flags_subtype == 0) {
-            print!("    Subtype: unknown font\n");
-        } else if (header.flags_subtype == 1) {
-            print!("    Subtype: raster font\n");
-        } else if (header.flags_subtype == 2) {
-            print!("    Subtype: vector font\n");
-        } else if (header.flags_subtype == 3) {
-            print!("    Subtype: TrueType font\n");
+        let (text, next) = read_rsrc_string(map, offset, wide);
+        let text = text.split('\t').next().unwrap_or("").replace('&', "");
+        let label = if prefix.is_empty() {
+            text
         } else {
-            print!("    Subtype: (unknown subtype {})\n", header.flags_subtype);
-        }
-    } else if (header.flags_type == 5) {
-        /* VXD */
-        print!("    Virtual device ID: {}\n", header.flags_subtype);
-    } else if (header.flags_subtype) {
-        /* according to MSDN nothing else is valid */
-        print!("    Subtype: (unknown subtype {})\n", header.flags_subtype);
-    }
-}
-
-pub fn print_rsrc_strings(map: &Vec<u8>, mut offset: usize, end: usize) {
-    let mut length = 0u16;
-
-    while offset < end {
-        /* first length is redundant */
-        length = read_word(map, offset + 2);
-        print!("        ");
-        offset = print_escaped_string0(map, offset + 4);
-        offset = (offset + 3) & !3;
-        print!(": ");
-        /* According to MSDN this is zero-terminated, and in most cases it is.
-         * However, at least one application (msbsolar) has NEs with what
-         * appears to be a non-zero-terminated string. In Windows this is cut
-         * off at one minus the given length, just like other strings, so
-         * we'll do that here.
-         *
-         * And another file has a zero length here. How do compilers screw this
-         * up so badly? */
-        print_escaped_string(
-            map,
-            offset,
-            (if length > 0 { length - 1 } else { 0 }) as i32,
-        );
-        offset += length;
-        offset = (offset + 3) & !3;
-        print!('\n');
-    }
-}
+            format!("{} > {}", prefix, text)
+        };
+        offset = next;
 
-pub fn print_rsrc_stringfileinfo(map: &Vec<u8>, mut offset: usize, end: usize) {
-    let mut length = 0u16;
-    let lang = 0u32;
-    let codepage = 0u32;
-
-    /* we already processed the StringFileInfo header */
-    while offset < end {
-        /* StringTable header */
-        length = read_word(map, offset);
-        /* codepage and language code */
-        let data = read_data(map, offset + 4, length as usize);
-        scan_fmt!(data, "{4x}{4x}", lang, codepage);
-        // sscanf(read_data(map, offset + 4), "%4x%4x", &lang, &codepage);
-        println!(
-            "    String table (lang={:04x}, codepage={:04x}):",
-            lang, codepage
-        );
-        print_rsrc_strings(map, offset + 16, offset + length);
-        offset += length;
-    }
-}
+        if flags & 0x0010 != 0 {
+            offset = collect_rsrc_menu_items(map, &label, offset, wide, items);
+        } else if let Some(id) = id {
+            /* separators have ID 0 and no text */
+            if id != 0 && !items.iter().any(|(i, _)| *i == id) {
+                items.push((id, label));
+            }
+        }
 
-pub fn print_rsrc_varfileinfo(mut offset: usize, end: usize) {
-    while offset < end {
-        /* first length is redundant */
-        let length = read_word(map, offset + 2);
-        offset += 16;
-        for i in (0..length).step_by(4) {
-            println!(
-                "    Var (lang={:04x}, codepage={:04x})",
-                read_word(map, offset + i),
-                read_word(map, offset + i + 2)
-            );
+        if flags & 0x0080 != 0 {
+            break;
         }
-        offset += length;
     }
+    offset
 }
 
 pub fn print_rsrc_resource(
@@ -1637,19 +1514,20 @@ pub fn print_rsrc_resource(
     rsrc_type: u16,
     mut offset: usize,
     length: usize,
-    rn_id: u16,
+    id: &ResourceId,
 ) {
     match rsrc_type
     {
-    0x8001 => {
-        /* Cursor */
-        println!("    Hotspot: ({}, {})", read_word(map, offset), read_word(map, offset + 2));
-        offset += 4;
-        /* fall through */
-    }
+    0x8001 | /* Cursor */
     0x8002 | /* Bitmap */
     0x8003 => {
         /* Icon */
+        let mut length = length;
+        if rsrc_type == 0x8001 {
+            println!("    Hotspot: ({}, {})", read_word(map, offset), read_word(map, offset + 2));
+            offset += 4;
+            length = length.saturating_sub(4);
+        }
         if read_dword(map, offset) == 12 /* BITMAPCOREHEADER */ {
             println!("    Size:{}x{}", read_word(map, offset + 4), read_word(map, offset + 6));
             println!("    Planes: {}", read_word(map, offset + 8));
@@ -1673,6 +1551,18 @@ pub fn print_rsrc_resource(
             }
             print!('\n');
         } else { eprintln!("Unknown bitmap header size {}.", read_dword(map, offset)); }
+
+        if opts & EXPORT_PNG {
+            let kind = match rsrc_type { 0x8001 => "cursor", 0x8002 => "bitmap", _ => "icon" };
+            let png_name = format!("{}_{}.png", kind, file_name_part(&id.to_string()));
+            match dib::decode_dib(&read_data(map, offset, length), rsrc_type != 0x8002) {
+                Ok(image) => match png::write_png(&png_name, image.width, image.height, &image.rgba) {
+                    Ok(()) => println!("    Exported to {}", png_name),
+                    Err(e) => eprintln!("Couldn't write {}: {}", png_name, e),
+                },
+                Err(e) => eprintln!("{}", e),
+            }
+        }
     }
     0x8004 => /* Menu */
     {
@@ -1694,7 +1584,7 @@ pub fn print_rsrc_resource(
         }
 
         println!("    Items:");
-        print_rsrc_menu_items(map, 0, offset);
+        print_rsrc_menu_items(map, 0, offset, false);
     }
     0x8005 => /* Dialog box */
     {
@@ -1766,11 +1656,11 @@ pub fn print_rsrc_resource(
 
         while cursor < offset + length
         {
-            cursor += 1;
             let str_length = read_byte(map, cursor);
+            cursor += 1;
             if str_length
             {
-                print!("    {} (0x{:06x}): ", i + ((rn_id & (!0x8000))-1)*16, cursor);
+                print!("    {} (0x{:06x}): ", i + (id.number().unwrap_or(1) - 1) * 16, cursor);
                 print_escaped_string(map, cursor, str_length as i32);
                 print!('\n');
                 cursor += str_length;
@@ -1780,56 +1670,42 @@ pub fn print_rsrc_resource(
     }
     // break;
 // #if 0 /* No testcases for this either */
-    0x8007 | /* Font directory */
+    0x8007 => /* Font directory */
+    {
+        font::print_rsrc_fontdir(map, offset, length);
+    }
     0x8008 => /* Font component */
-        {}
-    0x8009 => /* Accelerator table */
     {
-        /* This format seems to be similar but older. Five bytes per
-         * Entry, in the format:
-         * [byte] - flags
-         * [word] - key
-         * [word] - id
-         *
-         * Problem is, the key codes don't seem to make much sense. In
-         * particular we have instances where the virtual flag isn't set
-         * but we have C0 control codes. So the mapping must be different
-         * than it is for current accelerator tables.
-         */
-        // u8 flags;
-
-        loop {
-            flags = read_byte(map, offset);
-            key = read_word(map, offset);
-            rid = read_word(map, offset);
-
-            print!("    ");
-
-            if flags & 0x02 {
-                print!("(FNOINVERT) ");
-            }
-
-            if flags & 0x04 {
-                print!("Shift+");
-            }
-            if flags & 0x08 {
-                print!("Ctrl+");
-            }
-            if flags & 0x10 {
-                print!("Alt+");
-            }
-            if flags & 0x60 {
-                eprintln!("Unknown accelerator flags 0x{:02x}", flags & 0x60);
-            }
-
-            /* fixme: print the key itself */
-
-            println!(": {}", rn_id);
-            if !(!(flags & 0x80)) {
-                break;
+        font::print_rsrc_font(map, offset, length);
+        if opts & EXPORT_BDF {
+            if let Some(f) = font::read_font(map, offset, length) {
+                let face = match file_name_part(&f.face) {
+                    face if face.is_empty() => "font".to_string(),
+                    face => face,
+                };
+                /* the resource ID keeps weights and charsets of the same
+                 * face and size apart */
+                let bdf_name = format!(
+                    "{}_{}_{}.bdf",
+                    face,
+                    f.info.dfPoints,
+                    file_name_part(&id.to_string())
+                );
+                match font::write_bdf(&f, &bdf_name) {
+                    Ok(()) => println!("    Exported to {}", bdf_name),
+                    Err(e) => eprintln!("Couldn't write {}: {}", bdf_name, e),
+                }
             }
         }
     }
+    0x8009 => /* Accelerator table */
+    {
+        /* Five bytes per entry here, against eight in 32-bit modules.
+         * Entries without FVIRTKEY can have C0 control codes as their key;
+         * those are Ctrl+letter, as RC writes them with "^". */
+        let data = read_data(map, offset, length);
+        accel::print_accelerators(&accel::read_accelerators(&data, false));
+    }
     // break;
 // #endif
     /* Resource data (0x800a) is parsed as default, i.e. hex-dumped. */
@@ -1840,250 +1716,142 @@ pub fn print_rsrc_resource(
          * resource. Therefore we only list the components this refers to.
          * Fortunately, the headers are different but the relevant information
          * is stored in the same bytes. */
-        let mut count = read_word(map, offset + 4);
+        let count = read_word(map, offset + 4) as usize;
         offset += 6;
-        print!("    Resources: ");
-        count -= 1;
-        if count != 0 {
-            print!("#{}", read_word(map, offset + 12));
-            offset += 14;
-        }
-        count -= 1;
-        while count != 0 {
-            print!(", #{}", read_word(map, offset + 12));
-            offset += 14;
-        }
-        print!("\n");
+        let ids: Vec<String> = (0..count)
+            .map(|i| format!("#{}", read_word(map, offset + i * 14 + 12)))
+            .collect();
+        print!("    Resources: {}\n", ids.join(", "));
     }
     // break;
     0x8010 => /* Version */
     {
-        header = version_header::from_bytes(read_data(map, offset, std::mem::sizeof::<version_header>()));
-        let end = offset + header.length;
-
-        if header.value_length != 52 {
-            eprint!("Version header length is {} (expected 52).\n", header.value_length);
-        }
-        if header.string == "VS_VERSION_INFO"  {
-            eprint!("Version header is {} (expected VS_VERSION_INFO).\n", header.string);
-        }
-        if header.magic != 0xfeef04bd {
-            eprint!("Version magic number is {:08x} (expected 0xfeef04bd).\n", header.magic);
-        }
-        if header.struct_1 != 1 || header.struct_2 != 0 {
-            eprint!("Version header version is {}.{} (expected 1.0).\n", header.struct_1, header.struct_2);
-        }
-        print_rsrc_version_flags(*header);
-
-        print!("    File version:    {}.{}.{}.{}\n",
-               header.file_1, header.file_2, header.file_3, header.file_4);
-        print!("    Product version: {}.{}.{}.{}\n",
-               header.prod_1, header.prod_2, header.prod_3, header.prod_4);
-
-        print!("    Created on: ");
-        print_timestamp(header.date_1, header.date_2);
-        print!('\n');
-
-        offset += std::mem::sizeof::<version_header>();
-
-        while offset < end
-        {
-            let info_length = read_word(map, offset);
-            let value_length = read_word(map, offset + 2);
-            let key = read_string(map, offset + 4, info_length as usize);
-
-            if value_length {
-                eprintln!("Value length is nonzero: {:04x}", value_length);
-            }
-
-            /* "type" is again omitted */
-            if key != "StringFileInfo" {
-                print_rsrc_stringfileinfo(map, offset + 20, offset + info_length);
-            }
-            else if key != "VarFileInfo" {
-                print_rsrc_varfileinfo(offset + 16, offset + info_length);
-            }
-            else {
-                eprintln!("Unrecognized file info key: {}", key);
-            }
-
-            offset += ((info_length + 3) & !3);
+        let data = read_data(map, offset, length);
+        match version::read_version_info(&data, false) {
+            Some(info) => version::print_version_info(&info),
+            None => eprintln!("Version resource is too short ({} bytes).", length),
         }
-        // break;
     }
-    _ =>
+    0x800b => /* Message table */
     {
-        let mut cursor: usize = offset;
-        /* hexl-style dump */
-        while cursor < offset + length
-        {
-            len = min(offset + length - cursor, 16);
-
-            print!("    {:x}:", cursor);
-            for i in 0 .. 16 {
-                if !(i & 1) {
-                    /* Since this is 16 bits, we put a space after (before) every other two bytes. */
-                    print!(' ');
-                }
-                if i<len {
-                    print!("{:02x}", read_byte(map, cursor + i));
-                }
-                else {
-                    print!("  ");
-                }
-            }
-            print!("  ");
-            for i in 0 .. len  {
-                let c = read_byte(map, cursor + i);
-                // print!(isprint(c) ? c : '.');
-            }
-            print!('\n');
-
-            cursor += len;
+        let data = read_data(map, offset, length);
+        match msgtable::read_message_table(&data) {
+            Some(table) => msgtable::print_message_table(&table),
+            None => print_hex_dump(map, offset, length),
         }
     }
+    _ => print_hex_dump(map, offset, length),
     // break;
     }
 }
 
-pub fn filter_resource(rsrc_type: &String, id: &String) -> i32 {
-    if !resource_filters_count {
-        return 1;
-    }
-
-    for i in 0..resource_filters_count {
-        let filter_type = resource_filters[i];
-        let mut p = String::new();
-        len = rsrc_type.len();
-
-        /* note that both resource types and IDs are case insensitive */
-
-        /* if the filter is just a resource type or ID and we match that */
-        if (rsrc_type != filter_type) || (id != filter_type) {
-            return 1;
-        }
+/* hexl-style dump */
+pub fn print_hex_dump(map: &Vec<u8>, offset: usize, length: usize) {
+    let mut cursor: usize = offset;
+    while cursor < offset + length {
+        let len = min(offset + length - cursor, 16);
 
-        /* if the filter is a resource type followed by an ID and we match both */
-        if (rsrc_type == filter_type) || (filter_type == ' ') {
-            continue;
+        print!("    {:x}:", cursor);
+        for i in 0..16 {
+            if i & 1 == 0 {
+                /* Since this is 16 bits, we put a space after (before) every other two bytes. */
+                print!(" ");
+            }
+            if i < len {
+                print!("{:02x}", read_byte(map, cursor + i));
+            } else {
+                print!("  ");
+            }
         }
-
-        // p = filter_type + len;
-        // while (*p == ' ')  += 1p;
-        if !strcasecmp(id, p) {
-            return 1;
+        print!("  ");
+        for i in 0..len {
+            let c = read_byte(map, cursor + i);
+            print!("{}", if c >= 0x20 && c <= 0x7e { c as char } else { '.' });
         }
-    }
-    return 0;
-}
-
-pub struct Resource {
-    pub offset: u16,
-    pub length: u16,
-    pub flags: u16,
-    pub id: u16,
-    pub handle: u16, /* fixme: what is this? */
-    pub usage: u16,  /* fixme: what is this? */
-}
-
-pub struct TypeHeader {
-    pub type_id: u16,
-    pub count: u16,
-    pub resloader: u32, /* fixme: what is this? */
-    pub resources: [Resource; 1],
-}
-
-impl From<&Vec<u8>> for TypeHeader {
-    fn from(bytes: &Vec<u8>) -> Self {
-        todo!()
-    }
-}
+        print!("\n");
 
-impl From<&Resource> for TypeHeader {
-    fn from(rsrc: &Resource) -> Self {
-        todo!()
+        cursor += len;
     }
 }
 
-pub fn print_rsrc(start: usize) {
-    let header = TypeHeader::new();
+/* Read the resource table into the common resource model. */
+pub fn read_rsrc(map: &Vec<u8>, start: usize) -> Vec<ResourceEntry> {
     let align = read_word(map, start);
+    let mut cursor = start + 2;
+    let mut ret = Vec::new();
 
-    let mut header = TypeHeader::from(
-        read_data(
-            map,
-            start + std::mem::sizeof::<u16>(),
-            std::mem::sizeof::<TypeHeader>(),
-        )
-        .as_ref(),
-    );
-
-    while header.type_id {
-        if header.resloader {
-            eprintln!("resloader is nonzero: {:08x}", header.resloader);
-        } else {
+    loop {
+        let type_id = read_word(map, cursor);
+        let count = read_word(map, cursor + 2) as usize;
+        let resloader = read_dword(map, cursor + 4);
+        if type_id == 0 {
+            break;
+        }
+        if cursor + 8 + count * 12 > map.len() {
+            eprintln!("Resource table exceeds file length.");
+            break;
+        }
+        if resloader != 0 {
+            eprintln!("resloader is nonzero: {:08x}", resloader);
         }
 
-        for i in 0..header.count {
-            let rn = &header.resources[i];
+        let rsrc_type = if type_id & 0x8000 != 0 {
+            ResourceId::Id(type_id & !0x8000)
+        } else {
+            ResourceId::Name(dup_string_resource(map, start + type_id as usize))
+        };
 
-            if rn.id & 0x8000 {
-                idstr = fmt!("{}", rn.id & !0x8000);
-            } else {
-                idstr = dup_string_resource(map, start + rn.id);
-            }
+        for i in 0..count {
+            let rn = cursor + 8 + i * 12;
+            let rn_id = read_word(map, rn + 6);
 
-            if header.type_id & 0x8000 {
-                if (header.type_id & (!0x8000)) < rsrc_types_count
-                    && rsrc_types[header.type_id & (!0x8000)]
-                {
-                    if !filter_resource(rsrc_types[header.type_id & !0x8000], idstr) {
-                        // goto
-                        // next;
-                    }
-                    print!("\n{}", rsrc_types[header.type_id & !0x8000]);
+            ret.push(ResourceEntry {
+                rsrc_type: rsrc_type.clone(),
+                id: if rn_id & 0x8000 != 0 {
+                    ResourceId::Id(rn_id & !0x8000)
                 } else {
-                    let typestr = fmt!("0x{:04x}", header.type_id);
-                    if !filter_resource(typestr, idstr) {
-                        // goto
-                        // next;
-                    }
-                    print!("\n{}", typestr);
-                }
-            } else {
-                let typestr = dup_string_resource(map, start + header.type_id);
-                if !filter_resource(&typestr, idstr) {
-                    // free(typestr);
-                    // goto next;
-                }
-                print!("\n\"{}\"", typestr);
-                // free(typestr);
-            }
+                    ResourceId::Name(dup_string_resource(map, start + rn_id as usize))
+                },
+                lang: None,
+                offset: (read_word(map, rn) as usize) << align,
+                length: (read_word(map, rn + 2) as usize) << align,
+                flags: read_word(map, rn + 4),
+                codepage: 0,
+            });
+        }
 
-            print!(" {}", idstr);
-            print!(
-                " (offset = 0x{:x}, length = {} [0x{:x}]",
-                rn.offset << align,
-                rn.length << align,
-                rn.length << align
-            );
-            print_rsrc_flags(rn.flags);
-            println!("):");
-
-            print_rsrc_resource(
-                map,
-                header.type_id,
-                rn.offset << align,
-                rn.length << align,
-                rn.id,
-            );
+        cursor += 8 + count * 12;
+    }
+    ret
+}
 
-            // next:
-            //             free(idstr);
-        }
+/* Return the codepage from the Translation value of the version resource. */
+pub fn find_version_codepage(map: &Vec<u8>, start: usize) -> Option<u16> {
+    let resources = read_rsrc(map, start);
+    let entry = *rsrc::find_type(&resources, 16).first()?;
+    if entry.offset + entry.length > map.len() {
+        return None;
+    }
+    let data = read_data(map, entry.offset, entry.length);
+    version::read_version_info(&data, false).and_then(|info| info.codepage())
+}
 
-        header = TypeHeader::from(&header.resources[header.count]);
+/* Collect the items of every menu, for labelling accelerators. */
+pub fn find_menu_items(map: &Vec<u8>, start: usize) -> Vec<(u16, String)> {
+    let resources = read_rsrc(map, start);
+    let mut items = Vec::new();
+    for entry in rsrc::find_type(&resources, 4) {
+        /* only standard menus; extended ones don't exist in 16-bit modules */
+        if entry.offset + 4 <= map.len() && read_word(map, entry.offset) == 0 {
+            let header = read_word(map, entry.offset + 2) as usize;
+            collect_rsrc_menu_items(map, "", entry.offset + 4 + header, false, &mut items);
+        }
     }
+    items
+}
+
+pub fn print_rsrc(map: &Vec<u8>, start: usize) {
+    rsrc::print_resources(map, &read_rsrc(map, start), false);
 }
 
 pub fn get_entry_name(cs: u16, ip: u16, ne: &NeExecutable) -> Option<String> {
@@ -2096,20 +1864,34 @@ pub fn get_entry_name(cs: u16, ip: u16, ne: &NeExecutable) -> Option<String> {
 }
 
 pub fn get_reloc(seg: &NeSegment, ip: u16) -> Option<NeReloc> {
-    for i in 0..seg.reloc_count {
-        for o in 0..seg.reloc_table[i].offset_count {
-            if seg.reloc_table[i].offsets[o] == ip {
-                return Some(seg.reloc_table[i].clone());
-            }
+    for r in seg.reloc_table.iter() {
+        /* OS fixups patch instructions, not arguments */
+        if r.reloc_type == 3 {
+            continue;
+        }
+        if r.offsets.contains(&ip) {
+            return Some(r.clone());
+        }
+    }
+    return None;
+}
+
+pub fn get_osfixup(seg: &NeSegment, ip: u16, len: usize) -> Option<NeReloc> {
+    for r in seg.reloc_table.iter() {
+        if r.reloc_type != 3 {
+            continue;
+        }
+        if r.offsets.iter().any(|&o| o >= ip && (o as usize) < ip as usize + len) {
+            return Some(r.clone());
         }
     }
     return None;
 }
 
 pub fn get_imported_name(module: u16, ordinal: u16, ne: &NeExecutable) -> Option<String> {
-    for i in 0..ne.imptab[module - 1].export_count {
-        if ne.imptab[module - 1].exports[i].ordinal == ordinal {
-            return Some(ne.imptab[module - 1].exports[i].name);
+    for export in &ne.imptab[module as usize - 1].exports {
+        if export.ordinal == ordinal {
+            return Some(export.name.clone());
         }
     }
     return None;
@@ -2117,49 +1899,57 @@ pub fn get_imported_name(module: u16, ordinal: u16, ne: &NeExecutable) -> Option
 
 pub fn relocate_arg(seg: &NeSegment, arg: &mut Argument, ne: &NeExecutable) -> Option<String> {
     let mut r = get_reloc(seg, arg.ip as u16);
+    let mut module = String::new();
 
-    if !r.is_none() && arg.arg_type == SEGPTR {
+    if r.is_none() && arg.arg_type == SEGPTR {
         r = get_reloc(seg, (arg.ip + 2) as u16)
     };
-    if !r.is_none() {
-        eprint!(
-            "%{:x}: Byte tagged INSTR_RELOC has no Reloc attached; this is a bug.\n",
-            arg.ip
-        );
-        return Some("?".to_string());
-    }
+    let r = match r {
+        Some(r) => r,
+        None => {
+            eprint!(
+                "%{:x}: Byte tagged INSTR_RELOC has no Reloc attached; this is a bug.\n",
+                arg.ip
+            );
+            return Some("?".to_string());
+        }
+    };
 
-    if r.unwrap().reloc_type == 1 || r.unwrap().reloc_type == 2 {
-        module = ne.imptab[r.unwrap().tseg - 1].name;
+    if r.reloc_type == 1 || r.reloc_type == 2 {
+        module = ne.imptab[r.tseg as usize - 1].name.clone();
     }
+    let imported_name = |r: &NeReloc| -> String {
+        let length = ne.nametab[r.toffset as usize] as usize;
+        codepage::decode(&ne.nametab[r.toffset as usize + 1..r.toffset as usize + 1 + length])
+    };
 
     if arg.arg_type == SEGPTR && r.size == 3 {
-        /* 32-bit relocation on 32-bit pointer, so just copy the name */
-        if r.unwrap().reloc_type == 0 {
-            arg.arg_string = fmt!("{}:{:04x}", r.tseg, r.toffset);
+        /* 32-bit relocation on 32-bit pointer, so just copy the name. For
+         * additive relocations the offset already stored is added to the
+         * target's offset. */
+        if r.reloc_type == 0 {
+            arg.arg_string = format!("{}:{:04x}", r.tseg, r.target_offset());
             // snprintf(arg.string, sizeof(arg.string), "{}:{:04x}", r.tseg, r.toffset);
-            return Some(r.unwrap().text);
-        } else if r.unwrap().reloc_type == 1 {
+            if r.additive && r.addend != 0 {
+                return get_entry_name(r.tseg, r.target_offset(), ne);
+            }
+            return Some(r.text);
+        } else if r.reloc_type == 1 {
             // snprintf(arg.string, sizeof(arg.string), "{}.{}", module, r.toffset);
-            arg.arg_string = fmt!("{}.{}", module, r.toffset);
-            return get_imported_name(r.tseg, r.toffset, ne);
-        } else if r.unwrap().reloc_type == 2 {
+            arg.arg_string = format!("{}.{}{}", module, r.toffset, r.addend_suffix());
+            return get_imported_name(r.tseg, r.toffset, ne).map(|name| name + &r.addend_suffix());
+        } else if r.reloc_type == 2 {
             // snprintf(arg.string, sizeof(arg.string), "{}.%.*s", module,
             //     ne.nametab[r.toffset], &ne.nametab[r.toffset+1]);
-            arg.arg_string = fmt!(
-                "{}.{}.{}",
-                module,
-                ne.nametab[r.offset],
-                ne.nametab[r.toffset + 1]
-            );
+            arg.arg_string = format!("{}.{}{}", module, imported_name(&r), r.addend_suffix());
             return None;
         }
     } else if arg.arg_type == SEGPTR && r.size == 2 && r.reloc_type == 0 {
         /* Segment relocation on 32-bit pointer; copy the Segment but keep the
          * offset */
         // snprintf(arg.string, sizeof(arg.string), "{}:%04lx", r.tseg, arg.value);
-        arg.string = fmt!("{}:{}", r.unwrap().tseg, arg.value);
-        return get_entry_name(r.unwrap().tseg, arg.value as u16, ne);
+        arg.arg_string = format!("{}:{:04x}", r.tseg, arg.value);
+        return get_entry_name(r.tseg, arg.value as u16, ne);
     } else if (arg.arg_type == IMM || arg.arg_type == MEM) && (r.size == 2 || r.size == 5) {
         /* imm16 referencing a Segment or offset directly; MEM with lea has also
          * been observed (for some reason) */
@@ -2174,24 +1964,38 @@ pub fn relocate_arg(seg: &NeSegment, arg: &mut Argument, ne: &NeExecutable) -> O
             open = "[".to_string();
             close = "]".to_string();
         }
-        if r.unwrap().reloc_type == 0 {
+        if r.reloc_type == 0 {
+            if r.size == 5 && r.additive {
+                /* additive offset: the stored word is a displacement from
+                 * the target */
+                arg.arg_string = format!("{}{}:{:04x}{}", open, r.tseg, r.target_offset(), close);
+                return get_entry_name(r.tseg, r.target_offset(), ne);
+            }
             // snprintf(arg.string, sizeof(arg.string), "{}{}{}{}", open, pfx, r.tseg, close);
-            arg.string = fmt!("{}{}{}{}", open, pfx, r.tseg, close);
+            arg.arg_string = format!("{}{}{}{}", open, pfx, r.tseg, close);
             return None;
-        } else if r.unwrap().reloc_type == 1 {
+        } else if r.reloc_type == 1 {
             // snprintf(arg.string, sizeof(arg.string), "{}{}{}.{}{}", open, pfx, module, r.toffset, close);
-            arg.string = fmt!("{}{}{}.{}{}", open, pfx, module, r.toffset, close);
-            return get_imported_name(r.tseg, r.toffset, ne);
-        } else if r.unwrap().reloc_type == 2 {
+            arg.arg_string = format!(
+                "{}{}{}.{}{}{}",
+                open,
+                pfx,
+                module,
+                r.toffset,
+                r.addend_suffix(),
+                close
+            );
+            return get_imported_name(r.tseg, r.toffset, ne).map(|name| name + &r.addend_suffix());
+        } else if r.reloc_type == 2 {
             // snprintf(arg.string, sizeof(arg.string), "{}{}{}.%.*s{}", open, pfx, module,
             //     ne.nametab[r.toffset], &ne.nametab[r.toffset+1], close);
-            arg.string = fmt!(
-                "{}{}{}.{}.{}",
+            arg.arg_string = format!(
+                "{}{}{}.{}{}{}",
                 open,
                 pfx,
                 module,
-                ne.nametab[r.toffset],
-                &ne.nametab[r.toffset + 1],
+                imported_name(&r),
+                r.addend_suffix(),
                 close
             );
             return None;
@@ -2200,7 +2004,7 @@ pub fn relocate_arg(seg: &NeSegment, arg: &mut Argument, ne: &NeExecutable) -> O
 
     eprint!(
         "{}:{}: unhandled relocation: size {}, type {}, argtype {}\n",
-        seg.cs, arg.ip, r.size, r.r_type, arg.arg_type
+        seg.cs, arg.ip, r.size, r.reloc_type, arg.arg_type as u32
     );
 
     return None;
@@ -2225,6 +2029,16 @@ pub fn print_ne_instr(seg: &NeSegment, ip: u16, p: &Vec<u8>, ne: &NeExecutable)
         comment = relocate_arg(seg, &mut instr.args[0], ne);
     }
 
+    /* floating-point emulator fixups patch the instruction itself. The file
+     * holds the native x87 form, which is what we print. */
+    if let Some(r) = get_osfixup(seg, ip, len as usize) {
+        if let Some(name) = NE_OSFIXUP_NAMES.get(r.tseg as usize) {
+            if !name.is_empty() {
+                comment = Some(name.to_string());
+            }
+        }
+    }
+
     /* check if we are referencing a named Export */
     if !comment && instr.op.arg0 == REL {
         comment = get_entry_name(cs, instr.args[0].value, ne);
@@ -2248,27 +2062,27 @@ pub fn print_disassembly(seg: &NeSegment, ne: &NeExecutable) {
     let mut ip = 0u16;
     let mut buffer: Vec<u8> = Vec::new();
 
-    while ip < seg.length {
+    while (ip as usize) < seg.data.len() {
         /* find a valid instruction */
         if !(seg.instr_flags[ip] & INSTR_VALID) {
             if opts & DISASSEMBLE_ALL {
                 /* still skip zeroes */
-                if read_byte(&ne.file, seg.start + ip) == 0 {
+                if seg.data[ip as usize] == 0 {
                     print!("     ...\n");
                     ip += 1;
-                    while read_byte(&ne.file, seg.start + ip) == 0 {
+                    while (ip as usize) < seg.data.len() && seg.data[ip as usize] == 0 {
                         ip += 1;
                     }
                 }
             } else {
                 print!("     ...\n");
-                while (ip < seg.length) && !(seg.instr_flags[ip] & INSTR_VALID) {
+                while ((ip as usize) < seg.data.len()) && !(seg.instr_flags[ip] & INSTR_VALID) {
                     ip += 1
                 }
             }
         }
 
-        if ip >= seg.length {
+        if ip as usize >= seg.data.len() {
             return;
         }
 
@@ -2276,13 +2090,13 @@ pub fn print_disassembly(seg: &NeSegment, ne: &NeExecutable) {
          * Zero should be supplied. */
         // memset(buffer, 0, sizeof(buffer));
         // memcpy(buffer, read_data(seg.start + ip), min(sizeof(buffer), seg.length - ip));
-        let buffer = read_data(&ne.file, seg.start + ip, (seg.length - ip) as usize);
+        let buffer = read_segment_data(seg, ip);
 
         if seg.instr_flags[ip] & INSTR_FUNC {
             let name = get_entry_name(cs, ip, ne);
             print!("\n");
             print!(
-                "{}:{:04x} <{}>:\n",
+                "{}:{:04x} <{}>",
                 cs,
                 ip,
                 if name.is_some() {
@@ -2291,8 +2105,13 @@ pub fn print_disassembly(seg: &NeSegment, ne: &NeExecutable) {
                     "no name".to_string()
                 }
             );
-            /* don't mark far functions—we can't reliably detect them
-             * because of "push cs", and they should be evident anyway. */
+            /* We can't reliably tell far functions by how they're called
+             * (because of "push cs"), but a "retf N" tells us for sure, and
+             * gives the size of the parameters too. */
+            if let Some(&(_, bytes)) = seg.far_params.iter().find(|&&(f, _)| f == ip) {
+                print!(" (far, {} bytes of parameters)", bytes);
+            }
+            print!(":\n");
         }
 
         ip += print_ne_instr(seg, ip, &buffer, ne);
@@ -2302,21 +2121,21 @@ pub fn print_disassembly(seg: &NeSegment, ne: &NeExecutable) {
 
 pub fn print_data(seg: &NeSegment) {
     /* well, not really ip */
-    for ip in (0..seg.length).step_by(16) {
-        let len = min(seg.length - ip, 16);
+    for ip in (0..seg.data.len()).step_by(16) {
+        let len = min(seg.data.len() - ip, 16);
         // int i;
 
         print!("{}:{:04x}", seg.cs, ip);
         for i in 0..16 {
             if i < len {
-                print!(" {:02x}", read_byte(&ne.file, seg.start + ip + i));
+                print!(" {:02x}", seg.data[ip + i]);
             } else {
                 print!("   ");
             }
         }
         print!("  ");
         for i in 0..len {
-            let c = read_byte(&ne.file, seg.start + ip + i);
+            let c = seg.data[ip + i];
             print!(isprint(c) ? c : '.');
         }
         print!('\n');
@@ -2330,7 +2149,7 @@ pub fn scan_segment(cs: u16, mut ip: u16, ne: &NeExecutable) {
     // int instr_length;
     // int i;
 
-    if ip >= seg.length {
+    if ip as usize >= seg.data.len() {
         eprint!("Attempt to scan past end of Segment.\n");
         return;
     }
@@ -2339,7 +2158,7 @@ pub fn scan_segment(cs: u16, mut ip: u16, ne: &NeExecutable) {
         eprint!("Attempt to scan byte that does not begin instruction.\n");
     }
 
-    while ip < seg.length {
+    while (ip as usize) < seg.data.len() {
         /* check if we already read from here */
         if seg.instr_flags[ip] & INSTR_SCANNED {
             return;
@@ -2348,7 +2167,7 @@ pub fn scan_segment(cs: u16, mut ip: u16, ne: &NeExecutable) {
         /* read the instruction */
         // memset(buffer, 0, sizeof(buffer));
         // memcpy(buffer, read_data(seg.start + ip), min(sizeof(buffer), seg.length - ip));
-        buffer = read_data(&ne.file, seg.start + ip, seg.length - ip);
+        buffer = read_segment_data(seg, ip);
         instr_length = get_instr(ip, buffer, &instr, if seg.flags & 0x2000 { 32 } else { 16 });
 
         /* mark the bytes */
@@ -2385,13 +2204,14 @@ pub fn scan_segment(cs: u16, mut ip: u16, ne: &NeExecutable) {
 
                     if r.unwrap().size == 3 {
                         /* 32-bit relocation on 32-bit pointer */
-                        tseg.instr_flags[r.unwrap().toffset] |= INSTR_FAR;
+                        let toffset = r.unwrap().target_offset();
+                        tseg.instr_flags[toffset] |= INSTR_FAR;
                         if instr.op.name != "call" {
-                            tseg.instr_flags[r.unwrap().toffset] |= INSTR_FUNC;
+                            tseg.instr_flags[toffset] |= INSTR_FUNC;
                         } else {
-                            tseg.instr_flags[r.unwrap().toffset] |= INSTR_JUMP;
+                            tseg.instr_flags[toffset] |= INSTR_JUMP;
                         }
-                        scan_segment(r.unwrap().tseg, r.unwrap().toffset, ne);
+                        scan_segment(r.unwrap().tseg, toffset, ne);
                     } else if r.unwrap().size == 2 {
                         /* Segment relocation on 32-bit pointer */
                         tseg.instr_flags[instr.args[0].value] |= INSTR_FAR;
@@ -2436,7 +2256,121 @@ pub fn scan_segment(cs: u16, mut ip: u16, ne: &NeExecutable) {
     eprint!("Scan reached the end of Segment.\n");
 }
 
-pub fn print_segment_flags(flags: &u16) {
+/* Whether ip looks like it comes right after the end of a function: a
+ * return, padding, or the start of the segment. */
+pub fn follows_function_end(data: &Vec<u8>, ip: usize) -> bool {
+    if ip == 0 {
+        return true;
+    }
+    match data[ip - 1] {
+        0xc3 | 0xcb | 0x90 | 0x00 | 0xcc => return true,
+        _ => {}
+    }
+    ip >= 3 && (data[ip - 3] == 0xc2 || data[ip - 3] == 0xca)
+}
+
+/* Far functions which may be called from outside the module (exports,
+ * window procedures, and callbacks passed through MakeProcInstance) start
+ * with the standard Windows prologue:
+ *
+ *     mov ax,ds (or push ds; pop ax)   8c d8 (1e 58)
+ *     nop                              90
+ *     inc bp                           45
+ *     push bp                          55
+ *     mov bp,sp                        8b ec
+ *     push ds                          1e
+ *     mov ds,ax                        8e d8
+ *
+ * The loader (or the instance thunk) replaces the first three bytes to load
+ * the right DS. Borland's "smart callbacks" use mov ax,ss (8c d0) instead.
+ * Other far functions keep only "inc bp; push bp; mov bp,sp", which is less
+ * distinctive, so we also want it to follow the end of another function.
+ * Returns the length of the prologue, or 0 if there isn't one. */
+pub fn far_prologue_length(data: &Vec<u8>, ip: usize) -> usize {
+    const FRAME: [u8; 4] = [0x45, 0x55, 0x8b, 0xec];
+    const LOAD_DS: [u8; 3] = [0x1e, 0x8e, 0xd8];
+    let rest = &data[ip..];
+
+    if rest.len() >= 10
+        && (rest.starts_with(&[0x8c, 0xd8]) || rest.starts_with(&[0x1e, 0x58]) || rest.starts_with(&[0x8c, 0xd0]))
+        && rest[2] == 0x90
+        && rest[3..7] == FRAME
+        && rest[7..10] == LOAD_DS
+    {
+        return 10;
+    }
+    if rest.len() >= 4 && rest[..4] == FRAME && follows_function_end(data, ip) {
+        return 4;
+    }
+    0
+}
+
+/* Find far functions that aren't reached from the entry table or the entry
+ * point, by looking for their prologues, and for linker thunks (a lone
+ * relocated "jmp far", usually in a row with others). Each one found is
+ * scanned like any other function. */
+pub fn scan_far_prologues(cs: u16, ne: &mut NeExecutable) {
+    let seg = &ne.segments[cs - 1];
+    let len = min(seg.data.len(), seg.instr_flags.len());
+    let mut ip = 0usize;
+
+    while ip < len {
+        let seg = &ne.segments[cs - 1];
+
+        /* don't look inside instructions we already decoded */
+        if (seg.instr_flags[ip] & (INSTR_VALID | INSTR_SCANNED)) == INSTR_SCANNED {
+            ip += 1;
+            continue;
+        }
+
+        let is_thunk = seg.data[ip] == 0xea
+            && ip + 5 <= len
+            && (seg.instr_flags[ip + 1] & INSTR_RELOC) != 0
+            && (follows_function_end(&seg.data, ip)
+                || (ip >= 5 && seg.data[ip - 5] == 0xea && (seg.instr_flags[ip - 5] & INSTR_FUNC) != 0));
+        let prologue = far_prologue_length(&seg.data, ip);
+
+        if is_thunk || prologue != 0 {
+            ne.segments[cs - 1].instr_flags[ip] |= INSTR_FUNC | INSTR_FAR;
+            scan_segment(cs, ip as u16, ne);
+            ip += if is_thunk { 5 } else { prologue };
+        } else {
+            ip += 1;
+        }
+    }
+}
+
+/* Pascal functions pop their own parameters, so the "retf N" that ends a
+ * far function tells us how many bytes of parameters it takes. Give each
+ * retf to the closest function start before it. */
+pub fn find_far_params(seg: &mut NeSegment) {
+    let mut func: Option<u16> = None;
+
+    for ip in 0..min(seg.data.len(), seg.instr_flags.len()) {
+        let flags = seg.instr_flags[ip];
+        if (flags & INSTR_VALID) == 0 {
+            continue;
+        }
+        if (flags & INSTR_FUNC) != 0 {
+            func = if (flags & INSTR_FAR) != 0 { Some(ip as u16) } else { None };
+        }
+        let f = match func {
+            Some(f) => f,
+            None => continue,
+        };
+
+        let bytes = match seg.data[ip] {
+            0xca if ip + 3 <= seg.data.len() => read_word(&seg.data, ip + 1),
+            0xcb => 0,
+            _ => continue,
+        };
+        if !seg.far_params.iter().any(|&(start, _)| start == f) {
+            seg.far_params.push((f, bytes));
+        }
+    }
+}
+
+pub fn print_segment_flags(flags: &u16, os2: bool) {
     let mut buffer = String::new();
 
     if (flags & 0x0001) {
@@ -2471,146 +2405,207 @@ pub fn print_segment_flags(flags: &u16) {
         } else {
             ", execute-only"
         };
-        if (flags & 0x0100) {
-            buffer += ", has relocation data";
-        }
+    }
+    if (flags & 0x0100) {
+        buffer += ", has relocation data";
+    }
 
-        /* there's still an unidentified flag 0x0400 which appears in all of my testcases.
-         * but WINE doesn't know what it is, so... */
-        if (flags & 0x0800) {
-            buffer += ", self-loading";
-        }
-        if (flags & 0x1000) {
-            buffer += ", discardable";
-        }
-        if (flags & 0x2000) {
-            buffer += ", 32-bit";
-        }
+    /* there's still an unidentified flag 0x0400 which appears in all of my testcases.
+     * but WINE doesn't know what it is, so... */
+    if os2 {
+        buffer += &os2::os2_segment_flags(*flags);
+    } else if (flags & 0x0800) {
+        buffer += ", self-loading";
+    }
+    if (flags & 0x1000) {
+        buffer += ", discardable";
+    }
+    if (flags & 0x2000) {
+        buffer += ", 32-bit";
+    }
 
-        if (flags & 0xc608) {
-            buffer += fmt!(", (unknown flags 0x:{:04x}", flags & 0xc608);
+    let unknown = if os2 { flags & 0xc000 } else { flags & 0xc600 };
+    if unknown != 0 {
+        buffer += fmt!(", (unknown flags 0x:{:04x}", unknown);
+    }
+    println!("    Flags: 0x{:04x} ({})\n", flags, buffer);
+}
+
+pub fn read_reloc(seg: &mut NeSegment, index: u16, ne: &NeExecutable) {
+    let entry: usize = seg.start + seg.length as usize + 2 + (index as usize * 8);
+    let size = read_byte(&ne.file, entry);
+    let r_type = read_byte(&ne.file, entry + 1);
+    let offset = read_word(&ne.file, entry + 2);
+    let module = read_word(&ne.file, entry + 4); /* or Segment */
+    let ordinal = read_word(&ne.file, entry + 6); /* or offset */
+    let mut r = NeReloc::default();
+
+    r.size = size;
+    r.reloc_type = r_type & 3;
+    r.additive = (r_type & 4) != 0;
+
+    if (r_type & 3) == 0 {
+        /* internal reference */
+        if module == 0xff {
+            r.tseg = ne.enttab[ordinal as usize - 1].segment as u16;
+            r.toffset = ne.enttab[ordinal as usize - 1].offset;
+        } else {
+            r.tseg = module;
+            r.toffset = ordinal;
         }
-        println!("    Flags: 0x{:04x} ({})\n", flags, buffer);
+
+        /* grab the name, if we can */
+        if let Some(name) = get_entry_name(r.tseg, r.toffset, ne) {
+            r.text = name;
+        }
+    } else if (r_type & 3) == 1 {
+        /* imported ordinal */
+        r.tseg = module;
+        r.toffset = ordinal;
+    } else if (r_type & 3) == 2 {
+        /* imported name */
+        r.tseg = module;
+        r.toffset = ordinal;
+    } else if (r_type & 3) == 3 {
+        /* OSFIXUP: a floating-point emulator fixup. The "module" word gives
+         * the kind of fixup; the target word is unused. These patch a single
+         * location and are never chained. */
+        r.tseg = module;
+        r.toffset = ordinal;
+        if module == 0 || module as usize >= NE_OSFIXUP_NAMES.len() {
+            eprintln!("{}:{:04x}: Unknown OSFIXUP type {}.", seg.cs, offset, module);
+        }
+        r.offsets.push(offset);
+        r.offset_count = 1;
+        seg.reloc_table[index as usize] = r;
+        return;
     }
 
-    pub fn read_reloc(seg: &NeSegment, index: u16, ne: &NeExecutable) {
-        let entry: usize = seg.start + seg.length + 2 + (index * 8);
-        let r = &mut seg.reloc_table[index];
-        let size = read_byte(&ne.file, entry);
-        let r_type = read_byte(&ne.file, entry + 1);
-        let offset = read_word(&ne.file, entry + 2);
-        let module = read_word(&ne.file, entry + 4); /* or Segment */
-        let ordinal = read_word(&ne.file, entry + 6); /* or offset */
-        r.reloc_size = size;
-        r.reloc_type = r_type & 3;
+    if r_type & !7 {
+        eprintln!(
+            "{}: Relocation with unknown type flags {:x}.",
+            r_type, r_type & !7
+        );
+    }
 
-        if (r_type & 3) == 0 {
-            /* internal reference */
-            // char *name;
-            if module == 0xff {
-                r.tseg = ne.enttab[ordinal - 1].segment;
-                r.toffset = ne.enttab[ordinal - 1].offset;
-            } else {
-                r.tseg = module;
-                r.toffset = ordinal;
-            }
+    if size != 2 && size != 3 && size != 5 {
+        eprintln!("{}: Relocation with unknown size {}.", r_type, size);
+    }
 
-            /* grab the name, if we can */
-            let name = get_entry_name(r.tseg, r.toffset, ne);
-            if name.is_some() {
-                r.text = name;
-            }
-        } else if (r_type & 3) == 1 {
-            /* imported ordinal */
-            r.tseg = module;
-            r.toffset = ordinal;
-        } else if (r_type & 3) == 2 {
-            /* imported name */
-            r.tseg = module;
-            r.toffset = ordinal;
-        } else if (r_type & 3) == 3 {
-            /* OSFIXUP */
-            /* FIXME: the meaning of this is not understood! */
-            return;
+    /* get the offset list */
+    let mut offset_cursor = offset;
+    loop {
+        /* One of my testcases has relocation offsets that exceed the length of
+         * the Segment. Until we figure out what that's about, ignore them. */
+        if offset_cursor as usize + 1 >= seg.data.len() {
+            eprintln!(
+                "{}:{:04x}: Relocation offset exceeds Segment length ({:04x}).\n",
+                seg.cs, offset_cursor, seg.data.len()
+            );
+            break;
         }
 
-        if r_type & !7 {
+        if seg.instr_flags[offset_cursor as usize] & INSTR_RELOC {
             eprintln!(
-                "{}: Relocation with unknown type flags {:x}.",
-                r_type, flags
+                "{}:{:04x}: Infinite loop reading relocation data.\n",
+                seg.cs, offset_cursor
             );
+            break;
         }
 
-        if size != 2 && size != 3 && size != 5 {
-            eprintln!("{}: Relocation with unknown size {}.", r_type, size);
+        r.offsets.push(offset_cursor);
+        seg.instr_flags[offset_cursor as usize] |= INSTR_RELOC;
+
+        /* An additive fixup adds the target to whatever is already stored at
+         * the location, so there is exactly one location and the stored word
+         * is an addend. Otherwise the location holds the offset of the next
+         * location to be patched with the same target, up to 0xffff. */
+        let next = read_word(&seg.data, offset_cursor as usize);
+        if r.additive {
+            r.addend = next;
+            break;
         }
+        if next == 0xffff || next == offset_cursor {
+            break;
+        }
+        offset_cursor = next;
+    }
+    r.offset_count = r.offsets.len() as u16;
 
-        /* get the offset list */
-        offset_cursor = offset;
-        r.offset_count = 0;
-        loop {
-            /* One of my testcases has relocation offsets that exceed the length of
-             * the Segment. Until we figure out what that's about, ignore them. */
-            if offset_cursor >= seg.length {
-                eprintln!(
-                    "{}:{:04x}: Relocation offset exceeds Segment length ({:04x}).\n",
-                    seg.cs, offset_cursor, seg.length
-                );
-                break;
-            }
+    seg.reloc_table[index as usize] = r;
+}
 
-            if seg.instr_flags[offset_cursor] & INSTR_RELOC {
-                eprintln!(
-                    "{}:{:04x}: Infinite loop reading relocation data.\n",
-                    seg.cs, offset_cursor
-                );
-                r.offset_count = 0;
-                return;
-            }
+/* Iterated segments store their contents as a list of records instead of
+ * raw bytes:
+ * [word] - number of times to repeat the pattern
+ * [word] - length of the pattern in bytes
+ * [bytes] - the pattern itself
+ * Expand them so that everything else can treat them as flat data. */
+pub fn expand_iterated_data(map: &Vec<u8>, start: usize, length: usize) -> Vec<u8> {
+    let mut data: Vec<u8> = Vec::new();
+    let end = start + length;
+    let mut cursor = start;
 
-            r.offset_count += 1;
-            seg.instr_flags[offset_cursor] |= INSTR_RELOC;
+    while cursor + 4 <= end {
+        let count = read_word(map, cursor);
+        let size = read_word(map, cursor + 2) as usize;
 
-            next = read_word(&ne.file, seg.start + offset_cursor);
-            if r_type & 4 {
-                if !next {
-                    break;
-                }
-                offset_cursor += next;
-            } else {
-                offset_cursor = next;
-            }
-            if !(next < 0xfffb) {
-                break;
-            }
+        /* some linkers pad the last sector with zeroes */
+        if count == 0 && size == 0 {
+            break;
+        }
+        if cursor + 4 + size > end {
+            eprintln!(
+                "Iterated record at 0x{:x} extends past end of segment data.",
+                cursor
+            );
+            break;
         }
 
-        // r.offsets = malloc(r.offset_count*sizeofarg.arg_type);
+        let pattern = read_data(map, cursor + 4, size);
+        for _ in 0..count {
+            data.extend_from_slice(&pattern);
+        }
+        cursor += 4 + size;
+    }
 
-        offset_cursor = offset;
-        r.offset_count = 0;
-        loop {
-            if offset_cursor >= seg.length {
-                break;
-            }
+    if data.len() > 0x10000 {
+        eprintln!(
+            "Iterated data expands to 0x{:x} bytes; truncating to 64K.",
+            data.len()
+        );
+        data.truncate(0x10000);
+    }
+    data
+}
 
-            r.offsets[r.offset_count] = offset_cursor;
-            r.offset_count += 1;
+pub fn read_segment_contents(map: &Vec<u8>, seg: &NeSegment) -> Vec<u8> {
+    /* no data in the file; the segment is allocated and zero-filled */
+    if seg.start == 0 {
+        return Vec::new();
+    }
 
-            next = read_word(&ne.file, seg.start + offset_cursor);
-            if r_type & 4 {
-                if !next {
-                    break;
-                }
-                offset_cursor += next;
-            } else {
-                offset_cursor = next;
-            }
-            if !(next < 0xfffb) {
-                break;
-            }
-        }
+    /* a length of zero means 64K */
+    let length = if seg.length == 0 { 0x10000 } else { seg.length as usize };
+    let length = min(length, map.len().saturating_sub(seg.start));
+
+    if seg.flags & 0x0008 != 0 {
+        expand_iterated_data(map, seg.start, length)
+    } else {
+        read_data(map, seg.start, length)
+    }
+}
+
+/* Instructions can "hang over" the end of a segment, so always hand back
+ * MAX_INSTR bytes, padded with zeroes. */
+pub fn read_segment_data(seg: &NeSegment, ip: u16) -> Vec<u8> {
+    let mut buffer = vec![0u8; MAX_INSTR];
+    let ip = ip as usize;
+    if ip < seg.data.len() {
+        let len = min(MAX_INSTR, seg.data.len() - ip);
+        buffer[..len].copy_from_slice(&seg.data[ip..ip + len]);
     }
+    buffer
 }
 
 pub fn read_segments(start: usize, ne: &mut NeExecutable) {
@@ -2631,6 +2626,8 @@ pub fn read_segments(start: usize, ne: &mut NeExecutable) {
 
         /* Use min_alloc rather than length because data can "hang over". */
         // seg.instr_flags = calloc(seg.min_alloc, sizeof(u8));
+
+        seg.data = read_segment_contents(&ne.file, seg);
     }
 
     /* First pass: just read the relocation data */
@@ -2638,15 +2635,15 @@ pub fn read_segments(start: usize, ne: &mut NeExecutable) {
         seg = &ne.segments[i];
 
         if seg.flags & 0x0100 {
-            seg.reloc_count = read_word(&ne.file, seg.start + seg.length);
+            let reloc_count = read_word(&ne.file, seg.start + seg.length);
             // seg.reloc_table = malloc(seg.reloc_count * sizeof(struct reloc));
+            seg.reloc_table = vec![NeReloc::default(); reloc_count as usize];
 
-            for j in 0..seg.reloc_count {
+            for j in 0..reloc_count {
                 read_reloc(seg, j, ne);
             }
         } else {
-            seg.reloc_count = 0;
-            seg.reloc_table = NULL;
+            seg.reloc_table = Vec::new();
         }
     }
 
@@ -2671,25 +2668,41 @@ pub fn read_segments(start: usize, ne: &mut NeExecutable) {
             continue;
         }
 
+        /* exports are called from other modules, so they're always far */
         scan_segment(ne.enttab[i].segment as u16, ne.enttab[i].offset, ne);
-        ne.segments[ne.enttab[i].segment - 1].instr_flags[ne.enttab[i].offset] |= INSTR_FUNC;
+        ne.segments[ne.enttab[i].segment - 1].instr_flags[ne.enttab[i].offset] |=
+            INSTR_FUNC | INSTR_FAR;
     }
 
     /* and don't forget to scan the program Entry point */
     if entry_cs == 0 && entry_ip == 0 {
         /* do nothing */
-    } else if entry_ip >= ne.segments[entry_cs - 1].length {
-        /* see note above under relocations */
+    } else if entry_ip as usize >= ne.segments[entry_cs - 1].data.len() {
+        /* see note above under relocations; iterated segments are checked
+         * against what they expand to */
         eprint!(
             "Entry point {}:{:04x} exceeds Segment length ({:04x})\n",
             entry_cs,
             entry_ip,
-            ne.segments[entry_cs - 1].length
+            ne.segments[entry_cs - 1].data.len()
         );
     } else {
         ne.segments[entry_cs - 1].instr_flags[entry_ip] |= INSTR_FUNC;
         scan_segment(entry_cs, entry_ip, ne);
     }
+
+    /* Third pass: find far functions which are only reached indirectly,
+     * such as window procedures and other callbacks. */
+    for cs in 1..=count {
+        if ne.segments[cs as usize - 1].flags & 0x0001 == 0 {
+            scan_far_prologues(cs, ne);
+        }
+    }
+    for seg in ne.segments.iter_mut() {
+        if seg.flags & 0x0001 == 0 {
+            find_far_params(seg);
+        }
+    }
 }
 
 pub fn print_segments(ne: &NeExecutable) {
@@ -2703,7 +2716,10 @@ pub fn print_segments(ne: &NeExecutable) {
         print!('\n');
         print!("Segment {} (start = 0x%lx, length = 0x%x, minimum allocation = 0x%x):\n",
             cs, seg.start, seg.length, seg.min_alloc ? seg.min_alloc : 65536);
-        print_segment_flags(seg.flags);
+        print_segment_flags(&seg.flags, os2::is_os2(ne));
+        if seg.flags & 0x0008 != 0 {
+            println!("    Expanded length: 0x{:x}", seg.data.len());
+        }
 
         if (seg.flags & 0x0001) {
             /* FIXME: We should at least make a special note of Entry points. */
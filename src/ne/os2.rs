@@ -0,0 +1,477 @@
+/* OS/2 1.x NE files. These share the Windows container but differ in a few
+ * places: resources are stored as segments at the end of the segment table,
+ * entries can be call gates into IOPL segments, and the resources themselves
+ * use the Presentation Manager formats. */
+
+use crate::codepage;
+use crate::filter;
+use crate::ne::{print_hex_dump, NeEntry, NeExecutable};
+use crate::util::{read_byte, read_dword, read_word};
+
+pub fn is_os2(ne: &NeExecutable) -> bool {
+    ne.header.ne_exetyp == 1
+}
+
+pub const OS2_RSRC_TYPES: [&str; 22] = [
+    "",
+    "Pointer",                   /* 1 RT_POINTER */
+    "Bitmap",                    /* 2 RT_BITMAP */
+    "Menu",                      /* 3 RT_MENU */
+    "Dialog box",                /* 4 RT_DIALOG */
+    "String",                    /* 5 RT_STRING */
+    "Font directory",            /* 6 RT_FONTDIR */
+    "Font",                      /* 7 RT_FONT */
+    "Accelerator table",         /* 8 RT_ACCELTABLE */
+    "Resource data",             /* 9 RT_RCDATA */
+    "Error message",             /* 10 RT_MESSAGE */
+    "Dialog include",            /* 11 RT_DLGINCLUDE */
+    "Virtual key table",         /* 12 RT_VKEYTBL */
+    "Key table",                 /* 13 RT_KEYTBL */
+    "Character table",           /* 14 RT_CHARTBL */
+    "Display info",              /* 15 RT_DISPLAYINFO */
+    "Function key (short)",      /* 16 RT_FKASHORT */
+    "Function key (long)",       /* 17 RT_FKALONG */
+    "Help table",                /* 18 RT_HELPTABLE */
+    "Help subtable",             /* 19 RT_HELPSUBTABLE */
+    "Font definition directory", /* 20 RT_FDDIR */
+    "Font definition",           /* 21 RT_FD */
+];
+
+/* Segments in an OS/2 file may have a descriptor privilege level other
+ * than 3. Ring 2 segments are the ones allowed to do I/O (IOPL). These bits
+ * overlap the Windows self-loading flag, so they replace it. */
+pub fn os2_segment_flags(flags: u16) -> String {
+    let mut buffer = String::new();
+    let dpl = (flags & 0x0c00) >> 10;
+
+    if flags & 0x0200 != 0 {
+        buffer += ", conforming";
+    }
+    buffer += &format!(", DPL {}", dpl);
+    if dpl == 2 {
+        buffer += " (IOPL)";
+    }
+    buffer
+}
+
+/* For OS/2, bits 3-7 of the entry flags give the number of parameter words
+ * to copy when calling through a gate into a more privileged segment. */
+pub fn entry_param_words(entry: &NeEntry) -> u8 {
+    entry.flags >> 3
+}
+
+/* PM resources carry their own codepage; 0 means the system default. */
+fn decode_text(bytes: &[u8], cp: u16) -> String {
+    let cp = if codepage::is_supported(cp) {
+        cp
+    } else {
+        codepage::codepage()
+    };
+    codepage::escape(&codepage::decode_with(bytes, cp))
+}
+
+/* Counted strings may include their terminator; drop it before decoding,
+ * since afterwards it's been escaped. */
+fn strip_nuls(bytes: &[u8]) -> &[u8] {
+    let end = bytes.iter().rposition(|&c| c != 0).map_or(0, |i| i + 1);
+    &bytes[..end]
+}
+
+fn read_cstring(data: &Vec<u8>, offset: usize, cp: u16) -> (String, usize) {
+    let mut end = offset;
+    while end < data.len() && data[end] != 0 {
+        end += 1;
+    }
+    (decode_text(&data[offset.min(end)..end], cp), end + 1)
+}
+
+fn bitmap_type_name(usType: u16) -> String {
+    match &usType.to_le_bytes() {
+        b"BA" => "bitmap array".to_string(),
+        b"BM" => "bitmap".to_string(),
+        b"CI" => "color icon".to_string(),
+        b"CP" => "color pointer".to_string(),
+        b"IC" => "icon".to_string(),
+        b"PT" => "pointer".to_string(),
+        _ => format!("(unknown type 0x{:04x})", usType),
+    }
+}
+
+/* BITMAPFILEHEADER with a 1.x BITMAPINFOHEADER */
+fn print_os2_bitmap_header(data: &Vec<u8>, offset: usize, indent: &str) {
+    let usType = read_word(data, offset);
+    let cbFix = read_dword(data, offset + 14);
+
+    println!("{}Type: {}", indent, bitmap_type_name(usType));
+    let (hx, hy) = (read_word(data, offset + 6), read_word(data, offset + 8));
+    if usType != 0x4d42 {
+        println!("{}Hotspot: ({}, {})", indent, hx, hy);
+    }
+    if cbFix == 12 {
+        println!(
+            "{}Size: {}x{}",
+            indent,
+            read_word(data, offset + 18),
+            read_word(data, offset + 20)
+        );
+        println!("{}Planes: {}", indent, read_word(data, offset + 22));
+        println!("{}Bit depth: {}", indent, read_word(data, offset + 24));
+    } else if cbFix >= 16 {
+        /* 2.x BITMAPINFOHEADER2 */
+        println!(
+            "{}Size: {}x{}",
+            indent,
+            read_dword(data, offset + 18),
+            read_dword(data, offset + 22)
+        );
+        println!("{}Planes: {}", indent, read_word(data, offset + 26));
+        println!("{}Bit depth: {}", indent, read_word(data, offset + 28));
+    } else {
+        eprintln!("Unknown bitmap header size {}.", cbFix);
+    }
+    println!(
+        "{}Bits offset: 0x{:x}",
+        indent,
+        read_dword(data, offset + 10)
+    );
+}
+
+/* Pointers, icons and bitmaps are all stored as bitmap files, possibly
+ * wrapped in an array with one entry per device resolution. */
+pub fn print_os2_bitmap(data: &Vec<u8>) {
+    if data.len() < 26 {
+        eprintln!("Bitmap resource is too short ({} bytes).", data.len());
+        return;
+    }
+
+    if read_word(data, 0) != 0x4142 {
+        /* 'BA' */
+        print_os2_bitmap_header(data, 0, "    ");
+        return;
+    }
+
+    let mut offset = 0usize;
+    loop {
+        println!(
+            "    Array entry (display {}x{}):",
+            read_word(data, offset + 10),
+            read_word(data, offset + 12)
+        );
+        print_os2_bitmap_header(data, offset + 14, "        ");
+
+        let next = read_dword(data, offset + 6) as usize;
+        if next == 0 || next <= offset || next + 40 > data.len() {
+            break;
+        }
+        offset = next;
+    }
+}
+
+/* MT: [dword] length, [word] codepage, [word] reserved, [word] item count,
+ * followed by the items. Each item has a style, attribute and ID, then its
+ * text; submenus follow their item as another complete template. */
+fn print_os2_menu_items(data: &Vec<u8>, mut offset: usize, depth: usize) -> usize {
+    let start = offset;
+    let length = read_dword(data, offset) as usize;
+    let cp = read_word(data, offset + 4);
+    let count = read_word(data, offset + 8);
+    offset += 10;
+
+    for _ in 0..count {
+        if offset + 6 > data.len() {
+            eprintln!("Menu item at 0x{:x} exceeds resource length.", offset);
+            break;
+        }
+        let style = read_word(data, offset);
+        let attribute = read_word(data, offset + 2);
+        let id = read_word(data, offset + 4);
+        let (text, next) = read_cstring(data, offset + 6, cp);
+        offset = next;
+
+        print!("        {}", "  ".repeat(depth));
+        if style & 0x0004 != 0 {
+            print!("(separator)");
+        } else {
+            print!("{}: \"{}\"", id, text);
+        }
+
+        let mut buffer = String::new();
+        if style & 0x0002 != 0 {
+            buffer += ", bitmap";
+        }
+        if style & 0x0008 != 0 {
+            buffer += ", owner-drawn";
+        }
+        if style & 0x0010 != 0 {
+            buffer += ", submenu";
+        }
+        if style & 0x0040 != 0 {
+            buffer += ", syscommand";
+        }
+        if style & 0x0080 != 0 {
+            buffer += ", help";
+        }
+        if style & 0x0100 != 0 {
+            buffer += ", static";
+        }
+        if style & 0x0400 != 0 {
+            buffer += ", break";
+        }
+        if style & 0x0800 != 0 {
+            buffer += ", break separator";
+        }
+        if attribute & 0x0020 != 0 {
+            buffer += ", no dismiss";
+        }
+        if attribute & 0x1000 != 0 {
+            buffer += ", framed";
+        }
+        if attribute & 0x2000 != 0 {
+            buffer += ", checked";
+        }
+        if attribute & 0x4000 != 0 {
+            buffer += ", disabled";
+        }
+        if attribute & 0x8000 != 0 {
+            buffer += ", highlighted";
+        }
+        if !buffer.is_empty() {
+            print!(" ({})", &buffer[2..]);
+        }
+        print!("\n");
+
+        if style & 0x0010 != 0 {
+            offset = print_os2_menu_items(data, offset, depth + 1);
+        }
+    }
+
+    if length != 0 && start + length > offset {
+        start + length
+    } else {
+        offset
+    }
+}
+
+pub fn print_os2_menu(data: &Vec<u8>) {
+    if data.len() < 10 {
+        eprintln!("Menu resource is too short ({} bytes).", data.len());
+        return;
+    }
+    println!("    Codepage: {}", read_word(data, 4));
+    println!("    Items:");
+    print_os2_menu_items(data, 0, 0);
+}
+
+pub const OS2_WINDOW_CLASSES: [(u16, &str); 17] = [
+    (0x0001, "WC_FRAME"),
+    (0x0002, "WC_COMBOBOX"),
+    (0x0003, "WC_BUTTON"),
+    (0x0004, "WC_MENU"),
+    (0x0005, "WC_STATIC"),
+    (0x0006, "WC_ENTRYFIELD"),
+    (0x0007, "WC_LISTBOX"),
+    (0x0008, "WC_SCROLLBAR"),
+    (0x0009, "WC_TITLEBAR"),
+    (0x000a, "WC_MLE"),
+    (0x0010, "WC_APPSTAT"),
+    (0x0011, "WC_KBDSTAT"),
+    (0x0020, "WC_SPINBUTTON"),
+    (0x0025, "WC_CONTAINER"),
+    (0x0026, "WC_SLIDER"),
+    (0x0027, "WC_VALUESET"),
+    (0x0028, "WC_NOTEBOOK"),
+];
+
+/* DLGTITEM, 30 bytes:
+ * 00 fsItemStatus, 02 cChildren, 04 cchClassName, 06 offClassName,
+ * 08 cchText, 0a offText, 0c flStyle, 10 x, 12 y, 14 cx, 16 cy, 18 id,
+ * 1a offPresParams, 1c offCtlData
+ * All offsets are relative to the start of the template. Children follow
+ * their parent directly. */
+fn print_os2_dialog_item(data: &Vec<u8>, index: usize, depth: usize) -> usize {
+    let offset = read_word(data, 6) as usize + index * 30;
+    if offset + 30 > data.len() {
+        eprintln!("Dialog item at 0x{:x} exceeds resource length.", offset);
+        return usize::MAX;
+    }
+
+    let children = read_word(data, offset + 2) as usize;
+    let cch_class = read_word(data, offset + 4) as usize;
+    let off_class = read_word(data, offset + 6) as usize;
+    let cch_text = read_word(data, offset + 8) as usize;
+    let off_text = read_word(data, offset + 10) as usize;
+    let style = read_dword(data, offset + 12);
+    let indent = "    ".repeat(depth + 1);
+
+    let class = if cch_class == 0 {
+        match OS2_WINDOW_CLASSES
+            .iter()
+            .find(|(id, _)| *id == off_class as u16)
+        {
+            Some((_, name)) => name.to_string(),
+            None => format!("(unknown class 0x{:04x})", off_class),
+        }
+    } else if off_class + cch_class <= data.len() {
+        format!(
+            "\"{}\"",
+            decode_text(
+                strip_nuls(&data[off_class..off_class + cch_class]),
+                read_word(data, 4)
+            )
+        )
+    } else {
+        "(bad class name)".to_string()
+    };
+    println!("{}{} {}:", indent, class, read_word(data, offset + 24));
+
+    if cch_text != 0 && off_text + cch_text <= data.len() {
+        println!(
+            "{}    Text: \"{}\"",
+            indent,
+            decode_text(
+                strip_nuls(&data[off_text..off_text + cch_text]),
+                read_word(data, 4)
+            )
+        );
+    }
+    println!(
+        "{}    Position: ({}, {})",
+        indent,
+        read_word(data, offset + 16) as i16,
+        read_word(data, offset + 18) as i16
+    );
+    println!(
+        "{}    Size: {}x{}",
+        indent,
+        read_word(data, offset + 20) as i16,
+        read_word(data, offset + 22) as i16
+    );
+    println!("{}    Style: 0x{:08x}", indent, style);
+
+    let mut next = index + 1;
+    for _ in 0..children {
+        next = print_os2_dialog_item(data, next, depth + 1);
+        if next == usize::MAX {
+            break;
+        }
+    }
+    next
+}
+
+/* DLGTEMPLATE: [word] size, [word] type, [word] codepage, [word] offset of
+ * the first item, [word] status, [word] focus item, [word] presentation
+ * parameters offset. The first item is the dialog frame itself. */
+pub fn print_os2_dialog(data: &Vec<u8>) {
+    if data.len() < 14 {
+        eprintln!("Dialog resource is too short ({} bytes).", data.len());
+        return;
+    }
+    println!("    Codepage: {}", read_word(data, 4));
+    let focus = read_word(data, 10);
+    if focus != 0xffff {
+        println!("    Focus item: {}", focus);
+    }
+    print_os2_dialog_item(data, 0, 0);
+}
+
+/* A bundle of sixteen strings: [word] codepage, then for each string a
+ * length byte (including the terminating null) and the string. */
+pub fn print_os2_strings(data: &Vec<u8>, name_id: u16) {
+    println!("    Codepage: {}", read_word(data, 0));
+    let mut offset = 2usize;
+    for i in 0..16u16 {
+        if offset >= data.len() {
+            break;
+        }
+        let length = read_byte(data, offset) as usize;
+        if length > 1 {
+            let (text, _) = read_cstring(data, offset + 1, read_word(data, 0));
+            println!("    {}: \"{}\"", (name_id.wrapping_sub(1)) * 16 + i, text);
+        }
+        offset += 1 + length;
+    }
+}
+
+/* [word] count, [word] codepage, then (flags, key, command) triples */
+pub fn print_os2_accelerators(data: &Vec<u8>) {
+    let count = read_word(data, 0) as usize;
+    println!("    Codepage: {}", read_word(data, 2));
+    for i in 0..count {
+        let offset = 4 + i * 6;
+        if offset + 6 > data.len() {
+            break;
+        }
+        let flags = read_word(data, offset);
+        let key = read_word(data, offset + 2);
+        let cmd = read_word(data, offset + 4);
+
+        print!("    ");
+        if flags & 0x0001 != 0 {
+            print!("Shift+");
+        }
+        if flags & 0x0002 != 0 {
+            print!("Ctrl+");
+        }
+        if flags & 0x0004 != 0 {
+            print!("Alt+");
+        }
+        if flags & 0x0010 != 0 {
+            print!("VK 0x{:02x}", key);
+        } else if flags & 0x0020 != 0 {
+            print!("scan code 0x{:02x}", key);
+        } else if key >= 0x20 && key <= 0x7e {
+            print!("'{}'", key as u8 as char);
+        } else {
+            print!("0x{:02x}", key);
+        }
+        println!(": {}", cmd);
+    }
+}
+
+/* The resource table holds ne_cres (type, name) pairs. Resource n lives in
+ * segment ne_cseg - ne_cres + n. */
+pub fn print_os2_rsrc(ne: &NeExecutable, start: usize) {
+    let count = ne.header.ne_cres as usize;
+    let cseg = ne.header.ne_cseg as usize;
+
+    if count > cseg {
+        eprintln!("Resource count {} exceeds segment count {}.", count, cseg);
+        return;
+    }
+
+    for i in 0..count {
+        let type_id = read_word(&ne.file, start + i * 4);
+        let name_id = read_word(&ne.file, start + i * 4 + 2);
+        let seg = &ne.segments[cseg - count + i];
+        let data = &seg.data;
+
+        let typestr = if (type_id as usize) < OS2_RSRC_TYPES.len()
+            && !OS2_RSRC_TYPES[type_id as usize].is_empty()
+        {
+            OS2_RSRC_TYPES[type_id as usize].to_string()
+        } else {
+            format!("0x{:04x}", type_id)
+        };
+        if !filter::filter_resource(&typestr, Some(type_id), &name_id.to_string(), None) {
+            continue;
+        }
+        print!("\n{}", typestr);
+        println!(
+            " {} (segment {}, offset = 0x{:x}, length = {} [0x{:x}]):",
+            name_id,
+            seg.cs,
+            seg.start,
+            data.len(),
+            data.len()
+        );
+
+        match type_id {
+            1 | 2 => print_os2_bitmap(data),
+            3 => print_os2_menu(data),
+            4 => print_os2_dialog(data),
+            5 => print_os2_strings(data, name_id),
+            8 => print_os2_accelerators(data),
+            _ => print_hex_dump(data, 0, data.len()),
+        }
+    }
+}
@@ -0,0 +1,381 @@
+/* Authenticode signatures (data directory 4).
+ *
+ * Unlike every other directory, the certificate table's address is a file
+ * offset, since it isn't loaded. It holds WIN_CERTIFICATE entries, each
+ * aligned to 8 bytes:
+ *     [dword] length, including this header
+ *     [word]  revision (0x0100 or 0x0200)
+ *     [word]  type (2 for PKCS#7 SignedData)
+ *     the certificate data
+ *
+ * The SignedData's content is an SpcIndirectDataContent holding a digest of
+ * the image. We check that digest against our own, but not the signature
+ * itself, and we don't know anything about whom to trust; that's not our
+ * business. Further signatures (e.g. SHA-1 and SHA-256 dual signing) are
+ * nested inside the first signer's unauthenticated attributes. */
+
+use crate::der::{
+    self, context, Element, TAG_INTEGER, TAG_OCTET_STRING, TAG_OID, TAG_SEQUENCE, TAG_SET,
+};
+use crate::hash::{DigestAlgorithm, Hasher};
+use crate::pe::PeExecutable;
+use crate::util::{read_dword, read_word};
+
+const OID_SIGNED_DATA: &str = "1.2.840.113549.1.7.2";
+const OID_SPC_INDIRECT_DATA: &str = "1.3.6.1.4.1.311.2.1.4";
+const OID_SPC_NESTED_SIGNATURE: &str = "1.3.6.1.4.1.311.2.4.1";
+const OID_COUNTERSIGNATURE: &str = "1.2.840.113549.1.9.6";
+const OID_RFC3161_TIMESTAMP: &str = "1.3.6.1.4.1.311.3.3.1";
+
+/* nested signatures can in theory nest further */
+const MAX_NESTING: usize = 8;
+
+pub struct Signer {
+    pub issuer: String,
+    pub serial: String,
+    pub subject: Option<String>, /* if we have its certificate */
+    pub timestamped: bool,
+}
+
+pub struct Signature {
+    pub nesting: usize, /* 0 for the outermost */
+    pub digest_oid: String,
+    pub digest: Vec<u8>, /* of the image, as signed */
+    pub signers: Vec<Signer>,
+    pub certificate_count: usize,
+    pub image_hash: Option<Vec<u8>>, /* ours, if we know the algorithm */
+}
+
+pub struct PeCertificate {
+    pub offset: usize,
+    pub length: usize,
+    pub revision: u16,
+    pub cert_type: u16,
+    pub signatures: Vec<Signature>,
+    pub error: Option<String>,
+}
+
+/* The Authenticode image hash: everything in the headers except the
+ * checksum and the certificate table's directory entry, then each section's
+ * raw data in file order, then anything after the sections that isn't the
+ * certificate table itself. */
+pub fn image_hash(
+    map: &Vec<u8>,
+    offset_pe: usize,
+    pe: &PeExecutable,
+    algorithm: DigestAlgorithm,
+) -> Option<Vec<u8>> {
+    let checksum = offset_pe + 0x58;
+    let cert_dir = offset_pe + if pe.magic == 0x10b { 0x78 } else { 0x88 } + 4 * 8;
+    let headers_end = if pe.magic == 0x10b {
+        pe.opt32.SizeOfHeaders
+    } else {
+        pe.opt64.SizeOfHeaders
+    } as usize;
+    if cert_dir + 8 > headers_end || headers_end > map.len() {
+        return None;
+    }
+
+    let mut hasher = Hasher::new(algorithm);
+    hasher.update(&map[..checksum]);
+    hasher.update(&map[checksum + 4..cert_dir]);
+    hasher.update(&map[cert_dir + 8..headers_end]);
+
+    let mut sections: Vec<(usize, usize)> = pe
+        .sections
+        .iter()
+        .filter(|sec| sec.length != 0)
+        .map(|sec| (sec.offset as usize, sec.length as usize))
+        .collect();
+    sections.sort();
+    let mut end = headers_end;
+    for (offset, length) in sections {
+        if offset + length > map.len() {
+            eprintln!("Section data at 0x{:x} exceeds file length.", offset);
+            return None;
+        }
+        hasher.update(&map[offset..offset + length]);
+        end = end.max(offset + length);
+    }
+
+    let cert_table = pe.dirs[4].address as usize;
+    let extra_end = if cert_table >= end && cert_table <= map.len() {
+        cert_table
+    } else {
+        map.len()
+    };
+    if extra_end > end {
+        hasher.update(&map[end..extra_end]);
+    }
+    Some(hasher.finish())
+}
+
+fn read_certificate_info(certificate: &Element) -> Option<(String, String, String)> {
+    /* subject, issuer and serial, from the TBSCertificate */
+    let tbs = certificate.child(0, TAG_SEQUENCE).ok()?;
+    let fields = tbs.children().ok()?;
+    let first = if fields.first()?.tag == context(0, true) {
+        1
+    } else {
+        0
+    };
+    let serial = fields.get(first)?.expect(TAG_INTEGER).ok()?;
+    let issuer = fields.get(first + 2)?;
+    let subject = fields.get(first + 4)?;
+    Some((
+        der::name_to_string(subject),
+        der::name_to_string(issuer),
+        der::integer_to_hex(serial.contents),
+    ))
+}
+
+/* Parse a PKCS#7 ContentInfo holding SignedData, adding it and anything
+ * nested in it to `signatures`. */
+fn read_signed_data(
+    data: &[u8],
+    nesting: usize,
+    signatures: &mut Vec<Signature>,
+) -> Result<(), String> {
+    let content_info = Element::parse(data)?;
+    content_info.expect(TAG_SEQUENCE)?;
+    let content_type = content_info.child(0, TAG_OID)?;
+    if der::oid_to_string(content_type.contents) != OID_SIGNED_DATA {
+        return Err(format!(
+            "Signature has content type {}, not SignedData.",
+            der::oid_to_string(content_type.contents)
+        ));
+    }
+    let signed_data = Element::parse(content_info.child(1, context(0, true))?.contents)?;
+    let fields = signed_data.expect(TAG_SEQUENCE)?.children()?;
+    if fields.len() < 4 {
+        return Err("SignedData is missing fields.".to_string());
+    }
+
+    /* the SpcIndirectDataContent: some data we don't care about, then the
+     * digest as a DigestInfo */
+    let encapsulated = fields[2].expect(TAG_SEQUENCE)?;
+    let encapsulated_type = der::oid_to_string(encapsulated.child(0, TAG_OID)?.contents);
+    if encapsulated_type != OID_SPC_INDIRECT_DATA {
+        return Err(format!(
+            "Signed content has type {}, not SpcIndirectDataContent.",
+            encapsulated_type
+        ));
+    }
+    let indirect = Element::parse(encapsulated.child(1, context(0, true))?.contents)?;
+    let digest_info = indirect.expect(TAG_SEQUENCE)?.child(1, TAG_SEQUENCE)?;
+    let digest_oid = der::oid_to_string(
+        digest_info
+            .child(0, TAG_SEQUENCE)?
+            .child(0, TAG_OID)?
+            .contents,
+    );
+    let digest = digest_info.child(1, TAG_OCTET_STRING)?.contents.to_vec();
+
+    let mut certificates = Vec::new();
+    let mut signer_infos = Vec::new();
+    for field in &fields[3..] {
+        if field.tag == context(0, true) {
+            for certificate in field.children()? {
+                if let Some(info) = read_certificate_info(&certificate) {
+                    certificates.push(info);
+                }
+            }
+        } else if field.tag == TAG_SET {
+            signer_infos = field.children()?;
+        }
+    }
+
+    let mut signature = Signature {
+        nesting,
+        digest_oid,
+        digest,
+        signers: Vec::new(),
+        certificate_count: certificates.len(),
+        image_hash: None,
+    };
+    let mut nested = Vec::new();
+
+    for signer_info in signer_infos {
+        let fields = signer_info.expect(TAG_SEQUENCE)?.children()?;
+        if fields.len() < 2 {
+            return Err("SignerInfo is missing fields.".to_string());
+        }
+        let mut signer = Signer {
+            issuer: "?".to_string(),
+            serial: String::new(),
+            subject: None,
+            timestamped: false,
+        };
+        /* version 3 signers may give a key identifier instead */
+        if fields[1].tag == TAG_SEQUENCE {
+            let id = fields[1].children()?;
+            if id.len() == 2 {
+                signer.issuer = der::name_to_string(&id[0]);
+                signer.serial = der::integer_to_hex(id[1].contents);
+                signer.subject = certificates
+                    .iter()
+                    .find(|(_, issuer, serial)| {
+                        *issuer == signer.issuer && *serial == signer.serial
+                    })
+                    .map(|(subject, _, _)| subject.clone());
+            }
+        }
+
+        if let Some(unauthenticated) = fields.iter().find(|f| f.tag == context(1, true)) {
+            for attribute in unauthenticated.children()? {
+                let oid = der::oid_to_string(attribute.child(0, TAG_OID)?.contents);
+                match oid.as_str() {
+                    OID_SPC_NESTED_SIGNATURE if nesting < MAX_NESTING => {
+                        for value in attribute.child(1, TAG_SET)?.children()? {
+                            nested.push(value.raw.to_vec());
+                        }
+                    }
+                    OID_COUNTERSIGNATURE | OID_RFC3161_TIMESTAMP => signer.timestamped = true,
+                    _ => {}
+                }
+            }
+        }
+        signature.signers.push(signer);
+    }
+
+    signatures.push(signature);
+    for data in nested {
+        read_signed_data(&data, nesting + 1, signatures)?;
+    }
+    Ok(())
+}
+
+pub fn get_certificates(map: &Vec<u8>, offset_pe: usize, pe: &mut PeExecutable) {
+    let start = pe.dirs[4].address as usize;
+    let end = start + pe.dirs[4].size as usize;
+    if end > map.len() {
+        eprintln!("Certificate table exceeds file length.");
+        return;
+    }
+
+    let mut hashes: Vec<(DigestAlgorithm, Option<Vec<u8>>)> = Vec::new();
+    let mut cursor = start;
+    while cursor + 8 <= end {
+        let length = read_dword(map, cursor) as usize;
+        if length < 8 || cursor + length > end {
+            eprintln!("Certificate at 0x{:x} has bad length {}.", cursor, length);
+            break;
+        }
+        let mut certificate = PeCertificate {
+            offset: cursor,
+            length,
+            revision: read_word(map, cursor + 4),
+            cert_type: read_word(map, cursor + 6),
+            signatures: Vec::new(),
+            error: None,
+        };
+
+        if certificate.cert_type == 2 {
+            let data = &map[cursor + 8..cursor + length];
+            if let Err(error) = read_signed_data(data, 0, &mut certificate.signatures) {
+                certificate.error = Some(error);
+            }
+        }
+
+        for signature in &mut certificate.signatures {
+            let algorithm = match DigestAlgorithm::from_oid(&signature.digest_oid) {
+                Some(algorithm) => algorithm,
+                None => continue,
+            };
+            /* hashing the whole file is slow, so do it once per algorithm */
+            if !hashes.iter().any(|(a, _)| *a == algorithm) {
+                hashes.push((algorithm, image_hash(map, offset_pe, pe, algorithm)));
+            }
+            signature.image_hash = hashes
+                .iter()
+                .find(|(a, _)| *a == algorithm)
+                .and_then(|(_, hash)| hash.clone());
+        }
+
+        pe.certificates.push(certificate);
+        cursor += (length + 7) & !7;
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn print_certificates(pe: &PeExecutable) {
+    print!("\nCertificate table:\n");
+    for certificate in &pe.certificates {
+        print!(
+            "\tCertificate at 0x{:x}, {} bytes, revision {}.{}, ",
+            certificate.offset,
+            certificate.length,
+            certificate.revision >> 8,
+            certificate.revision & 0xff
+        );
+        match certificate.cert_type {
+            1 => print!("X.509 certificate\n"),
+            2 => print!("PKCS#7 signed data\n"),
+            t => print!("unknown type {}\n", t),
+        }
+        if let Some(error) = &certificate.error {
+            print!("\t\tCan't read signature: {}\n", error);
+        }
+
+        for signature in &certificate.signatures {
+            let indent = "\t\t".to_string() + &"  ".repeat(signature.nesting);
+            let algorithm = DigestAlgorithm::from_oid(&signature.digest_oid);
+            print!(
+                "{}{}ignature, digest {}\n",
+                indent,
+                if signature.nesting > 0 {
+                    "Nested s"
+                } else {
+                    "S"
+                },
+                algorithm.map_or(signature.digest_oid.as_str(), |a| a.name())
+            );
+            print!(
+                "{}Signed image digest:   {}\n",
+                indent,
+                hex(&signature.digest)
+            );
+            match &signature.image_hash {
+                Some(hash) => print!(
+                    "{}Computed image digest: {} ({})\n",
+                    indent,
+                    hex(hash),
+                    if *hash == signature.digest {
+                        "matches"
+                    } else {
+                        "DOES NOT MATCH"
+                    }
+                ),
+                None if algorithm.is_none() => {
+                    print!("{}Don't know how to compute this digest.\n", indent)
+                }
+                None => print!("{}Couldn't compute the image digest.\n", indent),
+            }
+            print!("{}{} certificates\n", indent, signature.certificate_count);
+            for signer in &signature.signers {
+                print!(
+                    "{}Signer: {}\n",
+                    indent,
+                    signer
+                        .subject
+                        .as_deref()
+                        .unwrap_or("(certificate not included)")
+                );
+                print!(
+                    "{}  issued by {}, serial {}{}\n",
+                    indent,
+                    signer.issuer,
+                    signer.serial,
+                    if signer.timestamped {
+                        ", timestamped"
+                    } else {
+                        ""
+                    }
+                );
+            }
+        }
+    }
+}
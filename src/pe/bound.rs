@@ -0,0 +1,79 @@
+/* Bound imports (data directory 11).
+ *
+ * BIND.EXE resolves the imports of an image ahead of time and writes the
+ * addresses straight into its IATs. The loader can then skip resolving them,
+ * as long as the bound modules still have the timestamps recorded here:
+ *     [dword] timestamp of the module
+ *     [word]  offset of the module name
+ *     [word]  number of forwarder refs which follow
+ * Each forwarder ref names a module that the first one forwards some of the
+ * bound imports to, in the same layout (the last word being reserved). Name
+ * offsets are from the start of the directory, and the list ends with a
+ * zeroed descriptor.
+ *
+ * The directory usually lives in the headers, outside of any section, where
+ * addresses are the same as file offsets. */
+
+use crate::pe::{addr_to_offset, PeExecutable};
+use crate::util::{read_dword, read_string, read_word};
+use std::cmp::min;
+
+pub struct PeBoundForwarder {
+    pub module: String,
+    pub timestamp: u32,
+}
+
+pub struct PeBoundImport {
+    pub module: String,
+    pub timestamp: u32,
+    pub forwarders: Vec<PeBoundForwarder>,
+}
+
+pub fn get_bound_import_table(map: &Vec<u8>, pe: &mut PeExecutable) {
+    let mut offset = addr_to_offset(pe.dirs[11].address, pe);
+    if offset == 0 {
+        offset = pe.dirs[11].address as usize;
+    }
+    let end = min(offset + pe.dirs[11].size as usize, map.len());
+    let name = |name_offset: u16| read_string(map, offset + name_offset as usize, 0);
+
+    let mut cursor = offset;
+    while cursor + 8 <= end && read_dword(map, cursor) != 0 {
+        let mut import = PeBoundImport {
+            module: name(read_word(map, cursor + 4)),
+            timestamp: read_dword(map, cursor),
+            forwarders: Vec::new(),
+        };
+        let count = read_word(map, cursor + 6);
+        cursor += 8;
+
+        for _ in 0..count {
+            if cursor + 8 > end {
+                eprintln!("Bound import forwarder refs exceed directory size.");
+                break;
+            }
+            import.forwarders.push(PeBoundForwarder {
+                module: name(read_word(map, cursor + 4)),
+                timestamp: read_dword(map, cursor),
+            });
+            cursor += 8;
+        }
+        pe.bound_imports.push(import);
+    }
+}
+
+pub fn print_bound_imports(pe: &PeExecutable) {
+    print!("\nBound modules:\n");
+    for import in &pe.bound_imports {
+        print!(
+            "\t{} (timestamp 0x{:08x})\n",
+            import.module, import.timestamp
+        );
+        for forwarder in &import.forwarders {
+            print!(
+                "\t\tforwards to {} (timestamp 0x{:08x})\n",
+                forwarder.module, forwarder.timestamp
+            );
+        }
+    }
+}
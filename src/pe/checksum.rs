@@ -0,0 +1,124 @@
+/* The optional header's CheckSum, as computed by CheckSumMappedFile(): the
+ * file is summed as little-endian words with the carries folded back in,
+ * skipping the CheckSum field itself, and the file length is added at the
+ * end. The loader only insists on it for drivers and a few boot-time DLLs,
+ * but a wrong one on a file that has one at all usually means the file was
+ * touched after linking. */
+
+use crate::pe::PeExecutable;
+use crate::util::read_dword;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/* where --fix-checksum writes to, if anywhere */
+static FIX_CHECKSUM_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+const SUBSYSTEM_NATIVE: u16 = 1;
+
+pub fn set_fix_checksum_path(arg: &str) {
+    *FIX_CHECKSUM_PATH.lock().unwrap() = Some(PathBuf::from(arg));
+}
+
+pub fn fix_checksum_requested() -> bool {
+    FIX_CHECKSUM_PATH.lock().unwrap().is_some()
+}
+
+fn checksum_offset(offset_pe: usize) -> usize {
+    offset_pe + 0x58
+}
+
+pub fn compute_checksum(map: &Vec<u8>, offset_pe: usize) -> u32 {
+    let skip = checksum_offset(offset_pe);
+    let mut sum: u32 = 0;
+    for (i, word) in map.chunks(2).enumerate() {
+        if i * 2 == skip || i * 2 == skip + 2 {
+            continue;
+        }
+        /* an odd trailing byte counts as if padded with zero */
+        sum += word[0] as u32 | (*word.get(1).unwrap_or(&0) as u32) << 8;
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    sum = (sum & 0xffff) + (sum >> 16);
+    sum.wrapping_add(map.len() as u32)
+}
+
+fn stored_checksum(pe: &PeExecutable) -> u32 {
+    if pe.magic == 0x10b {
+        pe.opt32.CheckSum
+    } else {
+        pe.opt64.CheckSum
+    }
+}
+
+fn subsystem(pe: &PeExecutable) -> u16 {
+    if pe.magic == 0x10b {
+        pe.opt32.Subsystem
+    } else {
+        pe.opt64.Subsystem
+    }
+}
+
+/* Compute the checksum, complaining if the stored one is wrong, or missing
+ * where the loader requires one. */
+pub fn get_checksum(map: &Vec<u8>, offset_pe: usize, pe: &mut PeExecutable) {
+    if checksum_offset(offset_pe) + 4 > map.len() {
+        return;
+    }
+    pe.checksum = compute_checksum(map, offset_pe);
+
+    let stored = stored_checksum(pe);
+    if stored == 0 {
+        if subsystem(pe) == SUBSYSTEM_NATIVE {
+            eprintln!(
+                "Native image has no checksum (should be 0x{:08x}).",
+                pe.checksum
+            );
+        }
+    } else if stored != pe.checksum {
+        eprintln!(
+            "Checksum is 0x{:08x}, but should be 0x{:08x}.",
+            stored, pe.checksum
+        );
+    }
+}
+
+pub fn print_checksum(pe: &PeExecutable) {
+    let stored = stored_checksum(pe);
+    print!("Checksum: 0x{:08x}", stored); /* 58 */
+    if stored == 0 {
+        print!(" (not set; computed 0x{:08x})\n", pe.checksum);
+    } else if stored == pe.checksum {
+        print!(" (matches)\n");
+    } else {
+        print!(" (DOES NOT MATCH; computed 0x{:08x})\n", pe.checksum);
+    }
+}
+
+/* Write a copy of the file with the correct checksum, if asked to. The
+ * Authenticode image hash skips the checksum, so this doesn't break a
+ * signature. There's only the one output path, so it's used up by the first
+ * file rather than overwritten by each. */
+pub fn write_fixed_checksum(map: &Vec<u8>, offset_pe: usize, pe: &PeExecutable) {
+    let path = match FIX_CHECKSUM_PATH.lock().unwrap().take() {
+        Some(path) => path,
+        None => return,
+    };
+    let offset = checksum_offset(offset_pe);
+    if offset + 4 > map.len() {
+        eprintln!("File is too short to hold a checksum.");
+        return;
+    }
+
+    let mut fixed = map.clone();
+    fixed[offset..offset + 4].copy_from_slice(&pe.checksum.to_le_bytes());
+    match fs::write(&path, &fixed) {
+        Ok(()) => eprintln!(
+            "Wrote {} with checksum 0x{:08x} (was 0x{:08x}).",
+            path.display(),
+            pe.checksum,
+            read_dword(map, offset)
+        ),
+        Err(e) => eprintln!("Can't write {}: {}", path.display(), e),
+    }
+}
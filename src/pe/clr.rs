@@ -0,0 +1,1265 @@
+/* The CLR header (data directory 14), which makes this a .NET assembly.
+ *
+ * The header itself is small:
+ *     [dword] size of the header (0x48)
+ *     [word]  runtime version, major and minor
+ *     [8 bytes] metadata (RVA and size)
+ *     [dword] flags
+ *     [dword] entry point: a MethodDef token, or an RVA if the flags say so
+ *     [8 bytes each] resources, strong name signature, code manager table,
+ *         vtable fixups, export address table jumps, managed native header
+ * Everything interesting is in the metadata: a root ("BSJB") listing some
+ * streams, which are four heaps (#Strings, #US, #GUID and #Blob) and the
+ * tables (#~, or #- if uncompressed). The tables are stored back to back
+ * with no sizes given, and their column widths depend on how big the other
+ * tables and heaps are, so we have to know the layout of all of them even
+ * though we only care about a few.
+ *
+ * Each method's RVA points to its body: a tiny or fat header, the CIL code,
+ * then maybe some exception handling clauses. In the CIL, tokens (table in
+ * the high byte, 1-based row in the rest) refer back to the tables, which
+ * is how we name what's called. */
+
+use crate::cil;
+use crate::pe::{addr_to_offset, debug, PeExecutable};
+use crate::util::{read_byte, read_dword, read_string, read_word};
+
+const METADATA_MAGIC: u32 = 0x424a5342; /* "BSJB" */
+
+pub const TABLE_MODULE: u8 = 0x00;
+pub const TABLE_TYPEREF: u8 = 0x01;
+pub const TABLE_TYPEDEF: u8 = 0x02;
+pub const TABLE_FIELD: u8 = 0x04;
+pub const TABLE_METHODDEF: u8 = 0x06;
+pub const TABLE_PARAM: u8 = 0x08;
+pub const TABLE_INTERFACEIMPL: u8 = 0x09;
+pub const TABLE_MEMBERREF: u8 = 0x0a;
+pub const TABLE_DECLSECURITY: u8 = 0x0e;
+pub const TABLE_STANDALONESIG: u8 = 0x11;
+pub const TABLE_EVENT: u8 = 0x14;
+pub const TABLE_PROPERTY: u8 = 0x17;
+pub const TABLE_MODULEREF: u8 = 0x1a;
+pub const TABLE_TYPESPEC: u8 = 0x1b;
+pub const TABLE_ASSEMBLY: u8 = 0x20;
+pub const TABLE_ASSEMBLYREF: u8 = 0x23;
+pub const TABLE_FILE: u8 = 0x26;
+pub const TABLE_EXPORTEDTYPE: u8 = 0x27;
+pub const TABLE_MANIFESTRESOURCE: u8 = 0x28;
+pub const TABLE_NESTEDCLASS: u8 = 0x29;
+pub const TABLE_GENERICPARAM: u8 = 0x2a;
+pub const TABLE_METHODSPEC: u8 = 0x2b;
+pub const TABLE_GENERICPARAMCONSTRAINT: u8 = 0x2c;
+const TABLE_COUNT: usize = 0x2d;
+
+/* not a table, but tokens for user strings look like one */
+const TOKEN_STRING: u8 = 0x70;
+
+const TABLE_NAMES: [&str; TABLE_COUNT] = [
+    "Module",
+    "TypeRef",
+    "TypeDef",
+    "FieldPtr",
+    "Field",
+    "MethodPtr",
+    "MethodDef",
+    "ParamPtr",
+    "Param",
+    "InterfaceImpl",
+    "MemberRef",
+    "Constant",
+    "CustomAttribute",
+    "FieldMarshal",
+    "DeclSecurity",
+    "ClassLayout",
+    "FieldLayout",
+    "StandAloneSig",
+    "EventMap",
+    "EventPtr",
+    "Event",
+    "PropertyMap",
+    "PropertyPtr",
+    "Property",
+    "MethodSemantics",
+    "MethodImpl",
+    "ModuleRef",
+    "TypeSpec",
+    "ImplMap",
+    "FieldRVA",
+    "EncLog",
+    "EncMap",
+    "Assembly",
+    "AssemblyProcessor",
+    "AssemblyOS",
+    "AssemblyRef",
+    "AssemblyRefProcessor",
+    "AssemblyRefOS",
+    "File",
+    "ExportedType",
+    "ManifestResource",
+    "NestedClass",
+    "GenericParam",
+    "MethodSpec",
+    "GenericParamConstraint",
+];
+
+/* Coded indices: a token packed into as few bits as the tables need, with
+ * the table chosen by the low bits. 0xff marks tags that aren't used. */
+const TYPE_DEF_OR_REF: &[u8] = &[TABLE_TYPEDEF, TABLE_TYPEREF, TABLE_TYPESPEC];
+const HAS_CONSTANT: &[u8] = &[TABLE_FIELD, TABLE_PARAM, TABLE_PROPERTY];
+const HAS_CUSTOM_ATTRIBUTE: &[u8] = &[
+    TABLE_METHODDEF,
+    TABLE_FIELD,
+    TABLE_TYPEREF,
+    TABLE_TYPEDEF,
+    TABLE_PARAM,
+    TABLE_INTERFACEIMPL,
+    TABLE_MEMBERREF,
+    TABLE_MODULE,
+    TABLE_DECLSECURITY,
+    TABLE_PROPERTY,
+    TABLE_EVENT,
+    TABLE_STANDALONESIG,
+    TABLE_MODULEREF,
+    TABLE_TYPESPEC,
+    TABLE_ASSEMBLY,
+    TABLE_ASSEMBLYREF,
+    TABLE_FILE,
+    TABLE_EXPORTEDTYPE,
+    TABLE_MANIFESTRESOURCE,
+    TABLE_GENERICPARAM,
+    TABLE_GENERICPARAMCONSTRAINT,
+    TABLE_METHODSPEC,
+];
+const HAS_FIELD_MARSHAL: &[u8] = &[TABLE_FIELD, TABLE_PARAM];
+const HAS_DECL_SECURITY: &[u8] = &[TABLE_TYPEDEF, TABLE_METHODDEF, TABLE_ASSEMBLY];
+const MEMBER_REF_PARENT: &[u8] = &[
+    TABLE_TYPEDEF,
+    TABLE_TYPEREF,
+    TABLE_MODULEREF,
+    TABLE_METHODDEF,
+    TABLE_TYPESPEC,
+];
+const HAS_SEMANTICS: &[u8] = &[TABLE_EVENT, TABLE_PROPERTY];
+const METHOD_DEF_OR_REF: &[u8] = &[TABLE_METHODDEF, TABLE_MEMBERREF];
+const MEMBER_FORWARDED: &[u8] = &[TABLE_FIELD, TABLE_METHODDEF];
+const IMPLEMENTATION: &[u8] = &[TABLE_FILE, TABLE_ASSEMBLYREF, TABLE_EXPORTEDTYPE];
+const CUSTOM_ATTRIBUTE_TYPE: &[u8] = &[0xff, 0xff, TABLE_METHODDEF, TABLE_MEMBERREF, 0xff];
+const RESOLUTION_SCOPE: &[u8] = &[
+    TABLE_MODULE,
+    TABLE_MODULEREF,
+    TABLE_ASSEMBLYREF,
+    TABLE_TYPEREF,
+];
+const TYPE_OR_METHOD_DEF: &[u8] = &[TABLE_TYPEDEF, TABLE_METHODDEF];
+
+#[derive(Clone, Copy)]
+enum Column {
+    U16,
+    U32,
+    Str,
+    Guid,
+    Blob,
+    Index(u8),            /* a row in one table */
+    Coded(&'static [u8]), /* stored as a token */
+}
+
+use Column::*;
+
+fn table_columns(table: usize) -> &'static [Column] {
+    match table {
+        0x00 => &[U16, Str, Guid, Guid, Guid],
+        0x01 => &[Coded(RESOLUTION_SCOPE), Str, Str],
+        0x02 => &[
+            U32,
+            Str,
+            Str,
+            Coded(TYPE_DEF_OR_REF),
+            Index(TABLE_FIELD),
+            Index(TABLE_METHODDEF),
+        ],
+        0x03 => &[Index(TABLE_FIELD)],
+        0x04 => &[U16, Str, Blob],
+        0x05 => &[Index(TABLE_METHODDEF)],
+        0x06 => &[U32, U16, U16, Str, Blob, Index(TABLE_PARAM)],
+        0x07 => &[Index(TABLE_PARAM)],
+        0x08 => &[U16, U16, Str],
+        0x09 => &[Index(TABLE_TYPEDEF), Coded(TYPE_DEF_OR_REF)],
+        0x0a => &[Coded(MEMBER_REF_PARENT), Str, Blob],
+        0x0b => &[U16, Coded(HAS_CONSTANT), Blob],
+        0x0c => &[
+            Coded(HAS_CUSTOM_ATTRIBUTE),
+            Coded(CUSTOM_ATTRIBUTE_TYPE),
+            Blob,
+        ],
+        0x0d => &[Coded(HAS_FIELD_MARSHAL), Blob],
+        0x0e => &[U16, Coded(HAS_DECL_SECURITY), Blob],
+        0x0f => &[U16, U32, Index(TABLE_TYPEDEF)],
+        0x10 => &[U32, Index(TABLE_FIELD)],
+        0x11 => &[Blob],
+        0x12 => &[Index(TABLE_TYPEDEF), Index(TABLE_EVENT)],
+        0x13 => &[Index(TABLE_EVENT)],
+        0x14 => &[U16, Str, Coded(TYPE_DEF_OR_REF)],
+        0x15 => &[Index(TABLE_TYPEDEF), Index(TABLE_PROPERTY)],
+        0x16 => &[Index(TABLE_PROPERTY)],
+        0x17 => &[U16, Str, Blob],
+        0x18 => &[U16, Index(TABLE_METHODDEF), Coded(HAS_SEMANTICS)],
+        0x19 => &[
+            Index(TABLE_TYPEDEF),
+            Coded(METHOD_DEF_OR_REF),
+            Coded(METHOD_DEF_OR_REF),
+        ],
+        0x1a => &[Str],
+        0x1b => &[Blob],
+        0x1c => &[U16, Coded(MEMBER_FORWARDED), Str, Index(TABLE_MODULEREF)],
+        0x1d => &[U32, Index(TABLE_FIELD)],
+        0x1e => &[U32, U32],
+        0x1f => &[U32],
+        0x20 => &[U32, U16, U16, U16, U16, U32, Blob, Str, Str],
+        0x21 => &[U32],
+        0x22 => &[U32, U32, U32],
+        0x23 => &[U16, U16, U16, U16, U32, Blob, Str, Str, Blob],
+        0x24 => &[U32, Index(TABLE_ASSEMBLYREF)],
+        0x25 => &[U32, U32, U32, Index(TABLE_ASSEMBLYREF)],
+        0x26 => &[U32, Str, Blob],
+        0x27 => &[U32, U32, Str, Str, Coded(IMPLEMENTATION)],
+        0x28 => &[U32, U32, Str, Coded(IMPLEMENTATION)],
+        0x29 => &[Index(TABLE_TYPEDEF), Index(TABLE_TYPEDEF)],
+        0x2a => &[U16, U16, Coded(TYPE_OR_METHOD_DEF), Str],
+        0x2b => &[Coded(METHOD_DEF_OR_REF), Blob],
+        0x2c => &[Index(TABLE_GENERICPARAM), Coded(TYPE_DEF_OR_REF)],
+        _ => &[],
+    }
+}
+
+pub struct ClrStream {
+    pub name: String,
+    pub offset: u32, /* from the metadata root */
+    pub size: u32,
+}
+
+pub struct ClrTypeDef {
+    pub flags: u32,
+    pub name: String,
+    pub namespace: String,
+    pub extends: u32,           /* token, or 0 */
+    pub fields: (usize, usize), /* range of rows, 0-based */
+    pub methods: (usize, usize),
+    pub enclosing: Option<usize>, /* for nested types */
+}
+
+pub struct ClrField {
+    pub flags: u16,
+    pub name: String,
+    pub signature: u32, /* blob index */
+    pub owner: Option<usize>,
+}
+
+pub struct ClrMethodDef {
+    pub rva: u32,
+    pub impl_flags: u16,
+    pub flags: u16,
+    pub name: String,
+    pub signature: u32, /* blob index */
+    pub owner: Option<usize>,
+}
+
+pub struct ClrMemberRef {
+    pub parent: u32, /* token */
+    pub name: String,
+    pub signature: u32, /* blob index */
+}
+
+pub struct ClrTypeRef {
+    pub scope: u32, /* token, or 0 */
+    pub name: String,
+    pub namespace: String,
+}
+
+pub struct ClrAssemblyRef {
+    pub version: [u16; 4],
+    pub flags: u32,
+    pub public_key: Vec<u8>, /* or the token, if flags & 1 is clear */
+    pub name: String,
+    pub culture: String,
+}
+
+pub struct ClrModule {
+    pub name: String,
+    pub mvid: Option<[u8; 16]>,
+}
+
+pub struct PeClr {
+    pub runtime_version: (u16, u16),
+    pub metadata_addr: u32,
+    pub metadata_size: u32,
+    pub flags: u32,
+    pub entry_point: u32, /* token, or RVA if flags & 0x10 */
+    pub resources: (u32, u32),
+    pub strong_name: (u32, u32),
+    pub vtable_fixups: (u32, u32),
+    pub version: String, /* from the metadata root */
+    pub streams: Vec<ClrStream>,
+    pub row_counts: [u32; TABLE_COUNT],
+    pub rows: Vec<Vec<Vec<u32>>>, /* every column of every table */
+    pub strings: Vec<u8>,
+    pub user_strings: Vec<u8>,
+    pub blobs: Vec<u8>,
+    pub guids: Vec<u8>,
+    pub module: Option<ClrModule>,
+    pub typerefs: Vec<ClrTypeRef>,
+    pub typedefs: Vec<ClrTypeDef>,
+    pub fields: Vec<ClrField>,
+    pub methods: Vec<ClrMethodDef>,
+    pub memberrefs: Vec<ClrMemberRef>,
+    pub assembly_refs: Vec<ClrAssemblyRef>,
+}
+
+pub struct ClrExceptionClause {
+    pub flags: u32, /* 0 catch, 1 filter, 2 finally, 4 fault */
+    pub try_offset: u32,
+    pub try_length: u32,
+    pub handler_offset: u32,
+    pub handler_length: u32,
+    pub class_or_filter: u32, /* a token for catch, an offset for filter */
+}
+
+pub struct ClrMethodBody {
+    pub header_size: usize,
+    pub max_stack: u16,
+    pub local_sig: u32, /* StandAloneSig token, or 0 */
+    pub init_locals: bool,
+    pub code: Vec<u8>,
+    pub clauses: Vec<ClrExceptionClause>,
+}
+
+/* A compressed unsigned integer, as used in blobs and signatures: one, two
+ * or four bytes, big-endian, with the length in the top bits. */
+fn read_compressed(data: &[u8], pos: &mut usize) -> Option<u32> {
+    let b0 = *data.get(*pos)? as u32;
+    let (value, length) = if b0 & 0x80 == 0 {
+        (b0, 1)
+    } else if b0 & 0xc0 == 0x80 {
+        ((b0 & 0x3f) << 8 | *data.get(*pos + 1)? as u32, 2)
+    } else if b0 & 0xe0 == 0xc0 {
+        let rest = data.get(*pos + 1..*pos + 4)?;
+        (
+            (b0 & 0x1f) << 24 | (rest[0] as u32) << 16 | (rest[1] as u32) << 8 | rest[2] as u32,
+            4,
+        )
+    } else {
+        return None;
+    };
+    *pos += length;
+    Some(value)
+}
+
+fn table_of(token: u32) -> u8 {
+    (token >> 24) as u8
+}
+
+fn row_of(token: u32) -> usize {
+    (token & 0xffffff) as usize
+}
+
+impl PeClr {
+    fn string(&self, index: u32) -> String {
+        let index = index as usize;
+        if index >= self.strings.len() {
+            return String::new();
+        }
+        let end = self.strings[index..]
+            .iter()
+            .position(|&b| b == 0)
+            .map_or(self.strings.len(), |n| index + n);
+        String::from_utf8_lossy(&self.strings[index..end]).into_owned()
+    }
+
+    pub fn blob(&self, index: u32) -> &[u8] {
+        let mut pos = index as usize;
+        match read_compressed(&self.blobs, &mut pos) {
+            Some(length) => self.blobs.get(pos..pos + length as usize).unwrap_or(&[]),
+            None => &[],
+        }
+    }
+
+    fn guid(&self, index: u32) -> Option<[u8; 16]> {
+        /* 1-based, in units of GUIDs */
+        let start = (index as usize).checked_sub(1)? * 16;
+        let mut guid = [0u8; 16];
+        guid.copy_from_slice(self.guids.get(start..start + 16)?);
+        Some(guid)
+    }
+
+    /* The user string a 0x70 token refers to: UTF-16, with a trailing byte
+     * saying whether there's anything special in it. */
+    pub fn user_string(&self, index: u32) -> Option<String> {
+        let mut pos = index as usize;
+        let length = read_compressed(&self.user_strings, &mut pos)? as usize;
+        let bytes = self.user_strings.get(pos..pos + length)?;
+        let units: Vec<u16> = bytes
+            .chunks(2)
+            .filter(|c| c.len() == 2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect();
+        Some(String::from_utf16_lossy(&units))
+    }
+
+    fn row(&self, table: u8, row: usize) -> Option<&Vec<u32>> {
+        self.rows.get(table as usize)?.get(row.checked_sub(1)?)
+    }
+
+    /* Type names, as ildasm would print them. Names can nest (types inside
+     * types, type references scoped by type references) and signatures can
+     * contain types, so keep a depth limit against malicious loops. */
+    fn typedef_name(&self, index: usize, depth: usize) -> String {
+        let typedef = match self.typedefs.get(index) {
+            Some(typedef) => typedef,
+            None => return format!("<bad TypeDef {}>", index + 1),
+        };
+        match typedef.enclosing {
+            Some(outer) if depth < 16 => {
+                format!("{}/{}", self.typedef_name(outer, depth + 1), typedef.name)
+            }
+            _ if typedef.namespace.is_empty() => typedef.name.clone(),
+            _ => format!("{}.{}", typedef.namespace, typedef.name),
+        }
+    }
+
+    fn typeref_name(&self, index: usize, depth: usize) -> String {
+        let typeref = match self.typerefs.get(index) {
+            Some(typeref) => typeref,
+            None => return format!("<bad TypeRef {}>", index + 1),
+        };
+        let name = if typeref.namespace.is_empty() {
+            typeref.name.clone()
+        } else {
+            format!("{}.{}", typeref.namespace, typeref.name)
+        };
+        let scope = typeref.scope;
+        match table_of(scope) {
+            TABLE_ASSEMBLYREF if row_of(scope) != 0 => {
+                match self.assembly_refs.get(row_of(scope) - 1) {
+                    Some(assembly) => format!("[{}]{}", assembly.name, name),
+                    None => name,
+                }
+            }
+            TABLE_MODULEREF if row_of(scope) != 0 => {
+                match self.row(TABLE_MODULEREF, row_of(scope)) {
+                    Some(row) => format!("[.module {}]{}", self.string(row[0]), name),
+                    None => name,
+                }
+            }
+            TABLE_TYPEREF if row_of(scope) != 0 && depth < 16 => format!(
+                "{}/{}",
+                self.typeref_name(row_of(scope) - 1, depth + 1),
+                typeref.name
+            ),
+            _ => name,
+        }
+    }
+
+    /* Read a type from a signature blob. */
+    fn signature_type(&self, sig: &[u8], pos: &mut usize, depth: usize) -> Option<String> {
+        if depth > 16 {
+            return None;
+        }
+        let element = *sig.get(*pos)?;
+        *pos += 1;
+        let name = match element {
+            0x01 => "void".to_string(),
+            0x02 => "bool".to_string(),
+            0x03 => "char".to_string(),
+            0x04 => "int8".to_string(),
+            0x05 => "uint8".to_string(),
+            0x06 => "int16".to_string(),
+            0x07 => "uint16".to_string(),
+            0x08 => "int32".to_string(),
+            0x09 => "uint32".to_string(),
+            0x0a => "int64".to_string(),
+            0x0b => "uint64".to_string(),
+            0x0c => "float32".to_string(),
+            0x0d => "float64".to_string(),
+            0x0e => "string".to_string(),
+            0x0f => format!("{}*", self.signature_type(sig, pos, depth + 1)?),
+            0x10 => format!("{}&", self.signature_type(sig, pos, depth + 1)?),
+            0x11 | 0x12 => {
+                let coded = read_compressed(sig, pos)?;
+                let table = *TYPE_DEF_OR_REF.get((coded & 3) as usize)?;
+                let token = (table as u32) << 24 | coded >> 2;
+                format!(
+                    "{} {}",
+                    if element == 0x11 {
+                        "valuetype"
+                    } else {
+                        "class"
+                    },
+                    self.token_name(token, depth + 1)
+                )
+            }
+            0x13 => format!("!{}", read_compressed(sig, pos)?),
+            0x14 => {
+                let element = self.signature_type(sig, pos, depth + 1)?;
+                let rank = read_compressed(sig, pos)?;
+                /* sizes and lower bounds; we only show the rank */
+                for _ in 0..2 {
+                    let count = read_compressed(sig, pos)?;
+                    for _ in 0..count {
+                        read_compressed(sig, pos)?;
+                    }
+                }
+                format!(
+                    "{}[{}]",
+                    element,
+                    ",".repeat(rank.saturating_sub(1) as usize)
+                )
+            }
+            0x15 => {
+                let generic = self.signature_type(sig, pos, depth + 1)?;
+                let count = read_compressed(sig, pos)?;
+                let mut args = Vec::new();
+                for _ in 0..count {
+                    args.push(self.signature_type(sig, pos, depth + 1)?);
+                }
+                format!("{}<{}>", generic, args.join(", "))
+            }
+            0x16 => "typedref".to_string(),
+            0x18 => "native int".to_string(),
+            0x19 => "native uint".to_string(),
+            0x1b => {
+                /* a method signature; skip it */
+                *pos += 1;
+                let count = read_compressed(sig, pos)?;
+                for _ in 0..=count {
+                    self.signature_type(sig, pos, depth + 1)?;
+                }
+                "method*".to_string()
+            }
+            0x1c => "object".to_string(),
+            0x1d => format!("{}[]", self.signature_type(sig, pos, depth + 1)?),
+            0x1e => format!("!!{}", read_compressed(sig, pos)?),
+            0x1f | 0x20 => {
+                /* custom modifiers */
+                let coded = read_compressed(sig, pos)?;
+                let table = *TYPE_DEF_OR_REF.get((coded & 3) as usize)?;
+                let token = (table as u32) << 24 | coded >> 2;
+                let modified = self.signature_type(sig, pos, depth + 1)?;
+                format!(
+                    "{} {}({})",
+                    modified,
+                    if element == 0x1f { "modreq" } else { "modopt" },
+                    self.token_name(token, depth + 1)
+                )
+            }
+            0x45 => format!("{} pinned", self.signature_type(sig, pos, depth + 1)?),
+            _ => return None,
+        };
+        Some(name)
+    }
+
+    /* The types of a method's locals, from its StandAloneSig. */
+    pub fn local_types(&self, token: u32) -> Option<Vec<String>> {
+        let row = self.row(TABLE_STANDALONESIG, row_of(token))?;
+        let sig = self.blob(row[0]);
+        if sig.first() != Some(&0x07) {
+            return None;
+        }
+        let mut pos = 1;
+        let count = read_compressed(sig, &mut pos)?;
+        let mut types = Vec::new();
+        for _ in 0..count {
+            types.push(self.signature_type(sig, &mut pos, 0)?);
+        }
+        Some(types)
+    }
+
+    fn member_name(&self, owner: Option<usize>, name: &str, depth: usize) -> String {
+        match owner {
+            Some(owner) => format!("{}::{}", self.typedef_name(owner, depth), name),
+            None => name.to_string(),
+        }
+    }
+
+    /* Whatever a token refers to, by name. */
+    pub fn token_name(&self, token: u32, depth: usize) -> String {
+        let row = row_of(token);
+        let name = match table_of(token) {
+            _ if row == 0 || depth > 16 => None,
+            TABLE_TYPEDEF if row <= self.typedefs.len() => Some(self.typedef_name(row - 1, depth)),
+            TABLE_TYPEREF if row <= self.typerefs.len() => Some(self.typeref_name(row - 1, depth)),
+            TABLE_TYPESPEC => self
+                .row(TABLE_TYPESPEC, row)
+                .and_then(|columns| self.signature_type(self.blob(columns[0]), &mut 0, depth + 1)),
+            TABLE_FIELD => self
+                .fields
+                .get(row - 1)
+                .map(|field| self.member_name(field.owner, &field.name, depth)),
+            TABLE_METHODDEF => self
+                .methods
+                .get(row - 1)
+                .map(|method| self.member_name(method.owner, &method.name, depth)),
+            TABLE_MEMBERREF => self.memberrefs.get(row - 1).map(|member| {
+                if member.parent == 0 {
+                    member.name.clone()
+                } else {
+                    format!(
+                        "{}::{}",
+                        self.token_name(member.parent, depth + 1),
+                        member.name
+                    )
+                }
+            }),
+            TABLE_MODULEREF => self
+                .row(TABLE_MODULEREF, row)
+                .map(|columns| format!("[.module {}]", self.string(columns[0]))),
+            TABLE_METHODSPEC => self.row(TABLE_METHODSPEC, row).map(|columns| {
+                let method = self.token_name(columns[0], depth + 1);
+                let sig = self.blob(columns[1]);
+                let mut pos = 1;
+                let args = read_compressed(sig, &mut pos).and_then(|count| {
+                    (0..count)
+                        .map(|_| self.signature_type(sig, &mut pos, depth + 1))
+                        .collect::<Option<Vec<_>>>()
+                });
+                match args {
+                    Some(args) if sig.first() == Some(&0x0a) => {
+                        format!("{}<{}>", method, args.join(", "))
+                    }
+                    _ => method,
+                }
+            }),
+            TOKEN_STRING => self.user_string(row as u32).map(|s| cil::quote_string(&s)),
+            _ => None,
+        };
+        name.unwrap_or_else(|| format!("0x{:08x}", token))
+    }
+}
+
+/* Work out the width of each column and read every table. */
+fn read_tables(map: &Vec<u8>, offset: usize, end: usize, clr: &mut PeClr) -> Result<(), String> {
+    if offset + 24 > end {
+        return Err("Metadata tables are truncated.".to_string());
+    }
+    let heap_sizes = read_byte(map, offset + 6);
+    let valid = read_dword(map, offset + 8) as u64 | (read_dword(map, offset + 12) as u64) << 32;
+
+    let mut cursor = offset + 24;
+    for table in 0..64 {
+        if valid & (1 << table) == 0 {
+            continue;
+        }
+        if table >= TABLE_COUNT {
+            return Err(format!("Unknown metadata table 0x{:02x}.", table));
+        }
+        if cursor + 4 > end {
+            return Err("Metadata row counts are truncated.".to_string());
+        }
+        clr.row_counts[table] = read_dword(map, cursor);
+        cursor += 4;
+    }
+    /* set by edit-and-continue builds */
+    if heap_sizes & 0x40 != 0 {
+        cursor += 4;
+    }
+
+    let index_size = |table: u8| {
+        if clr.row_counts[table as usize] < 0x10000 {
+            2
+        } else {
+            4
+        }
+    };
+    let column_size = |column: Column| match column {
+        U16 => 2,
+        U32 => 4,
+        Str => 2 + 2 * (heap_sizes & 1) as usize,
+        Guid => 2 + (heap_sizes & 2) as usize,
+        Blob => 2 + (heap_sizes & 4) as usize / 2,
+        Index(table) => index_size(table),
+        Coded(tables) => {
+            let bits = (tables.len() as u32).next_power_of_two().trailing_zeros();
+            let max = tables
+                .iter()
+                .filter(|&&t| t != 0xff)
+                .map(|&t| clr.row_counts[t as usize])
+                .max()
+                .unwrap_or(0);
+            if max < 1 << (16 - bits) {
+                2
+            } else {
+                4
+            }
+        }
+    };
+
+    let mut rows = vec![Vec::new(); TABLE_COUNT];
+    for table in 0..TABLE_COUNT {
+        let columns = table_columns(table);
+        let row_size: usize = columns.iter().map(|&c| column_size(c)).sum();
+        let count = clr.row_counts[table] as usize;
+        if cursor + row_size * count > end {
+            return Err(format!("{} table is truncated.", TABLE_NAMES[table]));
+        }
+        for _ in 0..count {
+            let mut row = Vec::new();
+            for &column in columns {
+                let size = column_size(column);
+                let value = if size == 2 {
+                    read_word(map, cursor) as u32
+                } else {
+                    read_dword(map, cursor)
+                };
+                cursor += size;
+                row.push(match column {
+                    Coded(tables) => {
+                        let bits = (tables.len() as u32).next_power_of_two().trailing_zeros();
+                        match tables.get((value & ((1 << bits) - 1)) as usize) {
+                            Some(&table) if table != 0xff && value >> bits != 0 => {
+                                (table as u32) << 24 | value >> bits
+                            }
+                            _ => 0,
+                        }
+                    }
+                    _ => value,
+                });
+            }
+            rows[table].push(row);
+        }
+    }
+    clr.rows = rows;
+    Ok(())
+}
+
+/* A list (of fields, methods or params) owned by each row runs from the
+ * row's index up to the next row's, or to the end of the table. */
+fn list_range(rows: &Vec<Vec<u32>>, row: usize, column: usize, total: usize) -> (usize, usize) {
+    let start = (rows[row][column] as usize).saturating_sub(1).min(total);
+    let end = rows
+        .get(row + 1)
+        .map_or(total, |next| (next[column] as usize).saturating_sub(1))
+        .min(total);
+    (start, end.max(start))
+}
+
+/* Build the core tables from the raw rows. */
+fn read_core_tables(clr: &mut PeClr) {
+    if let Some(row) = clr.rows[TABLE_MODULE as usize].first() {
+        clr.module = Some(ClrModule {
+            name: clr.string(row[1]),
+            mvid: clr.guid(row[2]),
+        });
+    }
+
+    clr.typerefs = clr.rows[TABLE_TYPEREF as usize]
+        .iter()
+        .map(|row| ClrTypeRef {
+            scope: row[0],
+            name: clr.string(row[1]),
+            namespace: clr.string(row[2]),
+        })
+        .collect();
+
+    let field_count = clr.row_counts[TABLE_FIELD as usize] as usize;
+    let method_count = clr.row_counts[TABLE_METHODDEF as usize] as usize;
+    let typedef_rows = &clr.rows[TABLE_TYPEDEF as usize];
+    let mut typedefs: Vec<ClrTypeDef> = (0..typedef_rows.len())
+        .map(|i| ClrTypeDef {
+            flags: typedef_rows[i][0],
+            name: clr.string(typedef_rows[i][1]),
+            namespace: clr.string(typedef_rows[i][2]),
+            extends: typedef_rows[i][3],
+            fields: list_range(typedef_rows, i, 4, field_count),
+            methods: list_range(typedef_rows, i, 5, method_count),
+            enclosing: None,
+        })
+        .collect();
+    for row in &clr.rows[TABLE_NESTEDCLASS as usize] {
+        let (nested, enclosing) = (row[0] as usize, row[1] as usize);
+        if nested >= 1 && nested <= typedefs.len() && enclosing >= 1 && enclosing != nested {
+            typedefs[nested - 1].enclosing = Some(enclosing - 1);
+        }
+    }
+
+    clr.fields = clr.rows[TABLE_FIELD as usize]
+        .iter()
+        .enumerate()
+        .map(|(i, row)| ClrField {
+            flags: row[0] as u16,
+            name: clr.string(row[1]),
+            signature: row[2],
+            owner: typedefs
+                .iter()
+                .position(|t| i >= t.fields.0 && i < t.fields.1),
+        })
+        .collect();
+    clr.methods = clr.rows[TABLE_METHODDEF as usize]
+        .iter()
+        .enumerate()
+        .map(|(i, row)| ClrMethodDef {
+            rva: row[0],
+            impl_flags: row[1] as u16,
+            flags: row[2] as u16,
+            name: clr.string(row[3]),
+            signature: row[4],
+            owner: typedefs
+                .iter()
+                .position(|t| i >= t.methods.0 && i < t.methods.1),
+        })
+        .collect();
+    clr.typedefs = typedefs;
+
+    clr.memberrefs = clr.rows[TABLE_MEMBERREF as usize]
+        .iter()
+        .map(|row| ClrMemberRef {
+            parent: row[0],
+            name: clr.string(row[1]),
+            signature: row[2],
+        })
+        .collect();
+    clr.assembly_refs = clr.rows[TABLE_ASSEMBLYREF as usize]
+        .iter()
+        .map(|row| ClrAssemblyRef {
+            version: [row[0] as u16, row[1] as u16, row[2] as u16, row[3] as u16],
+            flags: row[4],
+            public_key: clr.blob(row[5]).to_vec(),
+            name: clr.string(row[6]),
+            culture: clr.string(row[7]),
+        })
+        .collect();
+}
+
+fn read_metadata(map: &Vec<u8>, clr: &mut PeClr, pe: &PeExecutable) -> Result<(), String> {
+    let root = addr_to_offset(clr.metadata_addr, pe);
+    if root == 0 {
+        return Err("Metadata isn't in a section?".to_string());
+    }
+    let end = (root + clr.metadata_size as usize).min(map.len());
+    if root + 16 > end || read_dword(map, root) != METADATA_MAGIC {
+        return Err("Metadata has a bad signature.".to_string());
+    }
+
+    let version_length = read_dword(map, root + 12) as usize;
+    clr.version = read_string(map, root + 16, version_length);
+    let mut cursor = root + 16 + ((version_length + 3) & !3);
+    if cursor + 4 > end {
+        return Err("Metadata root is truncated.".to_string());
+    }
+    let stream_count = read_word(map, cursor + 2);
+    cursor += 4;
+    for _ in 0..stream_count {
+        if cursor + 8 > end {
+            return Err("Metadata stream headers are truncated.".to_string());
+        }
+        let name = read_string(map, cursor + 8, 32);
+        clr.streams.push(ClrStream {
+            offset: read_dword(map, cursor),
+            size: read_dword(map, cursor + 4),
+            name: name.clone(),
+        });
+        cursor += 8 + ((name.len() + 4) & !3);
+    }
+
+    let mut tables = None;
+    for stream in &clr.streams {
+        let start = root + stream.offset as usize;
+        let stop = start + stream.size as usize;
+        if stop > end {
+            eprintln!("Metadata stream {} exceeds the metadata.", stream.name);
+            continue;
+        }
+        let data = map[start..stop].to_vec();
+        match stream.name.as_str() {
+            "#Strings" => clr.strings = data,
+            "#US" => clr.user_strings = data,
+            "#Blob" => clr.blobs = data,
+            "#GUID" => clr.guids = data,
+            /* #- is the uncompressed (edit-and-continue) form; it can use
+             * the Ptr tables for indirection, which we don't follow */
+            "#~" | "#-" => tables = Some((start, stop)),
+            _ => eprintln!("Unknown metadata stream {}.", stream.name),
+        }
+    }
+
+    match tables {
+        Some((start, stop)) => read_tables(map, start, stop, clr)?,
+        None => return Err("Metadata has no tables.".to_string()),
+    }
+    read_core_tables(clr);
+    Ok(())
+}
+
+pub fn get_clr_header(map: &Vec<u8>, pe: &mut PeExecutable) {
+    let offset = addr_to_offset(pe.dirs[14].address, pe);
+    if offset == 0 {
+        eprintln!("CLR header isn't in a section?");
+        return;
+    }
+    if offset + 0x48 > map.len() {
+        eprintln!("CLR header is truncated.");
+        return;
+    }
+
+    let mut clr = PeClr {
+        runtime_version: (read_word(map, offset + 4), read_word(map, offset + 6)),
+        metadata_addr: read_dword(map, offset + 8),
+        metadata_size: read_dword(map, offset + 12),
+        flags: read_dword(map, offset + 16),
+        entry_point: read_dword(map, offset + 20),
+        resources: (read_dword(map, offset + 24), read_dword(map, offset + 28)),
+        strong_name: (read_dword(map, offset + 32), read_dword(map, offset + 36)),
+        vtable_fixups: (read_dword(map, offset + 48), read_dword(map, offset + 52)),
+        version: String::new(),
+        streams: Vec::new(),
+        row_counts: [0; TABLE_COUNT],
+        rows: vec![Vec::new(); TABLE_COUNT],
+        strings: Vec::new(),
+        user_strings: Vec::new(),
+        blobs: Vec::new(),
+        guids: Vec::new(),
+        module: None,
+        typerefs: Vec::new(),
+        typedefs: Vec::new(),
+        fields: Vec::new(),
+        methods: Vec::new(),
+        memberrefs: Vec::new(),
+        assembly_refs: Vec::new(),
+    };
+    if let Err(error) = read_metadata(map, &mut clr, pe) {
+        eprintln!("{}", error);
+    }
+    pe.clr = Some(clr);
+}
+
+/* Read a method body: the header, the code and any exception clauses. */
+pub fn get_method_body(map: &Vec<u8>, rva: u32, pe: &PeExecutable) -> Option<ClrMethodBody> {
+    let offset = addr_to_offset(rva, pe);
+    if offset == 0 || offset >= map.len() {
+        return None;
+    }
+
+    let first = read_byte(map, offset);
+    let mut body = ClrMethodBody {
+        header_size: 1,
+        max_stack: 8,
+        local_sig: 0,
+        init_locals: false,
+        code: Vec::new(),
+        clauses: Vec::new(),
+    };
+    let (code_size, more_sections) = match first & 3 {
+        /* tiny: the size is in the top six bits */
+        2 => (first as usize >> 2, false),
+        3 => {
+            if offset + 12 > map.len() {
+                return None;
+            }
+            let flags = read_word(map, offset);
+            body.header_size = (flags >> 12) as usize * 4;
+            /* anything shorter would overlap the code */
+            if body.header_size < 12 {
+                return None;
+            }
+            body.max_stack = read_word(map, offset + 2);
+            body.local_sig = read_dword(map, offset + 8);
+            body.init_locals = flags & 0x10 != 0;
+            (read_dword(map, offset + 4) as usize, flags & 0x08 != 0)
+        }
+        _ => return None,
+    };
+    let start = offset + body.header_size;
+    if start + code_size > map.len() {
+        return None;
+    }
+    body.code = map[start..start + code_size].to_vec();
+
+    /* extra sections follow the code, dword aligned */
+    let mut section = (start + code_size + 3) & !3;
+    let mut more = more_sections;
+    while more && section + 4 <= map.len() {
+        let kind = read_byte(map, section);
+        let fat = kind & 0x40 != 0;
+        let size = if fat {
+            read_dword(map, section) as usize >> 8
+        } else {
+            read_byte(map, section + 1) as usize
+        };
+        if kind & 0x01 != 0 {
+            let clause_size = if fat { 24 } else { 12 };
+            let count = size.saturating_sub(4) / clause_size;
+            for i in 0..count {
+                let c = section + 4 + i * clause_size;
+                if c + clause_size > map.len() {
+                    break;
+                }
+                body.clauses.push(if fat {
+                    ClrExceptionClause {
+                        flags: read_dword(map, c),
+                        try_offset: read_dword(map, c + 4),
+                        try_length: read_dword(map, c + 8),
+                        handler_offset: read_dword(map, c + 12),
+                        handler_length: read_dword(map, c + 16),
+                        class_or_filter: read_dword(map, c + 20),
+                    }
+                } else {
+                    ClrExceptionClause {
+                        flags: read_word(map, c) as u32,
+                        try_offset: read_word(map, c + 2) as u32,
+                        try_length: read_byte(map, c + 4) as u32,
+                        handler_offset: read_word(map, c + 5) as u32,
+                        handler_length: read_byte(map, c + 7) as u32,
+                        class_or_filter: read_dword(map, c + 8),
+                    }
+                });
+            }
+        }
+        more = kind & 0x80 != 0;
+        if size == 0 {
+            break;
+        }
+        section = (section + size + 3) & !3;
+    }
+    Some(body)
+}
+
+fn print_version(version: &[u16; 4]) -> String {
+    format!(
+        "{}.{}.{}.{}",
+        version[0], version[1], version[2], version[3]
+    )
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn print_clr_header(clr: &PeClr) {
+    print!("\nCLR header:\n");
+    print!(
+        "Runtime version: {}.{}\n",
+        clr.runtime_version.0, clr.runtime_version.1
+    );
+    print!("Flags: 0x{:08x}", clr.flags);
+    let flag_names = [
+        (0x00000001, "IL only"),
+        (0x00000002, "32-bit required"),
+        (0x00000004, "IL library"),
+        (0x00000008, "strong name signed"),
+        (0x00000010, "native entry point"),
+        (0x00010000, "track debug data"),
+        (0x00020000, "32-bit preferred"),
+    ];
+    let names: Vec<&str> = flag_names
+        .iter()
+        .filter(|(flag, _)| clr.flags & flag != 0)
+        .map(|(_, name)| *name)
+        .collect();
+    if !names.is_empty() {
+        print!(" ({})", names.join(", "));
+    }
+    print!("\n");
+    if clr.flags & 0x10 != 0 {
+        print!("Entry point: RVA 0x{:x}\n", clr.entry_point);
+    } else if clr.entry_point != 0 {
+        print!(
+            "Entry point: {:08x} <{}>\n",
+            clr.entry_point,
+            clr.token_name(clr.entry_point, 0)
+        );
+    }
+    if clr.resources.1 != 0 {
+        print!(
+            "Resources: RVA 0x{:x}, {} bytes\n",
+            clr.resources.0, clr.resources.1
+        );
+    }
+    if clr.strong_name.1 != 0 {
+        print!(
+            "Strong name signature: RVA 0x{:x}, {} bytes\n",
+            clr.strong_name.0, clr.strong_name.1
+        );
+    }
+    if clr.vtable_fixups.1 != 0 {
+        print!(
+            "VTable fixups: RVA 0x{:x}, {} bytes\n",
+            clr.vtable_fixups.0, clr.vtable_fixups.1
+        );
+    }
+    print!(
+        "Metadata: RVA 0x{:x}, {} bytes, version {}\n",
+        clr.metadata_addr, clr.metadata_size, clr.version
+    );
+    for stream in &clr.streams {
+        print!(
+            "\t{:<10} offset 0x{:x}, {} bytes\n",
+            stream.name, stream.offset, stream.size
+        );
+    }
+    print!("Tables:\n");
+    for table in 0..TABLE_COUNT {
+        if clr.row_counts[table] != 0 {
+            print!(
+                "\t{:02x} {:<24} {} rows\n",
+                table, TABLE_NAMES[table], clr.row_counts[table]
+            );
+        }
+    }
+
+    if let Some(module) = &clr.module {
+        print!("\nModule: {}", module.name);
+        if let Some(mvid) = &module.mvid {
+            print!(" {{{}}}", debug::format_guid(mvid));
+        }
+        print!("\n");
+    }
+    if let Some(row) = clr.rows[TABLE_ASSEMBLY as usize].first() {
+        print!(
+            "Assembly: {} {}",
+            clr.string(row[7]),
+            print_version(&[row[1] as u16, row[2] as u16, row[3] as u16, row[4] as u16])
+        );
+        let culture = clr.string(row[8]);
+        if !culture.is_empty() {
+            print!(", culture {}", culture);
+        }
+        print!("\n");
+    }
+
+    if !clr.assembly_refs.is_empty() {
+        print!("\nAssembly references:\n");
+        for assembly in &clr.assembly_refs {
+            print!("\t{} {}", assembly.name, print_version(&assembly.version));
+            if !assembly.culture.is_empty() {
+                print!(", culture {}", assembly.culture);
+            }
+            if !assembly.public_key.is_empty() {
+                print!(
+                    ", public key{} {}",
+                    if assembly.flags & 1 != 0 {
+                        ""
+                    } else {
+                        " token"
+                    },
+                    hex(&assembly.public_key)
+                );
+            }
+            print!("\n");
+        }
+    }
+
+    if !clr.typedefs.is_empty() {
+        print!("\nTypes:\n");
+        for (i, typedef) in clr.typedefs.iter().enumerate() {
+            print!(
+                "\t{:08x} {} (flags 0x{:08x})",
+                (TABLE_TYPEDEF as u32) << 24 | (i as u32 + 1),
+                clr.typedef_name(i, 0),
+                typedef.flags
+            );
+            if typedef.extends != 0 {
+                print!(" extends {}", clr.token_name(typedef.extends, 0));
+            }
+            print!("\n");
+            for f in typedef.fields.0..typedef.fields.1 {
+                let field = &clr.fields[f];
+                let mut pos = 1;
+                let sig = clr.blob(field.signature);
+                print!(
+                    "\t\t{:08x} field {} {}\n",
+                    (TABLE_FIELD as u32) << 24 | (f as u32 + 1),
+                    clr.signature_type(sig, &mut pos, 0)
+                        .unwrap_or_else(|| "?".to_string()),
+                    field.name
+                );
+            }
+            for m in typedef.methods.0..typedef.methods.1 {
+                let method = &clr.methods[m];
+                print!(
+                    "\t\t{:08x} method {}",
+                    (TABLE_METHODDEF as u32) << 24 | (m as u32 + 1),
+                    method.name
+                );
+                if method.rva != 0 {
+                    print!(" (RVA 0x{:x})", method.rva);
+                }
+                print!("\n");
+            }
+        }
+    }
+
+    if !clr.memberrefs.is_empty() {
+        print!("\nMember references:\n");
+        for i in 0..clr.memberrefs.len() {
+            let token = (TABLE_MEMBERREF as u32) << 24 | (i as u32 + 1);
+            print!("\t{:08x} {}\n", token, clr.token_name(token, 0));
+        }
+    }
+}
+
+fn clause_kind(clause: &ClrExceptionClause, clr: &PeClr) -> String {
+    match clause.flags & 7 {
+        0 => format!("catch {}", clr.token_name(clause.class_or_filter, 0)),
+        1 => format!("filter at {:x}", clause.class_or_filter),
+        2 => "finally".to_string(),
+        4 => "fault".to_string(),
+        n => format!("unknown kind {}", n),
+    }
+}
+
+/* Disassemble every method with a CIL body. Native (e.g. C++/CLI) methods
+ * are left to the x86 side. */
+pub fn print_cil_disassembly(clr: &PeClr, pe: &PeExecutable, pe_rel_addr: i32) {
+    print!("\nCIL disassembly:\n");
+    for (i, method) in clr.methods.iter().enumerate() {
+        /* code type: 0 IL, 1 native, 3 runtime */
+        if method.rva == 0 || method.impl_flags & 3 != 0 {
+            continue;
+        }
+        let token = (TABLE_METHODDEF as u32) << 24 | (i as u32 + 1);
+        let body = match get_method_body(&pe.file, method.rva, pe) {
+            Some(body) => body,
+            None => {
+                eprintln!("Bad method body at RVA 0x{:x}.", method.rva);
+                continue;
+            }
+        };
+
+        let mut base = method.rva as u64 + body.header_size as u64;
+        if pe_rel_addr == 0 {
+            base += pe.imagebase;
+        }
+        print!("\n{:x} <{}>:\n", base, clr.token_name(token, 0));
+        print!("\t; method {:08x}, max stack {}", token, body.max_stack);
+        if body.local_sig != 0 {
+            match clr.local_types(body.local_sig) {
+                Some(types) => print!(", locals ({})", types.join(", ")),
+                None => print!(", locals {:08x}", body.local_sig),
+            }
+            if body.init_locals {
+                print!(", init locals");
+            }
+        }
+        print!("\n");
+        for clause in &body.clauses {
+            print!(
+                "\t; try {:x}-{:x}, {} {:x}-{:x}\n",
+                base + clause.try_offset as u64,
+                base + clause.try_offset as u64 + clause.try_length as u64,
+                clause_kind(clause, clr),
+                base + clause.handler_offset as u64,
+                base + clause.handler_offset as u64 + clause.handler_length as u64
+            );
+        }
+
+        let targets = cil::get_cil_branch_targets(&body.code);
+        let resolve = |token: u32| clr.token_name(token, 0);
+        let mut offset = 0;
+        while offset < body.code.len() {
+            offset += cil::print_cil_instr(
+                &body.code,
+                offset,
+                base,
+                targets.binary_search(&offset).is_ok(),
+                &resolve,
+            );
+        }
+    }
+}
@@ -0,0 +1,572 @@
+/* Debug directory (data directory 6).
+ *
+ * An array of 28-byte entries, each pointing to some debug data:
+ *     [dword] characteristics (reserved)
+ *     [dword] timestamp
+ *     [word]  major version
+ *     [word]  minor version
+ *     [dword] type
+ *     [dword] size of data
+ *     [dword] address of data, if it's mapped
+ *     [dword] file offset of data
+ * The data itself is usually in .rdata, but needn't be loaded at all, which
+ * is why we go by the file offset.
+ *
+ * The important one is the CodeView entry, which names the PDB and gives the
+ * GUID and age a symbol server indexes it by. */
+
+use crate::pe::{addr_to_offset, PeExecutable};
+use crate::util::{
+    json_output, json_string, read_byte, read_data, read_dword, read_string, read_word,
+};
+use std::cmp::min;
+
+pub const IMAGE_DEBUG_TYPE_COFF: u32 = 1;
+pub const IMAGE_DEBUG_TYPE_CODEVIEW: u32 = 2;
+pub const IMAGE_DEBUG_TYPE_FPO: u32 = 3;
+pub const IMAGE_DEBUG_TYPE_MISC: u32 = 4;
+pub const IMAGE_DEBUG_TYPE_VC_FEATURE: u32 = 12;
+pub const IMAGE_DEBUG_TYPE_POGO: u32 = 13;
+pub const IMAGE_DEBUG_TYPE_REPRO: u32 = 16;
+pub const IMAGE_DEBUG_TYPE_EMBEDDED_PDB: u32 = 17;
+pub const IMAGE_DEBUG_TYPE_PDBCHECKSUM: u32 = 19;
+pub const IMAGE_DEBUG_TYPE_EX_DLLCHARACTERISTICS: u32 = 20;
+
+const DEBUG_TYPES: [&str; 21] = [
+    "unknown",
+    "COFF",
+    "CodeView",
+    "FPO",
+    "misc",
+    "exception",
+    "fixup",
+    "OMAP to source",
+    "OMAP from source",
+    "Borland",
+    "reserved",
+    "CLSID",
+    "VC feature",
+    "POGO",
+    "ILTCG",
+    "MPX",
+    "repro",
+    "embedded portable PDB",
+    "SPGO",
+    "PDB checksum",
+    "extended DLL characteristics",
+];
+
+const VC_FEATURES: [&str; 5] = ["Pre-VC++ 11.00", "C/C++", "/GS", "/sdl", "guardN"];
+
+pub struct FpoData {
+    pub start: u32,
+    pub size: u32,
+    pub locals: u32, /* in dwords */
+    pub params: u16, /* in dwords */
+    pub prolog: u8,
+    pub regs: u8,
+    pub has_seh: bool,
+    pub use_bp: bool,
+    pub frame: u8, /* 0 FPO, 1 trap, 2 TSS, 3 non-FPO */
+}
+
+pub enum DebugInfo {
+    CodeView {
+        signature: String,
+        guid: Option<[u8; 16]>, /* RSDS */
+        timestamp: u32,         /* NB10 */
+        age: u32,
+        path: String,
+    },
+    Pogo {
+        signature: String,
+        entries: Vec<(u32, u32, String)>, /* address, size, section name */
+    },
+    Repro(Vec<u8>),
+    VcFeature([u32; 5]),
+    Misc {
+        data_type: u32,
+        unicode: bool,
+        text: String,
+    },
+    Fpo(Vec<FpoData>),
+    EmbeddedPdb {
+        uncompressed_size: u32,
+    },
+    PdbChecksum {
+        algorithm: String,
+        checksum: Vec<u8>,
+    },
+    ExDllCharacteristics(u32),
+    None,
+}
+
+pub struct PeDebugEntry {
+    pub timestamp: u32,
+    pub major_version: u16,
+    pub minor_version: u16,
+    pub debug_type: u32,
+    pub size: u32,
+    pub address: u32,
+    pub offset: u32,
+    pub info: DebugInfo,
+}
+
+fn type_name(debug_type: u32) -> String {
+    match DEBUG_TYPES.get(debug_type as usize) {
+        Some(name) => name.to_string(),
+        None => format!("type {}", debug_type),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/* the usual {xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx} form; the first three
+ * parts are little-endian */
+pub fn format_guid(guid: &[u8; 16]) -> String {
+    format!(
+        "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{}",
+        u32::from_le_bytes([guid[0], guid[1], guid[2], guid[3]]),
+        u16::from_le_bytes([guid[4], guid[5]]),
+        u16::from_le_bytes([guid[6], guid[7]]),
+        guid[8],
+        guid[9],
+        hex(&guid[10..]).to_uppercase()
+    )
+}
+
+/* what a symbol server files the PDB under: the GUID without dashes, then
+ * the age in hex */
+fn symbol_server_key(guid: &[u8; 16], age: u32) -> String {
+    format!("{}{:X}", format_guid(guid).replace('-', ""), age)
+}
+
+fn read_codeview(map: &Vec<u8>, offset: usize, end: usize) -> DebugInfo {
+    let signature = String::from_utf8_lossy(&map[offset..offset + 4]).into_owned();
+    match signature.as_str() {
+        "RSDS" if offset + 24 <= end => {
+            let mut guid = [0u8; 16];
+            guid.copy_from_slice(&map[offset + 4..offset + 20]);
+            DebugInfo::CodeView {
+                signature,
+                guid: Some(guid),
+                timestamp: 0,
+                age: read_dword(map, offset + 20),
+                path: read_string(map, offset + 24, end - (offset + 24)),
+            }
+        }
+        "NB10" if offset + 16 <= end => DebugInfo::CodeView {
+            signature,
+            guid: None,
+            timestamp: read_dword(map, offset + 8),
+            age: read_dword(map, offset + 12),
+            path: read_string(map, offset + 16, end - (offset + 16)),
+        },
+        /* NB09 and NB11 have the debug info right here; not much to say */
+        _ => DebugInfo::CodeView {
+            signature,
+            guid: None,
+            timestamp: 0,
+            age: 0,
+            path: String::new(),
+        },
+    }
+}
+
+/* A signature ("LTCG", "PGI", "PGU"), then entries of
+ *     [dword] address
+ *     [dword] size
+ *     name, zero-terminated and padded to a dword */
+fn read_pogo(map: &Vec<u8>, offset: usize, end: usize) -> DebugInfo {
+    let signature = read_data(map, offset, 4)
+        .iter()
+        .rev()
+        .filter(|&&c| c != 0)
+        .map(|&c| c as char)
+        .collect();
+    let mut entries = Vec::new();
+    let mut cursor = offset + 4;
+    while cursor + 8 < end {
+        let name = read_string(map, cursor + 8, end - (cursor + 8));
+        entries.push((
+            read_dword(map, cursor),
+            read_dword(map, cursor + 4),
+            name.clone(),
+        ));
+        /* go by the bytes, since the name needn't be valid UTF-8 */
+        let length = map[cursor + 8..end]
+            .iter()
+            .position(|&c| c == 0)
+            .unwrap_or(end - (cursor + 8));
+        cursor += (8 + length + 1 + 3) & !3;
+    }
+    DebugInfo::Pogo { signature, entries }
+}
+
+fn read_fpo(map: &Vec<u8>, offset: usize, end: usize) -> DebugInfo {
+    let mut records = Vec::new();
+    let mut cursor = offset;
+    while cursor + 16 <= end {
+        let bits = read_word(map, cursor + 14);
+        records.push(FpoData {
+            start: read_dword(map, cursor),
+            size: read_dword(map, cursor + 4),
+            locals: read_dword(map, cursor + 8),
+            params: read_word(map, cursor + 12),
+            prolog: (bits & 0xff) as u8,
+            regs: ((bits >> 8) & 7) as u8,
+            has_seh: bits & 0x0800 != 0,
+            use_bp: bits & 0x1000 != 0,
+            frame: (bits >> 14) as u8,
+        });
+        cursor += 16;
+    }
+    DebugInfo::Fpo(records)
+}
+
+/* IMAGE_DEBUG_MISC: usually the name of the executable, for DBG files */
+fn read_misc(map: &Vec<u8>, offset: usize, end: usize) -> DebugInfo {
+    let data_type = read_dword(map, offset);
+    let length = min(read_dword(map, offset + 4) as usize, end - offset);
+    let unicode = read_byte(map, offset + 8) != 0;
+    let data = offset + 12;
+    let text = if data >= offset + length {
+        String::new()
+    } else if unicode {
+        let units: Vec<u16> = (data..offset + length - 1)
+            .step_by(2)
+            .map(|i| read_word(map, i))
+            .take_while(|&c| c != 0)
+            .collect();
+        String::from_utf16_lossy(&units)
+    } else {
+        read_string(map, data, offset + length - data)
+    };
+    DebugInfo::Misc {
+        data_type,
+        unicode,
+        text,
+    }
+}
+
+fn read_debug_info(map: &Vec<u8>, entry: &PeDebugEntry) -> DebugInfo {
+    let offset = entry.offset as usize;
+    let end = offset + entry.size as usize;
+    if entry.offset == 0 || entry.size == 0 || end > map.len() {
+        return DebugInfo::None;
+    }
+    let size = entry.size as usize;
+
+    match entry.debug_type {
+        IMAGE_DEBUG_TYPE_CODEVIEW if size >= 4 => read_codeview(map, offset, end),
+        IMAGE_DEBUG_TYPE_FPO => read_fpo(map, offset, end),
+        IMAGE_DEBUG_TYPE_MISC if size >= 12 => read_misc(map, offset, end),
+        IMAGE_DEBUG_TYPE_VC_FEATURE if size >= 20 => {
+            let mut counts = [0u32; 5];
+            for (i, count) in counts.iter_mut().enumerate() {
+                *count = read_dword(map, offset + i * 4);
+            }
+            DebugInfo::VcFeature(counts)
+        }
+        IMAGE_DEBUG_TYPE_POGO if size >= 4 => read_pogo(map, offset, end),
+        IMAGE_DEBUG_TYPE_REPRO if size >= 4 => {
+            let length = min(read_dword(map, offset) as usize, size - 4);
+            DebugInfo::Repro(read_data(map, offset + 4, length))
+        }
+        IMAGE_DEBUG_TYPE_EMBEDDED_PDB if size >= 8 => DebugInfo::EmbeddedPdb {
+            uncompressed_size: read_dword(map, offset + 4),
+        },
+        IMAGE_DEBUG_TYPE_PDBCHECKSUM => {
+            let algorithm = read_string(map, offset, size);
+            let checksum = min(offset + algorithm.len() + 1, end);
+            DebugInfo::PdbChecksum {
+                algorithm,
+                checksum: map[checksum..end].to_vec(),
+            }
+        }
+        IMAGE_DEBUG_TYPE_EX_DLLCHARACTERISTICS if size >= 4 => {
+            DebugInfo::ExDllCharacteristics(read_dword(map, offset))
+        }
+        _ => DebugInfo::None,
+    }
+}
+
+pub fn get_debug_directory(map: &Vec<u8>, pe: &mut PeExecutable) {
+    let offset = addr_to_offset(pe.dirs[6].address, pe);
+    if offset == 0 {
+        eprintln!("Debug directory isn't in a section?");
+        return;
+    }
+
+    let count = pe.dirs[6].size as usize / 28;
+    for i in 0..count {
+        let cursor = offset + i * 28;
+        if cursor + 28 > map.len() {
+            eprintln!("Debug directory exceeds file length.");
+            break;
+        }
+        let mut entry = PeDebugEntry {
+            timestamp: read_dword(map, cursor + 4),
+            major_version: read_word(map, cursor + 8),
+            minor_version: read_word(map, cursor + 10),
+            debug_type: read_dword(map, cursor + 12),
+            size: read_dword(map, cursor + 16),
+            address: read_dword(map, cursor + 20),
+            offset: read_dword(map, cursor + 24),
+            info: DebugInfo::None,
+        };
+        if entry.offset as usize + entry.size as usize > map.len() {
+            eprintln!(
+                "Debug data at 0x{:x} ({} bytes) exceeds file length.",
+                entry.offset, entry.size
+            );
+        }
+        entry.info = read_debug_info(map, &entry);
+        pe.debug.push(entry);
+    }
+}
+
+fn print_debug_info(entry: &PeDebugEntry) {
+    match &entry.info {
+        DebugInfo::CodeView {
+            signature,
+            guid,
+            timestamp,
+            age,
+            path,
+        } => {
+            print!("\t\tSignature: {}\n", signature);
+            if let Some(guid) = guid {
+                print!("\t\tGUID: {{{}}}\n", format_guid(guid));
+            }
+            if signature == "NB10" {
+                print!("\t\tPDB timestamp: 0x{:08x}\n", timestamp);
+            }
+            if signature == "RSDS" || signature == "NB10" {
+                print!("\t\tAge: {}\n", age);
+                print!("\t\tPDB: {}\n", path);
+            }
+            if let Some(guid) = guid {
+                print!("\t\tSymbol server key: {}\n", symbol_server_key(guid, *age));
+            }
+        }
+        DebugInfo::Pogo { signature, entries } => {
+            print!("\t\tSignature: {}\n", signature);
+            for (address, size, name) in entries {
+                print!("\t\t0x{:08x} {:8} {}\n", address, size, name);
+            }
+        }
+        DebugInfo::Repro(hash) => {
+            print!("\t\tHash: {}\n", hex(hash));
+        }
+        DebugInfo::VcFeature(counts) => {
+            for (name, count) in VC_FEATURES.iter().zip(counts.iter()) {
+                print!("\t\t{}: {}\n", name, count);
+            }
+        }
+        DebugInfo::Misc {
+            data_type,
+            unicode,
+            text,
+        } => {
+            if *data_type == 1 {
+                print!("\t\tExecutable name: {}", text);
+            } else {
+                print!("\t\tData type {}: {}", data_type, text);
+            }
+            print!("{}\n", if *unicode { " [Unicode]" } else { "" });
+        }
+        DebugInfo::Fpo(records) => {
+            const FRAMES: [&str; 4] = ["FPO", "trap", "TSS", "non-FPO"];
+            for r in records {
+                print!(
+                    "\t\t0x{:08x} {:6} bytes: {} locals, {} params, prologue {} bytes, {} regs, {}",
+                    r.start, r.size, r.locals, r.params, r.prolog, r.regs, FRAMES[r.frame as usize]
+                );
+                if r.has_seh {
+                    print!(", SEH");
+                }
+                if r.use_bp {
+                    print!(", uses EBP");
+                }
+                print!("\n");
+            }
+        }
+        DebugInfo::EmbeddedPdb { uncompressed_size } => {
+            print!(
+                "\t\tCompressed portable PDB, {} bytes uncompressed\n",
+                uncompressed_size
+            );
+        }
+        DebugInfo::PdbChecksum {
+            algorithm,
+            checksum,
+        } => {
+            print!("\t\t{}: {}\n", algorithm, hex(checksum));
+        }
+        DebugInfo::ExDllCharacteristics(flags) => {
+            print!("\t\tFlags: 0x{:08x}", flags);
+            if flags & 0x01 != 0 {
+                print!(", CET compatible");
+            }
+            if flags & 0x02 != 0 {
+                print!(", CET strict");
+            }
+            print!("\n");
+        }
+        DebugInfo::None => {}
+    }
+}
+
+pub fn print_debug_directory(pe: &PeExecutable) {
+    print!("\nDebug directory:\n");
+    if json_output() {
+        print!("\t{}\n", debug_directory_json(&pe.debug));
+        return;
+    }
+    for entry in &pe.debug {
+        print!(
+            "\t{} (timestamp 0x{:08x}, version {}.{}, offset 0x{:x}, length {})\n",
+            type_name(entry.debug_type),
+            entry.timestamp,
+            entry.major_version,
+            entry.minor_version,
+            entry.offset,
+            entry.size
+        );
+        /* with /Brepro the timestamp is part of the hash, not a time */
+        if entry.debug_type == IMAGE_DEBUG_TYPE_REPRO && entry.size == 0 {
+            print!("\t\tTimestamp is a hash of the image.\n");
+        }
+        print_debug_info(entry);
+    }
+}
+
+fn debug_info_json(info: &DebugInfo) -> String {
+    match info {
+        DebugInfo::CodeView {
+            signature,
+            guid,
+            timestamp,
+            age,
+            path,
+        } => {
+            let mut fields = vec![format!("\"signature\": {}", json_string(signature))];
+            if let Some(guid) = guid {
+                fields.push(format!("\"guid\": \"{}\"", format_guid(guid)));
+                fields.push(format!(
+                    "\"symbol_server_key\": \"{}\"",
+                    symbol_server_key(guid, *age)
+                ));
+            } else {
+                fields.push(format!("\"pdb_timestamp\": {}", timestamp));
+            }
+            fields.push(format!("\"age\": {}", age));
+            fields.push(format!("\"pdb\": {}", json_string(path)));
+            fields.join(", ")
+        }
+        DebugInfo::Pogo { signature, entries } => {
+            let entries: Vec<String> = entries
+                .iter()
+                .map(|(address, size, name)| {
+                    format!(
+                        "{{\"address\": {}, \"size\": {}, \"name\": {}}}",
+                        address,
+                        size,
+                        json_string(name)
+                    )
+                })
+                .collect();
+            format!(
+                "\"signature\": {}, \"sections\": [{}]",
+                json_string(signature),
+                entries.join(", ")
+            )
+        }
+        DebugInfo::Repro(hash) => format!("\"hash\": \"{}\"", hex(hash)),
+        DebugInfo::VcFeature(counts) => {
+            let counts: Vec<String> = VC_FEATURES
+                .iter()
+                .zip(counts.iter())
+                .map(|(name, count)| format!("{}: {}", json_string(name), count))
+                .collect();
+            format!("\"features\": {{{}}}", counts.join(", "))
+        }
+        DebugInfo::Misc {
+            data_type,
+            unicode,
+            text,
+        } => format!(
+            "\"data_type\": {}, \"unicode\": {}, \"text\": {}",
+            data_type,
+            unicode,
+            json_string(text)
+        ),
+        DebugInfo::Fpo(records) => {
+            let records: Vec<String> = records
+                .iter()
+                .map(|r| {
+                    format!(
+                        "{{\"start\": {}, \"size\": {}, \"locals\": {}, \"params\": {}, \
+                         \"prolog\": {}, \"regs\": {}, \"seh\": {}, \"use_bp\": {}, \"frame\": {}}}",
+                        r.start,
+                        r.size,
+                        r.locals,
+                        r.params,
+                        r.prolog,
+                        r.regs,
+                        r.has_seh,
+                        r.use_bp,
+                        r.frame
+                    )
+                })
+                .collect();
+            format!("\"fpo\": [{}]", records.join(", "))
+        }
+        DebugInfo::EmbeddedPdb { uncompressed_size } => {
+            format!("\"uncompressed_size\": {}", uncompressed_size)
+        }
+        DebugInfo::PdbChecksum {
+            algorithm,
+            checksum,
+        } => format!(
+            "\"algorithm\": {}, \"checksum\": \"{}\"",
+            json_string(algorithm),
+            hex(checksum)
+        ),
+        DebugInfo::ExDllCharacteristics(flags) => format!("\"flags\": {}", flags),
+        DebugInfo::None => String::new(),
+    }
+}
+
+/* The directory as a JSON array of {"type", "timestamp", "version",
+ * "offset", "size", ...} objects, with the decoded fields of each type, for
+ * --json. */
+pub fn debug_directory_json(entries: &Vec<PeDebugEntry>) -> String {
+    let entries: Vec<String> = entries
+        .iter()
+        .map(|e| {
+            let mut fields = format!(
+                "\"type\": {}, \"type_name\": {}, \"timestamp\": {}, \"version\": \"{}.{}\", \
+                 \"address\": {}, \"offset\": {}, \"size\": {}",
+                e.debug_type,
+                json_string(&type_name(e.debug_type)),
+                e.timestamp,
+                e.major_version,
+                e.minor_version,
+                e.address,
+                e.offset,
+                e.size
+            );
+            let info = debug_info_json(&e.info);
+            if !info.is_empty() {
+                fields += ", ";
+                fields += &info;
+            }
+            format!("{{{}}}", fields)
+        })
+        .collect();
+    format!("[{}]", entries.join(", "))
+}
@@ -0,0 +1,305 @@
+/* Delay-load imports (data directory 13).
+ *
+ * Each module has a 32-byte descriptor:
+ *     [dword] attributes: 1 if the other fields are RVAs, else VAs (VC6)
+ *     [dword] module name
+ *     [dword] module handle
+ *     [dword] delay IAT
+ *     [dword] delay import name table
+ *     [dword] bound delay IAT
+ *     [dword] unload delay IAT
+ *     [dword] timestamp of the bound module, or 0
+ * The table ends with a zeroed descriptor.
+ *
+ * Until the first call, each IAT slot points to a small thunk that loads the
+ * address of the slot and jumps to a per-module "tail merge" routine, which
+ * calls __delayLoadHelper2 to resolve the import and patch the slot:
+ *     mov eax, offset slot        (x64: lea rax, [slot])
+ *     jmp __tailMerge_module
+ * We name the thunks, tail merges and the helper so that call sites make
+ * sense in the disassembly. */
+
+use crate::pe::{addr2section, addr_to_offset, PeExecutable, PeNameTableEntry};
+use crate::util::{read_byte, read_dword, read_qword, read_string, read_word};
+use std::cmp::min;
+
+#[derive(Default)]
+pub struct PeDelayImportModule {
+    pub module: String,
+    pub attributes: u32,
+    pub hmod_addr: u32,
+    pub iat_addr: u32,
+    pub int_addr: u32,
+    pub bound_iat_addr: u32,
+    pub unload_iat_addr: u32,
+    pub timestamp: u32,
+    pub nametab: Vec<PeNameTableEntry>,
+}
+
+impl PeDelayImportModule {
+    /* the name of an IAT slot, or None if it isn't one of ours */
+    pub fn slot_name(&self, address: u32, pe: &PeExecutable) -> Option<String> {
+        let size = if pe.magic == 0x10b { 4 } else { 8 };
+        if address < self.iat_addr || (address - self.iat_addr) % size != 0 {
+            return None;
+        }
+        let index = ((address - self.iat_addr) / size) as usize;
+        self.nametab.get(index).map(|entry| {
+            if entry.is_ordinal {
+                format!("{}!{}", self.module, entry.ordinal)
+            } else {
+                format!("{}!{}", self.module, entry.name)
+            }
+        })
+    }
+}
+
+/* VC6 wrote VAs rather than RVAs; both are 32 bits even in PE32+ files. */
+fn to_rva(value: u32, attributes: u32, pe: &PeExecutable) -> u32 {
+    if attributes & 1 != 0 || value == 0 {
+        value
+    } else {
+        value.wrapping_sub(pe.imagebase as u32)
+    }
+}
+
+/* Where a table at the given address has to stop: the end of its section's
+ * data, or of the file if that comes first. */
+fn table_end(address: u32, map: &Vec<u8>, pe: &PeExecutable) -> usize {
+    match addr2section(address, pe) {
+        Some(sec) => min(sec.offset as usize + sec.length as usize, map.len()),
+        None => map.len(),
+    }
+}
+
+fn read_delay_name_table(map: &Vec<u8>, module: &mut PeDelayImportModule, pe: &PeExecutable) {
+    let offset = addr_to_offset(module.int_addr, pe);
+    if offset == 0 {
+        eprintln!(
+            "Delay import name table for {} isn't in a section?",
+            module.module
+        );
+        return;
+    }
+
+    let size = if pe.magic == 0x10b { 4 } else { 8 };
+    let end = table_end(module.int_addr, map, pe);
+    let mut i = 0;
+    loop {
+        if offset + (i + 1) * size > end {
+            eprintln!(
+                "Delay import name table for {} isn't terminated.",
+                module.module
+            );
+            break;
+        }
+        let (value, is_ordinal) = if size == 4 {
+            let value = read_dword(map, offset + i * 4);
+            (value as u64, value & 0x80000000 != 0)
+        } else {
+            let value = read_qword(map, offset + i * 8);
+            (value, value & (1 << 63) != 0)
+        };
+        if value == 0 {
+            break;
+        }
+
+        let mut entry = PeNameTableEntry {
+            name: String::new(),
+            ordinal: 0,
+            is_ordinal,
+            bound: None,
+        };
+        if is_ordinal {
+            entry.ordinal = value as u16;
+        } else {
+            /* skip the hint */
+            let address = to_rva(value as u32, module.attributes, pe);
+            let name = addr_to_offset(address, pe);
+            if name == 0 {
+                eprintln!("Delay import name at {:x} isn't in a section?", address);
+            } else {
+                entry.name = read_string(map, name + 2, 0);
+            }
+        }
+        module.nametab.push(entry);
+        i += 1;
+    }
+}
+
+pub fn get_delay_import_table(map: &Vec<u8>, pe: &mut PeExecutable) {
+    let offset = addr_to_offset(pe.dirs[13].address, pe);
+    if offset == 0 {
+        eprintln!("Delay import directory isn't in a section?");
+        return;
+    }
+
+    let mut cursor = offset;
+    while cursor + 32 <= map.len() && read_dword(map, cursor + 4) != 0 {
+        let attributes = read_dword(map, cursor);
+        let field = |n: usize| to_rva(read_dword(map, cursor + n * 4), attributes, pe);
+
+        let mut module = PeDelayImportModule {
+            module: String::new(),
+            attributes,
+            hmod_addr: field(2),
+            iat_addr: field(3),
+            int_addr: field(4),
+            bound_iat_addr: field(5),
+            unload_iat_addr: field(6),
+            timestamp: read_dword(map, cursor + 28),
+            nametab: Vec::new(),
+        };
+        if attributes & !1 != 0 {
+            eprintln!(
+                "Delay import descriptor has unknown attributes 0x{:x}.",
+                attributes
+            );
+        }
+        let name = addr_to_offset(field(1), pe);
+        module.module = if name != 0 {
+            read_string(map, name, 0)
+        } else {
+            format!("<module at {:x}>", field(1))
+        };
+
+        read_delay_name_table(map, &mut module, pe);
+        pe.delay_imports.push(module);
+        cursor += 32;
+    }
+
+    find_delay_thunks(map, pe);
+}
+
+/* Follow each IAT slot's initial value to its thunk, and from there to the
+ * tail merge and the helper it calls. */
+fn find_delay_thunks(map: &Vec<u8>, pe: &mut PeExecutable) {
+    let size = if pe.magic == 0x10b { 4 } else { 8 };
+    let mut thunks = Vec::new();
+
+    for module in &pe.delay_imports {
+        let iat = addr_to_offset(module.iat_addr, pe);
+        if iat == 0 {
+            continue;
+        }
+        let end = table_end(module.iat_addr, map, pe);
+        let mut tail_merge = None;
+
+        for i in 0..module.nametab.len() {
+            if iat + (i + 1) * size > end {
+                eprintln!("Delay IAT for {} is cut off.", module.module);
+                break;
+            }
+            let value = if size == 4 {
+                read_dword(map, iat + i * 4) as u64
+            } else {
+                read_qword(map, iat + i * 8)
+            };
+            let thunk = value.wrapping_sub(pe.imagebase) as u32;
+            let offset = addr_to_offset(thunk, pe);
+            if offset == 0 || offset + 12 > map.len() {
+                continue;
+            }
+
+            /* mov eax, imm32 / lea rax, [rip+rel32]; then jmp rel32 */
+            let (slot, jmp) = if size == 4 && read_byte(map, offset) == 0xb8 {
+                (
+                    (read_dword(map, offset + 1) as u64).wrapping_sub(pe.imagebase) as u32,
+                    5,
+                )
+            } else if size == 8
+                && read_byte(map, offset) == 0x48
+                && read_word(map, offset + 1) == 0x058d
+            {
+                (
+                    thunk
+                        .wrapping_add(7)
+                        .wrapping_add(read_dword(map, offset + 3)),
+                    7,
+                )
+            } else {
+                continue;
+            };
+            if slot != module.iat_addr + (i * size) as u32 || read_byte(map, offset + jmp) != 0xe9 {
+                continue;
+            }
+
+            if let Some(name) = module.slot_name(slot, pe) {
+                thunks.push((thunk, name));
+            }
+            let target = thunk
+                .wrapping_add(jmp as u32 + 5)
+                .wrapping_add(read_dword(map, offset + jmp + 1));
+            tail_merge = Some(target);
+        }
+
+        if let Some(tail_merge) = tail_merge {
+            thunks.push((
+                tail_merge,
+                format!("__tailMerge_{}", module.module.replace('.', "_")),
+            ));
+
+            /* the first call in the tail merge is to the helper */
+            let offset = addr_to_offset(tail_merge, pe);
+            if offset == 0 {
+                continue;
+            }
+            for j in 0..64 {
+                if offset + j + 5 > map.len() {
+                    break;
+                }
+                if read_byte(map, offset + j) == 0xe8 {
+                    let helper = tail_merge
+                        .wrapping_add(j as u32 + 5)
+                        .wrapping_add(read_dword(map, offset + j + 1));
+                    if !thunks.iter().any(|(a, _)| *a == helper) {
+                        thunks.push((helper, "__delayLoadHelper2".to_string()));
+                    }
+                    break;
+                }
+            }
+        }
+    }
+
+    pe.delay_thunks = thunks;
+}
+
+/* A name for an IAT slot or thunk address, for comments in the
+ * disassembly. */
+pub fn get_delay_import_name(address: u32, pe: &PeExecutable) -> Option<String> {
+    for module in &pe.delay_imports {
+        if let Some(name) = module.slot_name(address, pe) {
+            return Some(name);
+        }
+    }
+    pe.delay_thunks
+        .iter()
+        .find(|(a, _)| *a == address)
+        .map(|(_, name)| name.clone())
+}
+
+pub fn print_delay_imports(pe: &PeExecutable) {
+    print!("\nDelay-loaded modules:\n");
+    for module in &pe.delay_imports {
+        print!("\t{}", module.module);
+        if module.attributes & 1 == 0 {
+            print!(" (old VA-based descriptor)");
+        }
+        if module.timestamp != 0 {
+            print!(" (bound, timestamp 0x{:08x})", module.timestamp);
+        }
+        print!("\n");
+    }
+
+    print!("\nDelay-loaded functions:\n");
+    for module in &pe.delay_imports {
+        print!("\t{}:\n", module.module);
+        for entry in &module.nametab {
+            if entry.is_ordinal {
+                print!("\t\t<ordinal {}>\n", entry.ordinal);
+            } else {
+                print!("\t\t{}\n", entry.name);
+            }
+        }
+    }
+}
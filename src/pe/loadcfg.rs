@@ -0,0 +1,370 @@
+/* Load configuration (data directory 10).
+ *
+ * IMAGE_LOAD_CONFIG_DIRECTORY has grown with nearly every release of
+ * Windows; its first dword is its size, which tells us which fields it has.
+ * Most fields are pointer-sized, so the 32-bit and 64-bit layouts differ.
+ * Addresses in it are VAs.
+ *
+ * What we care about most is what it tells us about code: the SafeSEH
+ * handler table (32-bit only), the Control Flow Guard table of valid
+ * indirect call targets, and the security cookie, which lets us find
+ * __security_check_cookie. */
+
+use crate::pe::{addr_to_offset, PeExecutable};
+use crate::util::{read_byte, read_dword, read_qword, read_word};
+use std::cmp::min;
+
+/* How to print a field: a dword in hex ('x') or decimal ('d'), a word in
+ * hex ('w') or decimal ('h'), or a pointer-sized address ('p') or number
+ * ('n'). */
+const LOAD_CONFIG_FIELDS: [(&str, usize, usize, char); 46] = [
+    /* name, 32-bit offset, 64-bit offset, kind */
+    ("Size", 0x00, 0x00, 'd'),
+    ("Timestamp", 0x04, 0x04, 'x'),
+    ("Major version", 0x08, 0x08, 'h'),
+    ("Minor version", 0x0a, 0x0a, 'h'),
+    ("Global flags to clear", 0x0c, 0x0c, 'x'),
+    ("Global flags to set", 0x10, 0x10, 'x'),
+    ("Critical section default timeout", 0x14, 0x14, 'd'),
+    ("Decommit free block threshold", 0x18, 0x18, 'n'),
+    ("Decommit total free threshold", 0x1c, 0x20, 'n'),
+    ("Lock prefix table", 0x20, 0x28, 'p'),
+    ("Maximum allocation size", 0x24, 0x30, 'n'),
+    ("Virtual memory threshold", 0x28, 0x38, 'n'),
+    ("Process affinity mask", 0x30, 0x40, 'p'),
+    ("Process heap flags", 0x2c, 0x48, 'x'),
+    ("CSD version", 0x34, 0x4c, 'w'),
+    ("Dependent load flags", 0x36, 0x4e, 'w'),
+    ("Edit list", 0x38, 0x50, 'p'),
+    ("Security cookie", 0x3c, 0x58, 'p'),
+    ("SafeSEH handler table", 0x40, 0x60, 'p'),
+    ("SafeSEH handler count", 0x44, 0x68, 'n'),
+    ("CFG check function pointer", 0x48, 0x70, 'p'),
+    ("CFG dispatch function pointer", 0x4c, 0x78, 'p'),
+    ("CFG function table", 0x50, 0x80, 'p'),
+    ("CFG function count", 0x54, 0x88, 'n'),
+    ("Guard flags", 0x58, 0x90, 'x'),
+    ("Code integrity flags", 0x5c, 0x94, 'w'),
+    ("Code integrity catalog", 0x5e, 0x96, 'w'),
+    ("Code integrity catalog offset", 0x60, 0x98, 'x'),
+    ("Guard address-taken IAT table", 0x68, 0xa0, 'p'),
+    ("Guard address-taken IAT count", 0x6c, 0xa8, 'n'),
+    ("Guard longjmp target table", 0x70, 0xb0, 'p'),
+    ("Guard longjmp target count", 0x74, 0xb8, 'n'),
+    ("Dynamic value relocation table", 0x78, 0xc0, 'p'),
+    ("CHPE metadata", 0x7c, 0xc8, 'p'),
+    ("Guard RF failure routine", 0x80, 0xd0, 'p'),
+    ("Guard RF failure routine pointer", 0x84, 0xd8, 'p'),
+    ("Dynamic value relocation table offset", 0x88, 0xe0, 'x'),
+    ("Dynamic value relocation table section", 0x8c, 0xe4, 'h'),
+    ("Guard RF verify stack pointer pointer", 0x90, 0xe8, 'p'),
+    ("Hot patch table offset", 0x94, 0xf0, 'x'),
+    ("Enclave configuration", 0x9c, 0xf8, 'p'),
+    ("Volatile metadata", 0xa0, 0x100, 'p'),
+    ("Guard EH continuation table", 0xa4, 0x108, 'p'),
+    ("Guard EH continuation count", 0xa8, 0x110, 'n'),
+    ("Guard XFG check function pointer", 0xac, 0x118, 'p'),
+    ("Guard XFG dispatch function pointer", 0xb0, 0x120, 'p'),
+];
+
+const GUARD_FLAGS: [(u32, &str); 15] = [
+    (0x00000100, "CF instrumented"),
+    (0x00000200, "CF write instrumented"),
+    (0x00000400, "CF function table present"),
+    (0x00000800, "security cookie unused"),
+    (0x00001000, "protect delay-load IAT"),
+    (0x00002000, "delay-load IAT in its own section"),
+    (0x00004000, "CF export suppression info present"),
+    (0x00008000, "CF export suppression enabled"),
+    (0x00010000, "CF longjmp table present"),
+    (0x00020000, "RF instrumented"),
+    (0x00040000, "RF enabled"),
+    (0x00080000, "RF strict"),
+    (0x00100000, "retpoline present"),
+    (0x00400000, "EH continuation table present"),
+    (0x00800000, "XFG enabled"),
+];
+
+/* bits 28-31 of the guard flags give the size of the extra data after each
+ * RVA in the CFG function table */
+const GUARD_CF_FUNCTION_TABLE_SIZE_SHIFT: u32 = 28;
+
+#[derive(Default)]
+pub struct PeLoadConfig {
+    pub offset: usize, /* in the file */
+    pub size: usize,
+    pub security_cookie: u64,
+    pub guard_cf_check: u64,
+    pub guard_cf_dispatch: u64,
+    pub guard_flags: u32,
+    pub seh_handlers: Vec<u32>,       /* RVAs */
+    pub cf_functions: Vec<(u32, u8)>, /* RVAs and their flags */
+    pub security_check_cookie: Option<u32>,
+}
+
+impl PeLoadConfig {
+    /* A field from the table, if the structure is big enough to have it. */
+    fn field(&self, map: &Vec<u8>, name: &str, pe: &PeExecutable) -> Option<u64> {
+        let (_, off32, off64, kind) = LOAD_CONFIG_FIELDS.iter().find(|f| f.0 == name)?;
+        read_field(map, self, *off32, *off64, *kind, pe)
+    }
+}
+
+fn read_field(
+    map: &Vec<u8>,
+    config: &PeLoadConfig,
+    off32: usize,
+    off64: usize,
+    kind: char,
+    pe: &PeExecutable,
+) -> Option<u64> {
+    let wide = pe.magic != 0x10b;
+    let offset = if wide { off64 } else { off32 };
+    let width = match kind {
+        'w' | 'h' => 2,
+        'x' | 'd' => 4,
+        _ => {
+            if wide {
+                8
+            } else {
+                4
+            }
+        }
+    };
+    if offset + width > config.size {
+        return None;
+    }
+    let offset = config.offset + offset;
+    Some(match width {
+        2 => read_word(map, offset) as u64,
+        4 => read_dword(map, offset) as u64,
+        _ => read_qword(map, offset),
+    })
+}
+
+fn va_to_offset(va: u64, pe: &PeExecutable) -> usize {
+    addr_to_offset(va.wrapping_sub(pe.imagebase) as u32, pe)
+}
+
+/* MSVC's __security_check_cookie starts by comparing its argument with the
+ * cookie and returning if they match:
+ *     cmp ecx, [__security_cookie]       3b 0d <VA>
+ *     cmp rcx, [rip+__security_cookie]   48 3b 0d <rel32>
+ *     jnz ...                            75 xx
+ * Newer versions write the jnz with a bnd prefix (f2 75 xx), for MPX. */
+fn find_security_check_cookie(map: &Vec<u8>, cookie: u64, pe: &PeExecutable) -> Option<u32> {
+    let cookie_rva = cookie.wrapping_sub(pe.imagebase) as u32;
+
+    for sec in &pe.sections {
+        if sec.flags & 0x20 == 0 {
+            continue;
+        }
+        let start = sec.offset as usize;
+        let end = min(start + min(sec.length, sec.min_alloc) as usize, map.len());
+        let is_jnz = |at: usize| {
+            read_byte(map, at) == 0x75
+                || (at + 1 < end && read_byte(map, at) == 0xf2 && read_byte(map, at + 1) == 0x75)
+        };
+        let mut i = start;
+        while i + 8 <= end {
+            let ip = sec.address + (i - start) as u32;
+            if pe.magic == 0x10b {
+                if read_word(map, i) == 0x0d3b
+                    && read_dword(map, i + 2) as u64 == cookie
+                    && is_jnz(i + 6)
+                {
+                    return Some(ip);
+                }
+            } else if read_byte(map, i) == 0x48
+                && read_word(map, i + 1) == 0x0d3b
+                && ip.wrapping_add(7).wrapping_add(read_dword(map, i + 3)) == cookie_rva
+                && is_jnz(i + 7)
+            {
+                return Some(ip);
+            }
+            i += 1;
+        }
+    }
+    None
+}
+
+pub fn get_load_config(map: &Vec<u8>, pe: &mut PeExecutable) {
+    let offset = addr_to_offset(pe.dirs[10].address, pe);
+    if offset == 0 {
+        eprintln!("Load configuration directory isn't in a section?");
+        return;
+    }
+    if offset + 4 > map.len() {
+        eprintln!("Load configuration exceeds file length.");
+        return;
+    }
+
+    /* Some old linkers put 0x40 in the directory and the real size in the
+     * structure, or the other way around; the structure wins. */
+    let mut size = read_dword(map, offset) as usize;
+    if size == 0 {
+        size = pe.dirs[10].size as usize;
+    }
+    if offset + size > map.len() {
+        eprintln!("Load configuration exceeds file length.");
+        size = map.len() - offset;
+    }
+
+    let mut config = PeLoadConfig {
+        offset,
+        size,
+        ..Default::default()
+    };
+    config.security_cookie = config.field(map, "Security cookie", pe).unwrap_or(0);
+    config.guard_cf_check = config
+        .field(map, "CFG check function pointer", pe)
+        .unwrap_or(0);
+    config.guard_cf_dispatch = config
+        .field(map, "CFG dispatch function pointer", pe)
+        .unwrap_or(0);
+    config.guard_flags = config.field(map, "Guard flags", pe).unwrap_or(0) as u32;
+
+    /* SafeSEH: an array of handler RVAs */
+    let table = config.field(map, "SafeSEH handler table", pe).unwrap_or(0);
+    let count = config.field(map, "SafeSEH handler count", pe).unwrap_or(0) as usize;
+    if table != 0 && count != 0 {
+        let array = va_to_offset(table, pe);
+        let end = count.checked_mul(4).and_then(|n| n.checked_add(array));
+        if array == 0 || end.map_or(true, |end| end > map.len()) {
+            eprintln!("SafeSEH handler table at {:x} isn't in a section?", table);
+        } else {
+            for i in 0..count {
+                config.seh_handlers.push(read_dword(map, array + i * 4));
+            }
+        }
+    }
+
+    /* CFG: an array of RVAs, each followed by some bytes of flags */
+    let table = config.field(map, "CFG function table", pe).unwrap_or(0);
+    let count = config.field(map, "CFG function count", pe).unwrap_or(0) as usize;
+    if table != 0 && count != 0 {
+        let stride = 4 + (config.guard_flags >> GUARD_CF_FUNCTION_TABLE_SIZE_SHIFT) as usize;
+        let array = va_to_offset(table, pe);
+        let end = count.checked_mul(stride).and_then(|n| n.checked_add(array));
+        if array == 0 || end.map_or(true, |end| end > map.len()) {
+            eprintln!("CFG function table at {:x} isn't in a section?", table);
+        } else {
+            for i in 0..count {
+                let entry = array + i * stride;
+                let flags = if stride > 4 {
+                    read_byte(map, entry + 4)
+                } else {
+                    0
+                };
+                config.cf_functions.push((read_dword(map, entry), flags));
+            }
+        }
+    }
+
+    if config.security_cookie != 0 {
+        config.security_check_cookie = find_security_check_cookie(map, config.security_cookie, pe);
+    }
+
+    pe.load_config = Some(config);
+}
+
+/* A name for a function the load configuration told us about. */
+pub fn get_load_config_function_name(ip: u32, pe: &PeExecutable) -> Option<String> {
+    let config = pe.load_config.as_ref()?;
+    if config.security_check_cookie == Some(ip) {
+        return Some("__security_check_cookie".to_string());
+    }
+    None
+}
+
+/* A name for data the load configuration points to, so that e.g. calls
+ * through the CFG check pointer are commented. */
+pub fn get_load_config_data_name(address: u32, pe: &PeExecutable) -> Option<String> {
+    let config = pe.load_config.as_ref()?;
+    let va = address as u64 + pe.imagebase;
+    if config.security_cookie != 0 && va == config.security_cookie {
+        Some("__security_cookie".to_string())
+    } else if config.guard_cf_check != 0 && va == config.guard_cf_check {
+        Some("__guard_check_icall_fptr".to_string())
+    } else if config.guard_cf_dispatch != 0 && va == config.guard_cf_dispatch {
+        Some("__guard_dispatch_icall_fptr".to_string())
+    } else {
+        None
+    }
+}
+
+fn print_guard_flags(flags: u32) {
+    let mut buffer = String::new();
+    for (flag, name) in GUARD_FLAGS.iter() {
+        if flags & flag != 0 {
+            buffer += ", ";
+            buffer += name;
+        }
+    }
+    let known = GUARD_FLAGS.iter().fold(0xf0000000, |a, (flag, _)| a | flag);
+    if flags & !known != 0 {
+        buffer += &format!(", (unknown flags 0x{:08x})", flags & !known);
+    }
+    if flags >> GUARD_CF_FUNCTION_TABLE_SIZE_SHIFT != 0 {
+        buffer += &format!(
+            ", {} bytes of flags per CFG function",
+            flags >> GUARD_CF_FUNCTION_TABLE_SIZE_SHIFT
+        );
+    }
+    println!("Guard flags: 0x{:08x}{}", flags, buffer);
+}
+
+pub fn print_load_config(
+    map: &Vec<u8>,
+    config: &PeLoadConfig,
+    pe: &PeExecutable,
+    pe_rel_addr: i32,
+) {
+    let base = if pe_rel_addr != 0 { 0 } else { pe.imagebase };
+
+    print!("\nLoad configuration:\n");
+    for (name, off32, off64, kind) in LOAD_CONFIG_FIELDS.iter() {
+        let value = match read_field(map, config, *off32, *off64, *kind, pe) {
+            Some(value) => value,
+            None => continue,
+        };
+        match kind {
+            'x' if *name == "Guard flags" => print_guard_flags(value as u32),
+            'x' | 'w' => println!("{}: 0x{:x}", name, value),
+            'p' if value == 0 => println!("{}: (none)", name),
+            'p' => println!("{}: 0x{:x}", name, value),
+            _ => println!("{}: {}", name, value),
+        }
+    }
+
+    if let Some(ip) = config.security_check_cookie {
+        println!("__security_check_cookie: 0x{:x}", ip as u64 + base);
+    }
+
+    if !config.seh_handlers.is_empty() {
+        print!("SafeSEH handlers:\n");
+        for handler in &config.seh_handlers {
+            print!("\t0x{:x}\n", *handler as u64 + base);
+        }
+    }
+
+    if !config.cf_functions.is_empty() {
+        print!("CFG functions:\n");
+        for (address, flags) in &config.cf_functions {
+            print!("\t0x{:x}", *address as u64 + base);
+            if flags & 1 != 0 {
+                print!(" (suppressed)");
+            }
+            if flags & 2 != 0 {
+                print!(" (export suppressed)");
+            }
+            if flags & 4 != 0 {
+                print!(" (exception handler)");
+            }
+            if flags & 8 != 0 {
+                print!(" (XFG)");
+            }
+            print!("\n");
+        }
+    }
+}
@@ -1,10 +1,26 @@
+pub mod authenticode;
+pub mod bound;
+pub mod checksum;
+pub mod clr;
+pub mod debug;
+pub mod delay;
+pub mod loadcfg;
+pub mod resource;
+pub mod rich;
+pub mod tls;
+pub mod unwind;
+
+use crate::accel;
+use crate::codepage;
+use crate::msgtable;
+use crate::rsrc::{self, ResourceEntry};
 use crate::util::{read_byte, read_data, read_dword, read_qword, read_string, read_word};
 use crate::x86::defines::Instruction;
 use crate::x86::defines::X86ArgType::{IMM, MEM, MOFFS, NONE, REL, REL8, RM};
 use crate::x86::defines::{
     Argument, INSTR_FUNC, INSTR_JUMP, INSTR_RELOC, INSTR_SCANNED, INSTR_VALID, OP_BRANCH, OP_STOP,
 };
-use crate::{DISASSEMBLE, DISASSEMBLE_ALL, FULL_CONTENTS, SPECFILE};
+use crate::{DISASSEMBLE, DISASSEMBLE_ALL, DUMP_UNWIND, FULL_CONTENTS, SPECFILE};
 use std::cmp::min;
 use std::error::Error;
 use std::fs::File;
@@ -137,6 +153,7 @@ pub struct PeSection {
 pub struct PeReloc {
     pub offset: u32,
     pub reloc_type: u32,
+    pub param: u16, /* for HIGHADJ, the low half of the target */
 }
 
 pub struct PeExport {
@@ -149,10 +166,13 @@ pub struct PeNameTableEntry {
     pub name: String,
     pub ordinal: u16,
     pub is_ordinal: bool,
+    pub bound: Option<u32>, /* address written by BIND.EXE, if we have no name */
 }
 
 pub struct PeImportModule {
     pub module: String,
+    pub timestamp: u32, /* nonzero if bound; -1 if described by the bound import directory */
+    pub forwarder_chain: u32,
     pub iat_addr: u32,
     pub nametab: Vec<PeNameTableEntry>,
     pub count: usize,
@@ -176,6 +196,18 @@ pub struct PeExecutable {
     pub import_count: usize,
     pub relocs: Vec<PeReloc>,
     pub reloc_count: usize,
+    pub resources: Vec<ResourceEntry>,
+    pub bound_imports: Vec<bound::PeBoundImport>,
+    pub delay_imports: Vec<delay::PeDelayImportModule>,
+    pub delay_thunks: Vec<(u32, String)>, /* delay-load thunks and helpers */
+    pub tls: Option<tls::PeTlsDirectory>,
+    pub load_config: Option<loadcfg::PeLoadConfig>,
+    pub debug: Vec<debug::PeDebugEntry>,
+    pub certificates: Vec<authenticode::PeCertificate>,
+    pub checksum: u32, /* as computed, not as stored */
+    pub rich: Option<rich::RichHeader>,
+    pub clr: Option<clr::PeClr>,                 /* for .NET assemblies */
+    pub functions: Vec<unwind::RuntimeFunction>, /* from the exception directory */
 }
 
 /* in pe_section.c */
@@ -450,6 +482,7 @@ pub fn print_header(pe: &PeExecutable, pe_rel_addr: i32) {
         println!("Image type: 64-bit\n");
         print_opt64(&pe.opt64, pe_rel_addr);
     }
+    checksum::print_checksum(pe);
 }
 
 pub fn print_specfile(pe: &PeExecutable) -> Result<(), Box<dyn Error>> {
@@ -519,93 +552,256 @@ pub fn get_export_table(map: &Vec<u8>, pe: &mut PeExecutable) {
     pe.export_count = header.addr_table_count as usize;
 }
 
+/* Read the names of a module's imports from its lookup table, or failing
+ * that from its IAT. A bound IAT holds the addresses BIND.EXE resolved
+ * instead of names, which is all we can show for those. */
 pub fn get_import_name_table(
     map: &Vec<u8>,
-    module: &import_module,
+    module: &mut PeImportModule,
     nametab_addr: u32,
+    bound: bool,
     pe: &PeExecutable,
 ) {
-    let offset = addr2offset(nametab_addr, pe);
-    let mut count = 0usize;
-
-    if pe.magic == 0x10b {
-        while read_dword(map, offset + count * 4) {
-            count += 1;
-        }
-    } else {
-        while read_qword(map, offset + count * 8) {
-            count += 1;
-        }
+    let offset = addr_to_offset(nametab_addr, pe);
+    if offset == 0 {
+        eprintln!("Import name table for {} isn't in a section?", module.module);
+        return;
     }
 
-    // module.nametab = malloc(count * sizeof(*module.nametab));
-
-    for i in 0..count {
-        if pe.magic == 0x10b {
+    let size = if pe.magic == 0x10b { 4 } else { 8 };
+    let mut i = 0;
+    loop {
+        if offset + (i + 1) * size > map.len() {
+            eprintln!("Import name table for {} isn't terminated.", module.module);
+            break;
+        }
+        let (address, is_ordinal) = if size == 4 {
             let address = read_dword(map, offset + i * 4);
-            module.nametab[i].is_ordinal = !!(address & (1 << 31));
+            (address as u64, address & (1 << 31) != 0)
         } else {
-            address = read_qword(map, offset + i * 8);
-            module.nametab[i].is_ordinal = !!(address & (1 << 63));
+            let address = read_qword(map, offset + i * 8);
+            (address, address & (1 << 63) != 0)
+        };
+        if address == 0 {
+            break;
         }
-        if module.nametab[i].is_ordinal {
-            module.nametab[i].ordinal = address;
+
+        let mut entry = PeNameTableEntry {
+            name: String::new(),
+            ordinal: 0,
+            is_ordinal,
+            bound: None,
+        };
+        if is_ordinal && !bound {
+            entry.ordinal = address as u16;
         } else {
-            let name_len = 0usize;
-            module.nametab[i].name = read_data(map, addr2offset(address, pe) + 2, name_len);
-            /* skip hint */
+            let name = if bound { 0 } else { addr_to_offset(address as u32, pe) };
+            if name != 0 {
+                /* skip hint */
+                entry.name = read_string(map, name + 2, 0);
+            } else {
+                /* also what an unmarked bound IAT looks like */
+                entry.is_ordinal = false;
+                entry.bound = Some(address as u32);
+            }
         }
+        module.nametab.push(entry);
+        i += 1;
     }
-    module.count = count;
+    module.count = module.nametab.len();
 }
 
-pub fn get_import_module_table(pe: &mut PeExecutable) {
-    let offset = addr2offset(pe.dirs[1].address, pe);
-    let zeroes: [u32; 5] = [0; 5];
-    pe.import_count = 0;
-    // while (read_data(map, offset + pe.import_count * 20), zeroes, 20)) {
-    //     pe.import_count += 1;
-    // }
+pub fn get_import_module_table(map: &Vec<u8>, pe: &mut PeExecutable) {
+    let offset = addr_to_offset(pe.dirs[1].address, pe);
+    if offset == 0 {
+        eprintln!("Import directory isn't in a section?");
+        return;
+    }
+    let iat_dir = if pe.dirs.len() > 12 && pe.dirs[12].size > 0 {
+        Some(&pe.dirs[12])
+    } else {
+        None
+    };
 
-    // pe.imports = malloc(pe.import_count * sizeof(struct import_module));
+    /* The table ends with a zeroed descriptor:
+     *     [dword] import lookup table ("OriginalFirstThunk"), or 0
+     *     [dword] timestamp
+     *     [dword] forwarder chain
+     *     [dword] module name
+     *     [dword] IAT ("FirstThunk") */
+    let mut cursor = offset;
+    while cursor + 20 <= map.len() && read_data(map, cursor, 20) != [0u8; 20] {
+        let lookup_addr = read_dword(map, cursor);
+        let name_addr = read_dword(map, cursor + 12);
+        let mut module = PeImportModule {
+            module: read_string(map, addr_to_offset(name_addr, pe), 0),
+            timestamp: read_dword(map, cursor + 4),
+            forwarder_chain: read_dword(map, cursor + 8),
+            iat_addr: read_dword(map, cursor + 16),
+            nametab: Vec::new(),
+            count: 0,
+        };
+
+        /* Some linkers leave out the lookup table, so the IAT is all we
+         * have, and if the image was bound it no longer holds names. */
+        let nametab_addr = if lookup_addr != 0 {
+            lookup_addr
+        } else {
+            if let Some(dir) = iat_dir {
+                if module.iat_addr < dir.address || module.iat_addr >= dir.address + dir.size {
+                    eprintln!("IAT for {} isn't in the IAT directory?", module.module);
+                }
+            }
+            module.iat_addr
+        };
+        let bound = lookup_addr == 0 && module.timestamp != 0;
+        get_import_name_table(map, &mut module, nametab_addr, bound, pe);
+        pe.imports.push(module);
+        cursor += 20;
+    }
+    pe.import_count = pe.imports.len();
+}
 
-    for i in 0..pe.import_count {
-        let mut mod_addr_len = 0usize;
-        pe.imports[i].module = read_string(
-            map,
-            addr2offset(read_dword(map, offset + i * 20 + 12), pe),
-            mod_str_len,
-        );
-        pe.imports[i].iat_addr = read_dword(map, offset + i * 20 + 16);
-        get_import_name_table(map, &pe.imports[i], read_dword(map, offset + i * 20), pe);
+/* Base relocation types. 5, 7 and 9 mean different things on different
+ * machines; ARM64 doesn't have any of its own and just uses DIR64. */
+pub const IMAGE_REL_BASED_ABSOLUTE: u32 = 0;
+pub const IMAGE_REL_BASED_HIGH: u32 = 1;
+pub const IMAGE_REL_BASED_LOW: u32 = 2;
+pub const IMAGE_REL_BASED_HIGHLOW: u32 = 3;
+pub const IMAGE_REL_BASED_HIGHADJ: u32 = 4;
+pub const IMAGE_REL_BASED_MIPS_JMPADDR: u32 = 5;
+pub const IMAGE_REL_BASED_ARM_MOV32: u32 = 5;
+pub const IMAGE_REL_BASED_THUMB_MOV32: u32 = 7;
+pub const IMAGE_REL_BASED_MIPS_JMPADDR16: u32 = 9;
+pub const IMAGE_REL_BASED_DIR64: u32 = 10;
+
+fn is_mips(machine: u16) -> bool {
+    /* R3000, R4000, R10000, WCE MIPS v2, MIPS16, MIPS FPU, MIPS16 FPU */
+    [0x162, 0x166, 0x168, 0x169, 0x266, 0x366, 0x466].contains(&machine)
+}
+
+fn is_arm(machine: u16) -> bool {
+    /* ARM, Thumb, ARMv7 (Thumb-2) */
+    [0x1c0, 0x1c2, 0x1c4].contains(&machine)
+}
+
+pub fn reloc_type_name(reloc_type: u32, machine: u16) -> String {
+    match reloc_type {
+        IMAGE_REL_BASED_ABSOLUTE => "ABSOLUTE".to_string(),
+        IMAGE_REL_BASED_HIGH => "HIGH".to_string(),
+        IMAGE_REL_BASED_LOW => "LOW".to_string(),
+        IMAGE_REL_BASED_HIGHLOW => "HIGHLOW".to_string(),
+        IMAGE_REL_BASED_HIGHADJ => "HIGHADJ".to_string(),
+        5 if is_mips(machine) => "MIPS_JMPADDR".to_string(),
+        5 if is_arm(machine) => "ARM_MOV32".to_string(),
+        7 if is_arm(machine) => "THUMB_MOV32".to_string(),
+        9 if is_mips(machine) => "MIPS_JMPADDR16".to_string(),
+        IMAGE_REL_BASED_DIR64 => "DIR64".to_string(),
+        _ => format!("type {}", reloc_type),
     }
 }
 
-pub fn get_reloc_table(map: &Vec<u8>, pe: &mut pe) {
-    let mut offset = 0usize;
-    // let offset = addr2offset(pe.dirs[5].address, pe), cursor = offset;
-    // unsigned i, reloc_idx = 0;
+/* How many bytes a relocation patches, or 0 if we don't know it. The MOV32
+ * types patch a movw/movt pair. */
+pub fn reloc_size(reloc_type: u32, machine: u16) -> u32 {
+    match reloc_type {
+        IMAGE_REL_BASED_HIGH | IMAGE_REL_BASED_LOW | IMAGE_REL_BASED_HIGHADJ => 2,
+        IMAGE_REL_BASED_HIGHLOW => 4,
+        5 if is_mips(machine) => 4,
+        5 | 7 if is_arm(machine) => 8,
+        9 if is_mips(machine) => 4,
+        IMAGE_REL_BASED_DIR64 => 8,
+        _ => 0,
+    }
+}
+
+/* the 16-bit immediate of an ARM movw/movt */
+fn arm_mov_imm(insn: u32) -> u32 {
+    (insn >> 4) & 0xf000 | insn & 0xfff
+}
 
-    pe.reloc_count = 0;
-    while cursor < offset + pe.dirs[5].size {
-        pe.reloc_count += (read_dword(map, cursor + 4) - 8) / 2;
-        cursor += read_dword(map, cursor + 4);
+/* the same for Thumb-2, which is stored as two halfwords */
+fn thumb_mov_imm(map: &Vec<u8>, offset: usize) -> u32 {
+    let hw1 = read_word(map, offset) as u32;
+    let hw2 = read_word(map, offset + 2) as u32;
+    (hw1 & 0xf) << 12 | (hw1 & 0x400) << 1 | (hw2 & 0x7000) >> 4 | hw2 & 0xff
+}
+
+/* The address a relocation points to, relative to the image base, if the
+ * relocated bytes give all of it. HIGH and LOW only give half, and
+ * JMPADDR16 is MIPS16 code, which we don't decode. */
+pub fn get_reloc_target(map: &Vec<u8>, r: &PeReloc, pe: &PeExecutable) -> Option<u32> {
+    let offset = addr_to_offset(r.offset, pe);
+    let machine = pe.header.Machine;
+    if offset == 0 || offset + reloc_size(r.reloc_type, machine) as usize > map.len() {
+        return None;
     }
+    let value = match r.reloc_type {
+        IMAGE_REL_BASED_HIGHLOW => read_dword(map, offset) as u64,
+        IMAGE_REL_BASED_DIR64 => read_qword(map, offset),
+        IMAGE_REL_BASED_HIGHADJ => {
+            ((read_word(map, offset) as u32) << 16).wrapping_add(r.param as i16 as u32) as u64
+        }
+        5 if is_arm(machine) => {
+            (arm_mov_imm(read_dword(map, offset)) | arm_mov_imm(read_dword(map, offset + 4)) << 16)
+                as u64
+        }
+        7 if is_arm(machine) => {
+            (thumb_mov_imm(map, offset) | thumb_mov_imm(map, offset + 4) << 16) as u64
+        }
+        5 if is_mips(machine) => {
+            /* a J or JAL: the low 28 bits, within the 256 MB region of the
+             * delay slot */
+            let region = (pe.imagebase + r.offset as u64 + 4) & !0xfffffff;
+            region | ((read_dword(map, offset) & 0x3ffffff) << 2) as u64
+        }
+        _ => return None,
+    };
+    Some(value.wrapping_sub(pe.imagebase) as u32)
+}
 
-    // pe.relocs = malloc(pe.reloc_count * sizeof(*pe.relocs));
-    cursor = offset;
-    while cursor < offset + pe.dirs[5].size {
+pub fn get_reloc_table(map: &Vec<u8>, pe: &mut PeExecutable) {
+    let offset = addr_to_offset(pe.dirs[5].address, pe);
+    let end = min(offset + pe.dirs[5].size as usize, map.len());
+    let mut cursor = offset;
+
+    pe.relocs = Vec::new();
+    while cursor + 8 <= end {
         let block_base = read_dword(map, cursor);
-        let block_size = read_dword(map, cursor + 4);
+        let block_size = read_dword(map, cursor + 4) as usize;
+        if block_size < 8 || cursor + block_size > end {
+            eprint!(
+                "Relocation block for {:x} has bad size {}.\n",
+                block_base, block_size
+            );
+            break;
+        }
 
-        for i in 0..(block_size - 8) / 2 {
+        let count = (block_size - 8) / 2;
+        let mut i = 0;
+        while i < count {
             let r = read_word(map, cursor + 8 + i * 2);
-            pe.relocs[reloc_idx].offset = block_base + (r & 0xfff);
-            pe.relocs[reloc_idx].reloc_type = r >> 12;
-            reloc_idx += 1;
+            let reloc_type = (r >> 12) as u32;
+            let mut param = 0;
+            /* HIGHADJ takes two slots; the second holds the low half of the
+             * target, which decides whether the high half needs a carry */
+            if reloc_type == IMAGE_REL_BASED_HIGHADJ && i + 1 < count {
+                i += 1;
+                param = read_word(map, cursor + 8 + i * 2);
+            }
+            /* ABSOLUTE entries are only padding, to keep blocks aligned */
+            if reloc_type != IMAGE_REL_BASED_ABSOLUTE {
+                pe.relocs.push(PeReloc {
+                    offset: block_base + (r & 0xfff) as u32,
+                    reloc_type,
+                    param,
+                });
+            }
+            i += 1;
         }
         cursor += block_size;
     }
+    pe.reloc_count = pe.relocs.len();
 }
 
 pub fn readpe(map: &Vec<u8>, offset_pe: usize, pe: &mut PeExecutable) {
@@ -666,6 +862,9 @@ pub fn readpe(map: &Vec<u8>, offset_pe: usize, pe: &mut PeExecutable) {
         // }
     }
 
+    checksum::get_checksum(&pe.file, offset_pe, pe);
+    rich::get_rich_header(&pe.file, offset_pe, pe);
+
     /* Read the Data Directories.
      * PE is bizarre. It tries to make all of these things generic by putting
      * them in separate "directories". But the order of these seems to be fixed
@@ -675,11 +874,39 @@ pub fn readpe(map: &Vec<u8>, offset_pe: usize, pe: &mut PeExecutable) {
         get_export_table(&pe.file, pe);
     }
     if cdirs >= 2 && pe.dirs[1].size > 0 {
-        get_import_module_table(pe);
+        get_import_module_table(&pe.file, pe);
+    }
+    if cdirs >= 3 && pe.dirs[2].size > 0 {
+        pe.resources = resource::read_rsrc_tree(&pe.file, pe);
+    }
+    if cdirs >= 4 && pe.dirs[3].size > 0 {
+        unwind::get_exception_table(&pe.file, pe);
+    }
+    if cdirs >= 5 && pe.dirs[4].size > 0 {
+        /* the only directory given as a file offset rather than an RVA */
+        authenticode::get_certificates(&pe.file, offset_pe, pe);
     }
     if cdirs >= 6 && pe.dirs[5].size > 0 {
         get_reloc_table(&pe.file, pe);
     }
+    if cdirs >= 7 && pe.dirs[6].size > 0 {
+        debug::get_debug_directory(&pe.file, pe);
+    }
+    if cdirs >= 10 && pe.dirs[9].size > 0 {
+        tls::get_tls_directory(&pe.file, pe);
+    }
+    if cdirs >= 11 && pe.dirs[10].size > 0 {
+        loadcfg::get_load_config(&pe.file, pe);
+    }
+    if cdirs >= 12 && pe.dirs[11].size > 0 {
+        bound::get_bound_import_table(&pe.file, pe);
+    }
+    if cdirs >= 14 && pe.dirs[13].size > 0 {
+        delay::get_delay_import_table(&pe.file, pe);
+    }
+    if cdirs >= 15 && pe.dirs[14].size > 0 {
+        clr::get_clr_header(&pe.file, pe);
+    }
 
     /* Read the code. */
     if mode & DISASSEMBLE {
@@ -690,6 +917,7 @@ pub fn readpe(map: &Vec<u8>, offset_pe: usize, pe: &mut PeExecutable) {
 pub fn dumppe(map: &Vec<u8>, offset_pe: usize, mut pe_rel_addr: i32) {
     let mut pe = PeExecutable::new();
     readpe(map, offset_pe, &mut pe);
+    checksum::write_fixed_checksum(map, offset_pe, &pe);
 
     if mode == SPECFILE {
         print_specfile(&pe);
@@ -723,6 +951,24 @@ pub fn dumppe(map: &Vec<u8>, offset_pe: usize, mut pe_rel_addr: i32) {
 
     if (mode & DUMPHEADER) {
         print_header(&pe, pe_rel_addr);
+        if let Some(rich) = &pe.rich {
+            rich::print_rich_header(rich);
+        }
+        if let Some(tls) = &pe.tls {
+            tls::print_tls_directory(tls, &pe, pe_rel_addr);
+        }
+        if let Some(config) = &pe.load_config {
+            loadcfg::print_load_config(&pe.file, config, &pe, pe_rel_addr);
+        }
+        if !pe.debug.is_empty() {
+            debug::print_debug_directory(&pe);
+        }
+        if !pe.certificates.is_empty() {
+            authenticode::print_certificates(&pe);
+        }
+        if let Some(clr) = &pe.clr {
+            clr::print_clr_header(clr);
+        }
     }
 
     if (mode & DUMPEXPORT) {
@@ -760,7 +1006,13 @@ pub fn dumppe(map: &Vec<u8>, offset_pe: usize, mut pe_rel_addr: i32) {
         if (pe.imports) {
             print!("Imported modules:\n");
             for i in 0..pe.import_count {
-                print!("\t{}\n", pe.imports[i].module);
+                print!("\t{}", pe.imports[i].module);
+                match pe.imports[i].timestamp {
+                    0 => {}
+                    0xffffffff => print!(" (bound)"),
+                    t => print!(" (bound, timestamp 0x{:08x})", t),
+                }
+                print!("\n");
             }
 
             print!("\nImported functions:\n");
@@ -769,6 +1021,8 @@ pub fn dumppe(map: &Vec<u8>, offset_pe: usize, mut pe_rel_addr: i32) {
                 for j in 0..pe.imports[i].count {
                     if pe.imports[i].nametab[j].is_ordinal {
                         print!("\t\t<ordinal {}>\n", pe.imports[i].nametab[j].ordinal);
+                    } else if let Some(address) = pe.imports[i].nametab[j].bound {
+                        print!("\t\t<bound to 0x{:08x}>\n", address);
                     } else {
                         print!("\t\t{}\n", pe.imports[i].nametab[j].name);
                     }
@@ -777,10 +1031,34 @@ pub fn dumppe(map: &Vec<u8>, offset_pe: usize, mut pe_rel_addr: i32) {
         } else {
             print!("No imported module table\n");
         }
+
+        if !pe.bound_imports.is_empty() {
+            bound::print_bound_imports(&pe);
+        }
+        if !pe.delay_imports.is_empty() {
+            delay::print_delay_imports(&pe);
+        }
+    }
+
+    if mode & DUMP_UNWIND && !pe.functions.is_empty() {
+        unwind::print_exception_table(&pe, pe_rel_addr);
     }
 
     if (mode & DISASSEMBLE) {
+        msgtable::set_messages(resource::find_messages(&pe.file, &pe.resources));
         print_sections(&pe);
+        if let Some(clr) = &pe.clr {
+            clr::print_cil_disassembly(clr, &pe, pe_rel_addr);
+        }
+    }
+
+    if mode & DUMPRSRC {
+        if !pe.resources.is_empty() {
+            accel::set_menu_items(resource::find_menu_items(&pe.file, &pe.resources));
+            rsrc::print_resources(&pe.file, &pe.resources, true);
+        } else {
+            print!("No resource table\n");
+        }
     }
 
     // freepe(&pe);
@@ -821,60 +1099,60 @@ pub fn get_export_name(ip: u32, pe: &PeExecutable) -> Option<String> {
     return None;
 }
 
+/* The label of a function start, if we know of one. */
+pub fn get_function_name(ip: u32, pe: &PeExecutable) -> Option<String> {
+    get_export_name(ip, pe)
+        .or_else(|| tls::get_tls_callback_name(ip, pe))
+        .or_else(|| loadcfg::get_load_config_function_name(ip, pe))
+}
+
 pub fn get_imported_name(offset: usize, pe: &PeExecutable) -> Option<String> {
-    let mut comment: String;
-    for i in 0..pe.import_count {
-        let module = &pe.imports[i];
-        let mut index = offset - module.iat_addr;
-        if pe.magic == 0x10b {
-            index /= 4;
-        } else {
-            index /= 8;
+    let size = if pe.magic == 0x10b { 4 } else { 8 };
+    for module in &pe.imports[..pe.import_count] {
+        if offset < module.iat_addr as usize || (offset - module.iat_addr as usize) % size != 0 {
+            continue;
         }
+        let index = (offset - module.iat_addr as usize) / size;
 
         if index < module.count {
             if module.nametab[index].is_ordinal {
-                Some(format!(
-                    "{}.{}",
-                    module.module, module.nametab[index].ordinal
-                ))
+                return Some(format!("{}.{}", module.module, module.nametab[index].ordinal));
+            }
+            if let Some(address) = module.nametab[index].bound {
+                return Some(format!("{}.<bound to 0x{:08x}>", module.module, address));
             }
-            Some(module.nametab[index].name.clone())
+            return Some(module.nametab[index].name.clone());
         }
     }
-    None
+
+    /* Delay-load IAT slots, and the thunks they initially point to. Calls
+     * to the thunks are plain relative calls, so this is also what keeps them
+     * from being treated as mundane. */
+    delay::get_delay_import_name(offset as u32, pe)
+        .or_else(|| loadcfg::get_load_config_data_name(offset as u32, pe))
 }
 
-pub fn get_reloc(ip: u32, pe: &PeExecutable) -> Option<PeReloc> {
-    for i in 0..pe.reloc_count {
-        if pe.relocs[i].offset == ip {
-            return Some(pe.relocs[i].clone());
-        }
-    }
-    None
+pub fn get_reloc(ip: u32, pe: &PeExecutable) -> Option<&PeReloc> {
+    pe.relocs.iter().find(|r| r.offset == ip)
 }
 
 pub fn relocate_arg(instr: &Instruction, arg: &Argument, pe: &PeExecutable) -> Option<String> {
-    match get_reloc(arg.ip, pe) {
-        None => None,
-        Some(x) => match x.reloc_type {
-            0 => None,
-            3 => {
-                if arg.arg_type == IMM
-                    || (arg.arg_type == RM && instr.modrm_reg == -1)
-                    || arg.arg_type == MOFFS
-                {
-                    if pe_rel_addr {
-                        Some(format!("{}", arg.value - pe.opt32.ImageBase))
-                    } else {
-                        Some(format!("{}", arg.value))
-                    }
-                }
-            }
-            _ => None,
-        },
+    let r = get_reloc(arg.ip, pe)?;
+
+    /* only an immediate or absolute memory address can be relocated */
+    if arg.arg_type == IMM
+        || (arg.arg_type == RM && instr.modrm_reg == -1)
+        || arg.arg_type == MOFFS
+    {
+        let target = get_reloc_target(&pe.file, r, pe)? as u64;
+        if pe_rel_addr {
+            Some(format!("{:x}", target))
+        } else {
+            Some(format!("{:x}", target + pe.imagebase))
+        }
+    } else {
+        None
     }
-    None
 }
 
 pub fn get_arg_comment(
@@ -888,6 +1166,14 @@ pub fn get_arg_comment(
     if arg.arg_type == NONE {
         return None;
     }
+    /* Name error codes from the module's own message tables. Small numbers
+     * are too likely to be something else, so only take those with the
+     * severity or facility bits set. */
+    if arg.arg_type == IMM && arg.value > 0xffff && arg.value <= 0xffffffff {
+        if let Some(text) = msgtable::describe_message(arg.value as u32) {
+            return Some(format!("\"{}\"", codepage::escape(&text)));
+        }
+    }
     if instr.modrm_reg == 16 && arg.arg_type >= RM && arg.arg_type <= MEM {
         let tip = end_ip + arg.value;
         let mut abstip = tip;
@@ -988,7 +1274,7 @@ pub fn print_pe_instr(sec: &PeSection, ip: u32, p: &Vec<u8>, pe: &PeExecutable)
      * and relocated according to the contents of .Reloc. */
 
     let mut comment = get_arg_comment(map, sec, ip + len, &instr, &instr.args[0], pe);
-    if comment.is_some() {
+    if comment.is_none() {
         comment = get_arg_comment(map, sec, ip + len, &instr, &instr.args[1], pe);
     }
 
@@ -1048,9 +1334,12 @@ pub fn print_disassembly(sec: &PeSection, pe: &PeExecutable) {
         }
 
         if sec.instr_flags[relip] & INSTR_FUNC {
-            let name = get_export_name(ip, pe);
+            let name = get_function_name(ip, pe);
             print!("\n");
             print!("%lx <{}>:\n", absip, name ? name : "no name");
+            if mode & DUMP_UNWIND {
+                unwind::print_function_unwind(ip, pe, pe_rel_addr);
+            }
         }
 
         relip += print_pe_instr(sec, ip, &buffer.into_bytes(), pe);
@@ -1175,36 +1464,39 @@ pub fn scan_segment(mut ip: i32, pe: &PeExecutable) {
                     eprint!("Byte tagged INSTR_RELOC has no Reloc; this is a bug.\n");
                 }
 
-                match r.unwrap().reloc_type {
-                    3 => {
-                        /* HIGHLOW */
-                        if pe.magic != 0x10b {
-                            eprint!("HIGHLOW relocation in 64-bit image?\n");
-                        }
-                        taddr = read_dword(&pe.file, &sec.offset + i) - pe.imagebase;
-                        tsec = addr2section(taddr, pe);
-
-                        if !tsec {
-                            warn_at(
-                                "Relocation to %#x isn't in a section?\n",
-                                read_dword(&pe.file, &sec.offset + i),
-                            );
-                            continue;
-                        }
-
-                        /* Only try to scan it if it's an immediate address. If someone is
-                         * dereferencing an address inside a code section, it's data. */
-                        if tsec.flags & 0x20 && (instr.op.arg0 == IMM || instr.op.arg1 == IMM) {
-                            tsec.instr_flags[taddr - tsec.address] |= INSTR_FUNC;
-                            scan_segment(taddr, pe);
-                        }
-                    }
-                    _ => {
+                let r = r.unwrap();
+                if r.reloc_type == IMAGE_REL_BASED_HIGHLOW && pe.magic != 0x10b {
+                    eprint!("HIGHLOW relocation in 64-bit image?\n");
+                }
+                if r.reloc_type == IMAGE_REL_BASED_DIR64 && pe.magic == 0x10b {
+                    eprint!("DIR64 relocation in 32-bit image?\n");
+                }
+
+                taddr = match get_reloc_target(&pe.file, r, pe) {
+                    Some(taddr) => taddr,
+                    None => {
                         warn_at(
-                            "Don't know how to handle relocation type {}\n",
-                            r.unwrap().reloc_type,
+                            "Can't follow {} relocation in x86 code\n",
+                            reloc_type_name(r.reloc_type, pe.header.Machine),
                         );
+                        continue;
                     }
+                };
+                tsec = addr2section(taddr, pe);
+
+                if !tsec {
+                    warn_at(
+                        "Relocation to %#x isn't in a section?\n",
+                        taddr as u64 + pe.imagebase,
+                    );
+                    continue;
+                }
+
+                /* Only try to scan it if it's an immediate address. If someone is
+                 * dereferencing an address inside a code section, it's data. */
+                if tsec.flags & 0x20 && (instr.op.arg0 == IMM || instr.op.arg1 == IMM) {
+                    tsec.instr_flags[taddr - tsec.address] |= INSTR_FUNC;
+                    scan_segment(taddr, pe);
                 }
                 break;
             }
@@ -1317,6 +1609,19 @@ pub fn print_section_flags(flags: u32) {
     print!("    Alignment: {} (2**{})\n", 1 << alignment, alignment);
 }
 
+/* Mark a function we learned about from one of the tables, and scan it. */
+fn scan_function(address: u32, what: &str, pe: &PeExecutable) {
+    match addr2section(address, pe) {
+        None => eprint!("{} {:x} isn't in a section?\n", what, address),
+        Some(sec) => {
+            if sec.flags & 0x20 != 0 {
+                sec.instr_flags[(address - sec.address) as usize] |= INSTR_FUNC;
+                scan_segment(address as i32, pe);
+            }
+        }
+    }
+}
+
 pub fn read_sections(pe: &PeExecutable) {
     let entry_point = if pe.magic == 0x10b {
         pe.opt32.AddressOfEntryPoint
@@ -1336,20 +1641,14 @@ pub fn read_sections(pe: &PeExecutable) {
             continue;
         }
         if &sec.flags & 0x20 {
-            match (pe.relocs[i].reloc_type) {
-                0 => { /* padding */ }
-                3 => {
-                    /* HIGHLOW */
-                    /* scanning is done in scan_segment() */
-                    &sec.instr_flags[address - &sec.address] |= INSTR_RELOC;
-                }
-
-                _ => {
-                    eprint!(
-                        "{:x}: Don't know how to handle relocation type {}\n",
-                        pe.relocs[i].offset, pe.relocs[i].reloc_type
-                    );
-                }
+            if reloc_size(pe.relocs[i].reloc_type, pe.header.Machine) > 0 {
+                /* scanning is done in scan_segment() */
+                &sec.instr_flags[address - &sec.address] |= INSTR_RELOC;
+            } else {
+                eprint!(
+                    "{:x}: Don't know how to handle relocation type {}\n",
+                    pe.relocs[i].offset, pe.relocs[i].reloc_type
+                );
             }
         }
     }
@@ -1375,6 +1674,32 @@ pub fn read_sections(pe: &PeExecutable) {
         }
     }
 
+    /* On x64, the exception directory lists every function that isn't a
+     * leaf, which finds a lot that isn't reachable by scanning. */
+    for function in &pe.functions {
+        scan_function(function.begin, "Function", pe);
+    }
+
+    /* The load configuration lists SafeSEH handlers and valid targets of
+     * indirect calls, neither of which we'd find otherwise. */
+    if let Some(config) = &pe.load_config {
+        let roots = config
+            .seh_handlers
+            .iter()
+            .chain(config.cf_functions.iter().map(|(address, _)| address))
+            .chain(config.security_check_cookie.iter());
+        for &address in roots {
+            scan_function(address, "Function", pe);
+        }
+    }
+
+    /* TLS callbacks run before the entry point. */
+    if let Some(tls) = &pe.tls {
+        for &address in &tls.callbacks {
+            scan_function(address, "TLS callback", pe);
+        }
+    }
+
     if (entry_point) {
         let sec = addr2section(entry_point, pe);
         if (!sec) {
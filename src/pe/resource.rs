@@ -0,0 +1,617 @@
+/* The PE resource directory (data directory 2), and the 32-bit layouts of
+ * the resources that differ from their 16-bit counterparts.
+ *
+ * The directory is a tree of IMAGE_RESOURCE_DIRECTORY tables, three levels
+ * deep: type, name, and language. Each table is followed by its named
+ * entries and then its numbered ones, eight bytes each: [dword] name or ID,
+ * [dword] offset. A name has the high bit set and points to a counted
+ * UTF-16 string; an offset with the high bit set points to a subdirectory,
+ * and otherwise to an IMAGE_RESOURCE_DATA_ENTRY. All of those offsets are
+ * relative to the start of the directory, but the data entry's own pointer
+ * is an RVA. */
+
+use crate::accel;
+use crate::codepage;
+use crate::msgtable;
+use crate::ne::{
+    collect_rsrc_menu_items, print_hex_dump, print_rsrc_control_style, print_rsrc_dialog_style,
+    print_rsrc_menu_items, print_rsrc_resource as print_rsrc_resource16, read_rsrc_string,
+    rsrc_dialog_class,
+};
+use crate::pe::{addr_to_offset, PeExecutable};
+use crate::rsrc::{self, ResourceEntry, ResourceId};
+use crate::util::{read_byte, read_dword, read_word};
+use crate::version;
+use std::cmp::min;
+
+/* A subdirectory this deep, or pointing back up, means a corrupt tree. */
+const MAX_DEPTH: usize = 3;
+
+fn read_dir_string(map: &Vec<u8>, offset: usize, end: usize) -> Option<String> {
+    if offset + 2 > end {
+        return None;
+    }
+    let length = read_word(map, offset) as usize;
+    if offset + 2 + length * 2 > end {
+        return None;
+    }
+    let chars: Vec<u16> = (0..length)
+        .map(|i| read_word(map, offset + 2 + i * 2))
+        .collect();
+    Some(String::from_utf16_lossy(&chars))
+}
+
+fn read_rsrc_dir(
+    map: &Vec<u8>,
+    pe: &PeExecutable,
+    base: usize,
+    size: usize,
+    dir: usize,
+    path: &mut Vec<ResourceId>,
+    ret: &mut Vec<ResourceEntry>,
+) {
+    if dir + 16 > size || base + dir + 16 > map.len() {
+        eprintln!("Resource directory at 0x{:x} exceeds section.", dir);
+        return;
+    }
+    let count = read_word(map, base + dir + 12) as usize + read_word(map, base + dir + 14) as usize;
+    /* the directory size may be larger than what's in the file */
+    let end = min(base + size, map.len());
+
+    for i in 0..count {
+        let entry = base + dir + 16 + i * 8;
+        if entry + 8 > end {
+            eprintln!("Resource directory at 0x{:x} exceeds section.", dir);
+            return;
+        }
+        let name = read_dword(map, entry);
+        let target = read_dword(map, entry + 4);
+
+        let id = if name & 0x80000000 != 0 {
+            let offset = (name & 0x7fffffff) as usize;
+            match read_dir_string(map, base + offset, end) {
+                Some(name) => ResourceId::Name(name),
+                None => {
+                    eprintln!("Resource name at 0x{:x} exceeds section.", offset);
+                    continue;
+                }
+            }
+        } else {
+            ResourceId::Id(name as u16)
+        };
+
+        if target & 0x80000000 != 0 {
+            let sub = (target & 0x7fffffff) as usize;
+            if path.len() + 1 >= MAX_DEPTH || sub <= dir {
+                eprintln!("Resource subdirectory at 0x{:x} is misplaced.", sub);
+                continue;
+            }
+            path.push(id);
+            read_rsrc_dir(map, pe, base, size, sub, path, ret);
+            path.pop();
+            continue;
+        }
+
+        /* a data entry; the language is the last level's ID */
+        let data = base + target as usize;
+        if data + 16 > end {
+            eprintln!("Resource data entry at 0x{:x} exceeds section.", target);
+            continue;
+        }
+        let rva = read_dword(map, data);
+        let offset = addr_to_offset(rva, pe);
+        if offset == 0 {
+            eprintln!("Resource data at RVA 0x{:x} is outside any section.", rva);
+            continue;
+        }
+
+        let mut levels = path.clone();
+        levels.push(id);
+        if levels.len() < 3 {
+            eprintln!(
+                "Resource data entry at 0x{:x} is above the language level.",
+                target
+            );
+        }
+        let lang = match levels.get(2) {
+            Some(ResourceId::Id(n)) => Some(*n),
+            _ => None,
+        };
+        ret.push(ResourceEntry {
+            rsrc_type: levels[0].clone(),
+            id: levels.get(1).cloned().unwrap_or(ResourceId::Id(0)),
+            lang,
+            offset,
+            length: read_dword(map, data + 4) as usize,
+            flags: 0,
+            codepage: read_dword(map, data + 8),
+        });
+    }
+}
+
+/* Read the resource tree into the common resource model. */
+pub fn read_rsrc_tree(map: &Vec<u8>, pe: &PeExecutable) -> Vec<ResourceEntry> {
+    let mut ret = Vec::new();
+    let base = addr_to_offset(pe.dirs[2].address, pe);
+    if base == 0 {
+        eprintln!("Resource directory is outside any section.");
+        return ret;
+    }
+    read_rsrc_dir(
+        map,
+        pe,
+        base,
+        pe.dirs[2].size as usize,
+        0,
+        &mut Vec::new(),
+        &mut ret,
+    );
+    ret
+}
+
+fn align4(base: usize, offset: usize) -> usize {
+    base + ((offset - base + 3) & !3)
+}
+
+/* A name or ordinal, as used for menus, classes and control text in dialog
+ * templates: 0xffff followed by a word, or a UTF-16 string. */
+enum SzOrOrd {
+    Ord(u16),
+    Sz(String),
+}
+
+fn read_sz_or_ord(map: &Vec<u8>, offset: usize) -> (SzOrOrd, usize) {
+    if read_word(map, offset) == 0xffff {
+        (SzOrOrd::Ord(read_word(map, offset + 2)), offset + 4)
+    } else {
+        let (s, next) = read_rsrc_string(map, offset, true);
+        (SzOrOrd::Sz(s), next)
+    }
+}
+
+/* the predefined class atom of a class name, for print_rsrc_control_style */
+fn class_atom(class: &SzOrOrd) -> u8 {
+    match class {
+        SzOrOrd::Ord(n) => *n as u8,
+        SzOrOrd::Sz(s) => rsrc_dialog_class
+            .iter()
+            .position(|c| !c.is_empty() && c.eq_ignore_ascii_case(s))
+            .map_or(0, |i| 0x80 + i as u8),
+    }
+}
+
+fn print_dialog32(map: &Vec<u8>, base: usize, length: usize) {
+    let extended = read_word(map, base) == 1 && read_word(map, base + 2) == 0xffff;
+    let mut offset = base;
+    let (style, exstyle, count);
+
+    if extended {
+        println!("    Type: extended");
+        println!("    Help ID: {}", read_dword(map, base + 4));
+        exstyle = read_dword(map, base + 8);
+        style = read_dword(map, base + 12);
+        offset += 16;
+    } else {
+        style = read_dword(map, base);
+        exstyle = read_dword(map, base + 4);
+        offset += 8;
+    }
+    print_rsrc_dialog_style(style);
+    if exstyle != 0 {
+        println!("    Extended style: 0x{:08x}", exstyle);
+    }
+    count = read_word(map, offset);
+    println!(
+        "    Position: ({}, {})",
+        read_word(map, offset + 2) as i16,
+        read_word(map, offset + 4) as i16
+    );
+    println!(
+        "    Size: {}x{}",
+        read_word(map, offset + 6),
+        read_word(map, offset + 8)
+    );
+    offset += 10;
+
+    let (menu, next) = read_sz_or_ord(map, offset);
+    match menu {
+        SzOrOrd::Ord(n) => println!("    Menu resource: #{}", n),
+        SzOrOrd::Sz(s) if !s.is_empty() => println!("    Menu name: \"{}\"", codepage::escape(&s)),
+        _ => (),
+    }
+    let (class, next) = read_sz_or_ord(map, next);
+    match class {
+        SzOrOrd::Ord(n) => println!("    Class: #{}", n),
+        SzOrOrd::Sz(s) if !s.is_empty() => println!("    Class name: \"{}\"", codepage::escape(&s)),
+        _ => (),
+    }
+    let (caption, next) = read_rsrc_string(map, next, true);
+    println!("    Caption: \"{}\"", codepage::escape(&caption));
+    offset = next;
+
+    /* DS_SETFONT, or DS_SHELLFONT which includes it */
+    if style & 0x00000040 != 0 {
+        let size = read_word(map, offset);
+        if extended {
+            let weight = read_word(map, offset + 2);
+            let italic = read_byte(map, offset + 4);
+            let charset = read_byte(map, offset + 5);
+            let (face, next) = read_rsrc_string(map, offset + 6, true);
+            println!(
+                "    Font: \"{}\" ({} pt, weight {}{}, charset {})",
+                codepage::escape(&face),
+                size,
+                weight,
+                if italic != 0 { ", italic" } else { "" },
+                charset
+            );
+            offset = next;
+        } else {
+            let (face, next) = read_rsrc_string(map, offset + 2, true);
+            println!("    Font: \"{}\" ({} pt)", codepage::escape(&face), size);
+            offset = next;
+        }
+    }
+
+    for _ in 0..count {
+        /* each control starts on a dword boundary */
+        offset = align4(base, offset);
+        if offset >= base + length {
+            eprintln!("Dialog controls exceed resource length.");
+            break;
+        }
+
+        let (help_id, style, exstyle, id);
+        if extended {
+            help_id = read_dword(map, offset);
+            exstyle = read_dword(map, offset + 4);
+            style = read_dword(map, offset + 8);
+            id = read_dword(map, offset + 20);
+            offset += 12;
+        } else {
+            help_id = 0;
+            style = read_dword(map, offset);
+            exstyle = read_dword(map, offset + 4);
+            id = read_word(map, offset + 16) as u32;
+            offset += 8;
+        }
+        let (x, y) = (
+            read_word(map, offset) as i16,
+            read_word(map, offset + 2) as i16,
+        );
+        let (cx, cy) = (read_word(map, offset + 4), read_word(map, offset + 6));
+        offset += if extended { 12 } else { 10 };
+
+        let (class, next) = read_sz_or_ord(map, offset);
+        match &class {
+            SzOrOrd::Ord(n) if *n >= 0x80 && *n <= 0x85 => {
+                print!("    {}", rsrc_dialog_class[(*n & !0x80) as usize])
+            }
+            SzOrOrd::Ord(n) => print!("    (unknown class {})", n),
+            SzOrOrd::Sz(s) => print!("    \"{}\"", codepage::escape(s)),
+        }
+        println!(" {}:", id as i32);
+        println!("        Position: ({}, {})", x, y);
+        println!("        Size: {}x{}", cx, cy);
+        print_rsrc_control_style(class_atom(&class), style);
+        if exstyle != 0 {
+            println!("        Extended style: 0x{:08x}", exstyle);
+        }
+        if help_id != 0 {
+            println!("        Help ID: {}", help_id);
+        }
+
+        let (text, next) = read_sz_or_ord(map, next);
+        match text {
+            /* todo: we could check SS_ICON/SS_BITMAP and refer to the
+             * printed group icon or bitmap */
+            SzOrOrd::Ord(n) => println!("        Resource: #{}", n),
+            SzOrOrd::Sz(s) => println!("        Text: \"{}\"", codepage::escape(&s)),
+        }
+
+        /* The extended template counts the creation data after the count
+         * word; the standard one counts the word too. */
+        let extra = read_word(map, next) as usize;
+        if extended {
+            if extra != 0 {
+                println!("        Creation data: {} bytes", extra);
+            }
+            offset = next + 2 + extra;
+        } else if extra > 2 {
+            println!("        Creation data: {} bytes", extra - 2);
+            offset = next + extra;
+        } else {
+            offset = next + 2;
+        }
+    }
+}
+
+/* One MENUEX_TEMPLATE_ITEM, flattened with its depth. */
+struct MenuExItem {
+    depth: usize,
+    mft: u32,
+    mfs: u32,
+    id: u32,
+    res_info: u16,
+    text: String,
+    help_id: u32,
+}
+
+fn read_menuex_items(
+    map: &Vec<u8>,
+    base: usize,
+    end: usize,
+    mut offset: usize,
+    depth: usize,
+    items: &mut Vec<MenuExItem>,
+) -> usize {
+    while offset + 14 <= end {
+        let mft = read_dword(map, offset);
+        let mfs = read_dword(map, offset + 4);
+        let id = read_dword(map, offset + 8);
+        let res_info = read_word(map, offset + 12);
+        let (text, next) = read_rsrc_string(map, offset + 14, true);
+        offset = align4(base, next);
+
+        let mut help_id = 0;
+        if res_info & 0x01 != 0 {
+            help_id = read_dword(map, offset);
+            offset += 4;
+        }
+        items.push(MenuExItem {
+            depth,
+            mft,
+            mfs,
+            id,
+            res_info,
+            text,
+            help_id,
+        });
+
+        /* popups are followed by their items */
+        if res_info & 0x01 != 0 && depth < 16 {
+            offset = read_menuex_items(map, base, end, offset, depth + 1, items);
+        }
+        if res_info & 0x80 != 0 {
+            break;
+        }
+    }
+    offset
+}
+
+const MENUEX_TYPES: [(u32, &str); 8] = [
+    (0x0004, "bitmap"),
+    (0x0020, "menu bar break"),
+    (0x0040, "menu break"),
+    (0x0100, "owner-drawn"),
+    (0x0200, "radio check"),
+    (0x0800, "separator"),
+    (0x2000, "right-to-left"),
+    (0x4000, "right-justified"),
+];
+
+fn print_menuex_item(item: &MenuExItem) {
+    print!("        ");
+    for _ in 0..item.depth {
+        print!("  ");
+    }
+    if item.res_info & 0x01 == 0 {
+        print!("{}: ", item.id);
+    }
+    print!("\"{}\"", codepage::escape(&item.text));
+
+    let mut buffer = String::new();
+    for (flag, name) in MENUEX_TYPES.iter() {
+        if item.mft & flag != 0 {
+            buffer += ", ";
+            buffer += name;
+        }
+    }
+    let known = MENUEX_TYPES.iter().fold(0, |a, (f, _)| a | f);
+    if item.mft & !known != 0 {
+        buffer += &format!(", unknown type 0x{:x}", item.mft & !known);
+    }
+    if item.mfs & 0x0003 != 0 {
+        buffer += ", grayed";
+    }
+    if item.mfs & 0x0008 != 0 {
+        buffer += ", checked";
+    }
+    if item.mfs & 0x0080 != 0 {
+        buffer += ", highlighted";
+    }
+    if item.mfs & 0x1000 != 0 {
+        buffer += ", default";
+    }
+    if item.mfs & !0x108b != 0 {
+        buffer += &format!(", unknown state 0x{:x}", item.mfs & !0x108b);
+    }
+    if item.res_info & 0x01 != 0 {
+        buffer += ", popup";
+        if item.help_id != 0 {
+            buffer += &format!(", help ID {}", item.help_id);
+        }
+    }
+    if !buffer.is_empty() {
+        print!(" ({})", &buffer[2..]);
+    }
+    print!("\n");
+}
+
+fn print_menu32(map: &Vec<u8>, offset: usize, length: usize) {
+    let version = read_word(map, offset);
+    let header = read_word(map, offset + 2) as usize;
+
+    match version {
+        0 => {
+            println!("    Type: standard");
+            println!("    Items:");
+            print_rsrc_menu_items(map, 0, offset + 4 + header, true);
+        }
+        1 => {
+            println!("    Type: extended");
+            println!("    Help ID: {}", read_dword(map, offset + 4));
+            println!("    Items:");
+            let mut items = Vec::new();
+            read_menuex_items(
+                map,
+                offset,
+                offset + length,
+                offset + 4 + header,
+                0,
+                &mut items,
+            );
+            for item in &items {
+                print_menuex_item(item);
+            }
+        }
+        _ => {
+            eprintln!("Unknown menu version {}", version);
+            print_hex_dump(map, offset, length);
+        }
+    }
+}
+
+/* Collect the messages of every message table, for naming error codes in
+ * the disassembly. */
+pub fn find_messages(map: &Vec<u8>, resources: &Vec<ResourceEntry>) -> msgtable::MessageTable {
+    let mut ret = msgtable::MessageTable::default();
+    for entry in rsrc::find_type(resources, 11) {
+        if entry.offset + entry.length > map.len() {
+            continue;
+        }
+        let data = map[entry.offset..entry.offset + entry.length].to_vec();
+        if let Some(table) = msgtable::read_message_table(&data) {
+            ret.messages.extend(table.messages);
+        }
+    }
+    ret
+}
+
+/* Collect the items of every menu, for labelling accelerators. */
+pub fn find_menu_items(map: &Vec<u8>, resources: &Vec<ResourceEntry>) -> Vec<(u16, String)> {
+    let mut ret = Vec::new();
+    for entry in rsrc::find_type(resources, 4) {
+        if entry.offset + entry.length > map.len() || entry.length < 4 {
+            continue;
+        }
+        let header = read_word(map, entry.offset + 2) as usize;
+        let start = entry.offset + 4 + header;
+
+        match read_word(map, entry.offset) {
+            0 => {
+                collect_rsrc_menu_items(map, "", start, true, &mut ret);
+            }
+            1 => {
+                let mut items = Vec::new();
+                read_menuex_items(
+                    map,
+                    entry.offset,
+                    entry.offset + entry.length,
+                    start,
+                    0,
+                    &mut items,
+                );
+
+                /* build "Popup > Item" labels from the depths */
+                let mut path: Vec<String> = Vec::new();
+                for item in &items {
+                    path.truncate(item.depth);
+                    let text = item.text.split('\t').next().unwrap_or("").replace('&', "");
+                    if item.res_info & 0x01 != 0 {
+                        path.push(text);
+                    } else if item.id != 0 && item.mft & 0x0800 == 0 {
+                        let mut label = path.clone();
+                        label.push(text);
+                        if !ret.iter().any(|(i, _)| *i == item.id as u16) {
+                            ret.push((item.id as u16, label.join(" > ")));
+                        }
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    ret
+}
+
+/* A block of sixteen strings, each a [word] count of UTF-16 characters. The
+ * block's ID is one more than the strings' IDs divided by 16. */
+fn print_strings32(map: &Vec<u8>, offset: usize, length: usize, id: &ResourceId) {
+    let block = match id {
+        ResourceId::Id(n) if *n > 0 => (*n as u32 - 1) * 16,
+        _ => {
+            eprintln!("String table has no numeric ID.");
+            0
+        }
+    };
+    let mut cursor = offset;
+
+    for i in 0..16 {
+        if cursor + 2 > offset + length {
+            break;
+        }
+        let count = read_word(map, cursor) as usize;
+        cursor += 2;
+        if count != 0 {
+            let chars: Vec<u16> = (0..count).map(|j| read_word(map, cursor + j * 2)).collect();
+            print!("    {} (0x{:06x}): ", block + i, cursor);
+            print!(
+                "\"{}\"\n",
+                codepage::escape(&String::from_utf16_lossy(&chars))
+            );
+            cursor += count * 2;
+        }
+    }
+}
+
+/* Manifests and HTML are text, usually UTF-8. */
+fn print_text(map: &Vec<u8>, offset: usize, length: usize) {
+    let bytes = &map[offset..offset + length];
+    let bytes = bytes.strip_prefix(&[0xef, 0xbb, 0xbf][..]).unwrap_or(bytes);
+    for line in String::from_utf8_lossy(bytes).lines() {
+        println!("    {}", line.trim_end());
+    }
+}
+
+pub fn print_rsrc_resource(
+    map: &Vec<u8>,
+    rsrc_type: u16,
+    offset: usize,
+    length: usize,
+    id: &ResourceId,
+) {
+    match rsrc_type {
+        4 => print_menu32(map, offset, length),
+        5 => print_dialog32(map, offset, length),
+        6 => print_strings32(map, offset, length, id),
+        9 => {
+            let data = map[offset..offset + length].to_vec();
+            accel::print_accelerators(&accel::read_accelerators(&data, true));
+        }
+        11 => {
+            let data = map[offset..offset + length].to_vec();
+            match msgtable::read_message_table(&data) {
+                Some(table) => msgtable::print_message_table(&table),
+                None => print_hex_dump(map, offset, length),
+            }
+        }
+        16 => {
+            let data = map[offset..offset + length].to_vec();
+            match version::read_version_info(&data, true) {
+                Some(info) => version::print_version_info(&info),
+                None => eprintln!("Version resource is too short ({} bytes).", length),
+            }
+        }
+        23 | 24 => print_text(map, offset, length),
+        /* these are laid out the same as in NE files */
+        1 | 2 | 3 | 7 | 8 | 12 | 14 => print_rsrc_resource16(
+            map,
+            rsrc_type | 0x8000,
+            offset,
+            length,
+            id.number().map_or(0, |n| n | 0x8000),
+        ),
+        _ => print_hex_dump(map, offset, length),
+    }
+}
@@ -0,0 +1,429 @@
+/* The "Rich" header. Microsoft's linkers since Visual C++ 6.0 leave a record
+ * of every tool that contributed an object to the image, hidden between the
+ * DOS stub and the PE header:
+ *     "DanS" and three padding dwords, all XORed with the key
+ *     pairs of dwords, XORed with the key:
+ *         @comp.id: product ID << 16 | build number
+ *         number of objects built with it
+ *     "Rich", in the clear
+ *     the key
+ * The key is a checksum over the DOS header and stub and the entries, so we
+ * can tell whether anything was edited. The Rich hash is the MD5 of the
+ * decoded header, from "DanS" up to "Rich"; it's handy for clustering files
+ * built in the same environment. */
+
+use crate::hash::{self, DigestAlgorithm};
+use crate::pe::PeExecutable;
+use crate::util::read_dword;
+
+const RICH_MAGIC: u32 = 0x68636952; /* "Rich" */
+const DANS_MAGIC: u32 = 0x536e6144; /* "DanS" */
+
+pub struct RichEntry {
+    pub prodid: u16,
+    pub build: u16,
+    pub count: u32,
+}
+
+pub struct RichHeader {
+    pub offset: usize, /* of "DanS" */
+    pub key: u32,
+    pub checksum: u32, /* as computed, should equal the key */
+    pub entries: Vec<RichEntry>,
+    pub hash: Vec<u8>,
+}
+
+const VC50: &str = "Visual C++ 5.0";
+const VC60: &str = "Visual C++ 6.0";
+const VB60: &str = "Visual Basic 6.0";
+const VS2002: &str = "Visual Studio .NET 2002";
+const VS2003: &str = "Visual Studio .NET 2003";
+const VS2005: &str = "Visual Studio 2005";
+const VS2008: &str = "Visual Studio 2008";
+
+/* Product IDs up to Visual Studio 2008 were handed out one at a time. */
+const PRODIDS: [(&str, &str); 0x98] = [
+    ("Unknown", ""),                 /* 00 */
+    ("Import0", ""),                 /* 01 */
+    ("Linker510", VC50),             /* 02 */
+    ("Cvtomf510", VC50),             /* 03 */
+    ("Linker600", VC60),             /* 04 */
+    ("Cvtomf600", VC60),             /* 05 */
+    ("Cvtres500", VC50),             /* 06 */
+    ("Utc11_Basic", VC50),           /* 07 */
+    ("Utc11_C", VC50),               /* 08 */
+    ("Utc12_Basic", VC60),           /* 09 */
+    ("Utc12_C", VC60),               /* 0a */
+    ("Utc12_CPP", VC60),             /* 0b */
+    ("AliasObj60", VC60),            /* 0c */
+    ("VisualBasic60", VB60),         /* 0d */
+    ("Masm613", VC60),               /* 0e */
+    ("Masm710", VS2003),             /* 0f */
+    ("Linker511", VC50),             /* 10 */
+    ("Cvtomf511", VC50),             /* 11 */
+    ("Masm614", VC60),               /* 12 */
+    ("Linker512", VC50),             /* 13 */
+    ("Cvtomf512", VC50),             /* 14 */
+    ("Utc12_C_Std", VC60),           /* 15 */
+    ("Utc12_CPP_Std", VC60),         /* 16 */
+    ("Utc12_C_Book", VC60),          /* 17 */
+    ("Utc12_CPP_Book", VC60),        /* 18 */
+    ("Implib700", VS2002),           /* 19 */
+    ("Cvtomf700", VS2002),           /* 1a */
+    ("Utc13_Basic", VS2002),         /* 1b */
+    ("Utc13_C", VS2002),             /* 1c */
+    ("Utc13_CPP", VS2002),           /* 1d */
+    ("Linker610", VC60),             /* 1e */
+    ("Cvtomf610", VC60),             /* 1f */
+    ("Linker601", VC60),             /* 20 */
+    ("Cvtomf601", VC60),             /* 21 */
+    ("Utc12_1_Basic", VC60),         /* 22 */
+    ("Utc12_1_C", VC60),             /* 23 */
+    ("Utc12_1_CPP", VC60),           /* 24 */
+    ("Linker620", VC60),             /* 25 */
+    ("Cvtomf620", VC60),             /* 26 */
+    ("AliasObj70", VS2002),          /* 27 */
+    ("Linker621", VC60),             /* 28 */
+    ("Cvtomf621", VC60),             /* 29 */
+    ("Masm615", VC60),               /* 2a */
+    ("Utc13_LTCG_C", VS2002),        /* 2b */
+    ("Utc13_LTCG_CPP", VS2002),      /* 2c */
+    ("Masm620", VC60),               /* 2d */
+    ("ILAsm100", VS2002),            /* 2e */
+    ("Utc12_2_Basic", VC60),         /* 2f */
+    ("Utc12_2_C", VC60),             /* 30 */
+    ("Utc12_2_CPP", VC60),           /* 31 */
+    ("Utc12_2_C_Std", VC60),         /* 32 */
+    ("Utc12_2_CPP_Std", VC60),       /* 33 */
+    ("Utc12_2_C_Book", VC60),        /* 34 */
+    ("Utc12_2_CPP_Book", VC60),      /* 35 */
+    ("Implib622", VC60),             /* 36 */
+    ("Cvtomf622", VC60),             /* 37 */
+    ("Cvtres501", VC60),             /* 38 */
+    ("Utc13_C_Std", VS2002),         /* 39 */
+    ("Utc13_CPP_Std", VS2002),       /* 3a */
+    ("Cvtpgd1300", VS2002),          /* 3b */
+    ("Linker622", VC60),             /* 3c */
+    ("Linker700", VS2002),           /* 3d */
+    ("Export622", VC60),             /* 3e */
+    ("Export700", VS2002),           /* 3f */
+    ("Masm700", VS2002),             /* 40 */
+    ("Utc13_POGO_I_C", VS2002),      /* 41 */
+    ("Utc13_POGO_I_CPP", VS2002),    /* 42 */
+    ("Utc13_POGO_O_C", VS2002),      /* 43 */
+    ("Utc13_POGO_O_CPP", VS2002),    /* 44 */
+    ("Cvtres700", VS2002),           /* 45 */
+    ("Cvtres710p", VS2003),          /* 46 */
+    ("Linker710p", VS2003),          /* 47 */
+    ("Cvtomf710p", VS2003),          /* 48 */
+    ("Export710p", VS2003),          /* 49 */
+    ("Implib710p", VS2003),          /* 4a */
+    ("Masm710p", VS2003),            /* 4b */
+    ("Utc1310p_C", VS2003),          /* 4c */
+    ("Utc1310p_CPP", VS2003),        /* 4d */
+    ("Utc1310p_C_Std", VS2003),      /* 4e */
+    ("Utc1310p_CPP_Std", VS2003),    /* 4f */
+    ("Utc1310p_LTCG_C", VS2003),     /* 50 */
+    ("Utc1310p_LTCG_CPP", VS2003),   /* 51 */
+    ("Utc1310p_POGO_I_C", VS2003),   /* 52 */
+    ("Utc1310p_POGO_I_CPP", VS2003), /* 53 */
+    ("Utc1310p_POGO_O_C", VS2003),   /* 54 */
+    ("Utc1310p_POGO_O_CPP", VS2003), /* 55 */
+    ("Linker624", VC60),             /* 56 */
+    ("Cvtomf624", VC60),             /* 57 */
+    ("Export624", VC60),             /* 58 */
+    ("Implib624", VC60),             /* 59 */
+    ("Linker710", VS2003),           /* 5a */
+    ("Cvtomf710", VS2003),           /* 5b */
+    ("Export710", VS2003),           /* 5c */
+    ("Implib710", VS2003),           /* 5d */
+    ("Cvtres710", VS2003),           /* 5e */
+    ("Utc1310_C", VS2003),           /* 5f */
+    ("Utc1310_CPP", VS2003),         /* 60 */
+    ("Utc1310_C_Std", VS2003),       /* 61 */
+    ("Utc1310_CPP_Std", VS2003),     /* 62 */
+    ("Utc1310_LTCG_C", VS2003),      /* 63 */
+    ("Utc1310_LTCG_CPP", VS2003),    /* 64 */
+    ("Utc1310_POGO_I_C", VS2003),    /* 65 */
+    ("Utc1310_POGO_I_CPP", VS2003),  /* 66 */
+    ("Utc1310_POGO_O_C", VS2003),    /* 67 */
+    ("Utc1310_POGO_O_CPP", VS2003),  /* 68 */
+    ("AliasObj710", VS2003),         /* 69 */
+    ("AliasObj710p", VS2003),        /* 6a */
+    ("Cvtpgd1310", VS2003),          /* 6b */
+    ("Cvtpgd1310p", VS2003),         /* 6c */
+    ("Utc1400_C", VS2005),           /* 6d */
+    ("Utc1400_CPP", VS2005),         /* 6e */
+    ("Utc1400_C_Std", VS2005),       /* 6f */
+    ("Utc1400_CPP_Std", VS2005),     /* 70 */
+    ("Utc1400_LTCG_C", VS2005),      /* 71 */
+    ("Utc1400_LTCG_CPP", VS2005),    /* 72 */
+    ("Utc1400_POGO_I_C", VS2005),    /* 73 */
+    ("Utc1400_POGO_I_CPP", VS2005),  /* 74 */
+    ("Utc1400_POGO_O_C", VS2005),    /* 75 */
+    ("Utc1400_POGO_O_CPP", VS2005),  /* 76 */
+    ("Cvtpgd1400", VS2005),          /* 77 */
+    ("Linker800", VS2005),           /* 78 */
+    ("Cvtomf800", VS2005),           /* 79 */
+    ("Export800", VS2005),           /* 7a */
+    ("Implib800", VS2005),           /* 7b */
+    ("Cvtres800", VS2005),           /* 7c */
+    ("Masm800", VS2005),             /* 7d */
+    ("AliasObj800", VS2005),         /* 7e */
+    ("PhoenixPrerelease", VS2005),   /* 7f */
+    ("Utc1400_CVTCIL_C", VS2005),    /* 80 */
+    ("Utc1400_CVTCIL_CPP", VS2005),  /* 81 */
+    ("Utc1400_LTCG_MSIL", VS2005),   /* 82 */
+    ("Utc1500_C", VS2008),           /* 83 */
+    ("Utc1500_CPP", VS2008),         /* 84 */
+    ("Utc1500_C_Std", VS2008),       /* 85 */
+    ("Utc1500_CPP_Std", VS2008),     /* 86 */
+    ("Utc1500_CVTCIL_C", VS2008),    /* 87 */
+    ("Utc1500_CVTCIL_CPP", VS2008),  /* 88 */
+    ("Utc1500_LTCG_C", VS2008),      /* 89 */
+    ("Utc1500_LTCG_CPP", VS2008),    /* 8a */
+    ("Utc1500_LTCG_MSIL", VS2008),   /* 8b */
+    ("Utc1500_POGO_I_C", VS2008),    /* 8c */
+    ("Utc1500_POGO_I_CPP", VS2008),  /* 8d */
+    ("Utc1500_POGO_O_C", VS2008),    /* 8e */
+    ("Utc1500_POGO_O_CPP", VS2008),  /* 8f */
+    ("Cvtpgd1500", VS2008),          /* 90 */
+    ("Linker900", VS2008),           /* 91 */
+    ("Export900", VS2008),           /* 92 */
+    ("Implib900", VS2008),           /* 93 */
+    ("Cvtres900", VS2008),           /* 94 */
+    ("Masm900", VS2008),             /* 95 */
+    ("AliasObj900", VS2008),         /* 96 */
+    ("Resource", VS2008),            /* 97 */
+];
+
+/* From Visual Studio 2010 on, each release gets a block of IDs in the same
+ * order: the tools versioned after the product, then the compiler flavours
+ * versioned after the compiler (2010 has an extra set for Phoenix). */
+const BLOCK_TOOLS: [&str; 7] = [
+    "AliasObj", "Cvtpgd", "Cvtres", "Export", "Implib", "Linker", "Masm",
+];
+const BLOCK_COMPILERS: [&str; 11] = [
+    "C",
+    "CPP",
+    "CVTCIL_C",
+    "CVTCIL_CPP",
+    "LTCG_C",
+    "LTCG_CPP",
+    "LTCG_MSIL",
+    "POGO_I_C",
+    "POGO_I_CPP",
+    "POGO_O_C",
+    "POGO_O_CPP",
+];
+
+struct ProdidBlock {
+    first: u16,
+    tool_version: &'static str,
+    compiler_version: &'static str,
+    phoenix: bool,
+    name: &'static str, /* empty if we go by build number */
+}
+
+const PRODID_BLOCKS: [ProdidBlock; 6] = [
+    ProdidBlock {
+        first: 0x98,
+        tool_version: "1000",
+        compiler_version: "1600",
+        phoenix: true,
+        name: "Visual Studio 2010",
+    },
+    ProdidBlock {
+        first: 0xb5,
+        tool_version: "1010",
+        compiler_version: "1610",
+        phoenix: false,
+        name: "Visual Studio 2010 (10.10)",
+    },
+    ProdidBlock {
+        first: 0xc7,
+        tool_version: "1100",
+        compiler_version: "1700",
+        phoenix: false,
+        name: "Visual Studio 2012",
+    },
+    ProdidBlock {
+        first: 0xd9,
+        tool_version: "1200",
+        compiler_version: "1800",
+        phoenix: false,
+        name: "Visual Studio 2013",
+    },
+    ProdidBlock {
+        first: 0xeb,
+        tool_version: "1210",
+        compiler_version: "1810",
+        phoenix: false,
+        name: "Visual Studio 2013 (12.10)",
+    },
+    ProdidBlock {
+        first: 0xfd,
+        tool_version: "1400",
+        compiler_version: "1900",
+        phoenix: false,
+        name: "",
+    },
+];
+
+/* Everything since 2015 shares the 14.x IDs, so only the build tells them
+ * apart. */
+fn vs14_version(build: u16) -> &'static str {
+    match build {
+        0..=24999 => "Visual Studio 2015",
+        25000..=27499 => "Visual Studio 2017",
+        27500..=30499 => "Visual Studio 2019",
+        _ => "Visual Studio 2022 or later",
+    }
+}
+
+/* The tool's name and the Visual Studio release it came with. */
+pub fn prodid_name(prodid: u16, build: u16) -> Option<(String, &'static str)> {
+    if let Some((name, version)) = PRODIDS.get(prodid as usize) {
+        return Some((name.to_string(), version));
+    }
+
+    let block = PRODID_BLOCKS.iter().rev().find(|b| prodid >= b.first)?;
+    let mut index = (prodid - block.first) as usize;
+    let version = if block.name.is_empty() {
+        vs14_version(build)
+    } else {
+        block.name
+    };
+    if index < BLOCK_TOOLS.len() {
+        return Some((
+            format!("{}{}", BLOCK_TOOLS[index], block.tool_version),
+            version,
+        ));
+    }
+    index -= BLOCK_TOOLS.len();
+    let mut prefix = "Utc";
+    if block.phoenix {
+        if index < BLOCK_COMPILERS.len() {
+            prefix = "Phx";
+        } else {
+            index -= BLOCK_COMPILERS.len();
+        }
+    }
+    let compiler = BLOCK_COMPILERS.get(index)?;
+    Some((
+        format!("{}{}_{}", prefix, block.compiler_version, compiler),
+        version,
+    ))
+}
+
+/* The key: the offset of "DanS", plus every byte of the DOS header and stub
+ * (but e_lfanew) rotated by its offset, plus each @comp.id rotated by its
+ * count. */
+fn rich_checksum(map: &Vec<u8>, offset: usize, entries: &Vec<RichEntry>) -> u32 {
+    let mut checksum = offset as u32;
+    for (i, &b) in map[..offset].iter().enumerate() {
+        if (0x3c..0x40).contains(&i) {
+            continue;
+        }
+        checksum = checksum.wrapping_add((b as u32).rotate_left(i as u32));
+    }
+    for entry in entries {
+        let compid = (entry.prodid as u32) << 16 | entry.build as u32;
+        checksum = checksum.wrapping_add(compid.rotate_left(entry.count));
+    }
+    checksum
+}
+
+pub fn get_rich_header(map: &Vec<u8>, offset_pe: usize, pe: &mut PeExecutable) {
+    /* look for "Rich" between the DOS header and the PE header */
+    let end = match (0x40..offset_pe.saturating_sub(7))
+        .step_by(4)
+        .rev()
+        .find(|&i| read_dword(map, i) == RICH_MAGIC)
+    {
+        Some(end) => end,
+        None => return,
+    };
+    let key = read_dword(map, end + 4);
+
+    let offset = match (0x40..end)
+        .step_by(4)
+        .rev()
+        .find(|&i| read_dword(map, i) ^ key == DANS_MAGIC)
+    {
+        Some(offset) => offset,
+        None => {
+            eprintln!("Rich header at 0x{:x} has no start marker.", end);
+            return;
+        }
+    };
+    if offset + 16 > end
+        || (offset + 4..offset + 16)
+            .step_by(4)
+            .any(|i| read_dword(map, i) != key)
+    {
+        eprintln!("Rich header at 0x{:x} has bad padding.", offset);
+    }
+
+    let mut entries = Vec::new();
+    let mut clear = Vec::new();
+    for i in (offset..end).step_by(4) {
+        clear.extend_from_slice(&(read_dword(map, i) ^ key).to_le_bytes());
+    }
+    for i in (offset + 16..end.saturating_sub(7)).step_by(8) {
+        let compid = read_dword(map, i) ^ key;
+        entries.push(RichEntry {
+            prodid: (compid >> 16) as u16,
+            build: compid as u16,
+            count: read_dword(map, i + 4) ^ key,
+        });
+    }
+
+    let checksum = rich_checksum(map, offset, &entries);
+    if checksum != key {
+        eprintln!(
+            "Rich header checksum is 0x{:08x}, but should be 0x{:08x}.",
+            key, checksum
+        );
+    }
+
+    pe.rich = Some(RichHeader {
+        offset,
+        key,
+        checksum,
+        entries,
+        hash: hash::digest(DigestAlgorithm::Md5, &clear),
+    });
+}
+
+pub fn print_rich_header(rich: &RichHeader) {
+    print!("\nRich header at 0x{:x}:\n", rich.offset);
+    print!(
+        "\tKey: 0x{:08x} ({})\n",
+        rich.key,
+        if rich.key == rich.checksum {
+            "valid".to_string()
+        } else {
+            format!("INVALID; should be 0x{:08x}", rich.checksum)
+        }
+    );
+    print!(
+        "\tRich hash: {}\n",
+        rich.hash
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+    );
+    print!("\tProduct ID    Build    Count  Tool\n");
+    for entry in &rich.entries {
+        print!(
+            "\t    0x{:04x}  {:6}  {:7}  ",
+            entry.prodid, entry.build, entry.count
+        );
+        match prodid_name(entry.prodid, entry.build) {
+            Some((name, "")) => print!("{}\n", name),
+            Some((name, version)) => print!("{} ({})\n", name, version),
+            None => print!("(unknown)\n"),
+        }
+    }
+}
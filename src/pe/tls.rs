@@ -0,0 +1,122 @@
+/* Thread-local storage (data directory 9).
+ *
+ * The directory holds virtual addresses, not RVAs, with the first four
+ * fields being pointer-sized:
+ *     start of the raw data (the template copied for each thread)
+ *     end of the raw data
+ *     address of the TLS index
+ *     address of a zero-terminated array of callbacks
+ *     [dword] size of zero fill after the raw data
+ *     [dword] characteristics (alignment, as in section flags)
+ * The callbacks are called with DLL_PROCESS_ATTACH and so on, before the
+ * entry point, which makes them a good place to hide code. */
+
+use crate::pe::{addr_to_offset, PeExecutable};
+use crate::util::{read_dword, read_qword};
+
+#[derive(Default)]
+pub struct PeTlsDirectory {
+    pub raw_data_start: u64,
+    pub raw_data_end: u64,
+    pub index_addr: u64,
+    pub callbacks_addr: u64,
+    pub zero_fill: u32,
+    pub characteristics: u32,
+    pub callbacks: Vec<u32>, /* RVAs */
+}
+
+pub fn get_tls_directory(map: &Vec<u8>, pe: &mut PeExecutable) {
+    let offset = addr_to_offset(pe.dirs[9].address, pe);
+    if offset == 0 {
+        eprintln!("TLS directory isn't in a section?");
+        return;
+    }
+
+    let size = if pe.magic == 0x10b { 4 } else { 8 };
+    let read_ptr = |offset: usize| {
+        if size == 4 {
+            read_dword(map, offset) as u64
+        } else {
+            read_qword(map, offset)
+        }
+    };
+
+    let mut tls = PeTlsDirectory {
+        raw_data_start: read_ptr(offset),
+        raw_data_end: read_ptr(offset + size),
+        index_addr: read_ptr(offset + size * 2),
+        callbacks_addr: read_ptr(offset + size * 3),
+        zero_fill: read_dword(map, offset + size * 4),
+        characteristics: read_dword(map, offset + size * 4 + 4),
+        callbacks: Vec::new(),
+    };
+
+    if tls.callbacks_addr != 0 {
+        let array = addr_to_offset(tls.callbacks_addr.wrapping_sub(pe.imagebase) as u32, pe);
+        if array == 0 {
+            eprintln!(
+                "TLS callback array at {:x} isn't in a section?",
+                tls.callbacks_addr
+            );
+        } else {
+            let mut i = 0;
+            while array + (i + 1) * size <= map.len() {
+                let callback = read_ptr(array + i * size);
+                if callback == 0 {
+                    break;
+                }
+                tls.callbacks
+                    .push(callback.wrapping_sub(pe.imagebase) as u32);
+                i += 1;
+            }
+        }
+    }
+
+    pe.tls = Some(tls);
+}
+
+/* The label of a TLS callback, for the disassembly. */
+pub fn get_tls_callback_name(ip: u32, pe: &PeExecutable) -> Option<String> {
+    pe.tls.as_ref().and_then(|tls| {
+        tls.callbacks
+            .iter()
+            .position(|&callback| callback == ip)
+            .map(|i| format!("tls_callback_{}", i))
+    })
+}
+
+pub fn print_tls_directory(tls: &PeTlsDirectory, pe: &PeExecutable, pe_rel_addr: i32) {
+    print!("\nThread-local storage:\n");
+    print!(
+        "Raw data: 0x{:x}-0x{:x} ({} bytes)\n",
+        tls.raw_data_start,
+        tls.raw_data_end,
+        tls.raw_data_end.saturating_sub(tls.raw_data_start)
+    );
+    print!("Address of index: 0x{:x}\n", tls.index_addr);
+    print!("Address of callbacks: 0x{:x}\n", tls.callbacks_addr);
+    print!("Size of zero fill: {}\n", tls.zero_fill);
+    print!("Characteristics: 0x{:08x}", tls.characteristics);
+    let align = (tls.characteristics >> 20) & 0xf;
+    if align != 0 {
+        print!(" (aligned to {} bytes)", 1u32 << (align - 1));
+    }
+    if tls.characteristics & !0x00f00000 != 0 {
+        print!(
+            " (unknown flags 0x{:08x})",
+            tls.characteristics & !0x00f00000
+        );
+    }
+    print!("\n");
+
+    if !tls.callbacks.is_empty() {
+        print!("Callbacks:\n");
+        for (i, callback) in tls.callbacks.iter().enumerate() {
+            let mut address = *callback as u64;
+            if pe_rel_addr == 0 {
+                address += pe.imagebase;
+            }
+            print!("\ttls_callback_{}\t0x{:x}\n", i, address);
+        }
+    }
+}
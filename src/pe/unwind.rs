@@ -0,0 +1,463 @@
+/* x64 exception directory (data directory 3, usually .pdata).
+ *
+ * Every non-leaf function in a PE32+ image has a RUNTIME_FUNCTION:
+ *     [dword] start of the function
+ *     [dword] end of the function
+ *     [dword] UNWIND_INFO (usually in .xdata), or with the low bit set,
+ *             another RUNTIME_FUNCTION this one shares its unwind info with
+ * so this is the best list of function boundaries we'll get.
+ *
+ * UNWIND_INFO describes how the prologue changed the stack, so that the
+ * system can undo it while unwinding:
+ *     [byte]  version (low 3 bits) and flags
+ *     [byte]  size of prologue
+ *     [byte]  count of unwind code slots
+ *     [byte]  frame register (low 4 bits) and its offset from RSP / 16
+ *     unwind codes, in reverse order, padded to an even count:
+ *         [byte] offset of the end of the instruction in the prologue
+ *         [byte] operation (low 4 bits) and its info
+ *         ...and up to two more slots of operands
+ * followed by either a chained RUNTIME_FUNCTION, whose unwind info applies
+ * after ours, or the RVA of an exception handler and its data. For
+ * __C_specific_handler the data is a table of __try scopes. */
+
+use crate::pe::{addr_to_offset, get_function_name, get_imported_name, PeExecutable};
+use crate::util::{read_byte, read_dword, read_word};
+
+pub const UNW_FLAG_EHANDLER: u8 = 0x01;
+pub const UNW_FLAG_UHANDLER: u8 = 0x02;
+pub const UNW_FLAG_CHAININFO: u8 = 0x04;
+
+pub const UWOP_PUSH_NONVOL: u8 = 0;
+pub const UWOP_ALLOC_LARGE: u8 = 1;
+pub const UWOP_ALLOC_SMALL: u8 = 2;
+pub const UWOP_SET_FPREG: u8 = 3;
+pub const UWOP_SAVE_NONVOL: u8 = 4;
+pub const UWOP_SAVE_NONVOL_FAR: u8 = 5;
+pub const UWOP_EPILOG: u8 = 6; /* UWOP_SAVE_XMM in version 1 */
+pub const UWOP_SPARE: u8 = 7; /* UWOP_SAVE_XMM_FAR in version 1 */
+pub const UWOP_SAVE_XMM128: u8 = 8;
+pub const UWOP_SAVE_XMM128_FAR: u8 = 9;
+pub const UWOP_PUSH_MACHFRAME: u8 = 10;
+
+/* chained unwind info may not refer back to itself, but don't trust that */
+const MAX_CHAIN: u32 = 32;
+
+const REGISTERS: [&str; 16] = [
+    "rax", "rcx", "rdx", "rbx", "rsp", "rbp", "rsi", "rdi", "r8", "r9", "r10", "r11", "r12", "r13",
+    "r14", "r15",
+];
+
+#[derive(Clone, Debug)]
+pub struct UnwindCode {
+    pub prolog_offset: u8,
+    pub op: u8,
+    pub info: u8,
+    pub operand: u32, /* from the following slots, if any */
+}
+
+#[derive(Clone, Debug)]
+pub struct ScopeEntry {
+    pub begin: u32,
+    pub end: u32,
+    pub handler: u32, /* filter, or 1 for EXCEPTION_EXECUTE_HANDLER */
+    pub target: u32,  /* 0 for a __finally block */
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct UnwindInfo {
+    pub address: u32,
+    pub version: u8,
+    pub flags: u8,
+    pub prolog_size: u8,
+    pub frame_register: u8,
+    pub frame_offset: u8, /* scaled by 16 */
+    pub codes: Vec<UnwindCode>,
+    pub handler: u32,
+    pub handler_name: Option<String>,
+    pub handler_data: u32,
+    pub scopes: Vec<ScopeEntry>,
+    pub chained: Option<Box<RuntimeFunction>>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RuntimeFunction {
+    pub begin: u32,
+    pub end: u32,
+    pub unwind_addr: u32,
+    pub info: Option<UnwindInfo>,
+}
+
+/* How many slots an unwind code takes up, including its own. */
+fn code_slots(op: u8, info: u8, version: u8) -> usize {
+    match op {
+        UWOP_ALLOC_LARGE => {
+            if info == 0 {
+                2
+            } else {
+                3
+            }
+        }
+        UWOP_SAVE_NONVOL | UWOP_SAVE_XMM128 => 2,
+        UWOP_SAVE_NONVOL_FAR | UWOP_SAVE_XMM128_FAR => 3,
+        UWOP_EPILOG => {
+            if version >= 2 {
+                1
+            } else {
+                2
+            }
+        }
+        UWOP_SPARE => {
+            if version >= 2 {
+                2
+            } else {
+                3
+            }
+        }
+        _ => 1,
+    }
+}
+
+/* The exception handler's name: either a function we know of, or (more
+ * likely) an import reached through a jmp [rip+rel32] thunk. */
+fn handler_name(map: &Vec<u8>, address: u32, pe: &PeExecutable) -> Option<String> {
+    if let Some(name) = get_function_name(address, pe) {
+        return Some(name);
+    }
+    let offset = addr_to_offset(address, pe);
+    if offset != 0 && offset + 6 <= map.len() && read_word(map, offset) == 0x25ff {
+        let slot = address
+            .wrapping_add(6)
+            .wrapping_add(read_dword(map, offset + 2));
+        return get_imported_name(slot as usize, pe);
+    }
+    None
+}
+
+fn read_scope_table(map: &Vec<u8>, offset: usize) -> Vec<ScopeEntry> {
+    let mut scopes = Vec::new();
+    if offset + 4 > map.len() {
+        return scopes;
+    }
+    let count = read_dword(map, offset) as usize;
+    for i in 0..count {
+        let entry = offset + 4 + i * 16;
+        if entry + 16 > map.len() {
+            eprintln!("Scope table at {:x} exceeds file length.", offset);
+            break;
+        }
+        scopes.push(ScopeEntry {
+            begin: read_dword(map, entry),
+            end: read_dword(map, entry + 4),
+            handler: read_dword(map, entry + 8),
+            target: read_dword(map, entry + 12),
+        });
+    }
+    scopes
+}
+
+fn read_unwind_info(
+    map: &Vec<u8>,
+    address: u32,
+    depth: u32,
+    pe: &PeExecutable,
+) -> Option<UnwindInfo> {
+    let offset = addr_to_offset(address, pe);
+    if offset == 0 || offset + 4 > map.len() {
+        eprintln!("Unwind info at {:x} isn't in a section?", address);
+        return None;
+    }
+
+    let mut info = UnwindInfo {
+        address,
+        version: read_byte(map, offset) & 7,
+        flags: read_byte(map, offset) >> 3,
+        prolog_size: read_byte(map, offset + 1),
+        frame_register: read_byte(map, offset + 3) & 0xf,
+        frame_offset: read_byte(map, offset + 3) >> 4,
+        ..Default::default()
+    };
+    if info.version != 1 && info.version != 2 {
+        eprintln!(
+            "Unwind info at {:x} has unknown version {}.",
+            address, info.version
+        );
+    }
+
+    let count = read_byte(map, offset + 2) as usize;
+    let slots = offset + 4;
+    if slots + count * 2 > map.len() {
+        eprintln!("Unwind codes at {:x} exceed file length.", address);
+        return Some(info);
+    }
+    let slot = |i: usize| read_word(map, slots + i * 2) as u32;
+
+    let mut i = 0;
+    while i < count {
+        let op = read_byte(map, slots + i * 2 + 1) & 0xf;
+        let code_info = read_byte(map, slots + i * 2 + 1) >> 4;
+        let n = code_slots(op, code_info, info.version);
+        if i + n > count {
+            eprintln!("Unwind code at {:x} is missing its operands.", address);
+            break;
+        }
+        let operand = match n {
+            2 => slot(i + 1),
+            3 => slot(i + 1) | (slot(i + 2) << 16),
+            _ => 0,
+        };
+        info.codes.push(UnwindCode {
+            prolog_offset: read_byte(map, slots + i * 2),
+            op,
+            info: code_info,
+            operand,
+        });
+        i += n;
+    }
+
+    let after = slots + ((count + 1) & !1) * 2;
+    if info.flags & UNW_FLAG_CHAININFO != 0 {
+        if depth >= MAX_CHAIN {
+            eprintln!("Unwind info at {:x} is chained too deeply.", address);
+        } else if after + 12 <= map.len() {
+            info.chained = Some(Box::new(read_runtime_function(map, after, depth + 1, pe)));
+        }
+    } else if info.flags & (UNW_FLAG_EHANDLER | UNW_FLAG_UHANDLER) != 0 && after + 4 <= map.len() {
+        info.handler = read_dword(map, after);
+        info.handler_name = handler_name(map, info.handler, pe);
+        info.handler_data = address + (after + 4 - offset) as u32;
+
+        let name = info.handler_name.clone().unwrap_or_default();
+        if name.ends_with("__C_specific_handler") || name.ends_with("__GSHandlerCheck_SEH") {
+            info.scopes = read_scope_table(map, after + 4);
+        }
+    }
+
+    Some(info)
+}
+
+fn read_runtime_function(
+    map: &Vec<u8>,
+    offset: usize,
+    depth: u32,
+    pe: &PeExecutable,
+) -> RuntimeFunction {
+    let mut function = RuntimeFunction {
+        begin: read_dword(map, offset),
+        end: read_dword(map, offset + 4),
+        unwind_addr: read_dword(map, offset + 8),
+        info: None,
+    };
+
+    if function.unwind_addr & 1 != 0 {
+        /* shares the unwind info of another entry */
+        let target = addr_to_offset(function.unwind_addr & !1, pe);
+        if target == 0 || target + 12 > map.len() || depth >= MAX_CHAIN {
+            eprintln!(
+                "Runtime function at {:x} refers to a bad entry {:x}.",
+                function.begin,
+                function.unwind_addr & !1
+            );
+        } else {
+            function.info = read_runtime_function(map, target, depth + 1, pe).info;
+        }
+    } else if function.unwind_addr != 0 {
+        function.info = read_unwind_info(map, function.unwind_addr, depth, pe);
+    }
+    function
+}
+
+pub fn get_exception_table(map: &Vec<u8>, pe: &mut PeExecutable) {
+    if pe.magic != 0x20b || pe.header.Machine != 0x8664 {
+        eprintln!(
+            "Don't know how to read the exception directory for machine 0x{:04x}.",
+            pe.header.Machine
+        );
+        return;
+    }
+    let offset = addr_to_offset(pe.dirs[3].address, pe);
+    if offset == 0 {
+        eprintln!("Exception directory isn't in a section?");
+        return;
+    }
+
+    let count = pe.dirs[3].size as usize / 12;
+    let mut functions = Vec::with_capacity(count);
+    for i in 0..count {
+        if offset + i * 12 + 12 > map.len() {
+            eprintln!("Exception directory exceeds file length.");
+            break;
+        }
+        functions.push(read_runtime_function(map, offset + i * 12, 0, pe));
+    }
+    pe.functions = functions;
+}
+
+fn describe_code(code: &UnwindCode, info: &UnwindInfo) -> String {
+    let reg = REGISTERS[code.info as usize];
+    match code.op {
+        UWOP_PUSH_NONVOL => format!("push {}", reg),
+        UWOP_ALLOC_LARGE => {
+            let size = if code.info == 0 {
+                code.operand * 8
+            } else {
+                code.operand
+            };
+            format!("sub rsp, 0x{:x}", size)
+        }
+        UWOP_ALLOC_SMALL => format!("sub rsp, 0x{:x}", code.info as u32 * 8 + 8),
+        UWOP_SET_FPREG => format!(
+            "lea {}, [rsp+0x{:x}]",
+            REGISTERS[info.frame_register as usize],
+            info.frame_offset as u32 * 16
+        ),
+        UWOP_SAVE_NONVOL => format!("mov [rsp+0x{:x}], {}", code.operand * 8, reg),
+        UWOP_SAVE_NONVOL_FAR => format!("mov [rsp+0x{:x}], {}", code.operand, reg),
+        UWOP_EPILOG if info.version >= 2 => {
+            /* the first one gives the size, the rest offsets from the end */
+            format!(
+                "epilog (0x{:x})",
+                code.prolog_offset as u32 | (code.info as u32) << 8
+            )
+        }
+        UWOP_EPILOG => format!("movaps [rsp+0x{:x}], xmm{}", code.operand * 8, code.info),
+        UWOP_SPARE if info.version >= 2 => "(spare)".to_string(),
+        UWOP_SPARE => format!("movaps [rsp+0x{:x}], xmm{}", code.operand, code.info),
+        UWOP_SAVE_XMM128 => format!("movaps [rsp+0x{:x}], xmm{}", code.operand * 16, code.info),
+        UWOP_SAVE_XMM128_FAR => format!("movaps [rsp+0x{:x}], xmm{}", code.operand, code.info),
+        UWOP_PUSH_MACHFRAME => {
+            if code.info != 0 {
+                "push machine frame with error code".to_string()
+            } else {
+                "push machine frame".to_string()
+            }
+        }
+        _ => format!("unknown operation {} (info {})", code.op, code.info),
+    }
+}
+
+fn print_unwind_flags(flags: u8) {
+    let mut names = Vec::new();
+    if flags & UNW_FLAG_EHANDLER != 0 {
+        names.push("EHANDLER".to_string());
+    }
+    if flags & UNW_FLAG_UHANDLER != 0 {
+        names.push("UHANDLER".to_string());
+    }
+    if flags & UNW_FLAG_CHAININFO != 0 {
+        names.push("CHAININFO".to_string());
+    }
+    if flags & !7 != 0 {
+        names.push(format!("(unknown flags 0x{:02x})", flags & !7));
+    }
+    if names.is_empty() {
+        print!("(none)");
+    } else {
+        print!("{}", names.join(", "));
+    }
+}
+
+/* Print the unwind info, each line starting with `prefix`. Addresses are
+ * RVAs, plus `base`. */
+fn print_unwind_info(info: &UnwindInfo, prefix: &str, base: u64) {
+    print!(
+        "{}Unwind info at 0x{:x}: version {}, flags ",
+        prefix,
+        info.address as u64 + base,
+        info.version
+    );
+    print_unwind_flags(info.flags);
+    print!(", prologue {} bytes", info.prolog_size);
+    if info.frame_register != 0 {
+        print!(
+            ", frame {}+0x{:x}",
+            REGISTERS[info.frame_register as usize],
+            info.frame_offset as u32 * 16
+        );
+    }
+    print!("\n");
+
+    for code in &info.codes {
+        print!(
+            "{}  +0x{:02x}: {}\n",
+            prefix,
+            code.prolog_offset,
+            describe_code(code, info)
+        );
+    }
+
+    if info.flags & (UNW_FLAG_EHANDLER | UNW_FLAG_UHANDLER) != 0 {
+        print!("{}Handler: 0x{:x}", prefix, info.handler as u64 + base);
+        if let Some(name) = &info.handler_name {
+            print!(" <{}>", name);
+        }
+        print!(", data at 0x{:x}\n", info.handler_data as u64 + base);
+        for scope in &info.scopes {
+            print!(
+                "{}  __try 0x{:x}-0x{:x}: ",
+                prefix,
+                scope.begin as u64 + base,
+                scope.end as u64 + base
+            );
+            if scope.target == 0 {
+                print!("__finally 0x{:x}\n", scope.handler as u64 + base);
+            } else if scope.handler == 1 {
+                print!("__except 0x{:x}\n", scope.target as u64 + base);
+            } else {
+                print!(
+                    "filter 0x{:x}, __except 0x{:x}\n",
+                    scope.handler as u64 + base,
+                    scope.target as u64 + base
+                );
+            }
+        }
+    }
+
+    if let Some(chained) = &info.chained {
+        print!(
+            "{}Chained to 0x{:x}-0x{:x}\n",
+            prefix,
+            chained.begin as u64 + base,
+            chained.end as u64 + base
+        );
+    }
+}
+
+pub fn print_exception_table(pe: &PeExecutable, pe_rel_addr: i32) {
+    let base = if pe_rel_addr != 0 { 0 } else { pe.imagebase };
+
+    print!(
+        "\nException directory ({} functions):\n",
+        pe.functions.len()
+    );
+    for function in &pe.functions {
+        print!(
+            "\t0x{:x}-0x{:x}",
+            function.begin as u64 + base,
+            function.end as u64 + base
+        );
+        if let Some(name) = get_function_name(function.begin, pe) {
+            print!(" <{}>", name);
+        }
+        print!("\n");
+        if let Some(info) = &function.info {
+            print_unwind_info(info, "\t\t", base);
+        }
+    }
+}
+
+/* The unwind info of the function starting at `ip`, printed as comments
+ * above its disassembly. */
+pub fn print_function_unwind(ip: u32, pe: &PeExecutable, pe_rel_addr: i32) {
+    let base = if pe_rel_addr != 0 { 0 } else { pe.imagebase };
+    let mut info = pe
+        .functions
+        .iter()
+        .find(|f| f.begin == ip)
+        .and_then(|f| f.info.as_ref());
+    /* follow the chain, since the primary info isn't necessarily nearby */
+    while let Some(i) = info {
+        print_unwind_info(i, "\t\t; ", base);
+        info = i.chained.as_ref().and_then(|c| c.info.as_ref());
+    }
+}
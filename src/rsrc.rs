@@ -0,0 +1,161 @@
+/* Resources of NE and PE files, in a common form.
+ *
+ * NE files keep a flat table of types, each with a list of resources; PE
+ * files have a three-level tree of type, name and language. Either way we
+ * end up with a list of entries pointing into the file, which is what the
+ * listing, the filters and the exporters work on. The contents are then
+ * decoded by ne::print_rsrc_resource or pe::resource::print_rsrc_resource,
+ * since many of the 16-bit and 32-bit layouts differ. */
+
+use crate::filter;
+use crate::ne;
+use crate::pe;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ResourceId {
+    Id(u16),
+    Name(String),
+}
+
+impl ResourceId {
+    pub fn number(&self) -> Option<u16> {
+        match self {
+            ResourceId::Id(n) => Some(*n),
+            ResourceId::Name(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for ResourceId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ResourceId::Id(n) => write!(f, "{}", n),
+            ResourceId::Name(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ResourceEntry {
+    /* Numeric types are the RT_* values, i.e. without the high bit which
+     * NE sets on them. */
+    pub rsrc_type: ResourceId,
+    pub id: ResourceId,
+    pub lang: Option<u16>, /* PE only */
+    pub offset: usize,     /* in the file */
+    pub length: usize,
+    pub flags: u16,    /* NE only */
+    pub codepage: u32, /* PE only */
+}
+
+pub const RSRC_TYPE_NAMES: [&str; 25] = [
+    "",
+    "Cursor",            /* 1 */
+    "Bitmap",            /* 2 */
+    "Icon",              /* 3 */
+    "Menu",              /* 4 */
+    "Dialog box",        /* 5 */
+    "String",            /* 6 */
+    "Font directory",    /* 7 */
+    "Font component",    /* 8 */
+    "Accelerator table", /* 9 */
+    "Resource data",     /* a */
+    "Message table",     /* b */
+    "Cursor directory",  /* c */
+    "",
+    "Icon directory", /* e */
+    "Name table",     /* f */
+    "Version",        /* 10 */
+    "Dialog include", /* 11 */
+    "",
+    "Plug and Play",   /* 13 */
+    "VxD",             /* 14 */
+    "Animated cursor", /* 15 */
+    "Animated icon",   /* 16 */
+    "HTML",            /* 17 */
+    "Manifest",        /* 18 */
+];
+
+/* The type as we print it: a name from the table, a number, or a quoted
+ * custom name. */
+pub fn type_name(rsrc_type: &ResourceId) -> String {
+    match rsrc_type {
+        ResourceId::Id(n) => {
+            if (*n as usize) < RSRC_TYPE_NAMES.len() && !RSRC_TYPE_NAMES[*n as usize].is_empty() {
+                RSRC_TYPE_NAMES[*n as usize].to_string()
+            } else {
+                format!("0x{:04x}", n)
+            }
+        }
+        ResourceId::Name(s) => format!("\"{}\"", s),
+    }
+}
+
+/* for the filter, which wants custom names unquoted */
+fn filter_type_name(rsrc_type: &ResourceId) -> String {
+    match rsrc_type {
+        ResourceId::Name(s) => s.clone(),
+        _ => type_name(rsrc_type),
+    }
+}
+
+pub fn filter_entry(entry: &ResourceEntry) -> bool {
+    filter::filter_resource(
+        &filter_type_name(&entry.rsrc_type),
+        entry.rsrc_type.number(),
+        &entry.id.to_string(),
+        entry.lang,
+    )
+}
+
+/* The resources of one type, in the order they appear. */
+pub fn find_type(resources: &Vec<ResourceEntry>, rsrc_type: u16) -> Vec<&ResourceEntry> {
+    resources
+        .iter()
+        .filter(|r| r.rsrc_type == ResourceId::Id(rsrc_type))
+        .collect()
+}
+
+/* List and decode every resource that passes the filter. `wide` selects the
+ * 32-bit (PE) layouts. */
+pub fn print_resources(map: &Vec<u8>, resources: &Vec<ResourceEntry>, wide: bool) {
+    for entry in resources {
+        if !filter_entry(entry) {
+            continue;
+        }
+
+        print!("\n{} {}", type_name(&entry.rsrc_type), entry.id);
+        if let Some(lang) = entry.lang {
+            print!(" [language 0x{:04x}]", lang);
+        }
+        print!(
+            " (offset = 0x{:x}, length = {} [0x{:x}]",
+            entry.offset, entry.length, entry.length
+        );
+        if wide {
+            if entry.codepage != 0 {
+                print!(", codepage {}", entry.codepage);
+            }
+        } else {
+            ne::print_rsrc_flags(entry.flags);
+        }
+        println!("):");
+
+        if entry.offset + entry.length > map.len() {
+            eprintln!("Resource exceeds file length.");
+            continue;
+        }
+
+        /* the NE printer gets the type in the NE form, with the high bit set;
+         * custom types are only hex-dumped */
+        match (&entry.rsrc_type, wide) {
+            (ResourceId::Id(t), false) => {
+                ne::print_rsrc_resource(map, t | 0x8000, entry.offset, entry.length, &entry.id)
+            }
+            (ResourceId::Id(t), true) => {
+                pe::resource::print_rsrc_resource(map, *t, entry.offset, entry.length, &entry.id)
+            }
+            _ => ne::print_hex_dump(map, entry.offset, entry.length),
+        }
+    }
+}
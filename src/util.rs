@@ -1,3 +1,6 @@
+use std::cmp::min;
+use std::sync::Mutex;
+
 // static inline const void *read_data(off_t offset)
 pub fn read_data(map: &Vec<u8>, offset: usize, length: usize) -> Vec<u8> {
     // return map + offset;
@@ -43,6 +46,58 @@ pub fn read_qword(map: &Vec<u8>, offset: usize) -> u64 {
     u64::from_le_bytes(slice)
 }
 
+/* a zero-terminated string, of at most `length` bytes unless that is 0 */
 pub fn read_string(map: &Vec<u8>, offset: usize, length: usize) -> String {
-    unimplemented!()
+    if offset >= map.len() {
+        return String::new();
+    }
+    let limit = if length == 0 {
+        map.len()
+    } else {
+        min(map.len(), offset + length)
+    };
+    let mut end = offset;
+    while end < limit && map[end] != 0 {
+        end += 1;
+    }
+    String::from_utf8_lossy(&map[offset..end]).into_owned()
+}
+
+/* Something from the file made safe to use in an output file name: spaces
+ * become underscores and anything but letters, digits, '_' and '-' is
+ * dropped, so it can't name a directory. */
+pub fn file_name_part(s: &str) -> String {
+    s.chars()
+        .map(|c| if c == ' ' { '_' } else { c })
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '-')
+        .collect()
+}
+
+/* whether --json was given, for the structures that have a JSON form */
+static JSON_OUTPUT: Mutex<bool> = Mutex::new(false);
+
+pub fn set_json_output() {
+    *JSON_OUTPUT.lock().unwrap() = true;
+}
+
+pub fn json_output() -> bool {
+    *JSON_OUTPUT.lock().unwrap()
+}
+
+/* a string as a JSON string literal */
+pub fn json_string(s: &str) -> String {
+    let mut ret = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => ret += "\\\"",
+            '\\' => ret += "\\\\",
+            '\n' => ret += "\\n",
+            '\r' => ret += "\\r",
+            '\t' => ret += "\\t",
+            c if (c as u32) < 0x20 => ret += &format!("\\u{:04x}", c as u32),
+            c => ret.push(c),
+        }
+    }
+    ret.push('"');
+    ret
 }
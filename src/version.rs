@@ -0,0 +1,545 @@
+/* Version resources (VS_VERSIONINFO), for both NE and PE.
+ *
+ * A version resource is a tree of blocks. Each block is
+ *
+ *     [word] length of the whole block, including children
+ *     [word] length of the value
+ *     [word] type: 1 for text, 0 for binary (PE only)
+ *     key, zero-terminated: ANSI for NE, UTF-16 for PE
+ *     padding to a dword boundary
+ *     value
+ *     padding to a dword boundary
+ *     children
+ *
+ * The root is "VS_VERSION_INFO" whose value is the VS_FIXEDFILEINFO. Under it
+ * are "StringFileInfo", containing one string table per language (keyed by
+ * eight hex digits of language and codepage) which in turn contains the
+ * strings, and "VarFileInfo", whose "Translation" value lists the
+ * (language, codepage) pairs the module supports.
+ *
+ * The value length is in bytes, except for PE text values where it is in
+ * characters. */
+
+use crate::codepage;
+use crate::util::{read_dword, read_word};
+
+pub const VS_FIXEDFILEINFO_SIGNATURE: u32 = 0xfeef04bd;
+
+#[derive(Clone, Debug, Default)]
+pub struct FixedFileInfo {
+    pub dwSignature: u32,        /* 00 - 0xfeef04bd */
+    pub dwStrucVersion: u32,     /* 04 - 1.0 */
+    pub dwFileVersionMS: u32,    /* 08 */
+    pub dwFileVersionLS: u32,    /* 0c */
+    pub dwProductVersionMS: u32, /* 10 */
+    pub dwProductVersionLS: u32, /* 14 */
+    pub dwFileFlagsMask: u32,    /* 18 */
+    pub dwFileFlags: u32,        /* 1c */
+    pub dwFileOS: u32,           /* 20 */
+    pub dwFileType: u32,         /* 24 */
+    pub dwFileSubtype: u32,      /* 28 */
+    pub dwFileDateMS: u32,       /* 2c */
+    pub dwFileDateLS: u32,       /* 30 */
+}
+
+#[derive(Clone, Debug)]
+pub enum VersionValue {
+    None,
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+impl Default for VersionValue {
+    fn default() -> Self {
+        VersionValue::None
+    }
+}
+
+/* one block of the tree, as it is in the file */
+#[derive(Clone, Debug, Default)]
+pub struct VersionNode {
+    pub key: String,
+    pub value: VersionValue,
+    pub children: Vec<VersionNode>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct StringTable {
+    pub lang: u16,
+    pub codepage: u16,
+    pub strings: Vec<(String, String)>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct VersionInfo {
+    pub fixed: Option<FixedFileInfo>,
+    pub string_tables: Vec<StringTable>,
+    pub translations: Vec<(u16, u16)>,
+    pub root: VersionNode,
+}
+
+pub const VERSION_FILE_FLAGS: [&str; 6] = [
+    "VS_FF_DEBUG",        /* 0001 */
+    "VS_FF_PRERELEASE",   /* 0002 */
+    "VS_FF_PATCHED",      /* 0004 */
+    "VS_FF_PRIVATEBUILD", /* 0008 */
+    "VS_FF_INFOINFERRED", /* 0010 */
+    "VS_FF_SPECIALBUILD", /* 0020 */
+];
+
+pub const VERSION_TYPES: [&str; 8] = [
+    "unknown",             /* 0 VFT_UNKNOWN */
+    "application",         /* 1 VFT_APP */
+    "DLL",                 /* 2 VFT_DLL */
+    "device driver",       /* 3 VFT_DRV */
+    "font",                /* 4 VFT_FONT */
+    "virtual device",      /* 5 VFT_VXD */
+    "(unknown type 6)",    /* 6 */
+    "static-link library", /* 7 VFT_STATIC_LIB */
+];
+
+pub const VERSION_SUBTYPES_DRV: [&str; 13] = [
+    "unknown",           /* 0 VFT2_UNKNOWN */
+    "printer",           /* 1 VFT2_DRV_PRINTER etc. */
+    "keyboard",          /* 2 */
+    "language",          /* 3 */
+    "display",           /* 4 */
+    "mouse",             /* 5 */
+    "network",           /* 6 */
+    "system",            /* 7 */
+    "installable",       /* 8 */
+    "sound",             /* 9 */
+    "communications",    /* 10 */
+    "input method",      /* 11, found in WINE */
+    "versioned printer", /* 12 */
+];
+
+pub const VERSION_SUBTYPES_FONT: [&str; 4] = [
+    "unknown font",  /* 0 */
+    "raster font",   /* 1 VFT2_FONT_RASTER */
+    "vector font",   /* 2 VFT2_FONT_VECTOR */
+    "TrueType font", /* 3 VFT2_FONT_TRUETYPE */
+];
+
+fn version_string(ms: u32, ls: u32) -> String {
+    format!("{}.{}.{}.{}", ms >> 16, ms & 0xffff, ls >> 16, ls & 0xffff)
+}
+
+impl FixedFileInfo {
+    pub fn from_bytes(data: &Vec<u8>, offset: usize) -> Self {
+        let d = |i: usize| read_dword(data, offset + i * 4);
+        FixedFileInfo {
+            dwSignature: d(0),
+            dwStrucVersion: d(1),
+            dwFileVersionMS: d(2),
+            dwFileVersionLS: d(3),
+            dwProductVersionMS: d(4),
+            dwProductVersionLS: d(5),
+            dwFileFlagsMask: d(6),
+            dwFileFlags: d(7),
+            dwFileOS: d(8),
+            dwFileType: d(9),
+            dwFileSubtype: d(10),
+            dwFileDateMS: d(11),
+            dwFileDateLS: d(12),
+        }
+    }
+
+    pub fn file_version(&self) -> String {
+        version_string(self.dwFileVersionMS, self.dwFileVersionLS)
+    }
+
+    pub fn product_version(&self) -> String {
+        version_string(self.dwProductVersionMS, self.dwProductVersionLS)
+    }
+
+    /* only the flags that the mask says are valid */
+    pub fn file_flags(&self) -> Vec<String> {
+        let flags = self.dwFileFlags & self.dwFileFlagsMask;
+        let mut ret = Vec::new();
+        for i in 0..VERSION_FILE_FLAGS.len() {
+            if flags & (1 << i) != 0 {
+                ret.push(VERSION_FILE_FLAGS[i].to_string());
+            }
+        }
+        if flags & !0x3f != 0 {
+            ret.push(format!("(unknown flags 0x{:04x})", flags & !0x3f));
+        }
+        ret
+    }
+
+    pub fn os(&self) -> Vec<String> {
+        let mut ret = Vec::new();
+        if self.dwFileOS == 0 {
+            ret.push("VOS_UNKNOWN".to_string());
+            return ret;
+        }
+        match self.dwFileOS & 0xffff {
+            0 => {}
+            1 => ret.push("VOS__WINDOWS16".to_string()),
+            2 => ret.push("VOS__PM16".to_string()),
+            3 => ret.push("VOS__PM32".to_string()),
+            4 => ret.push("VOS__WINDOWS32".to_string()),
+            os => ret.push(format!("(unknown OS 0x{:04x})", os)),
+        }
+        match self.dwFileOS >> 16 {
+            0 => {}
+            1 => ret.push("VOS_DOS".to_string()),
+            2 => ret.push("VOS_OS216".to_string()),
+            3 => ret.push("VOS_OS232".to_string()),
+            4 => ret.push("VOS_NT".to_string()),
+            5 => ret.push("VOS_WINCE".to_string()), /* found in WINE */
+            os => ret.push(format!("(unknown OS 0x{:04x})", os)),
+        }
+        ret
+    }
+
+    pub fn file_type(&self) -> String {
+        match VERSION_TYPES.get(self.dwFileType as usize) {
+            Some(name) => name.to_string(),
+            None => format!("(unknown type {})", self.dwFileType),
+        }
+    }
+
+    /* The subtype means something different depending on the type, and
+     * nothing at all for most of them. */
+    pub fn file_subtype(&self) -> Option<String> {
+        let subtype = self.dwFileSubtype;
+        match self.dwFileType {
+            3 => Some(match VERSION_SUBTYPES_DRV.get(subtype as usize) {
+                Some(name) => format!("{} driver", name),
+                None => format!("(unknown subtype {})", subtype),
+            }),
+            4 => Some(match VERSION_SUBTYPES_FONT.get(subtype as usize) {
+                Some(name) => name.to_string(),
+                None => format!("(unknown subtype {})", subtype),
+            }),
+            5 => Some(format!("virtual device ID {}", subtype)),
+            _ if subtype != 0 => Some(format!("(unknown subtype {})", subtype)),
+            _ => None,
+        }
+    }
+
+    /* the file date is a FILETIME, and is almost always zero */
+    pub fn file_date(&self) -> Option<String> {
+        let filetime = ((self.dwFileDateMS as u64) << 32) | self.dwFileDateLS as u64;
+        if filetime == 0 {
+            return None;
+        }
+        let secs = filetime / 10_000_000;
+        if secs < 11_644_473_600 {
+            return Some(format!("(invalid date 0x{:016x})", filetime));
+        }
+        let unix = secs - 11_644_473_600;
+        let (days, rem) = ((unix / 86400) as i64, unix % 86400);
+
+        /* days since 1970-01-01 to a civil date */
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z - era * 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        Some(format!(
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            year,
+            month,
+            day,
+            rem / 3600,
+            rem / 60 % 60,
+            rem % 60
+        ))
+    }
+}
+
+fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+/* Returns the key and the offset just past it. */
+fn read_key(data: &Vec<u8>, offset: usize, end: usize, unicode: bool) -> (String, usize) {
+    let mut cursor = offset;
+    if unicode {
+        let mut chars = Vec::new();
+        while cursor + 2 <= end {
+            let c = read_word(data, cursor);
+            cursor += 2;
+            if c == 0 {
+                break;
+            }
+            chars.push(c);
+        }
+        (String::from_utf16_lossy(&chars), cursor)
+    } else {
+        while cursor < end && data[cursor] != 0 {
+            cursor += 1;
+        }
+        (codepage::decode(&data[offset..cursor]), cursor + 1)
+    }
+}
+
+fn decode_text(data: &[u8], unicode: bool, cp: u16) -> String {
+    if unicode {
+        let chars: Vec<u16> = data
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .take_while(|&c| c != 0)
+            .collect();
+        String::from_utf16_lossy(&chars)
+    } else {
+        let len = data.iter().position(|&c| c == 0).unwrap_or(data.len());
+        let cp = if codepage::is_supported(cp) {
+            cp
+        } else {
+            codepage::codepage()
+        };
+        codepage::decode_with(&data[..len], cp)
+    }
+}
+
+/* Read one block and its children. `depth` tells NE strings from binary
+ * values, since NE files don't have the type field; `cp` is the codepage
+ * of the string table we're in, if any. Returns the node and the
+ * offset of the next block. */
+fn read_node(
+    data: &Vec<u8>,
+    offset: usize,
+    limit: usize,
+    unicode: bool,
+    depth: usize,
+    cp: u16,
+) -> Option<(VersionNode, usize)> {
+    let header = if unicode { 6 } else { 4 };
+    if offset + header > limit {
+        return None;
+    }
+    let length = read_word(data, offset) as usize;
+    let value_length = read_word(data, offset + 2) as usize;
+    if length < header {
+        return None;
+    }
+    let end = std::cmp::min(offset + length, limit);
+
+    let (key, after_key) = read_key(data, offset + header, end, unicode);
+    let value_start = align4(after_key);
+
+    /* Strings live at depth 4, below VS_VERSION_INFO, StringFileInfo and
+     * their table. */
+    let text = if unicode {
+        read_word(data, offset + 4) == 1
+    } else {
+        depth == 4
+    };
+    let value_bytes = if unicode && text {
+        value_length * 2
+    } else {
+        value_length
+    };
+    let value_end = std::cmp::min(value_start + value_bytes, end);
+
+    let value = if value_length == 0 || value_start >= end {
+        VersionValue::None
+    } else if text {
+        /* According to MSDN this is zero-terminated, and in most cases it is.
+         * However, at least one application (msbsolar) has NEs with what
+         * appears to be a non-zero-terminated string. In Windows this is cut
+         * off at one minus the given length, just like other strings, so
+         * we'll do that here. */
+        let mut bytes = &data[value_start..value_end];
+        if !unicode && !bytes.contains(&0) && !bytes.is_empty() {
+            bytes = &bytes[..bytes.len() - 1];
+        }
+        VersionValue::Text(decode_text(bytes, unicode, cp))
+    } else {
+        VersionValue::Binary(data[value_start..value_end].to_vec())
+    };
+
+    /* a string table's key gives the codepage of its strings */
+    let child_cp = if depth == 3 && key.len() == 8 {
+        key.get(4..)
+            .and_then(|s| u16::from_str_radix(s, 16).ok())
+            .unwrap_or(cp)
+    } else {
+        cp
+    };
+
+    let mut children = Vec::new();
+    let mut cursor = align4(value_start + value_bytes);
+    while cursor < end {
+        match read_node(data, cursor, end, unicode, depth + 1, child_cp) {
+            Some((child, next)) => {
+                children.push(child);
+                cursor = next;
+            }
+            None => break,
+        }
+    }
+
+    Some((
+        VersionNode {
+            key,
+            value,
+            children,
+        },
+        align4(offset + length),
+    ))
+}
+
+impl VersionNode {
+    pub fn child(&self, key: &str) -> Option<&VersionNode> {
+        self.children
+            .iter()
+            .find(|c| c.key.eq_ignore_ascii_case(key))
+    }
+
+    /* Look up a node by a '\'-separated path below this one, as
+     * VerQueryValue does, e.g. "\StringFileInfo\040904E4\CompanyName". */
+    pub fn find(&self, path: &str) -> Option<&VersionNode> {
+        let mut node = self;
+        for key in path.split('\\').filter(|k| !k.is_empty()) {
+            node = node.child(key)?;
+        }
+        Some(node)
+    }
+}
+
+/* Parse a version resource. `unicode` is true for PE (32-bit) resources
+ * and false for NE ones. */
+pub fn read_version_info(data: &Vec<u8>, unicode: bool) -> Option<VersionInfo> {
+    let (root, _) = read_node(data, 0, data.len(), unicode, 1, 0)?;
+    let mut info = VersionInfo::default();
+
+    if root.key != "VS_VERSION_INFO" {
+        eprintln!("Version header is {} (expected VS_VERSION_INFO).", root.key);
+    }
+
+    if let VersionValue::Binary(value) = &root.value {
+        if value.len() < 52 {
+            eprintln!("Version header length is {} (expected 52).", value.len());
+        } else {
+            let fixed = FixedFileInfo::from_bytes(value, 0);
+            if fixed.dwSignature != VS_FIXEDFILEINFO_SIGNATURE {
+                eprintln!(
+                    "Version magic number is {:08x} (expected 0xfeef04bd).",
+                    fixed.dwSignature
+                );
+            }
+            if fixed.dwStrucVersion >> 16 != 1 {
+                eprintln!(
+                    "Version header version is {}.{} (expected 1.0).",
+                    fixed.dwStrucVersion >> 16,
+                    fixed.dwStrucVersion & 0xffff
+                );
+            }
+            info.fixed = Some(fixed);
+        }
+    }
+
+    for block in &root.children {
+        if block.key == "StringFileInfo" {
+            for table in &block.children {
+                let lang = u16::from_str_radix(table.key.get(..4).unwrap_or(""), 16).unwrap_or(0);
+                let cp = u16::from_str_radix(table.key.get(4..).unwrap_or(""), 16).unwrap_or(0);
+                let strings = table
+                    .children
+                    .iter()
+                    .map(|s| {
+                        let value = match &s.value {
+                            VersionValue::Text(text) => text.clone(),
+                            _ => String::new(),
+                        };
+                        (s.key.clone(), value)
+                    })
+                    .collect();
+                info.string_tables.push(StringTable {
+                    lang,
+                    codepage: cp,
+                    strings,
+                });
+            }
+        } else if block.key == "VarFileInfo" {
+            for var in &block.children {
+                if let VersionValue::Binary(value) = &var.value {
+                    if var.key == "Translation" {
+                        for i in (0..value.len() / 4 * 4).step_by(4) {
+                            info.translations
+                                .push((read_word(value, i), read_word(value, i + 2)));
+                        }
+                    }
+                }
+            }
+        } else {
+            eprintln!("Unrecognized file info key: {}", block.key);
+        }
+    }
+
+    info.root = root;
+    Some(info)
+}
+
+impl VersionInfo {
+    /* Look up a string such as "ProductVersion". Prefer the table for the
+     * first translation, as VerQueryValue users typically do, and fall back
+     * to any table which has it. */
+    pub fn query(&self, key: &str) -> Option<&str> {
+        let preferred = self.translations.first().and_then(|&(lang, cp)| {
+            self.string_tables
+                .iter()
+                .find(|t| t.lang == lang && t.codepage == cp)
+        });
+        preferred
+            .into_iter()
+            .chain(self.string_tables.iter())
+            .flat_map(|t| t.strings.iter())
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /* the codepage of the first translation, used to decode other
+     * resources */
+    pub fn codepage(&self) -> Option<u16> {
+        self.translations.first().map(|&(_, cp)| cp)
+    }
+}
+
+pub fn print_version_info(info: &VersionInfo) {
+    if let Some(fixed) = &info.fixed {
+        let flags = fixed.file_flags();
+        print!("    File flags: ");
+        if !flags.is_empty() {
+            print!("{}", flags.join(", "));
+        }
+        print!("\n    OS flags: {}\n", fixed.os().join(", "));
+        print!("    Type: {}\n", fixed.file_type());
+        if let Some(subtype) = fixed.file_subtype() {
+            print!("    Subtype: {}\n", subtype);
+        }
+        print!("    File version:    {}\n", fixed.file_version());
+        print!("    Product version: {}\n", fixed.product_version());
+        if let Some(date) = fixed.file_date() {
+            print!("    Created on: {}\n", date);
+        }
+    }
+
+    for table in &info.string_tables {
+        println!(
+            "    String table (lang={:04x}, codepage={:04x}):",
+            table.lang, table.codepage
+        );
+        for (key, value) in &table.strings {
+            println!(
+                "        \"{}\": \"{}\"",
+                codepage::escape(key),
+                codepage::escape(value)
+            );
+        }
+    }
+
+    for (lang, cp) in &info.translations {
+        println!("    Var (lang={:04x}, codepage={:04x})", lang, cp);
+    }
+}